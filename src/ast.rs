@@ -1,5 +1,61 @@
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    /// The expressions directly inside this one, including the ones in a
+    /// block's statements. Methods of type declarations inside a block
+    /// aren't included.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Literal(Literal::Array(elems) | Literal::Tuple(elems)) => elems.iter().collect(),
+            ExprKind::Literal(Literal::Map(entries)) => entries.iter().flat_map(|(key, value)| [key, value]).collect(),
+            ExprKind::Literal(_) | ExprKind::Variable(_) => Vec::new(),
+            ExprKind::StructLiteral { fields, .. } => fields.iter().map(|(_, value)| value).collect(),
+            ExprKind::FieldAccess { object, .. } => vec![object],
+            ExprKind::BinaryOp { left, right, .. } => vec![left, right],
+            ExprKind::UnaryOp { expr, .. } | ExprKind::Try(expr) => vec![expr],
+            ExprKind::Call { callee, args } => std::iter::once(&**callee).chain(args).collect(),
+            ExprKind::Block(statements) => statements.iter().flat_map(Stmt::expressions).collect(),
+            ExprKind::If { condition, then_branch, else_branch } => {
+                let mut children = vec![&**condition, &**then_branch];
+                children.extend(else_branch.as_deref());
+                children
+            }
+            ExprKind::While { condition, body } => vec![condition, body],
+            ExprKind::Match { expr, arms } => {
+                let mut children = vec![&**expr];
+                for arm in arms {
+                    children.extend(&arm.guard);
+                    children.push(&arm.body);
+                }
+                children
+            }
+            ExprKind::Function { body, .. } | ExprKind::Lambda { body, .. } => vec![body],
+            ExprKind::Index { array, index } => vec![array, index],
+            ExprKind::For { iterable, body, .. } => vec![iterable, body],
+            ExprKind::EnumVariant { data, .. } => data.as_deref().into_iter().collect(),
+            ExprKind::InterpolatedString(parts) => parts.iter()
+                .filter_map(|part| match part {
+                    StringPart::Expr { expr, .. } => Some(&**expr),
+                    StringPart::Literal(_) => None,
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Literal(Literal),
     Variable(String),
     StructLiteral {
@@ -8,7 +64,7 @@ pub enum Expr {
     },
     FieldAccess {
         object: Box<Expr>,
-        field: String,
+        field: String, // A tuple index such as "0" for `pair.0`
     },
     BinaryOp {
         left: Box<Expr>,
@@ -39,6 +95,12 @@ pub enum Expr {
     },
     Function {
         name: String,
+        type_params: Vec<TypeParam>,
+        params: Vec<Param>,
+        return_type: Option<Type>,
+        body: Box<Expr>,
+    },
+    Lambda { // |x| x * 2 or fn(x) { x * 2 }; anonymous, not bound to a name
         params: Vec<Param>,
         return_type: Option<Type>,
         body: Box<Expr>,
@@ -47,8 +109,9 @@ pub enum Expr {
         array: Box<Expr>,
         index: Box<Expr>,
     },
+    Try(Box<Expr>), // expr? gives the Ok/Some data, or returns the Err/None from the enclosing function
     For {
-        variable: String,
+        pattern: Pattern, // A variable, or a pattern to destructure each item
         iterable: Box<Expr>,
         body: Box<Expr>,
     },
@@ -57,52 +120,139 @@ pub enum Expr {
         variant_name: String,
         data: Option<Box<Expr>>, // Optional associated data
     },
+    InterpolatedString(Vec<StringPart>), // "Hello {name}"
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Stmt {
+pub enum StringPart {
+    Literal(String),
+    Expr {
+        expr: Box<Expr>,
+        format: Option<FormatSpec>, // From {expr:spec}
+    },
+}
+
+// Format spec: [[fill]align][0][width][.precision], e.g. `.2`, `>8`, `*^10`, `05`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<Align>, // None: numbers right-aligned, everything else left
+    pub zero: bool,           // Pad a number with zeros after its sign, ignoring fill and align
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+    pub public: bool, // Declared with `pub`, so other modules can use it
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Stmt { kind, span, public: false }
+    }
+
+    /// The expressions directly inside this statement. Type declarations
+    /// have none; their methods are separate bodies.
+    pub fn expressions(&self) -> Vec<&Expr> {
+        match &self.kind {
+            StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => vec![expr],
+            StmtKind::Let { value, .. } | StmtKind::LetPattern { value, .. } | StmtKind::Assign { value, .. } => {
+                vec![value]
+            }
+            StmtKind::AssignField { object, value, .. } => vec![object, value],
+            StmtKind::AssignIndex { object, index, value, .. } => vec![object, index, value],
+            StmtKind::StructDef { .. } | StmtKind::EnumDef { .. } | StmtKind::TraitDef { .. }
+            | StmtKind::ImplBlock { .. } | StmtKind::Import { .. } | StmtKind::Return(None)
+            | StmtKind::Break | StmtKind::Continue | StmtKind::Error => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
     Expr(Expr),
     Let {
         name: String,
         type_annotation: Option<Type>,
         value: Expr,
     },
+    LetPattern { // let Point { x, y } = p, let [first, ..rest] = items
+        pattern: Pattern,
+        value: Expr,
+    },
+    // `op` is set for compound assignment, `x += 1`, which reads the target
+    // and writes the result back to the same place
     Assign {
         name: String,
+        op: Option<BinaryOp>,
         value: Expr,
     },
     AssignField {
         object: Box<Expr>,
         field: String,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    AssignIndex { // counts[word] = 1
+        object: Box<Expr>,
+        index: Expr,
+        op: Option<BinaryOp>,
         value: Expr,
     },
     StructDef {
         name: String,
+        type_params: Vec<TypeParam>,
         fields: Vec<(String, Option<Type>)>,
         methods: Vec<MethodDecl>,
     },
     EnumDef {
         name: String,
+        type_params: Vec<TypeParam>,
         variants: Vec<EnumVariant>,
     },
     TraitDef {
         name: String,
+        type_params: Vec<TypeParam>,
         methods: Vec<TraitMethod>,
     },
     ImplBlock {
+        type_params: Vec<TypeParam>, // impl<T> ...
         trait_name: Option<String>, // None for inherent impl, Some for trait impl
-        type_name: String,
+        type_name: String, // Without type arguments: `impl<T> Show for Box<T>` has "Box"
         methods: Vec<MethodDecl>,
     },
+    Import { // import "utils.tog" or use math::vector, optionally `as name`
+        source: ImportSource,
+        alias: Option<String>,
+    },
     Return(Option<Expr>),
     Break,
     Continue,
+    Error, // A statement that failed to parse; the span covers the skipped tokens
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportSource {
+    File(String),      // import "lib/utils.tog"
+    Path(Vec<String>), // use math::vector, or use math::vector::dot for a single item
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct EnumVariant {
     pub name: String,
-    pub data_type: Option<Type>, // Optional associated data type
+    // Optional associated data type; a variant holding several values,
+    // `Move(int, int)`, holds them as a tuple
+    pub data_type: Option<Type>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -110,7 +260,7 @@ pub struct TraitMethod {
     pub name: String,
     pub params: Vec<Param>,
     pub return_type: Option<Type>,
-    // Trait methods are just signatures, no body
+    pub default_body: Option<Expr>, // Used when an impl doesn't provide the method
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -120,6 +270,8 @@ pub enum Literal {
     String(String),
     Bool(bool),
     Array(Vec<Expr>),
+    Tuple(Vec<Expr>), // (a, b); `()` is the empty tuple
+    Map(Vec<(Expr, Expr)>), // {"a": 1, "b": 2}; `{}` is the empty map
     None,
 }
 
@@ -149,7 +301,9 @@ pub enum UnaryOp {
 #[derive(Debug, Clone, PartialEq)]
 pub struct MatchArm {
     pub pattern: Pattern,
+    pub guard: Option<Expr>, // pattern if condition => ...
     pub body: Expr,
+    pub span: Span, // The pattern and guard, for diagnostics about the arm
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -158,16 +312,69 @@ pub enum Pattern {
     Variable(String),
     Wildcard,
     EnumVariant {
-        enum_name: String,
+        enum_name: String, // Empty for a bare variant name: `Some(x)`
         variant_name: String,
-        binding: Option<String>, // Variable name to bind the data to
+        inner: Option<Box<Pattern>>, // Pattern for the associated data
+    },
+    Struct { // Point { x, y: 0, .. }; fields that aren't listed are ignored
+        name: String,
+        fields: Vec<(String, Pattern)>,
+    },
+    Array(Vec<Pattern>), // [first, second, ..rest]; at most one element is a Rest
+    Tuple(Vec<Pattern>), // (x, y)
+    Rest(Option<String>), // `..` or `..name` inside an array pattern
+    Or(Vec<Pattern>), // 1 | 2 | 3; every alternative binds the same names
+    Range {
+        start: Literal,
+        end: Literal,
+        inclusive: bool, // 1..=9 rather than 1..9
     },
 }
 
+impl Pattern {
+    /// The variables the pattern binds, in order of appearance.
+    pub fn bindings(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_bindings(&mut names);
+        names
+    }
+
+    fn collect_bindings(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Variable(name) | Pattern::Rest(Some(name)) => names.push(name.clone()),
+            Pattern::EnumVariant { inner: Some(inner), .. } => inner.collect_bindings(names),
+            Pattern::Struct { fields, .. } => {
+                for (_, pattern) in fields {
+                    pattern.collect_bindings(names);
+                }
+            }
+            Pattern::Array(elements) | Pattern::Tuple(elements) => {
+                for element in elements {
+                    element.collect_bindings(names);
+                }
+            }
+            // Alternatives bind the same names
+            Pattern::Or(alternatives) => alternatives[0].collect_bindings(names),
+            Pattern::Literal(_) | Pattern::Wildcard | Pattern::EnumVariant { inner: None, .. }
+            | Pattern::Rest(None) | Pattern::Range { .. } => {}
+        }
+    }
+}
+
+// A type parameter with its trait bounds: `T: Display + Clone`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Vec<String>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Param {
     pub name: String,
     pub type_annotation: Option<Type>,
+    // `&mut self`: what the method leaves in `self` is written back to the
+    // receiver it was called on
+    pub by_ref: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -185,9 +392,16 @@ pub enum Type {
     String,
     Bool,
     Array(Box<Type>),
+    Tuple(Vec<Type>), // (int, string)
+    Map(Box<Type>, Box<Type>), // map[string, int]
+    Set(Box<Type>), // set[int]
     Struct(String),
     Enum(String),
-    #[allow(dead_code)] // Will be used for function type annotations
+    Param(String), // A type parameter such as `T`, inside its generic definition
+    Generic { // A generic struct or enum with type arguments: `Option<int>`
+        name: String,
+        args: Vec<Type>,
+    },
     Function {
         params: Vec<Type>,
         return_type: Box<Type>,
@@ -196,6 +410,39 @@ pub enum Type {
     Infer, // For type inference
 }
 
+// Types as written in TOG source, for error messages
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array(inner) => write!(f, "array[{}]", inner),
+            Type::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                match elements.as_slice() {
+                    [single] => write!(f, "({},)", single),
+                    _ => write!(f, "({})", elements.join(", ")),
+                }
+            }
+            Type::Map(key, value) => write!(f, "map[{}, {}]", key, value),
+            Type::Set(element) => write!(f, "set[{}]", element),
+            Type::Struct(name) | Type::Enum(name) | Type::Param(name) => write!(f, "{}", name),
+            Type::Generic { name, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}<{}>", name, args.join(", "))
+            }
+            Type::Function { params, return_type } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), return_type)
+            }
+            Type::None => write!(f, "none"),
+            Type::Infer => write!(f, "_"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Program {
     pub statements: Vec<Stmt>,
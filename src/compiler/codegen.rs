@@ -179,6 +179,8 @@ pub fn infer_expression_type_with_env(
                 _ => None,
             }
         }
+        // Struct layouts aren't part of the IR, so field types are unknown here
+        IrExpression::Field { .. } => None,
     }
 }
 
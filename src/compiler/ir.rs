@@ -50,6 +50,14 @@ pub enum IrStatement {
         name: String,
         value: IrExpression,
     },
+    /// Assignment through a field or index, e.g. `obj.items[i].x = y`.
+    /// `target` is an `Index` or `Field` expression. `op` is set for
+    /// `obj.items[i].x += y`, which evaluates the target once.
+    Store {
+        target: IrExpression,
+        op: Option<BinaryOp>,
+        value: IrExpression,
+    },
     Return(Option<IrExpression>),
     Break,
     Continue,
@@ -86,6 +94,10 @@ pub enum IrExpression {
         base: Box<IrExpression>,
         index: Box<IrExpression>,
     },
+    Field {
+        base: Box<IrExpression>,
+        field: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -104,8 +116,9 @@ pub fn ast_to_ir(program: Program) -> Result<IrProgram, TogError> {
     let mut globals = Vec::new();
     
     for stmt in program.statements {
-        match stmt {
-            Stmt::Expr(Expr::Function { name, params, return_type, body }) => {
+        let public = stmt.public;
+        match stmt.kind {
+            StmtKind::Expr(Expr { kind: ExprKind::Function { name, params, return_type, body, .. }, .. }) => {
                 let ir_params: Vec<IrParam> = params.iter().map(|p| IrParam {
                     name: p.name.clone(),
                     param_type: p.type_annotation.clone(),
@@ -118,10 +131,10 @@ pub fn ast_to_ir(program: Program) -> Result<IrProgram, TogError> {
                     params: ir_params,
                     return_type: return_type.clone(),
                     body: ir_body,
-                    is_public: true, // TODO: Determine from AST
+                    is_public: public,
                 });
             }
-            Stmt::Let { name, type_annotation, value } => {
+            StmtKind::Let { name, type_annotation, value } => {
                 // Global variable
                 let value_type = type_annotation.unwrap_or(Type::Infer);
                 let ir_value = expr_to_ir_value(&value)?;
@@ -143,8 +156,8 @@ pub fn ast_to_ir(program: Program) -> Result<IrProgram, TogError> {
 }
 
 fn expr_to_ir_block(expr: &Expr) -> Result<IrBlock, TogError> {
-    match expr {
-        Expr::Block(statements) => {
+    match &expr.kind {
+        ExprKind::Block(statements) => {
             let mut ir_stmts = Vec::new();
             for stmt in statements {
                 ir_stmts.push(stmt_to_ir(stmt)?);
@@ -159,60 +172,100 @@ fn expr_to_ir_block(expr: &Expr) -> Result<IrBlock, TogError> {
 }
 
 fn stmt_to_ir(stmt: &Stmt) -> Result<IrStatement, TogError> {
-    match stmt {
-        Stmt::Let { name, value, .. } => {
+    match &stmt.kind {
+        StmtKind::Let { name, value, .. } => {
             Ok(IrStatement::Let {
                 name: name.clone(),
                 value: expr_to_ir_expr(value)?,
             })
         }
-        Stmt::Assign { name, value } => {
+        StmtKind::LetPattern { .. } => {
+            Err(TogError::RuntimeError("Destructuring let not yet supported in IR".to_string(), None))
+        }
+        StmtKind::Assign { name, op, value } => {
+            let mut value = expr_to_ir_expr(value)?;
+            // Reading a variable has no effects, so `x += y` can be `x = x + y`
+            if let Some(op) = op {
+                value = IrExpression::BinaryOp {
+                    left: Box::new(IrExpression::Variable(name.clone())),
+                    op: *op,
+                    right: Box::new(value),
+                };
+            }
             Ok(IrStatement::Assign {
                 name: name.clone(),
+                value,
+            })
+        }
+        StmtKind::AssignField { object, field, op, value } => {
+            Ok(IrStatement::Store {
+                target: IrExpression::Field {
+                    base: Box::new(expr_to_ir_expr(object)?),
+                    field: field.clone(),
+                },
+                op: *op,
                 value: expr_to_ir_expr(value)?,
             })
         }
-        Stmt::AssignField { object: _, field: _, value: _ } => {
-            // Field assignment not yet supported in IR
-            Err(TogError::RuntimeError("Field assignment not yet supported in IR".to_string(), None))
+        StmtKind::AssignIndex { object, index, op, value } => {
+            Ok(IrStatement::Store {
+                target: IrExpression::Index {
+                    base: Box::new(expr_to_ir_expr(object)?),
+                    index: Box::new(expr_to_ir_expr(index)?),
+                },
+                op: *op,
+                value: expr_to_ir_expr(value)?,
+            })
         }
-        Stmt::Return(expr) => {
+        StmtKind::Return(expr) => {
             let ir_expr = expr.as_ref().map(expr_to_ir_expr).transpose()?;
             Ok(IrStatement::Return(ir_expr))
         }
-        Stmt::Break => {
+        StmtKind::Break => {
             Ok(IrStatement::Break)
         }
-        Stmt::Continue => {
+        StmtKind::Continue => {
             Ok(IrStatement::Continue)
         }
-        Stmt::StructDef { .. } => {
+        StmtKind::Import { .. } => {
+            Err(TogError::RuntimeError(
+                "Imports are only allowed at the top level".to_string(),
+                None
+            ))
+        }
+        StmtKind::Error => {
+            Err(TogError::RuntimeError(
+                "Cannot compile a statement that failed to parse".to_string(),
+                None
+            ))
+        }
+        StmtKind::StructDef { .. } => {
             Err(TogError::RuntimeError(
                 "Struct definitions not yet supported in IR conversion".to_string(),
                 None
             ))
         }
-        Stmt::EnumDef { .. } => {
+        StmtKind::EnumDef { .. } => {
             Err(TogError::RuntimeError(
                 "Enum definitions not yet supported in IR conversion".to_string(),
                 None
             ))
         }
-        Stmt::TraitDef { .. } => {
+        StmtKind::TraitDef { .. } => {
             Err(TogError::RuntimeError(
                 "Trait definitions not yet supported in IR conversion".to_string(),
                 None
             ))
         }
-        Stmt::ImplBlock { .. } => {
+        StmtKind::ImplBlock { .. } => {
             Err(TogError::RuntimeError(
                 "Impl blocks not yet supported in IR conversion".to_string(),
                 None
             ))
         }
-        Stmt::Expr(expr) => {
-            match expr {
-                Expr::If { condition, then_branch, else_branch } => {
+        StmtKind::Expr(expr) => {
+            match &expr.kind {
+                ExprKind::If { condition, then_branch, else_branch } => {
                     let else_ir = if let Some(else_expr) = else_branch {
                         Some(Box::new(expr_to_ir_block(else_expr)?))
                     } else {
@@ -224,7 +277,7 @@ fn stmt_to_ir(stmt: &Stmt) -> Result<IrStatement, TogError> {
                         else_branch: else_ir,
                     })
                 }
-                Expr::While { condition, body } => {
+                ExprKind::While { condition, body } => {
                     Ok(IrStatement::While {
                         condition: expr_to_ir_expr(condition)?,
                         body: Box::new(expr_to_ir_block(body)?),
@@ -239,29 +292,29 @@ fn stmt_to_ir(stmt: &Stmt) -> Result<IrStatement, TogError> {
 }
 
 fn expr_to_ir_expr(expr: &Expr) -> Result<IrExpression, TogError> {
-    match expr {
-        Expr::Literal(lit) => {
+    match &expr.kind {
+        ExprKind::Literal(lit) => {
             Ok(IrExpression::Literal(literal_to_ir_value(lit)?))
         }
-        Expr::Variable(name) => {
+        ExprKind::Variable(name) => {
             Ok(IrExpression::Variable(name.clone()))
         }
-        Expr::BinaryOp { left, op, right } => {
+        ExprKind::BinaryOp { left, op, right } => {
             Ok(IrExpression::BinaryOp {
                 left: Box::new(expr_to_ir_expr(left)?),
                 op: *op,
                 right: Box::new(expr_to_ir_expr(right)?),
             })
         }
-        Expr::UnaryOp { op, expr } => {
+        ExprKind::UnaryOp { op, expr } => {
             Ok(IrExpression::UnaryOp {
                 op: *op,
                 expr: Box::new(expr_to_ir_expr(expr)?),
             })
         }
-        Expr::Call { callee, args } => {
-            let callee_name = match callee.as_ref() {
-                Expr::Variable(name) => name.clone(),
+        ExprKind::Call { callee, args } => {
+            let callee_name = match &callee.kind {
+                ExprKind::Variable(name) => name.clone(),
                 _ => return Err(TogError::RuntimeError("Only variable calls supported in IR".to_string(), None)),
             };
             
@@ -273,25 +326,25 @@ fn expr_to_ir_expr(expr: &Expr) -> Result<IrExpression, TogError> {
                 args: ir_args?,
             })
         }
-        Expr::Index { array, index } => {
+        ExprKind::Index { array, index } => {
             Ok(IrExpression::Index {
                 base: Box::new(expr_to_ir_expr(array)?),
                 index: Box::new(expr_to_ir_expr(index)?),
             })
         }
-        Expr::StructLiteral { .. } => {
+        ExprKind::StructLiteral { .. } => {
             Err(TogError::RuntimeError(
                 "Struct literals not yet supported in IR codegen".to_string(),
                 None
             ))
         }
-        Expr::FieldAccess { .. } => {
-            Err(TogError::RuntimeError(
-                "Field access not yet supported in IR codegen".to_string(),
-                None
-            ))
+        ExprKind::FieldAccess { object, field } => {
+            Ok(IrExpression::Field {
+                base: Box::new(expr_to_ir_expr(object)?),
+                field: field.clone(),
+            })
         }
-        Expr::For { variable: _variable, iterable: _iterable, body: _body } => {
+        ExprKind::For { .. } => {
             // For loops in IR - convert to while loop for now
             // TODO: Implement proper for loop in IR
             Err(TogError::RuntimeError(
@@ -299,12 +352,18 @@ fn expr_to_ir_expr(expr: &Expr) -> Result<IrExpression, TogError> {
                 None
             ))
         }
-        Expr::EnumVariant { .. } => {
+        ExprKind::EnumVariant { .. } => {
             Err(TogError::RuntimeError(
                 "Enum variants not yet supported in IR codegen".to_string(),
                 None
             ))
         }
+        ExprKind::Try(_) => {
+            Err(TogError::RuntimeError(
+                "The `?` operator is not yet supported in IR codegen".to_string(),
+                None
+            ))
+        }
         _ => {
             Err(TogError::RuntimeError("Unsupported expression in IR conversion".to_string(), None))
         }
@@ -312,8 +371,8 @@ fn expr_to_ir_expr(expr: &Expr) -> Result<IrExpression, TogError> {
 }
 
 fn expr_to_ir_value(expr: &Expr) -> Result<IrValue, TogError> {
-    match expr {
-        Expr::Literal(lit) => literal_to_ir_value(lit),
+    match &expr.kind {
+        ExprKind::Literal(lit) => literal_to_ir_value(lit),
         _ => Err(TogError::RuntimeError("Expected literal value".to_string(), None)),
     }
 }
@@ -329,6 +388,12 @@ fn literal_to_ir_value(lit: &Literal) -> Result<IrValue, TogError> {
             // In a real implementation, we'd need proper array handling
             Err(TogError::RuntimeError("Array literals in IR not yet implemented".to_string(), None))
         }
+        Literal::Tuple(_) => {
+            Err(TogError::RuntimeError("Tuple literals in IR not yet implemented".to_string(), None))
+        }
+        Literal::Map(_) => {
+            Err(TogError::RuntimeError("Map literals in IR not yet implemented".to_string(), None))
+        }
         Literal::None => Ok(IrValue::None),
     }
 }
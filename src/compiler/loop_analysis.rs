@@ -129,6 +129,9 @@ fn is_simple_statement(stmt: &IrStatement) -> bool {
         IrStatement::Assign { value, .. } => {
             is_simple_expression(value)
         }
+        IrStatement::Store { target, value, .. } => {
+            is_simple_expression(target) && is_simple_expression(value)
+        }
         IrStatement::Expression(expr) => {
             is_simple_expression(expr)
         }
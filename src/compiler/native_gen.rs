@@ -46,7 +46,7 @@ impl NativeCodeGenerator {
     
     fn generate_global(&mut self, global: &IrGlobal) -> Result<(), TogError> {
         let c_type = type_to_c_type(&global.value_type);
-        self.output.push_str(&format!("{} {} = ", c_type, global.name));
+        self.output.push_str(&format!("{} {} = ", c_type, c_identifier(&global.name)));
         self.generate_value(&global.initializer)?;
         self.output.push_str(";\n");
         Ok(())
@@ -58,7 +58,7 @@ impl NativeCodeGenerator {
             .unwrap_or_else(|| "void".to_string());
         
         // Function signature
-        self.output.push_str(&format!("{} {}(", return_type, func.name));
+        self.output.push_str(&format!("{} {}(", return_type, c_identifier(&func.name)));
         
         // Parameters
         let params: Vec<String> = func.params.iter().map(|p| {
@@ -109,11 +109,21 @@ impl NativeCodeGenerator {
             }
             IrStatement::Assign { name, value } => {
                 self.indent();
-                self.output.push_str(name);
+                self.output.push_str(&c_identifier(name));
                 self.output.push_str(" = ");
                 self.generate_expression(value)?;
                 self.output.push_str(";\n");
             }
+            IrStatement::Store { target, op, value } => {
+                self.generate_expression(target)?;
+                // `+=` and friends keep the target evaluated once
+                match op {
+                    Some(op) => self.output.push_str(&format!(" {}= ", binary_op_to_c(op))),
+                    None => self.output.push_str(" = "),
+                }
+                self.generate_expression(value)?;
+                self.output.push_str(";\n");
+            }
             IrStatement::Return(expr) => {
                 self.output.push_str("return");
                 if let Some(e) = expr {
@@ -177,7 +187,7 @@ impl NativeCodeGenerator {
                 self.generate_value(val)?;
             }
             IrExpression::Variable(name) => {
-                self.output.push_str(name);
+                self.output.push_str(&c_identifier(name));
             }
             IrExpression::BinaryOp { left, op, right } => {
                 self.output.push_str("(");
@@ -195,7 +205,7 @@ impl NativeCodeGenerator {
                 self.output.push_str(")");
             }
             IrExpression::Call { callee, args } => {
-                self.output.push_str(callee);
+                self.output.push_str(&c_identifier(callee));
                 self.output.push_str("(");
                 for (i, arg) in args.iter().enumerate() {
                     if i > 0 {
@@ -211,6 +221,11 @@ impl NativeCodeGenerator {
                 self.generate_expression(index)?;
                 self.output.push_str("]");
             }
+            IrExpression::Field { base, field } => {
+                self.generate_expression(base)?;
+                self.output.push('.');
+                self.output.push_str(&c_identifier(field));
+            }
         }
         Ok(())
     }
@@ -256,10 +271,14 @@ fn type_to_c_type(ty: &crate::ast::Type) -> String {
         crate::ast::Type::Bool => "bool".to_string(),
         crate::ast::Type::None => "void".to_string(),
         crate::ast::Type::Array(_) => "int64_t*".to_string(), // Simplified
+        crate::ast::Type::Tuple(_) => "void*".to_string(), // Like structs
+        crate::ast::Type::Map(..) | crate::ast::Type::Set(_) => "void*".to_string(), // Runtime collections
         crate::ast::Type::Function { .. } => "void*".to_string(), // Function pointer
         crate::ast::Type::Infer => "int64_t".to_string(), // Default
         crate::ast::Type::Struct(_) => "void*".to_string(), // Placeholder for structs
         crate::ast::Type::Enum(_) => "int64_t".to_string(), // Enums as integers
+        crate::ast::Type::Param(_) => "int64_t".to_string(), // Generics are not monomorphised yet
+        crate::ast::Type::Generic { .. } => "void*".to_string(), // Like structs
     }
 }
 
@@ -288,6 +307,11 @@ fn unary_op_to_c(op: &crate::ast::UnaryOp) -> &str {
     }
 }
 
+// Items from other modules are named `utils::add`, which C can't spell
+fn c_identifier(name: &str) -> String {
+    name.replace("::", "__")
+}
+
 fn escape_string(s: &str) -> String {
     s.replace("\\", "\\\\")
         .replace("\"", "\\\"")
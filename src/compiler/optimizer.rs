@@ -102,6 +102,10 @@ fn fold_constants_in_stmt(stmt: &mut IrStatement) -> Result<(), TogError> {
         IrStatement::Assign { value, .. } => {
             *value = fold_constant_expr(value)?;
         }
+        IrStatement::Store { target, value, .. } => {
+            *target = fold_constant_expr(target)?;
+            *value = fold_constant_expr(value)?;
+        }
         IrStatement::Return(expr) => {
             if let Some(e) = expr {
                 *e = fold_constant_expr(e)?;
@@ -259,7 +263,7 @@ fn remove_unreachable_code(block: &mut IrBlock) -> Result<(), TogError> {
                         remove_unreachable_code(body.as_mut())?;
                         new_statements.push(stmt);
                     }
-                    IrStatement::Assign { .. } | IrStatement::Let { .. } | IrStatement::Expression(_) | IrStatement::Break | IrStatement::Continue => {
+                    IrStatement::Assign { .. } | IrStatement::Store { .. } | IrStatement::Let { .. } | IrStatement::Expression(_) | IrStatement::Break | IrStatement::Continue => {
                         new_statements.push(stmt);
                     }
                 }
@@ -316,6 +320,10 @@ fn find_function_calls_in_stmt(stmt: &IrStatement, called: &mut std::collections
         IrStatement::Assign { value, .. } => {
             find_function_calls_in_expr(value, called);
         }
+        IrStatement::Store { target, value, .. } => {
+            find_function_calls_in_expr(target, called);
+            find_function_calls_in_expr(value, called);
+        }
         IrStatement::Return(expr) => {
             if let Some(e) = expr {
                 find_function_calls_in_expr(e, called);
@@ -360,6 +368,9 @@ fn find_function_calls_in_expr(expr: &IrExpression, called: &mut std::collection
             find_function_calls_in_expr(base, called);
             find_function_calls_in_expr(index, called);
         }
+        IrExpression::Field { base, .. } => {
+            find_function_calls_in_expr(base, called);
+        }
         _ => {}
     }
 }
@@ -441,6 +452,10 @@ fn find_function_calls_in_stmt_for_target(stmt: &IrStatement, target: &str, foun
         IrStatement::Assign { value, .. } => {
             find_function_calls_in_expr_for_target(value, target, found);
         }
+        IrStatement::Store { target: place, value, .. } => {
+            find_function_calls_in_expr_for_target(place, target, found);
+            find_function_calls_in_expr_for_target(value, target, found);
+        }
         IrStatement::Return(expr) => {
             if let Some(e) = expr {
                 find_function_calls_in_expr_for_target(e, target, found);
@@ -561,6 +576,10 @@ fn inline_calls_in_stmt(
         IrStatement::Assign { value, .. } => {
             inline_calls_in_expr(value, all_functions, candidates, inlined_any)?;
         }
+        IrStatement::Store { target, value, .. } => {
+            inline_calls_in_expr(target, all_functions, candidates, inlined_any)?;
+            inline_calls_in_expr(value, all_functions, candidates, inlined_any)?;
+        }
         IrStatement::Return(expr) => {
             if let Some(e) = expr {
                 inline_calls_in_expr(e, all_functions, candidates, inlined_any)?;
@@ -615,6 +634,9 @@ fn inline_calls_in_expr(
             inline_calls_in_expr(base, all_functions, candidates, inlined_any)?;
             inline_calls_in_expr(index, all_functions, candidates, inlined_any)?;
         }
+        IrExpression::Field { base, .. } => {
+            inline_calls_in_expr(base, all_functions, candidates, inlined_any)?;
+        }
         _ => {}
     }
     Ok(())
@@ -0,0 +1,225 @@
+// Diagnostics rendering
+//
+// Turns a `TogError` into a report that shows where the error happened:
+//
+//   runtime error: Undefined variable: totl
+//    --> main.tog:4:11
+//     |
+//   4 |     print(totl)
+//     |           ^^^^
+//     |
+//     = help: did you mean `total`?
+//
+// Colours come from `colored`, so `colored::control::set_override(false)`
+// gives plain output for logs.
+
+use crate::error::{ErrorContext, StackFrame, TogError};
+use crate::span::{self, Span};
+use colored::{ColoredString, Colorize};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Renders an error as a multi-line report ending in a newline.
+pub fn render(error: &TogError, severity: Severity) -> String {
+    let empty = ErrorContext::default();
+    let (error, context) = match error {
+        TogError::WithContext(error, context) => (error.as_ref(), context),
+        error => (error, &empty),
+    };
+    let (kind, message, span) = match error {
+        TogError::LexError(msg, span) => ("lexer error", msg, Some(*span)),
+        TogError::ParseError(msg, span) => ("parse error", msg, Some(*span)),
+        TogError::RuntimeError(msg, span) => ("runtime error", msg, *span),
+        TogError::TypeError(msg, span) => ("type error", msg, *span),
+        TogError::IoError(msg) => ("io error", msg, None),
+        // Contexts are merged when added, so they never nest
+        TogError::WithContext(..) => unreachable!("nested error context"),
+    };
+    let span = span.filter(|s| s.is_known());
+
+    let mut out = String::new();
+    if !context.trace.is_empty() {
+        out.push_str(&format_trace(&context.trace, span.unwrap_or_default()));
+    }
+    let title = match severity {
+        Severity::Error => kind.red().bold(),
+        Severity::Warning => "warning".yellow().bold(),
+    };
+    let _ = writeln!(out, "{}{} {}", title, ":".bold(), message.bold());
+
+    // Every annotated line, primary first; labels in other files are listed
+    // under their own location line
+    let marker = match severity {
+        Severity::Error => |s: &str| s.red().bold(),
+        Severity::Warning => |s: &str| s.yellow().bold(),
+    };
+    let mut annotations: Vec<(Span, &str, bool)> = Vec::new();
+    if let Some(span) = span {
+        annotations.push((span, "", true));
+    }
+    for (label_span, label) in &context.labels {
+        if label_span.is_known() {
+            annotations.push((*label_span, label, false));
+        }
+    }
+    let width = annotations.iter().map(|(s, _, _)| s.line.to_string().len()).max().unwrap_or(1);
+    let gutter = |text: &str| format!("{:>width$} |", text, width = width).blue().bold();
+
+    if let Some(span) = span {
+        let _ = writeln!(out, "{}{} {}", " ".repeat(width), "-->".blue().bold(), span);
+    }
+    if !annotations.is_empty() {
+        let _ = writeln!(out, "{}", gutter(""));
+    }
+    let mut lines: Vec<Span> = annotations.iter().map(|(s, _, _)| *s).collect();
+    lines.sort_by_key(|s| (s.file != annotations[0].0.file, s.line));
+    lines.dedup_by_key(|s| (s.file, s.line));
+
+    let mut previous: Option<Span> = None;
+    for line_span in lines {
+        let Some(text) = span::source_line(line_span.file, line_span.line) else {
+            continue;
+        };
+        match previous {
+            Some(prev) if prev.file != line_span.file => {
+                let _ = writeln!(out, "{}{} {}", " ".repeat(width), ":::".blue().bold(), line_span);
+            }
+            Some(prev) if line_span.line > prev.line + 1 => {
+                let _ = writeln!(out, "{}", "...".blue().bold());
+            }
+            _ => {}
+        }
+        previous = Some(line_span);
+
+        let _ = writeln!(out, "{} {}", gutter(&line_span.line.to_string()), text);
+        for (span, label, primary) in &annotations {
+            if span.file != line_span.file || span.line != line_span.line {
+                continue;
+            }
+            let underline = underline(&text, span, if *primary { '^' } else { '-' });
+            let styled: ColoredString = if *primary {
+                marker(&underline)
+            } else {
+                underline.blue().bold()
+            };
+            if label.is_empty() {
+                let _ = writeln!(out, "{} {}", gutter(""), styled);
+            } else {
+                let label: ColoredString = if *primary { marker(label) } else { label.blue().bold() };
+                let _ = writeln!(out, "{} {} {}", gutter(""), styled, label);
+            }
+        }
+    }
+
+    if !context.notes.is_empty() || !context.help.is_empty() {
+        let _ = writeln!(out, "{}", gutter(""));
+    }
+    for note in &context.notes {
+        let _ = writeln!(out, "{} {} {}", " ".repeat(width), "= note:".bold(), note);
+    }
+    for help in &context.help {
+        let _ = writeln!(out, "{} {} {}", " ".repeat(width), "= help:".bold(), help);
+    }
+    out
+}
+
+/// Formats a call stack like Python's traceback, innermost call last.
+/// `current` is where the innermost function is executing.
+///
+///   Traceback (most recent call last):
+///     File "main.tog", line 12, in main
+///       let r = divide(10, 0)
+///     File "main.tog", line 3, in divide
+///       return a / b
+pub fn format_trace(frames: &[StackFrame], current: Span) -> String {
+    let mut entries: Vec<(String, Span)> = Vec::new();
+    if let Some(first) = frames.first() {
+        if first.call_site.is_known() {
+            entries.push(("<top level>".to_string(), first.call_site));
+        }
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        let location = frames.get(i + 1).map_or(current, |next| next.call_site);
+        entries.push((frame.to_string(), location));
+    }
+
+    let mut out = String::from("Traceback (most recent call last):\n");
+    let mut i = 0;
+    while i < entries.len() {
+        // Deep recursion repeats the same entry; show it a few times only
+        let mut repeats = 1;
+        while i + repeats < entries.len() && entries[i + repeats] == entries[i] {
+            repeats += 1;
+        }
+        let shown = repeats.min(MAX_REPEATED_FRAMES);
+        for _ in 0..shown {
+            write_trace_entry(&mut out, &entries[i].0, entries[i].1);
+        }
+        if repeats > shown {
+            let _ = writeln!(out, "  [Previous line repeated {} more times]", repeats - shown);
+        }
+        i += repeats;
+    }
+    out
+}
+
+const MAX_REPEATED_FRAMES: usize = 3;
+
+fn write_trace_entry(out: &mut String, function: &str, location: Span) {
+    if !location.is_known() {
+        let _ = writeln!(out, "  in {}", function);
+        return;
+    }
+    let _ = writeln!(out, "  File \"{}\", line {}, in {}", span::file_name(location.file), location.line, function);
+    if let Some(text) = span::source_line(location.file, location.line) {
+        let _ = writeln!(out, "    {}", text.trim());
+    }
+}
+
+/// Indentation up to the span's column, then the marker under the spanned
+/// text. Tabs are kept so the marker lines up with the source.
+fn underline(text: &str, span: &Span, marker: char) -> String {
+    let indent: String = text.chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let line_len = text.chars().count();
+    let end = if span.end_line == span.line { span.end_column } else { line_len + 1 };
+    let len = end.saturating_sub(span.column).max(1);
+    format!("{}{}", indent, marker.to_string().repeat(len))
+}
+
+/// Suggests the candidate closest to `name`, for "did you mean" help.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min()
+        .map(|(_, c)| format!("did you mean `{}`?", c))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
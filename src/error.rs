@@ -1,30 +1,115 @@
+use crate::span::Span;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum TogError {
-    LexError(String, usize, usize), // message, line, column
-    ParseError(String, usize, usize),
-    RuntimeError(String, Option<usize>), // message, optional line number
+    LexError(String, Span), // message, location
+    ParseError(String, Span),
+    RuntimeError(String, Option<Span>), // message, optional location
     IoError(String),
-    TypeError(String, Option<usize>), // message, optional line number
+    TypeError(String, Option<Span>), // message, optional location
+    WithContext(Box<TogError>, ErrorContext), // An error plus detail for the diagnostics renderer
+}
+
+/// Secondary labels, notes, help and the call stack shown with an error by
+/// the diagnostics renderer. `Display` ignores them.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorContext {
+    pub labels: Vec<(Span, String)>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+    pub trace: Vec<StackFrame>, // Outermost call first
+}
+
+/// A function call on the interpreter's stack.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String,
+    pub receiver: Option<String>, // Type name, for methods
+    pub call_site: Span,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.receiver {
+            Some(type_name) => write!(f, "{}.{}", type_name, self.function),
+            None => write!(f, "{}", self.function),
+        }
+    }
+}
+
+impl TogError {
+    /// Points at another location that explains the error.
+    pub fn with_label(self, span: Span, message: impl Into<String>) -> Self {
+        self.map_context(|context| context.labels.push((span, message.into())))
+    }
+
+    pub fn with_note(self, note: impl Into<String>) -> Self {
+        self.map_context(|context| context.notes.push(note.into()))
+    }
+
+    /// Suggests a fix, e.g. "did you mean `count`?".
+    pub fn with_help(self, help: impl Into<String>) -> Self {
+        self.map_context(|context| context.help.push(help.into()))
+    }
+
+    /// Records the call stack at the point the error was raised. Only the
+    /// first (innermost) trace is kept as the error unwinds.
+    pub fn with_trace(self, trace: &[StackFrame]) -> Self {
+        if self.has_trace() {
+            return self;
+        }
+        self.map_context(|context| context.trace = trace.to_vec())
+    }
+
+    fn has_trace(&self) -> bool {
+        matches!(self, TogError::WithContext(_, context) if !context.trace.is_empty())
+    }
+
+    fn map_context(self, f: impl FnOnce(&mut ErrorContext)) -> Self {
+        let (error, mut context) = match self {
+            TogError::WithContext(error, context) => (error, context),
+            error => (Box::new(error), ErrorContext::default()),
+        };
+        f(&mut context);
+        TogError::WithContext(error, context)
+    }
+
+    /// Attaches `span` to a runtime or type error that doesn't have a location
+    /// yet. The innermost expression that fails gets to set it.
+    pub fn with_span(self, span: Span) -> Self {
+        if !span.is_known() {
+            return self;
+        }
+        match self {
+            TogError::RuntimeError(msg, None) => TogError::RuntimeError(msg, Some(span)),
+            TogError::TypeError(msg, None) => TogError::TypeError(msg, Some(span)),
+            TogError::WithContext(error, context) => TogError::WithContext(Box::new(error.with_span(span)), context),
+            other => other,
+        }
+    }
+}
+
+fn location(span: &Option<Span>) -> Option<&Span> {
+    span.as_ref().filter(|s| s.is_known())
 }
 
 impl fmt::Display for TogError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TogError::LexError(msg, line, col) => {
-                write!(f, "Lexer Error at line {}:{}: {}", line, col, msg)
+            TogError::LexError(msg, span) => {
+                write!(f, "Lexer Error at {}: {}", span, msg)
             }
-            TogError::ParseError(msg, line, col) => {
-                if *line == 0 && *col == 0 {
+            TogError::ParseError(msg, span) => {
+                if !span.is_known() {
                     write!(f, "Parse Error: {}", msg)
                 } else {
-                    write!(f, "Parse Error at line {}:{}: {}", line, col, msg)
+                    write!(f, "Parse Error at {}: {}", span, msg)
                 }
             }
-            TogError::RuntimeError(msg, line) => {
-                if let Some(ln) = line {
-                    write!(f, "Runtime Error at line {}: {}", ln, msg)
+            TogError::RuntimeError(msg, span) => {
+                if let Some(span) = location(span) {
+                    write!(f, "Runtime Error at {}: {}", span, msg)
                 } else {
                     write!(f, "Runtime Error: {}", msg)
                 }
@@ -32,16 +117,16 @@ impl fmt::Display for TogError {
             TogError::IoError(msg) => {
                 write!(f, "IO Error: {}", msg)
             }
-            TogError::TypeError(msg, line) => {
-                if let Some(ln) = line {
-                    write!(f, "Type Error at line {}: {}", ln, msg)
+            TogError::TypeError(msg, span) => {
+                if let Some(span) = location(span) {
+                    write!(f, "Type Error at {}: {}", span, msg)
                 } else {
                     write!(f, "Type Error: {}", msg)
                 }
             }
+            TogError::WithContext(error, _) => write!(f, "{}", error),
         }
     }
 }
 
 impl std::error::Error for TogError {}
-
@@ -0,0 +1,572 @@
+// Match exhaustiveness and reachability
+//
+// Checks every `match` in a program, including the ones in function and
+// method bodies:
+//
+//   type error: Match is not exhaustive: missing `Color::Blue`
+//   warning: Unreachable match arm
+//
+// This is the usefulness algorithm from Maranget's "Warnings for pattern
+// matching". A pattern is useful after some arms if a value matches it and
+// none of them: the match is exhaustive when `_` isn't useful after all its
+// arms, and an arm is unreachable when it isn't useful after the ones before
+// it. Arms with guards may not match, so they never count as covering.
+//
+// Patterns are first lowered to constructors applied to sub-patterns.
+// Enums, bools, structs, tuples and arrays have a known set of constructors; numbers,
+// strings and ranges are opaque values that only a catch-all covers.
+
+use crate::ast::*;
+use crate::error::TogError;
+use crate::type_checker::{EnumDef, StructDef};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Checks every match in `program`. Unreachable arms are added to
+/// `warnings`; the first match that isn't exhaustive is the error.
+pub fn check_program(
+    program: &Program,
+    enum_defs: &HashMap<String, EnumDef>,
+    struct_defs: &HashMap<String, StructDef>,
+    warnings: &mut Vec<TogError>,
+) -> Result<(), TogError> {
+    let mut checker = MatchChecker { enum_defs, struct_defs, warnings, unresolved: false };
+    for stmt in &program.statements {
+        checker.stmt(stmt)?;
+    }
+    Ok(())
+}
+
+// A lowered pattern. Witnesses, the values a match misses, use it too.
+#[derive(Debug, Clone, PartialEq)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+    Or(Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant {
+        enum_name: String,
+        name: String,
+        has_data: bool,
+    },
+    Bool(bool),
+    Struct {
+        name: String,
+        fields: Vec<String>, // Every declared field, in declaration order
+    },
+    Tuple(usize), // The number of elements
+    // `[a, b]` has prefix 2 and no rest; `[a, .., b]` has prefix 1, suffix 1
+    // and a rest, and matches every array of at least two elements
+    Array {
+        prefix: usize,
+        suffix: usize,
+        rest: bool,
+    },
+    Value(String), // A number, string, `none` or range, as written
+}
+
+impl Ctor {
+    fn arity(&self) -> usize {
+        match self {
+            Ctor::Variant { has_data, .. } => usize::from(*has_data),
+            Ctor::Struct { fields, .. } => fields.len(),
+            Ctor::Tuple(len) => *len,
+            Ctor::Array { prefix, suffix, .. } => prefix + suffix,
+            Ctor::Bool(_) | Ctor::Value(_) => 0,
+        }
+    }
+
+    /// Whether every value built with `other` also matches this constructor.
+    fn covers(&self, other: &Ctor) -> bool {
+        match (self, other) {
+            (Ctor::Array { prefix, suffix, rest: true }, Ctor::Array { prefix: p, suffix: s, .. }) => {
+                p + s >= prefix + suffix
+            }
+            _ => self == other,
+        }
+    }
+}
+
+struct MatchChecker<'a> {
+    enum_defs: &'a HashMap<String, EnumDef>,
+    struct_defs: &'a HashMap<String, StructDef>,
+    warnings: &'a mut Vec<TogError>,
+    unresolved: bool, // Lowering met an enum or struct it doesn't know
+}
+
+impl MatchChecker<'_> {
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => self.expr(expr),
+            StmtKind::Let { value, .. } | StmtKind::LetPattern { value, .. } | StmtKind::Assign { value, .. } => {
+                self.expr(value)
+            }
+            StmtKind::AssignField { object, value, .. } => {
+                self.expr(object)?;
+                self.expr(value)
+            }
+            StmtKind::AssignIndex { object, index, value, .. } => {
+                self.expr(object)?;
+                self.expr(index)?;
+                self.expr(value)
+            }
+            StmtKind::StructDef { methods, .. } | StmtKind::ImplBlock { methods, .. } => {
+                for method in methods {
+                    self.expr(&method.body)?;
+                }
+                Ok(())
+            }
+            StmtKind::TraitDef { methods, .. } => {
+                for body in methods.iter().filter_map(|m| m.default_body.as_ref()) {
+                    self.expr(body)?;
+                }
+                Ok(())
+            }
+            StmtKind::EnumDef { .. } | StmtKind::Import { .. } | StmtKind::Return(None)
+            | StmtKind::Break | StmtKind::Continue | StmtKind::Error => Ok(()),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), TogError> {
+        match &expr.kind {
+            ExprKind::Literal(Literal::Array(elems) | Literal::Tuple(elems)) => self.exprs(elems),
+            ExprKind::Literal(Literal::Map(entries)) => {
+                for (key, value) in entries {
+                    self.expr(key)?;
+                    self.expr(value)?;
+                }
+                Ok(())
+            }
+            ExprKind::Literal(_) | ExprKind::Variable(_) => Ok(()),
+            ExprKind::StructLiteral { fields, .. } => {
+                for (_, value) in fields {
+                    self.expr(value)?;
+                }
+                Ok(())
+            }
+            ExprKind::FieldAccess { object, .. } => self.expr(object),
+            ExprKind::BinaryOp { left, right, .. } => {
+                self.expr(left)?;
+                self.expr(right)
+            }
+            ExprKind::UnaryOp { expr, .. } | ExprKind::Try(expr) => self.expr(expr),
+            ExprKind::Call { callee, args } => {
+                self.expr(callee)?;
+                self.exprs(args)
+            }
+            ExprKind::Block(statements) => {
+                for stmt in statements {
+                    self.stmt(stmt)?;
+                }
+                Ok(())
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition)?;
+                self.expr(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.expr(else_branch)?;
+                }
+                Ok(())
+            }
+            ExprKind::While { condition, body } => {
+                self.expr(condition)?;
+                self.expr(body)
+            }
+            ExprKind::Match { expr: scrutinee, arms } => {
+                self.check_match(arms, expr)?;
+                self.expr(scrutinee)?;
+                for arm in arms {
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard)?;
+                    }
+                    self.expr(&arm.body)?;
+                }
+                Ok(())
+            }
+            ExprKind::Function { body, .. } | ExprKind::Lambda { body, .. } => self.expr(body),
+            ExprKind::Index { array, index } => {
+                self.expr(array)?;
+                self.expr(index)
+            }
+            ExprKind::For { iterable, body, .. } => {
+                self.expr(iterable)?;
+                self.expr(body)
+            }
+            ExprKind::EnumVariant { data, .. } => match data {
+                Some(data) => self.expr(data),
+                None => Ok(()),
+            },
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr { expr, .. } = part {
+                        self.expr(expr)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<(), TogError> {
+        for expr in exprs {
+            self.expr(expr)?;
+        }
+        Ok(())
+    }
+
+    /// Warns about arms that can't match and errors if some value matches no arm.
+    fn check_match(&mut self, arms: &[MatchArm], match_expr: &Expr) -> Result<(), TogError> {
+        self.unresolved = false;
+        let patterns: Vec<Pat> = arms.iter().map(|arm| self.lower(&arm.pattern)).collect();
+
+        // Each arm against the unguarded arms before it
+        let mut covering: Vec<Vec<Pat>> = Vec::new();
+        for (i, (arm, pattern)) in arms.iter().zip(&patterns).enumerate() {
+            if self.useful(&covering, std::slice::from_ref(pattern)).is_empty() {
+                let catch_all = arms[..i].iter().zip(&patterns)
+                    .find(|(earlier, pattern)| earlier.guard.is_none() && **pattern == Pat::Wild);
+                let warning = TogError::TypeError("Unreachable match arm".to_string(), Some(arm.span));
+                self.warnings.push(match catch_all {
+                    Some((earlier, _)) => warning.with_label(earlier.span, "this arm matches every value"),
+                    None => warning.with_note("the arms before it already match every value this pattern can"),
+                });
+            }
+            if arm.guard.is_none() {
+                covering.push(vec![pattern.clone()]);
+            }
+        }
+
+        // Unknown enums and structs are reported by the type checker, and
+        // their variants can't be listed
+        if self.unresolved {
+            return Ok(());
+        }
+        let missing: Vec<Pat> = self.useful(&covering, &[Pat::Wild]).into_iter()
+            .map(|mut witness| witness.remove(0))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let error = if missing.iter().all(|w| *w == Pat::Wild) {
+            TogError::TypeError("Match is not exhaustive".to_string(), Some(match_expr.span))
+                .with_help("add a wildcard `_` arm for the remaining values")
+        } else {
+            let help = match missing.as_slice() {
+                [only] => format!("add an arm for `{}`, or a wildcard `_` arm", only),
+                _ => "add arms for the missing patterns, or a wildcard `_` arm".to_string(),
+            };
+            TogError::TypeError(
+                format!("Match is not exhaustive: missing {}", describe(&missing)),
+                Some(match_expr.span)
+            ).with_help(help)
+        };
+        Err(if arms.iter().any(|arm| arm.guard.is_some()) {
+            error.with_note("arms with an `if` guard don't count, since the guard may be false")
+        } else {
+            error
+        })
+    }
+
+    fn lower(&mut self, pattern: &Pattern) -> Pat {
+        match pattern {
+            Pattern::Variable(_) | Pattern::Wildcard | Pattern::Rest(_) => Pat::Wild,
+            Pattern::Literal(Literal::Bool(value)) => Pat::Ctor(Ctor::Bool(*value), Vec::new()),
+            Pattern::Literal(lit) => opaque(literal_text(lit)),
+            Pattern::Range { start, end, inclusive } => {
+                let op = if *inclusive { "..=" } else { ".." };
+                opaque(format!("{}{}{}", literal_text(start), op, literal_text(end)))
+            }
+            Pattern::EnumVariant { enum_name, variant_name, inner } => {
+                let enum_name = if enum_name.is_empty() {
+                    self.enum_with_variant(variant_name)
+                } else {
+                    Some(enum_name.clone())
+                };
+                let variant = enum_name.as_ref()
+                    .and_then(|name| self.enum_defs.get(name))
+                    .and_then(|(_, variants)| variants.iter().find(|v| v.name == *variant_name));
+                let (Some(enum_name), Some(variant)) = (enum_name, variant) else {
+                    self.unresolved = true;
+                    return opaque(variant_name.clone());
+                };
+                let has_data = variant.data_type.is_some();
+                // `Option::Some` without a pattern matches any data
+                let data = match inner {
+                    Some(inner) if has_data => vec![self.lower(inner)],
+                    _ if has_data => vec![Pat::Wild],
+                    _ => Vec::new(),
+                };
+                Pat::Ctor(Ctor::Variant { enum_name, name: variant_name.clone(), has_data }, data)
+            }
+            Pattern::Struct { name, fields } => {
+                let Some((_, declared, _)) = self.struct_defs.get(name) else {
+                    self.unresolved = true;
+                    return opaque(format!("{} {{ .. }}", name));
+                };
+                let names: Vec<String> = declared.iter().map(|(field, _)| field.clone()).collect();
+                let subpatterns = names.iter()
+                    .map(|field| match fields.iter().find(|(name, _)| name == field) {
+                        Some((_, pattern)) => self.lower(pattern),
+                        None => Pat::Wild,
+                    })
+                    .collect();
+                Pat::Ctor(Ctor::Struct { name: name.clone(), fields: names }, subpatterns)
+            }
+            Pattern::Tuple(elements) => {
+                Pat::Ctor(Ctor::Tuple(elements.len()), elements.iter().map(|p| self.lower(p)).collect())
+            }
+            Pattern::Array(elements) => {
+                let rest = elements.iter().position(|p| matches!(p, Pattern::Rest(_)));
+                let subpatterns = elements.iter()
+                    .filter(|p| !matches!(p, Pattern::Rest(_)))
+                    .map(|p| self.lower(p))
+                    .collect();
+                let ctor = match rest {
+                    Some(rest) => Ctor::Array { prefix: rest, suffix: elements.len() - rest - 1, rest: true },
+                    None => Ctor::Array { prefix: elements.len(), suffix: 0, rest: false },
+                };
+                Pat::Ctor(ctor, subpatterns)
+            }
+            Pattern::Or(alternatives) => Pat::Or(alternatives.iter().map(|p| self.lower(p)).collect()),
+        }
+    }
+
+    /// The enum a bare variant name such as `Some` belongs to, if only one
+    /// enum has a variant of that name.
+    fn enum_with_variant(&self, variant_name: &str) -> Option<String> {
+        let mut owners = self.enum_defs.iter()
+            .filter(|(_, (_, variants))| variants.iter().any(|v| v.name == variant_name));
+        match (owners.next(), owners.next()) {
+            (Some((name, _)), None) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// The values that match `row` but no row of `matrix`, as witness rows.
+    /// Empty when `row` is useless after `matrix`.
+    fn useful(&self, matrix: &[Vec<Pat>], row: &[Pat]) -> Vec<Vec<Pat>> {
+        let Some((head, tail)) = row.split_first() else {
+            return if matrix.is_empty() { vec![Vec::new()] } else { Vec::new() };
+        };
+        if let Pat::Or(alternatives) = head {
+            return alternatives.iter()
+                .flat_map(|alternative| {
+                    let mut row = vec![alternative.clone()];
+                    row.extend_from_slice(tail);
+                    self.useful(matrix, &row)
+                })
+                .collect();
+        }
+        let matrix = expand_or(matrix);
+
+        // The row's own constructor decides the type when it has one
+        let mut heads: Vec<&Ctor> = Vec::new();
+        if let Pat::Ctor(ctor, _) = head {
+            heads.push(ctor);
+        }
+        heads.extend(matrix.iter().filter_map(|r| match &r[0] {
+            Pat::Ctor(ctor, _) => Some(ctor),
+            _ => None,
+        }));
+
+        match (head, self.all_constructors(&heads)) {
+            (Pat::Ctor(ctor, _), Some(all)) => all.iter()
+                .filter(|c| ctor.covers(c))
+                .flat_map(|c| self.useful_specialized(&matrix, row, c))
+                .collect(),
+            (Pat::Ctor(ctor, _), None) => self.useful_specialized(&matrix, row, ctor),
+            (_, Some(all)) => all.iter()
+                .flat_map(|c| self.useful_specialized(&matrix, row, c))
+                .collect(),
+            (_, None) => {
+                // Only the catch-all rows can cover what no constructor names
+                let defaults: Vec<Vec<Pat>> = matrix.iter()
+                    .filter(|r| r[0] == Pat::Wild)
+                    .map(|r| r[1..].to_vec())
+                    .collect();
+                self.useful(&defaults, tail).into_iter()
+                    .map(|witness| std::iter::once(Pat::Wild).chain(witness).collect())
+                    .collect()
+            }
+        }
+    }
+
+    /// `useful` for the values built with `ctor`, with witnesses rebuilt
+    /// around the constructor.
+    fn useful_specialized(&self, matrix: &[Vec<Pat>], row: &[Pat], ctor: &Ctor) -> Vec<Vec<Pat>> {
+        let Some(row) = specialize(row, ctor) else {
+            return Vec::new();
+        };
+        let matrix: Vec<Vec<Pat>> = matrix.iter().filter_map(|r| specialize(r, ctor)).collect();
+        self.useful(&matrix, &row).into_iter()
+            .map(|mut witness| {
+                let rest = witness.split_off(ctor.arity());
+                std::iter::once(Pat::Ctor(ctor.clone(), witness)).chain(rest).collect()
+            })
+            .collect()
+    }
+
+    /// Every constructor of the type `heads` belong to, or None when the
+    /// type has too many to list or isn't known.
+    fn all_constructors(&self, heads: &[&Ctor]) -> Option<Vec<Ctor>> {
+        match heads.first()? {
+            Ctor::Variant { enum_name, .. } => {
+                let (_, variants) = self.enum_defs.get(enum_name)?;
+                Some(variants.iter()
+                    .map(|v| Ctor::Variant {
+                        enum_name: enum_name.clone(),
+                        name: v.name.clone(),
+                        has_data: v.data_type.is_some(),
+                    })
+                    .collect())
+            }
+            Ctor::Bool(_) => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+            ctor @ (Ctor::Struct { .. } | Ctor::Tuple(_)) => Some(vec![(*ctor).clone()]),
+            Ctor::Array { .. } => {
+                // Each length up to the longest pattern is its own case;
+                // arrays longer than that all match the same patterns
+                let mut min_rest_len = 0;
+                let mut max_prefix = 0;
+                for head in heads {
+                    match head {
+                        Ctor::Array { prefix, rest: false, .. } => min_rest_len = min_rest_len.max(prefix + 1),
+                        Ctor::Array { prefix, suffix, rest: true } => {
+                            min_rest_len = min_rest_len.max(prefix + suffix);
+                            max_prefix = max_prefix.max(*prefix);
+                        }
+                        _ => {}
+                    }
+                }
+                let mut ctors: Vec<Ctor> = (0..min_rest_len)
+                    .map(|len| Ctor::Array { prefix: len, suffix: 0, rest: false })
+                    .collect();
+                ctors.push(Ctor::Array { prefix: max_prefix, suffix: min_rest_len - max_prefix, rest: true });
+                Some(ctors)
+            }
+            Ctor::Value(_) => None,
+        }
+    }
+}
+
+/// The rows of `matrix` with the or-patterns in their first column split
+/// into one row per alternative.
+fn expand_or(matrix: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    let mut rows = Vec::new();
+    for row in matrix {
+        match &row[0] {
+            Pat::Or(alternatives) => {
+                let alternative_rows: Vec<Vec<Pat>> = alternatives.iter()
+                    .map(|alternative| std::iter::once(alternative.clone()).chain(row[1..].iter().cloned()).collect())
+                    .collect();
+                rows.extend(expand_or(&alternative_rows));
+            }
+            _ => rows.push(row.clone()),
+        }
+    }
+    rows
+}
+
+/// The row for values built with `ctor`: the first pattern replaced by its
+/// sub-patterns, or None if it can't match such a value. `ctor` comes from
+/// `all_constructors`, so a rest pattern that covers it has no more
+/// elements than it.
+fn specialize(row: &[Pat], ctor: &Ctor) -> Option<Vec<Pat>> {
+    let mut specialized = match &row[0] {
+        Pat::Wild => vec![Pat::Wild; ctor.arity()],
+        Pat::Ctor(head @ Ctor::Array { prefix, suffix, rest: true }, elements) if head.covers(ctor) => {
+            // Line the elements up with the constructor's, the rest matching
+            // whatever is in between
+            let middle = ctor.arity() - prefix - suffix;
+            let mut lined_up = elements[..*prefix].to_vec();
+            lined_up.extend(std::iter::repeat_n(Pat::Wild, middle));
+            lined_up.extend_from_slice(&elements[*prefix..]);
+            lined_up
+        }
+        Pat::Ctor(head, subpatterns) if head == ctor => subpatterns.clone(),
+        Pat::Ctor(..) => return None,
+        Pat::Or(_) => unreachable!("or-patterns are expanded before specializing"),
+    };
+    specialized.extend_from_slice(&row[1..]);
+    Some(specialized)
+}
+
+fn opaque(text: String) -> Pat {
+    Pat::Ctor(Ctor::Value(text), Vec::new())
+}
+
+fn literal_text(lit: &Literal) -> String {
+    match lit {
+        Literal::Int(n) => n.to_string(),
+        Literal::Float(f) => format!("{:?}", f),
+        Literal::String(s) => format!("{:?}", s),
+        Literal::Bool(b) => b.to_string(),
+        Literal::None => "none".to_string(),
+        Literal::Array(_) => "[..]".to_string(),
+        Literal::Tuple(_) => "(..)".to_string(),
+        Literal::Map(_) => "{..}".to_string(),
+    }
+}
+
+/// "`A`", "`A` and `B`", or "`A`, `B`, `C` and 2 more".
+fn describe(missing: &[Pat]) -> String {
+    const SHOWN: usize = 3;
+    let names: Vec<String> = missing.iter().take(SHOWN).map(|w| format!("`{}`", w)).collect();
+    match missing.len() {
+        1 => names[0].clone(),
+        n if n <= SHOWN => format!("{} and {}", names[..n - 1].join(", "), names[n - 1]),
+        n => format!("{} and {} more", names.join(", "), n - SHOWN),
+    }
+}
+
+// Witnesses as TOG patterns, for error messages
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::Or(alternatives) => {
+                let alternatives: Vec<String> = alternatives.iter().map(|p| p.to_string()).collect();
+                write!(f, "{}", alternatives.join(" | "))
+            }
+            Pat::Ctor(Ctor::Variant { enum_name, name, .. }, data) => match data.first() {
+                // `Move(x, y)` rather than `Move((x, y))`
+                Some(Pat::Ctor(Ctor::Tuple(len), elements)) if *len > 1 => {
+                    write!(f, "{}::{}{}", enum_name, name, Pat::Ctor(Ctor::Tuple(*len), elements.clone()))
+                }
+                Some(data) => write!(f, "{}::{}({})", enum_name, name, data),
+                None => write!(f, "{}::{}", enum_name, name),
+            },
+            Pat::Ctor(Ctor::Bool(value), _) => write!(f, "{}", value),
+            Pat::Ctor(Ctor::Struct { name, fields }, subpatterns) => {
+                let shown: Vec<String> = fields.iter().zip(subpatterns)
+                    .filter(|(_, p)| **p != Pat::Wild)
+                    .map(|(field, p)| format!("{}: {}", field, p))
+                    .collect();
+                if shown.is_empty() {
+                    write!(f, "{} {{ .. }}", name)
+                } else if shown.len() == fields.len() {
+                    write!(f, "{} {{ {} }}", name, shown.join(", "))
+                } else {
+                    write!(f, "{} {{ {}, .. }}", name, shown.join(", "))
+                }
+            }
+            Pat::Ctor(Ctor::Array { prefix, rest, .. }, elements) => {
+                let mut shown: Vec<String> = elements.iter().map(|p| p.to_string()).collect();
+                if *rest {
+                    shown.insert(*prefix, "..".to_string());
+                }
+                write!(f, "[{}]", shown.join(", "))
+            }
+            Pat::Ctor(Ctor::Tuple(_), elements) => {
+                let shown: Vec<String> = elements.iter().map(|p| p.to_string()).collect();
+                match shown.as_slice() {
+                    [single] => write!(f, "({},)", single),
+                    _ => write!(f, "({})", shown.join(", ")),
+                }
+            }
+            Pat::Ctor(Ctor::Value(text), _) => write!(f, "{}", text),
+        }
+    }
+}
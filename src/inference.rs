@@ -0,0 +1,1260 @@
+// Type inference across function boundaries
+//
+// Works out the parameter and return types a function doesn't declare from
+// how its body uses the parameters, what it returns and the functions it
+// calls:
+//
+//   fn half(n) { n / 2 }         // fn(int) -> int
+//   fn apply(f, x) { f(x) }      // fn(fn('a) -> 'b, 'a) -> 'b
+//
+//   half("ten")
+//   type error: Argument to 'half' has type string, but the parameter has type int
+//
+// This is Hindley–Milner inference. Every unknown type is a variable;
+// using a value constrains its variable (`n / 2` makes `n` an int, calling
+// `f(x)` makes `f` a function of `x`'s type), and unification solves the
+// constraints. Each binding remembers where it was made, so a conflict points
+// at both the code that decided a type and the code that disagrees with it.
+//
+// Functions are inferred callees first, one group of mutually recursive
+// functions at a time. A type a group leaves open becomes a type parameter of
+// its functions (`'a` above), so each call may use it at a different type.
+//
+// Types that only the running program knows (builtins other than a few
+// common ones, method calls, fields of unknown values, branches of different
+// types) are dynamic: they match anything and constrain nothing, which keeps
+// unannotated code gradually typed.
+
+use crate::ast::*;
+use crate::error::TogError;
+use crate::span::Span;
+use crate::type_checker::{EnumDef, StructDef};
+use std::collections::{HashMap, HashSet};
+
+/// A function's inferred signature. Types it leaves open are type
+/// parameters named `'a`, `'b`, ..., or after the function's own type
+/// parameter when they come from one.
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub return_type: Type,
+    pub inferred_at: Vec<Option<Span>>, // Where each parameter's type was decided
+}
+
+/// Infers the signatures of the top-level functions in `program`, checking
+/// every call, return and assignment against them along the way, and the
+/// trait bounds of the generics they use.
+pub fn infer_program(
+    program: &Program,
+    struct_defs: &HashMap<String, StructDef>,
+    enum_defs: &HashMap<String, EnumDef>,
+    trait_impls: &HashSet<(String, String)>,
+) -> Result<HashMap<String, Signature>, TogError> {
+    let functions: Vec<TopLevelFunction> = program.statements.iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Expr(Expr { kind: ExprKind::Function { name, type_params, params, return_type, body }, .. }) => {
+                Some(TopLevelFunction { name, type_params, params, return_type: return_type.as_ref(), body })
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut inferrer = Inferrer {
+        struct_defs,
+        enum_defs,
+        trait_impls,
+        bindings: Vec::new(),
+        schemes: HashMap::new(),
+        group: HashMap::new(),
+        scopes: vec![HashMap::new()],
+        returns: Vec::new(),
+        in_scope: Vec::new(),
+    };
+    for group in call_graph_groups(&functions) {
+        inferrer.infer_group(&group.iter().map(|&i| &functions[i]).collect::<Vec<_>>())?;
+    }
+    for stmt in &program.statements {
+        if !matches!(stmt.kind, StmtKind::Expr(Expr { kind: ExprKind::Function { .. }, .. })) {
+            inferrer.stmt(stmt)?;
+        }
+    }
+
+    Ok(inferrer.schemes.iter()
+        .map(|(name, scheme)| (name.clone(), inferrer.signature(scheme)))
+        .collect())
+}
+
+struct TopLevelFunction<'p> {
+    name: &'p String,
+    type_params: &'p [TypeParam],
+    params: &'p [Param],
+    return_type: Option<&'p Type>,
+    body: &'p Expr,
+}
+
+/// Groups the functions into strongly connected components of the call
+/// graph (Tarjan's algorithm), callees before their callers.
+fn call_graph_groups(functions: &[TopLevelFunction]) -> Vec<Vec<usize>> {
+    let index_of: HashMap<&str, usize> = functions.iter().enumerate().map(|(i, f)| (f.name.as_str(), i)).collect();
+    let calls: Vec<Vec<usize>> = functions.iter()
+        .map(|f| {
+            let mut names = HashSet::new();
+            referenced_names(f.body, &mut names);
+            let mut callees: Vec<usize> = names.iter().filter_map(|name| index_of.get(name.as_str()).copied()).collect();
+            callees.sort_unstable();
+            callees
+        })
+        .collect();
+
+    struct Tarjan<'c> {
+        calls: &'c [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next: usize,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.calls[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                group.reverse();
+                self.groups.push(group);
+            }
+        }
+    }
+
+    let count = functions.len();
+    let mut tarjan = Tarjan {
+        calls: &calls,
+        index: vec![None; count],
+        low: vec![0; count],
+        stack: Vec::new(),
+        on_stack: vec![false; count],
+        next: 0,
+        groups: Vec::new(),
+    };
+    for v in 0..count {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.groups
+}
+
+fn referenced_names(expr: &Expr, names: &mut HashSet<String>) {
+    if let ExprKind::Variable(name) = &expr.kind {
+        names.insert(name.clone());
+    }
+    for child in expr.children() {
+        referenced_names(child, names);
+    }
+}
+
+// A type during inference
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Var(usize),
+    Dynamic, // Only known when the program runs; matches anything
+    Int,
+    Float,
+    String,
+    Bool,
+    None,
+    Array(Box<Ty>),
+    Tuple(Vec<Ty>),
+    Map(Box<Ty>, Box<Ty>),
+    Set(Box<Ty>),
+    Named(String, Vec<Ty>), // A struct or enum and its type arguments
+    Function(Vec<Ty>, Box<Ty>),
+}
+
+// A top-level function's type, with the variables each call replaces
+struct Scheme {
+    vars: Vec<usize>,
+    param_names: Vec<String>,
+    type_params: Vec<(TypeParam, Ty)>, // Declared type parameters and their variables
+    params: Vec<Ty>,
+    return_type: Ty,
+    inferred_at: Vec<Option<Span>>,
+    var_names: HashMap<usize, String>, // Variables that stand for a declared type parameter
+}
+
+// A function's parameters and result. Calls within the group being inferred
+// share its types; other calls get an instance of the function's scheme
+struct FunctionType {
+    param_names: Vec<String>,
+    type_params: Vec<(TypeParam, Ty)>, // Empty within the group, whose calls don't check bounds
+    params: Vec<Ty>,
+    return_type: Ty,
+}
+
+struct Inferrer<'a> {
+    struct_defs: &'a HashMap<String, StructDef>,
+    enum_defs: &'a HashMap<String, EnumDef>,
+    trait_impls: &'a HashSet<(String, String)>, // (type name, trait name)
+    bindings: Vec<Option<(Ty, Span)>>, // By variable: what it was unified with, and where
+    schemes: HashMap<String, Scheme>,
+    group: HashMap<String, FunctionType>, // The group being inferred
+    scopes: Vec<HashMap<String, Ty>>,
+    returns: Vec<Vec<(Ty, Span)>>, // The values each function being inferred returns, innermost last
+    in_scope: Vec<(TypeParam, Ty)>, // The declared type parameters of the function being inferred
+}
+
+impl Inferrer<'_> {
+    fn fresh(&mut self) -> Ty {
+        self.bindings.push(None);
+        Ty::Var(self.bindings.len() - 1)
+    }
+
+    /// Follows bound variables to the type they stand for, one level deep.
+    fn shallow(&self, ty: &Ty) -> Ty {
+        let mut ty = ty.clone();
+        while let Ty::Var(var) = ty {
+            match &self.bindings[var] {
+                Some((bound, _)) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Where a variable's type was decided: the last binding on its chain.
+    fn origin(&self, ty: &Ty) -> Option<Span> {
+        let mut origin = None;
+        let mut ty = ty;
+        while let Ty::Var(var) = ty {
+            match &self.bindings[*var] {
+                Some((bound, span)) => {
+                    origin = Some(*span);
+                    ty = bound;
+                }
+                None => break,
+            }
+        }
+        origin
+    }
+
+    /// `ty` with every bound variable replaced.
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match self.shallow(ty) {
+            Ty::Array(inner) => Ty::Array(Box::new(self.resolve(&inner))),
+            Ty::Tuple(elements) => Ty::Tuple(elements.iter().map(|e| self.resolve(e)).collect()),
+            Ty::Map(key, value) => Ty::Map(Box::new(self.resolve(&key)), Box::new(self.resolve(&value))),
+            Ty::Set(inner) => Ty::Set(Box::new(self.resolve(&inner))),
+            Ty::Named(name, args) => Ty::Named(name, args.iter().map(|a| self.resolve(a)).collect()),
+            Ty::Function(params, ret) => {
+                Ty::Function(params.iter().map(|p| self.resolve(p)).collect(), Box::new(self.resolve(&ret)))
+            }
+            other => other,
+        }
+    }
+
+    fn occurs(&self, var: usize, ty: &Ty) -> bool {
+        match self.shallow(ty) {
+            Ty::Var(other) => other == var,
+            Ty::Array(inner) | Ty::Set(inner) => self.occurs(var, &inner),
+            Ty::Map(key, value) => self.occurs(var, &key) || self.occurs(var, &value),
+            Ty::Tuple(elements) | Ty::Named(_, elements) => elements.iter().any(|e| self.occurs(var, e)),
+            Ty::Function(params, ret) => params.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &ret),
+            _ => false,
+        }
+    }
+
+    /// Makes `a` and `b` the same type, binding variables at `span`. Fails
+    /// if they can't be.
+    fn unify(&mut self, a: &Ty, b: &Ty, span: Span) -> bool {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+        match (&a, &b) {
+            (Ty::Dynamic, _) | (_, Ty::Dynamic) => true,
+            (Ty::Var(x), Ty::Var(y)) if x == y => true,
+            (Ty::Var(var), other) | (other, Ty::Var(var)) => {
+                // A type that contains itself can't be written; leave it dynamic
+                if !self.occurs(*var, other) {
+                    self.bindings[*var] = Some((other.clone(), span));
+                }
+                true
+            }
+            (Ty::Array(x), Ty::Array(y)) | (Ty::Set(x), Ty::Set(y)) => self.unify(x, y, span),
+            (Ty::Map(k1, v1), Ty::Map(k2, v2)) => self.unify(k1, k2, span) && self.unify(v1, v2, span),
+            (Ty::Tuple(x), Ty::Tuple(y)) => {
+                x.len() == y.len() && x.iter().zip(y).all(|(x, y)| self.unify(x, y, span))
+            }
+            (Ty::Named(n1, a1), Ty::Named(n2, a2)) => {
+                // Annotations may leave out type arguments
+                n1 == n2 && (a1.len() != a2.len() || a1.iter().zip(a2).all(|(x, y)| self.unify(x, y, span)))
+            }
+            (Ty::Function(p1, r1), Ty::Function(p2, r2)) => {
+                p1.len() == p2.len() && p1.iter().zip(p2).all(|(x, y)| self.unify(x, y, span)) && self.unify(r1, r2, span)
+            }
+            _ => a == b,
+        }
+    }
+
+    /// Unifies what the code at `span` has, `found`, with what it needs,
+    /// `expected`. A conflict is reported at `span`, pointing also at where
+    /// the expected type was decided (or at `origin`, if that's not known).
+    fn unify_at(
+        &mut self,
+        expected: &Ty,
+        found: &Ty,
+        span: Span,
+        origin: Option<Span>,
+        message: impl FnOnce(&Type, &Type) -> String,
+    ) -> Result<(), TogError> {
+        let origin = self.origin(expected).or(origin);
+        let saved = self.bindings.clone();
+        if self.unify(expected, found, span) {
+            return Ok(());
+        }
+        self.bindings = saved;
+        let (expected, found) = (self.to_type(expected, &HashMap::new()), self.to_type(found, &HashMap::new()));
+        let error = TogError::TypeError(message(&expected, &found), Some(span));
+        Err(match origin {
+            Some(origin) if origin != span => error.with_label(origin, format!("inferred as {} here", expected)),
+            _ => error,
+        })
+    }
+
+    /// The type of a value that is one of `a` or `b`: their unified type, or
+    /// dynamic if they differ.
+    fn join(&mut self, a: &Ty, b: &Ty, span: Span) -> Ty {
+        let saved = self.bindings.clone();
+        if self.unify(a, b, span) {
+            return a.clone();
+        }
+        self.bindings = saved;
+        Ty::Dynamic
+    }
+
+    /// Converts a type written in the source. `params` gives the types that
+    /// type parameters in it stand for; any others are dynamic.
+    fn of_type(&mut self, ty: &Type, params: &HashMap<String, Ty>) -> Ty {
+        match ty {
+            Type::Int => Ty::Int,
+            Type::Float => Ty::Float,
+            Type::String => Ty::String,
+            Type::Bool => Ty::Bool,
+            Type::None => Ty::None,
+            Type::Array(inner) => Ty::Array(Box::new(self.of_type(inner, params))),
+            Type::Tuple(elements) => Ty::Tuple(elements.iter().map(|e| self.of_type(e, params)).collect()),
+            Type::Map(key, value) => Ty::Map(Box::new(self.of_type(key, params)), Box::new(self.of_type(value, params))),
+            Type::Set(inner) => Ty::Set(Box::new(self.of_type(inner, params))),
+            Type::Struct(name) | Type::Enum(name) => Ty::Named(name.clone(), Vec::new()),
+            Type::Generic { name, args } => Ty::Named(name.clone(), args.iter().map(|a| self.of_type(a, params)).collect()),
+            Type::Function { params: fn_params, return_type } => Ty::Function(
+                fn_params.iter().map(|p| self.of_type(p, params)).collect(),
+                Box::new(self.of_type(return_type, params)),
+            ),
+            Type::Param(name) => params.get(name).cloned().unwrap_or(Ty::Dynamic),
+            Type::Infer => self.fresh(),
+        }
+    }
+
+    /// Converts an inferred type for the type checker. Unbound variables
+    /// become type parameters, named from `names` or `'a`, `'b`, ... in order.
+    fn to_type(&self, ty: &Ty, names: &HashMap<usize, String>) -> Type {
+        let mut names = names.clone();
+        self.to_type_named(ty, &mut names)
+    }
+
+    fn to_type_named(&self, ty: &Ty, names: &mut HashMap<usize, String>) -> Type {
+        match self.shallow(ty) {
+            Ty::Var(var) => {
+                let count = names.values().filter(|n| n.starts_with('\'')).count();
+                let name = names.entry(var).or_insert_with(|| type_variable_name(count)).clone();
+                Type::Param(name)
+            }
+            Ty::Dynamic => Type::Infer,
+            Ty::Int => Type::Int,
+            Ty::Float => Type::Float,
+            Ty::String => Type::String,
+            Ty::Bool => Type::Bool,
+            Ty::None => Type::None,
+            Ty::Array(inner) => Type::Array(Box::new(self.to_type_named(&inner, names))),
+            Ty::Tuple(elements) => Type::Tuple(elements.iter().map(|e| self.to_type_named(e, names)).collect()),
+            Ty::Map(key, value) => Type::Map(Box::new(self.to_type_named(&key, names)), Box::new(self.to_type_named(&value, names))),
+            Ty::Set(inner) => Type::Set(Box::new(self.to_type_named(&inner, names))),
+            Ty::Named(name, args) if args.is_empty() => match self.enum_defs.contains_key(&name) {
+                true => Type::Enum(name),
+                false => Type::Struct(name),
+            },
+            Ty::Named(name, args) => Type::Generic { name, args: args.iter().map(|a| self.to_type_named(a, names)).collect() },
+            Ty::Function(params, ret) => Type::Function {
+                params: params.iter().map(|p| self.to_type_named(p, names)).collect(),
+                return_type: Box::new(self.to_type_named(&ret, names)),
+            },
+        }
+    }
+
+    fn signature(&self, scheme: &Scheme) -> Signature {
+        let mut names = scheme.var_names.clone();
+        Signature {
+            params: scheme.params.iter().map(|p| self.to_type_named(p, &mut names)).collect(),
+            return_type: self.to_type_named(&scheme.return_type, &mut names),
+            inferred_at: scheme.inferred_at.clone(),
+        }
+    }
+
+    fn free_vars(&self, ty: &Ty, vars: &mut Vec<usize>) {
+        match self.shallow(ty) {
+            Ty::Var(var) if !vars.contains(&var) => vars.push(var),
+            Ty::Array(inner) | Ty::Set(inner) => self.free_vars(&inner, vars),
+            Ty::Map(key, value) => {
+                self.free_vars(&key, vars);
+                self.free_vars(&value, vars);
+            }
+            Ty::Tuple(elements) | Ty::Named(_, elements) => elements.iter().for_each(|e| self.free_vars(e, vars)),
+            Ty::Function(params, ret) => {
+                params.iter().for_each(|p| self.free_vars(p, vars));
+                self.free_vars(&ret, vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// A top-level function's type with fresh variables for the ones its
+    /// scheme leaves open.
+    /// Also returns where each parameter's type was decided.
+    fn instantiate(&mut self, name: &str) -> Option<(FunctionType, Vec<Option<Span>>)> {
+        let scheme = self.schemes.get(name)?;
+        let (vars, param_names, type_params, params, return_type, inferred_at) = (
+            scheme.vars.clone(), scheme.param_names.clone(), scheme.type_params.clone(), scheme.params.clone(),
+            scheme.return_type.clone(), scheme.inferred_at.clone(),
+        );
+        let fresh: HashMap<usize, Ty> = vars.into_iter().map(|var| (var, self.fresh())).collect();
+        let type_params = type_params.into_iter().map(|(param, ty)| (param, self.replace_vars(&ty, &fresh))).collect();
+        let params = params.iter().map(|p| self.replace_vars(p, &fresh)).collect();
+        let return_type = self.replace_vars(&return_type, &fresh);
+        Some((FunctionType { param_names, type_params, params, return_type }, inferred_at))
+    }
+
+    fn replace_vars(&self, ty: &Ty, fresh: &HashMap<usize, Ty>) -> Ty {
+        match self.shallow(ty) {
+            Ty::Var(var) => fresh.get(&var).cloned().unwrap_or(Ty::Var(var)),
+            Ty::Array(inner) => Ty::Array(Box::new(self.replace_vars(&inner, fresh))),
+            Ty::Tuple(elements) => Ty::Tuple(elements.iter().map(|e| self.replace_vars(e, fresh)).collect()),
+            Ty::Map(key, value) => Ty::Map(Box::new(self.replace_vars(&key, fresh)), Box::new(self.replace_vars(&value, fresh))),
+            Ty::Set(inner) => Ty::Set(Box::new(self.replace_vars(&inner, fresh))),
+            Ty::Named(name, args) => Ty::Named(name, args.iter().map(|a| self.replace_vars(a, fresh)).collect()),
+            Ty::Function(params, ret) => Ty::Function(
+                params.iter().map(|p| self.replace_vars(p, fresh)).collect(),
+                Box::new(self.replace_vars(&ret, fresh)),
+            ),
+            other => other,
+        }
+    }
+
+    /// Checks the trait bounds of type parameters whose types are known by
+    /// now. A type parameter of the function being inferred satisfies only
+    /// its own bounds. `context` names what declared them, e.g. "function 'show'".
+    fn check_bounds(&self, type_params: &[(TypeParam, Ty)], context: &str) -> Result<(), TogError> {
+        let mut names = HashMap::new();
+        let mut in_scope = Vec::new();
+        for (param, ty) in &self.in_scope {
+            if let Ty::Var(var) = self.shallow(ty) {
+                names.insert(var, param.name.clone());
+                in_scope.push(param.clone());
+            }
+        }
+        let bindings = type_params.iter()
+            .map(|(param, ty)| (param.name.clone(), self.to_type(ty, &names)))
+            .collect();
+        let params: Vec<TypeParam> = type_params.iter().map(|(param, _)| param.clone()).collect();
+        crate::type_checker::check_bounds(self.trait_impls, &in_scope, &params, &bindings, context)
+    }
+
+    /// Infers a group of mutually recursive functions together, then opens
+    /// the types they leave undecided to every caller.
+    fn infer_group(&mut self, functions: &[&TopLevelFunction]) -> Result<(), TogError> {
+        let mut var_names = HashMap::new();
+        let mut signatures = Vec::new();
+        for function in functions {
+            // A declared type parameter is a variable like any other
+            let mut type_params = HashMap::new();
+            let mut declared = Vec::new();
+            for param in function.type_params {
+                let var = self.fresh();
+                if let Ty::Var(id) = var {
+                    var_names.insert(id, param.name.clone());
+                }
+                type_params.insert(param.name.clone(), var.clone());
+                declared.push((param.clone(), var));
+            }
+            let params: Vec<Ty> = function.params.iter()
+                .map(|p| match &p.type_annotation {
+                    Some(ty) => self.of_type(ty, &type_params),
+                    None => self.fresh(),
+                })
+                .collect();
+            let return_type = match function.return_type {
+                Some(ty) => self.of_type(ty, &type_params),
+                None => self.fresh(),
+            };
+            self.group.insert(function.name.clone(), FunctionType {
+                param_names: function.params.iter().map(|p| p.name.clone()).collect(),
+                type_params: Vec::new(),
+                params: params.clone(),
+                return_type: return_type.clone(),
+            });
+            signatures.push((declared, params, return_type));
+        }
+
+        for (function, (declared, params, return_type)) in functions.iter().zip(&signatures) {
+            let params: Vec<(String, Ty)> = function.params.iter().map(|p| p.name.clone()).zip(params.iter().cloned()).collect();
+            let what = format!("Function '{}'", function.name);
+            self.in_scope = declared.clone();
+            let body = self.function_body(&what, &params, return_type, function.return_type.is_some(), function.body);
+            self.in_scope.clear();
+            body?;
+        }
+
+        for (function, (declared, params, return_type)) in functions.iter().zip(signatures) {
+            self.group.remove(function.name.as_str());
+            let mut vars = Vec::new();
+            for ty in params.iter().chain(std::iter::once(&return_type)) {
+                self.free_vars(ty, &mut vars);
+            }
+            let inferred_at = function.params.iter().zip(&params)
+                .map(|(param, ty)| if param.type_annotation.is_some() { None } else { self.origin(ty) })
+                .collect();
+            self.schemes.insert(function.name.clone(), Scheme {
+                vars,
+                param_names: function.params.iter().map(|p| p.name.clone()).collect(),
+                type_params: declared.into_iter().map(|(param, ty)| (param, self.resolve(&ty))).collect(),
+                params: params.iter().map(|p| self.resolve(p)).collect(),
+                return_type: self.resolve(&return_type),
+                inferred_at,
+                var_names: var_names.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Infers a function, lambda or method body. `what` names it in errors.
+    fn function_body(&mut self, what: &str, params: &[(String, Ty)], return_type: &Ty, annotated: bool, body: &Expr) -> Result<(), TogError> {
+        self.scopes.push(params.iter().cloned().collect());
+        self.returns.push(Vec::new());
+        let body_type = self.expr(body);
+        self.scopes.pop();
+        let mut returns = self.returns.pop().expect("returns");
+        let body_type = body_type?;
+
+        let tail_span = match &body.kind {
+            ExprKind::Block(statements) => match statements.last() {
+                Some(Stmt { kind: StmtKind::Expr(tail), .. }) => tail.span,
+                Some(stmt) => stmt.span,
+                None => body.span,
+            },
+            _ => body.span,
+        };
+        returns.push((body_type, tail_span));
+
+        // Returning nothing on some paths makes the result dynamic, unless
+        // it's declared
+        let returns_none = returns.iter().any(|(ty, _)| self.shallow(ty) == Ty::None);
+        let returns_value = returns.iter().any(|(ty, _)| !matches!(self.shallow(ty), Ty::None | Ty::Dynamic));
+        if returns_none && returns_value {
+            if !annotated {
+                return Ok(());
+            }
+            returns.retain(|(ty, _)| self.shallow(ty) != Ty::None);
+        }
+        for (ty, span) in returns {
+            self.unify_at(return_type, &ty, span, None, |expected, found| match annotated {
+                true => format!("{} is declared to return {}, but returns {} here", what, expected, found),
+                false => format!("{} returns {} here, but {} elsewhere", what, found, expected),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<Ty> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn bind(&mut self, name: &str, ty: Ty) {
+        self.scopes.last_mut().expect("scope").insert(name.to_string(), ty);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expr(expr)?;
+            }
+            StmtKind::Let { name, type_annotation, value } => {
+                let value_type = self.expr(value)?;
+                let ty = match type_annotation {
+                    Some(annotated) => {
+                        let annotated = self.of_type(annotated, &HashMap::new());
+                        self.unify_at(&annotated, &value_type, value.span, None, |expected, found| {
+                            format!("Type mismatch: expected {}, got {}", expected, found)
+                        })?;
+                        annotated
+                    }
+                    None => value_type,
+                };
+                self.bind(name, ty);
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                let value_type = self.expr(value)?;
+                self.bind_pattern(pattern, &value_type);
+            }
+            StmtKind::Assign { name, op, value } => {
+                let var_type = self.lookup(name);
+                let current = var_type.clone().unwrap_or(Ty::Dynamic);
+                let value_type = self.assigned(&current, *op, value, stmt.span)?;
+                if let Some(var_type) = var_type {
+                    self.unify_at(&var_type, &value_type, value.span, None, |expected, found| {
+                        format!("Type mismatch in assignment: variable '{}' has type {}, but assigned value has type {}", name, expected, found)
+                    })?;
+                }
+            }
+            StmtKind::AssignField { object, field, op, value } => {
+                let object_type = self.expr(object)?;
+                let field_type = self.field_type(&object_type, field);
+                let current = field_type.clone().unwrap_or(Ty::Dynamic);
+                let value_type = self.assigned(&current, *op, value, stmt.span)?;
+                if let Some(field_type) = field_type {
+                    self.unify_at(&field_type, &value_type, value.span, None, |expected, found| {
+                        format!("Type mismatch in field assignment: field '{}' has type {}, but assigned value has type {}", field, expected, found)
+                    })?;
+                }
+            }
+            StmtKind::AssignIndex { object, index, op, value } => {
+                let object_type = self.expr(object)?;
+                let index_type = self.expr(index)?;
+                let Some((key, element)) = (match self.shallow(&object_type) {
+                    Ty::Map(key, element) => Some((*key, *element)),
+                    Ty::Array(element) => Some((Ty::Int, *element)),
+                    _ => None,
+                }) else {
+                    self.assigned(&Ty::Dynamic, *op, value, stmt.span)?;
+                    return Ok(());
+                };
+                let value_type = self.assigned(&element, *op, value, stmt.span)?;
+                self.unify_at(&key, &index_type, index.span, None, |expected, found| {
+                    format!("Index must be {}, got {}", expected, found)
+                })?;
+                self.unify_at(&element, &value_type, value.span, None, |expected, found| {
+                    format!("Type mismatch in index assignment: elements have type {}, but assigned value has type {}", expected, found)
+                })?;
+            }
+            StmtKind::Return(value) => {
+                let (ty, span) = match value {
+                    Some(value) => (self.expr(value)?, value.span),
+                    None => (Ty::None, stmt.span),
+                };
+                if let Some(returns) = self.returns.last_mut() {
+                    returns.push((ty, span));
+                }
+            }
+            StmtKind::StructDef { name, methods, .. } | StmtKind::ImplBlock { type_name: name, methods, .. } => {
+                for method in methods {
+                    self.method(Some(name), &method.params, method.return_type.as_ref(), &method.body)?;
+                }
+            }
+            StmtKind::TraitDef { methods, .. } => {
+                for method in methods {
+                    if let Some(body) = &method.default_body {
+                        self.method(None, &method.params, method.return_type.as_ref(), body)?;
+                    }
+                }
+            }
+            StmtKind::EnumDef { .. } | StmtKind::Import { .. } | StmtKind::Break | StmtKind::Continue | StmtKind::Error => {}
+        }
+        Ok(())
+    }
+
+    /// The type of the value `target op= value` stores, where the target
+    /// holds `current`; just `value`'s for a plain assignment.
+    fn assigned(&mut self, current: &Ty, op: Option<BinaryOp>, value: &Expr, span: Span) -> Result<Ty, TogError> {
+        let value_type = self.expr(value)?;
+        match op {
+            Some(op) => self.binary(op, (current, span), (&value_type, value.span)).map_err(|e| e.with_span(span)),
+            None => Ok(value_type),
+        }
+    }
+
+    /// Infers a method body. `self` has the type the method is defined on,
+    /// or is dynamic in a trait's default method.
+    fn method(&mut self, type_name: Option<&String>, params: &[Param], return_type: Option<&Type>, body: &Expr) -> Result<(), TogError> {
+        let params: Vec<(String, Ty)> = params.iter()
+            .map(|p| {
+                let ty = match (&p.type_annotation, type_name) {
+                    (Some(ty), _) => self.of_type(ty, &HashMap::new()),
+                    (None, Some(type_name)) if p.name == "self" => match self.type_params_of(type_name) {
+                        0 => Ty::Named(type_name.clone(), Vec::new()),
+                        _ => Ty::Dynamic,
+                    },
+                    (None, None) if p.name == "self" => Ty::Dynamic,
+                    (None, _) => self.fresh(),
+                };
+                (p.name.clone(), ty)
+            })
+            .collect();
+        let annotated = return_type.is_some();
+        let return_type = match return_type {
+            Some(ty) => self.of_type(ty, &HashMap::new()),
+            None => self.fresh(),
+        };
+        self.function_body("Method", &params, &return_type, annotated, body)
+    }
+
+    fn type_params_of(&self, type_name: &str) -> usize {
+        self.struct_defs.get(type_name).map(|(params, _, _)| params.len())
+            .or_else(|| self.enum_defs.get(type_name).map(|(params, _)| params.len()))
+            .unwrap_or(0)
+    }
+
+    /// Fresh variables for a generic struct or enum's type parameters.
+    fn type_arguments(&mut self, type_params: &[TypeParam]) -> (Vec<Ty>, HashMap<String, Ty>) {
+        let args: Vec<Ty> = type_params.iter().map(|_| self.fresh()).collect();
+        let map = type_params.iter().map(|p| p.name.clone()).zip(args.iter().cloned()).collect();
+        (args, map)
+    }
+
+    /// The type arguments of `ty` if it's `name`, or fresh ones.
+    fn arguments_of(&mut self, ty: &Ty, name: &str, type_params: &[TypeParam]) -> HashMap<String, Ty> {
+        match self.shallow(ty) {
+            Ty::Named(n, args) if n == name && args.len() == type_params.len() => {
+                type_params.iter().map(|p| p.name.clone()).zip(args).collect()
+            }
+            _ => self.type_arguments(type_params).1,
+        }
+    }
+
+    /// The declared type of `field` in a value of type `object`, if known.
+    fn field_type(&mut self, object: &Ty, field: &str) -> Option<Ty> {
+        let Ty::Named(name, _) = self.shallow(object) else {
+            return None;
+        };
+        let (type_params, fields, _) = self.struct_defs.get(&name)?;
+        let field_type = fields.iter().find(|(n, _)| n == field)?.1.as_ref()?;
+        let params = self.arguments_of(object, &name, type_params);
+        Some(self.of_type(field_type, &params))
+    }
+
+    /// The data type of `variant` in the enum type `ty`.
+    fn variant_data(&mut self, enum_name: &str, variant: &str, ty: &Ty) -> Option<Ty> {
+        let (type_params, variants) = self.enum_defs.get(enum_name)?;
+        let data_type = variants.iter().find(|v| v.name == variant)?.data_type.as_ref()?;
+        let params = self.arguments_of(ty, enum_name, type_params);
+        Some(self.of_type(data_type, &params))
+    }
+
+    /// Binds the variables `pattern` introduces. Patterns don't constrain
+    /// the value they match.
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: &Ty) {
+        match pattern {
+            Pattern::Variable(name) | Pattern::Rest(Some(name)) => self.bind(name, ty.clone()),
+            Pattern::EnumVariant { enum_name, variant_name, inner: Some(inner) } => {
+                let enum_name = match (enum_name.is_empty(), self.shallow(ty)) {
+                    (false, _) => enum_name.clone(),
+                    (true, Ty::Named(name, _)) => name,
+                    (true, _) => String::new(),
+                };
+                let data = self.variant_data(&enum_name, variant_name, ty).unwrap_or(Ty::Dynamic);
+                self.bind_pattern(inner, &data);
+            }
+            Pattern::Struct { fields, .. } => {
+                for (field, field_pattern) in fields {
+                    let field_type = self.field_type(ty, field).unwrap_or(Ty::Dynamic);
+                    self.bind_pattern(field_pattern, &field_type);
+                }
+            }
+            Pattern::Array(elements) => {
+                let element = match self.shallow(ty) {
+                    Ty::Array(element) => *element,
+                    _ => Ty::Dynamic,
+                };
+                for pattern in elements {
+                    match pattern {
+                        Pattern::Rest(_) => self.bind_pattern(pattern, &Ty::Array(Box::new(element.clone()))),
+                        _ => self.bind_pattern(pattern, &element),
+                    }
+                }
+            }
+            Pattern::Tuple(elements) => {
+                let types = match self.shallow(ty) {
+                    Ty::Tuple(types) if types.len() == elements.len() => types,
+                    _ => vec![Ty::Dynamic; elements.len()],
+                };
+                for (pattern, ty) in elements.iter().zip(&types) {
+                    self.bind_pattern(pattern, ty);
+                }
+            }
+            Pattern::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.bind_pattern(alternative, ty);
+                }
+            }
+            Pattern::EnumVariant { inner: None, .. } | Pattern::Literal(_) | Pattern::Wildcard
+            | Pattern::Rest(None) | Pattern::Range { .. } => {}
+        }
+    }
+
+    /// Checks a call's arguments against the parameter types of `name`.
+    /// `origins` says where each parameter's type was decided, if that's
+    /// known apart from the types themselves.
+    fn call_arguments(&mut self, name: &str, param_names: &[String], params: &[Ty], origins: &[Option<Span>], args: &[Expr]) -> Result<(), TogError> {
+        if args.len() != params.len() {
+            return Err(TogError::TypeError(
+                format!("Function '{}' expects {} arguments, got {}", name, params.len(), args.len()),
+                None
+            ));
+        }
+        for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+            let arg_type = self.expr(arg)?;
+            let origin = origins.get(i).copied().flatten();
+            self.unify_at(param, &arg_type, arg.span, origin, |expected, found| {
+                format!("Argument to '{}' has type {}, but the parameter has type {}", name, found, expected)
+            }).map_err(|e| match param_names.get(i) {
+                Some(param_name) => e.with_note(format!("`{}` is parameter {} of '{}'", param_name, i + 1, name)),
+                None => e,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Calls a value of type `callee`: a lambda, or a parameter that must
+    /// then be a function.
+    fn call_value(&mut self, callee: &Ty, args: &[Expr], span: Span) -> Result<Ty, TogError> {
+        let arg_types = args.iter().map(|arg| self.expr(arg)).collect::<Result<Vec<_>, _>>()?;
+        match self.shallow(callee) {
+            Ty::Function(params, ret) => {
+                if params.len() != args.len() {
+                    return Err(TogError::TypeError(
+                        format!("Function expects {} arguments, got {}", params.len(), args.len()),
+                        None
+                    ));
+                }
+                for ((param, arg_type), arg) in params.iter().zip(&arg_types).zip(args) {
+                    self.unify_at(param, arg_type, arg.span, None, |expected, found| {
+                        format!("Argument has type {}, but the parameter has type {}", found, expected)
+                    })?;
+                }
+                Ok(*ret)
+            }
+            callee @ Ty::Var(_) => {
+                let ret = self.fresh();
+                self.unify_at(&callee, &Ty::Function(arg_types, Box::new(ret.clone())), span, None, |expected, found| {
+                    format!("Called value has type {}, but it is called as {}", expected, found)
+                })?;
+                Ok(ret)
+            }
+            _ => Ok(Ty::Dynamic),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Ty, TogError> {
+        self.expr_kind(expr).map_err(|e| e.with_span(expr.span))
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) -> Result<Ty, TogError> {
+        Ok(match &expr.kind {
+            ExprKind::Literal(lit) => match lit {
+                Literal::Int(_) => Ty::Int,
+                Literal::Float(_) => Ty::Float,
+                Literal::String(_) => Ty::String,
+                Literal::Bool(_) => Ty::Bool,
+                Literal::None => Ty::None,
+                Literal::Array(elements) => {
+                    let mut element = self.fresh();
+                    for e in elements {
+                        let ty = self.expr(e)?;
+                        element = self.join(&element, &ty, e.span);
+                    }
+                    Ty::Array(Box::new(element))
+                }
+                Literal::Tuple(elements) => Ty::Tuple(elements.iter().map(|e| self.expr(e)).collect::<Result<_, _>>()?),
+                Literal::Map(entries) => {
+                    let (mut key, mut value) = (self.fresh(), self.fresh());
+                    for (k, v) in entries {
+                        let (k_type, v_type) = (self.expr(k)?, self.expr(v)?);
+                        key = self.join(&key, &k_type, k.span);
+                        value = self.join(&value, &v_type, v.span);
+                    }
+                    Ty::Map(Box::new(key), Box::new(value))
+                }
+            },
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr { expr, .. } = part {
+                        self.expr(expr)?;
+                    }
+                }
+                Ty::String
+            }
+            ExprKind::Variable(name) => {
+                if let Some(ty) = self.lookup(name) {
+                    ty
+                } else if let Some(member) = self.group.get(name) {
+                    Ty::Function(member.params.clone(), Box::new(member.return_type.clone()))
+                } else if let Some((instance, _)) = self.instantiate(name) {
+                    Ty::Function(instance.params, Box::new(instance.return_type))
+                } else {
+                    Ty::Dynamic
+                }
+            }
+            ExprKind::StructLiteral { name, fields } => {
+                let Some((type_params, declared, _)) = self.struct_defs.get(name) else {
+                    for (_, value) in fields {
+                        self.expr(value)?;
+                    }
+                    return Ok(Ty::Named(name.clone(), Vec::new()));
+                };
+                let (args, params) = self.type_arguments(type_params);
+                for (field, value) in fields {
+                    let value_type = self.expr(value)?;
+                    if let Some((_, Some(field_type))) = declared.iter().find(|(n, _)| n == field) {
+                        let field_type = self.of_type(field_type, &params);
+                        self.unify_at(&field_type, &value_type, value.span, None, |expected, found| {
+                            format!("Field '{}' of {} has type {}, but the value has type {}", field, name, expected, found)
+                        })?;
+                    }
+                }
+                let type_params: Vec<(TypeParam, Ty)> = type_params.iter().cloned().zip(args.iter().cloned()).collect();
+                self.check_bounds(&type_params, &format!("struct {}", name))?;
+                Ty::Named(name.clone(), args)
+            }
+            ExprKind::EnumVariant { enum_name, variant_name, data } => {
+                let data_type = match data {
+                    Some(data) => Some((self.expr(data)?, data.span)),
+                    None => None,
+                };
+                self.variant(enum_name, variant_name, data_type)?
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                let left_type = self.expr(left)?;
+                let right_type = self.expr(right)?;
+                self.binary(*op, (&left_type, left.span), (&right_type, right.span))?
+            }
+            ExprKind::UnaryOp { op: UnaryOp::Not, expr: operand } => {
+                let ty = self.expr(operand)?;
+                self.unify_at(&Ty::Bool, &ty, operand.span, None, |_, found| {
+                    format!("Not operator requires bool operand, got {}", found)
+                })?;
+                Ty::Bool
+            }
+            ExprKind::UnaryOp { op: UnaryOp::Neg, expr: operand } => self.expr(operand)?,
+            ExprKind::Call { callee, args } => match &callee.kind {
+                // `Move(1, 2)` holds its values as a tuple; `Type::name(args)` calls a static method
+                ExprKind::EnumVariant { enum_name, variant_name, data: None } => {
+                    let arg_types = args.iter().map(|arg| self.expr(arg)).collect::<Result<Vec<_>, _>>()?;
+                    let is_variant = self.enum_defs.get(enum_name)
+                        .is_some_and(|(_, variants)| variants.iter().any(|v| v.name == *variant_name));
+                    match (is_variant, arg_types.as_slice()) {
+                        (true, []) => self.variant(enum_name, variant_name, None)?,
+                        (true, _) => self.variant(enum_name, variant_name, Some((Ty::Tuple(arg_types), expr.span)))?,
+                        (false, _) => self.builtin_associated(enum_name, variant_name, &arg_types),
+                    }
+                }
+                ExprKind::Variable(name) if self.lookup(name).is_none() => {
+                    if let Some(member) = self.group.get(name) {
+                        let (param_names, params, ret) = (member.param_names.clone(), member.params.clone(), member.return_type.clone());
+                        let origins: Vec<Option<Span>> = params.iter().map(|p| self.origin(p)).collect();
+                        self.call_arguments(name, &param_names, &params, &origins, args)?;
+                        ret
+                    } else if let Some((instance, origins)) = self.instantiate(name) {
+                        self.call_arguments(name, &instance.param_names, &instance.params, &origins, args)?;
+                        self.check_bounds(&instance.type_params, &format!("function '{}'", name))?;
+                        instance.return_type
+                    } else {
+                        for arg in args {
+                            self.expr(arg)?;
+                        }
+                        builtin_return_type(name)
+                    }
+                }
+                ExprKind::FieldAccess { object, .. } => {
+                    // A method; its receiver's type picks it at run time
+                    self.expr(object)?;
+                    for arg in args {
+                        self.expr(arg)?;
+                    }
+                    Ty::Dynamic
+                }
+                _ => {
+                    let callee_type = self.expr(callee)?;
+                    self.call_value(&callee_type, args, callee.span)?
+                }
+            },
+            ExprKind::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                let result = self.block(statements);
+                self.scopes.pop();
+                result?
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition)?;
+                let then_type = self.expr(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_type = self.expr(else_branch)?;
+                        self.join(&then_type, &else_type, else_branch.span)
+                    }
+                    None => Ty::None,
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.expr(condition)?;
+                self.expr(body)?;
+                Ty::None
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                let iterable_type = self.expr(iterable)?;
+                let item = match self.shallow(&iterable_type) {
+                    Ty::Array(item) | Ty::Set(item) => *item,
+                    Ty::Map(key, value) => Ty::Tuple(vec![*key, *value]),
+                    Ty::String => Ty::String,
+                    _ => Ty::Dynamic,
+                };
+                self.scopes.push(HashMap::new());
+                self.bind_pattern(pattern, &item);
+                let result = self.expr(body);
+                self.scopes.pop();
+                result?;
+                Ty::None
+            }
+            ExprKind::Match { expr: subject, arms } => {
+                let subject_type = self.expr(subject)?;
+                let mut result: Option<Ty> = None;
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    self.bind_pattern(&arm.pattern, &subject_type);
+                    let arm_type = self.match_arm(arm);
+                    self.scopes.pop();
+                    let arm_type = arm_type?;
+                    result = Some(match result {
+                        Some(ty) => self.join(&ty, &arm_type, arm.body.span),
+                        None => arm_type,
+                    });
+                }
+                result.unwrap_or(Ty::Dynamic)
+            }
+            ExprKind::Function { name, params, return_type, body, .. } => {
+                let param_types: Vec<(String, Ty)> = params.iter()
+                    .map(|p| (p.name.clone(), match &p.type_annotation {
+                        Some(ty) => self.of_type(ty, &HashMap::new()),
+                        None => self.fresh(),
+                    }))
+                    .collect();
+                let ret = match return_type {
+                    Some(ty) => self.of_type(ty, &HashMap::new()),
+                    None => self.fresh(),
+                };
+                let ty = Ty::Function(param_types.iter().map(|(_, ty)| ty.clone()).collect(), Box::new(ret.clone()));
+                // Declared before its body, which may call it
+                self.bind(name, ty.clone());
+                self.function_body(&format!("Function '{}'", name), &param_types, &ret, return_type.is_some(), body)?;
+                ty
+            }
+            ExprKind::Lambda { params, return_type, body } => {
+                let param_types: Vec<(String, Ty)> = params.iter()
+                    .map(|p| (p.name.clone(), match &p.type_annotation {
+                        Some(ty) => self.of_type(ty, &HashMap::new()),
+                        None => self.fresh(),
+                    }))
+                    .collect();
+                let ret = match return_type {
+                    Some(ty) => self.of_type(ty, &HashMap::new()),
+                    None => self.fresh(),
+                };
+                self.function_body("Lambda", &param_types, &ret, return_type.is_some(), body)?;
+                Ty::Function(param_types.into_iter().map(|(_, ty)| ty).collect(), Box::new(ret))
+            }
+            ExprKind::Index { array, index } => {
+                let array_type = self.expr(array)?;
+                let index_type = self.expr(index)?;
+                match self.shallow(&array_type) {
+                    Ty::Map(key, value) => {
+                        self.unify_at(&key, &index_type, index.span, None, |expected, found| {
+                            format!("Map key must be {}, got {}", expected, found)
+                        })?;
+                        *value
+                    }
+                    Ty::Array(element) => {
+                        self.unify_at(&Ty::Int, &index_type, index.span, None, |_, found| {
+                            format!("Array index must be int, got {}", found)
+                        })?;
+                        *element
+                    }
+                    Ty::String => {
+                        self.unify_at(&Ty::Int, &index_type, index.span, None, |_, found| {
+                            format!("String index must be int, got {}", found)
+                        })?;
+                        Ty::String
+                    }
+                    _ => Ty::Dynamic,
+                }
+            }
+            ExprKind::FieldAccess { object, field } => {
+                let object_type = self.expr(object)?;
+                match self.shallow(&object_type) {
+                    Ty::Tuple(elements) => field.parse::<usize>().ok()
+                        .and_then(|i| elements.get(i).cloned())
+                        .unwrap_or(Ty::Dynamic),
+                    _ => self.field_type(&object_type, field).unwrap_or(Ty::Dynamic),
+                }
+            }
+            ExprKind::Try(operand) => {
+                let operand_type = self.expr(operand)?;
+                match self.shallow(&operand_type) {
+                    Ty::Named(name, _) if name == "Result" => self.variant_data(&name, "Ok", &operand_type).unwrap_or(Ty::Dynamic),
+                    Ty::Named(name, _) if name == "Option" => self.variant_data(&name, "Some", &operand_type).unwrap_or(Ty::Dynamic),
+                    _ => Ty::Dynamic,
+                }
+            }
+        })
+    }
+
+    /// The type of a block: its last expression's, or none.
+    fn block(&mut self, statements: &[Stmt]) -> Result<Ty, TogError> {
+        let Some((last, init)) = statements.split_last() else {
+            return Ok(Ty::None);
+        };
+        for stmt in init {
+            self.stmt(stmt)?;
+        }
+        match &last.kind {
+            StmtKind::Expr(tail) => self.expr(tail),
+            // The block is left before its end
+            StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue => {
+                self.stmt(last)?;
+                Ok(Ty::Dynamic)
+            }
+            _ => {
+                self.stmt(last)?;
+                Ok(Ty::None)
+            }
+        }
+    }
+
+    fn match_arm(&mut self, arm: &MatchArm) -> Result<Ty, TogError> {
+        if let Some(guard) = &arm.guard {
+            let guard_type = self.expr(guard)?;
+            self.unify_at(&Ty::Bool, &guard_type, guard.span, None, |_, found| {
+                format!("Match guard must be a bool, got {}", found)
+            })?;
+        }
+        self.expr(&arm.body)
+    }
+
+    fn variant(&mut self, enum_name: &str, variant_name: &str, data: Option<(Ty, Span)>) -> Result<Ty, TogError> {
+        let Some((type_params, variants)) = self.enum_defs.get(enum_name) else {
+            let args: Vec<Ty> = data.into_iter().map(|(ty, _)| ty).collect();
+            return Ok(self.builtin_associated(enum_name, variant_name, &args));
+        };
+        let declared = variants.iter().find(|v| v.name == variant_name).and_then(|v| v.data_type.clone());
+        let (args, params) = self.type_arguments(type_params);
+        if let (Some((data_type, span)), Some(declared)) = (data, declared) {
+            let declared = self.of_type(&declared, &params);
+            self.unify_at(&declared, &data_type, span, None, |expected, found| {
+                format!("{}::{} holds {}, but the value has type {}", enum_name, variant_name, expected, found)
+            })?;
+        }
+        let type_params: Vec<(TypeParam, Ty)> = type_params.iter().cloned().zip(args.iter().cloned()).collect();
+        self.check_bounds(&type_params, &format!("enum {}", enum_name))?;
+        Ok(Ty::Named(enum_name.to_string(), args))
+    }
+
+    /// `Map::new()` and friends; dynamic for static methods of user types.
+    fn builtin_associated(&mut self, type_name: &str, name: &str, args: &[Ty]) -> Ty {
+        match (type_name, name, args) {
+            ("Map", "new", []) => Ty::Map(Box::new(self.fresh()), Box::new(self.fresh())),
+            ("Set", "new", []) => Ty::Set(Box::new(self.fresh())),
+            ("Set", "from", [items]) => match self.shallow(items) {
+                Ty::Array(element) => Ty::Set(element),
+                _ => Ty::Set(Box::new(self.fresh())),
+            },
+            _ => Ty::Dynamic,
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, (left, left_span): (&Ty, Span), (right, right_span): (&Ty, Span)) -> Result<Ty, TogError> {
+        let (l, r) = (self.shallow(left), self.shallow(right));
+        match op {
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => Ok(Ty::Bool),
+            BinaryOp::And | BinaryOp::Or => {
+                for (ty, span) in [(left, left_span), (right, right_span)] {
+                    self.unify_at(&Ty::Bool, ty, span, None, |_, found| {
+                        format!("Logical operations require bool operands, got {}", found)
+                    })?;
+                }
+                Ok(Ty::Bool)
+            }
+            BinaryOp::Mod => {
+                for (ty, span) in [(left, left_span), (right, right_span)] {
+                    self.unify_at(&Ty::Int, ty, span, None, |_, found| {
+                        format!("Modulo requires int operands, got {}", found)
+                    })?;
+                }
+                Ok(Ty::Int)
+            }
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => match (&l, &r) {
+                (Ty::Int, Ty::Int) => Ok(Ty::Int),
+                (Ty::Float, Ty::Int | Ty::Float) | (Ty::Int, Ty::Float) => Ok(Ty::Float),
+                (Ty::String, _) | (_, Ty::String) => Ok(Ty::String),
+                // `+` also joins strings, so `x + 1` doesn't say what `x` is
+                (Ty::Var(_), _) | (_, Ty::Var(_)) if op == BinaryOp::Add => Ok(Ty::Dynamic),
+                // Only numbers of one kind subtract, multiply and divide
+                (Ty::Var(_), Ty::Int | Ty::Float | Ty::Var(_)) => {
+                    self.unify(&l, &r, left_span);
+                    Ok(r)
+                }
+                (Ty::Int | Ty::Float, Ty::Var(_)) => {
+                    self.unify(&r, &l, right_span);
+                    Ok(l)
+                }
+                (Ty::Dynamic, _) | (_, Ty::Dynamic) | (Ty::Var(_), _) | (_, Ty::Var(_)) => Ok(Ty::Dynamic),
+                _ => Err(TogError::TypeError(
+                    format!("Invalid operation: {} {:?} {}", self.to_type(&l, &HashMap::new()), op, self.to_type(&r, &HashMap::new())),
+                    None
+                )),
+            },
+        }
+    }
+}
+
+/// What the builtins whose result is always the same type return.
+fn builtin_return_type(name: &str) -> Ty {
+    match name {
+        "print" => Ty::None,
+        "len" => Ty::Int,
+        "to_string" => Ty::String,
+        "range" => Ty::Array(Box::new(Ty::Int)),
+        _ => Ty::Dynamic,
+    }
+}
+
+fn type_variable_name(index: usize) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => format!("'{}", letter),
+        n => format!("'{}{}", letter, n),
+    }
+}
@@ -1,6 +1,13 @@
 use crate::ast::*;
-use crate::error::TogError;
-use std::collections::HashMap;
+use crate::error::{StackFrame, TogError};
+use crate::type_checker::check_trait_impl;
+use crate::stdlib::FunctionCaller;
+use crate::parallel::{captured_variables, SharedClosure, SharedFunction, SharedValue};
+use crate::diagnostics::{did_you_mean, format_trace};
+use crate::span::Span;
+use crate::vm::Closure;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -9,6 +16,7 @@ enum ControlFlow {
     Normal,
     Break,
     Continue,
+    Return(Value),
 }
 
 #[derive(Debug, Clone)]
@@ -50,8 +58,13 @@ impl Environment {
         Err(TogError::RuntimeError(format!("Cannot assign to undefined variable: {}", name), None))
     }
 
-    fn remove(&mut self, name: &str) {
-        self.values.remove(name);
+    /// Every name visible from this scope, for "did you mean" suggestions.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.borrow().names());
+        }
+        names
     }
 }
 
@@ -63,6 +76,9 @@ pub enum Value {
     String(String),
     Bool(bool),
     Array(Vec<Value>),
+    Tuple(Vec<Value>),
+    Map(BTreeMap<MapKey, Value>),
+    Set(BTreeSet<MapKey>),
     Struct {
         name: String,
         fields: HashMap<String, Value>,
@@ -79,6 +95,7 @@ pub enum Value {
         closure: Rc<RefCell<Environment>>,
         bound_self: Option<Box<Value>>,
     },
+    Closure(Rc<Closure>), // A function compiled for the bytecode VM
     None,
 }
 
@@ -90,6 +107,9 @@ impl PartialEq for Value {
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Set(a), Value::Set(b)) => a == b,
             (Value::Struct { name: n1, fields: f1 }, Value::Struct { name: n2, fields: f2 }) => {
                 n1 == n2 && f1 == f2
             }
@@ -100,6 +120,7 @@ impl PartialEq for Value {
             // Functions are compared by reference/pointer, not content.
             // For simplicity here, we'll consider them unequal unless we add IDs.
             (Value::Function { .. }, Value::Function { .. }) => false,
+            (Value::Closure(_), Value::Closure(_)) => false,
             (Value::None, Value::None) => true,
             _ => false,
         }
@@ -107,31 +128,160 @@ impl PartialEq for Value {
 }
 
 
+/// A map key or set element. Keys are ordered, so maps and sets iterate in
+/// key order however they were built, and program output is reproducible.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Tuple(Vec<MapKey>),
+    Enum {
+        enum_name: String,
+        variant_name: String,
+        data: Option<Box<MapKey>>,
+    },
+}
+
+impl MapKey {
+    /// Floats, arrays, maps, structs and functions can't be keys.
+    pub fn from_value(value: &Value) -> Result<MapKey, TogError> {
+        Ok(match value {
+            Value::Bool(b) => MapKey::Bool(*b),
+            Value::Int(n) => MapKey::Int(*n),
+            Value::String(s) => MapKey::String(s.clone()),
+            Value::Tuple(elements) => MapKey::Tuple(
+                elements.iter().map(MapKey::from_value).collect::<Result<_, _>>()?
+            ),
+            Value::Enum { enum_name, variant_name, data } => MapKey::Enum {
+                enum_name: enum_name.clone(),
+                variant_name: variant_name.clone(),
+                data: match data {
+                    Some(d) => Some(Box::new(MapKey::from_value(d)?)),
+                    None => None,
+                },
+            },
+            _ => {
+                return Err(TogError::RuntimeError(
+                    format!("{} can't be a map key or set element", value_to_string(value)),
+                    None
+                ).with_help("keys can be ints, strings, bools, enums, or tuples of those"));
+            }
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Int(n) => Value::Int(*n),
+            MapKey::String(s) => Value::String(s.clone()),
+            MapKey::Tuple(elements) => Value::Tuple(elements.iter().map(MapKey::to_value).collect()),
+            MapKey::Enum { enum_name, variant_name, data } => Value::Enum {
+                enum_name: enum_name.clone(),
+                variant_name: variant_name.clone(),
+                data: data.as_ref().map(|d| Box::new(d.to_value())),
+            },
+        }
+    }
+}
+
+// Struct definition: declared fields plus methods written inline in the struct body
+pub(crate) type StructDef = (Vec<(String, Option<Type>)>, Vec<MethodDecl>);
+
+/// Snapshot of the program's type declarations, handed to worker threads so
+/// they can run methods and build structs and enums.
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    pub(crate) struct_defs: HashMap<String, StructDef>,
+    pub(crate) enum_defs: HashMap<String, Vec<EnumVariant>>,
+    pub(crate) trait_defs: HashMap<String, Vec<TraitMethod>>,
+    pub(crate) trait_impls: HashMap<(String, String), Vec<MethodDecl>>,
+    pub(crate) inherent_impls: HashMap<String, Vec<MethodDecl>>,
+}
+
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
-    struct_defs: HashMap<String, (Vec<(String, Option<Type>)>, Vec<MethodDecl>)>,
+    globals: Rc<RefCell<Environment>>,
+    struct_defs: HashMap<String, StructDef>,
     enum_defs: HashMap<String, Vec<EnumVariant>>,
     trait_defs: HashMap<String, Vec<TraitMethod>>,
     // trait_impls: (type_name, trait_name) -> methods
     trait_impls: HashMap<(String, String), Vec<MethodDecl>>,
     // inherent_impls: type_name -> methods
     inherent_impls: HashMap<String, Vec<MethodDecl>>,
+    call_stack: Vec<StackFrame>,
+    call_site: Span, // The call being dispatched, recorded in the callee's frame
+    // The Err or None a `?` is returning. Inside an expression the `?`
+    // unwinds as an error; `evaluate_with_flow` turns it into a return.
+    early_return: Option<Value>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
         Self {
-            environment: Rc::new(RefCell::new(Environment::new(None))),
+            environment: Rc::clone(&globals),
+            globals,
             struct_defs: HashMap::new(),
             enum_defs: HashMap::new(),
             trait_defs: HashMap::new(),
             trait_impls: HashMap::new(),
             inherent_impls: HashMap::new(),
+            call_stack: Vec::new(),
+            call_site: Span::default(),
+            early_return: None,
         }
     }
     
-    pub fn interpret(program: Program) -> Result<(), TogError> {
+    /// Builds an interpreter for a worker thread from a shared function and
+    /// returns it along with the function's value.
+    pub fn for_shared_function(shared: &SharedFunction) -> (Self, Value) {
         let mut interpreter = Self::new();
+        let defs = shared.definitions.clone();
+        interpreter.struct_defs = defs.struct_defs;
+        interpreter.enum_defs = defs.enum_defs;
+        interpreter.trait_defs = defs.trait_defs;
+        interpreter.trait_impls = defs.trait_impls;
+        interpreter.inherent_impls = defs.inherent_impls;
+
+        // Each function gets its own scope for its captures, so two functions
+        // capturing different values under the same name stay independent
+        let scopes: Vec<_> = shared.functions.iter()
+            .map(|_| Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&interpreter.globals))))))
+            .collect();
+        let functions: Vec<Value> = shared.functions.iter().zip(&scopes)
+            .map(|(func, scope)| Value::Function {
+                name: func.name.clone(),
+                params: func.params.clone(),
+                body: Rc::new(func.body.clone()),
+                closure: Rc::clone(scope),
+                bound_self: None,
+            })
+            .collect();
+        for ((func, scope), value) in shared.functions.iter().zip(&scopes).zip(&functions) {
+            let mut scope = scope.borrow_mut();
+            for (name, captured) in &func.captures {
+                scope.define(name.clone(), captured.clone().into_value());
+            }
+            for (name, index) in &func.captured_functions {
+                scope.define(name.clone(), functions[*index].clone());
+            }
+            // Methods look functions up in globals
+            interpreter.globals.borrow_mut().define(func.name.clone(), value.clone());
+        }
+
+        let entry = functions[0].clone();
+        (interpreter, entry)
+    }
+
+    pub fn interpret(program: Program, load_prelude: bool) -> Result<(), TogError> {
+        let mut interpreter = Self::new();
+
+        if load_prelude {
+            for stmt in &crate::stdlib::prelude()?.statements {
+                interpreter.execute_stmt(stmt)?;
+            }
+        }
 
         // Single pass execution
         for stmt in &program.statements {
@@ -154,41 +304,86 @@ impl Interpreter {
             // Execute main in its own top-level scope.
             let old_env = Rc::clone(&interpreter.environment);
             interpreter.environment = Rc::new(RefCell::new(Environment::new(Some(closure))));
-            interpreter.evaluate(&body)?;
+            let result = interpreter.with_frame("main".to_string(), None, |interp| interp.call_body(&body));
             interpreter.environment = old_env;
+            result?;
         }
         
         Ok(())
     }
     
     fn execute_stmt(&mut self, stmt: &Stmt) -> Result<(Value, ControlFlow), TogError> {
+        self.execute_stmt_kind(stmt).map_err(|e| e.with_span(stmt.span))
+    }
+
+    fn execute_stmt_kind(&mut self, stmt: &Stmt) -> Result<(Value, ControlFlow), TogError> {
         // println!("[DEBUG] execute_stmt(): stmt: {:?}", stmt); // Removed: causes infinite recursion with closures
-        match stmt {
-            Stmt::Expr(expr) => {
-                let val = self.evaluate(expr)?;
-                Ok((val, ControlFlow::Normal))
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.evaluate_with_flow(expr)
             }
-            Stmt::Let { name, value, .. } => {
-                let val = self.evaluate(value)?;
+            StmtKind::Let { name, value, .. } => {
+                let (val, flow) = self.evaluate_with_flow(value)?;
+                if flow != ControlFlow::Normal {
+                    return Ok((val, flow));
+                }
                 self.environment.borrow_mut().define(name.clone(), val.clone());
                 Ok((val, ControlFlow::Normal))
             }
-            Stmt::Assign { name, value } => {
-                let val = self.evaluate(value)?;
+            StmtKind::LetPattern { pattern, value } => {
+                let (val, flow) = self.evaluate_with_flow(value)?;
+                if flow != ControlFlow::Normal {
+                    return Ok((val, flow));
+                }
+                let mut bindings = Vec::new();
+                if !match_pattern(pattern, &val, &mut bindings)? {
+                    return Err(TogError::RuntimeError(
+                        format!("Value {} doesn't match the pattern in this let", value_to_string(&val)),
+                        None
+                    ));
+                }
+                for (name, bound) in bindings {
+                    self.environment.borrow_mut().define(name, bound);
+                }
+                Ok((val, ControlFlow::Normal))
+            }
+            StmtKind::Assign { name, op, value } => {
+                // `x op= value` reads `x` before evaluating the value
+                let current = match op {
+                    Some(_) => Some(self.environment.borrow().get(name)?),
+                    None => None,
+                };
+                let (val, flow) = self.evaluate_with_flow(value)?;
+                if flow != ControlFlow::Normal {
+                    return Ok((val, flow));
+                }
+                let val = match (op, current) {
+                    (Some(op), Some(current)) => evaluate_binary_op(&current, *op, &val)?,
+                    _ => val,
+                };
                 self.environment.borrow_mut().assign(name, val.clone())?;
                 Ok((val, ControlFlow::Normal))
             }
-            Stmt::AssignField { object, field, value } => {
-                // Support nested field assignment: obj.field = value where obj can be nested access
+            StmtKind::AssignField { object, field, op, value } => {
+                // Nested field assignment: obj.field = value where obj can be nested access
                 let new_val = self.evaluate(value)?;
-                self.assign_field_chain(object, field, new_val)?;
+                let (root, mut steps) = self.place(object)?;
+                steps.push(PlaceStep::Field(field.clone()));
+                self.store_place(&root, &steps, *op, new_val)?;
                 Ok((Value::None, ControlFlow::Normal))
             }
-            Stmt::StructDef { name, fields, methods } => {
+            StmtKind::AssignIndex { object, index, op, value } => {
+                let new_val = self.evaluate(value)?;
+                let (root, mut steps) = self.place(object)?;
+                steps.push(PlaceStep::Index(self.evaluate(index)?));
+                self.store_place(&root, &steps, *op, new_val)?;
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            StmtKind::StructDef { name, fields, methods, .. } => {
                 self.struct_defs.insert(name.clone(), (fields.clone(), methods.clone()));
                 Ok((Value::None, ControlFlow::Normal))
             }
-            Stmt::EnumDef { name, variants } => {
+            StmtKind::EnumDef { name, variants, .. } => {
                 self.enum_defs.insert(name.clone(), variants.clone());
                 // Register enum variants as constructors in the environment
                 for variant in variants {
@@ -199,52 +394,218 @@ impl Interpreter {
                 }
                 Ok((Value::None, ControlFlow::Normal))
             }
-            Stmt::TraitDef { name, methods } => {
+            StmtKind::TraitDef { name, methods, .. } => {
                 self.trait_defs.insert(name.clone(), methods.clone());
                 Ok((Value::None, ControlFlow::Normal))
             }
-            Stmt::ImplBlock { trait_name, type_name, methods } => {
+            StmtKind::ImplBlock { trait_name, type_name, methods, .. } => {
                 if let Some(trait_name) = trait_name {
-                    // Trait implementation
-                    self.trait_impls.insert((type_name.clone(), trait_name.clone()), methods.clone());
+                    // Trait implementation: must conform, and inherits default methods
+                    let trait_methods = self.trait_defs.get(trait_name)
+                        .ok_or_else(|| TogError::RuntimeError(
+                            format!("Unknown trait '{}' in impl for {}", trait_name, type_name),
+                            None
+                        ))?;
+                    check_trait_impl(trait_name, trait_methods, type_name, methods)?;
+
+                    let mut all_methods = methods.clone();
+                    for trait_method in trait_methods {
+                        if let Some(body) = &trait_method.default_body {
+                            if !methods.iter().any(|m| m.name == trait_method.name) {
+                                all_methods.push(MethodDecl {
+                                    name: trait_method.name.clone(),
+                                    params: trait_method.params.clone(),
+                                    return_type: trait_method.return_type.clone(),
+                                    body: body.clone(),
+                                });
+                            }
+                        }
+                    }
+                    self.trait_impls.insert((type_name.clone(), trait_name.clone()), all_methods);
                 } else {
-                    // Inherent implementation
-                    self.inherent_impls.insert(type_name.clone(), methods.clone());
+                    // Inherent implementation; a type may have several `impl` blocks
+                    self.inherent_impls.entry(type_name.clone()).or_default().extend(methods.iter().cloned());
                 }
                 Ok((Value::None, ControlFlow::Normal))
             }
-            Stmt::Return(expr) => {
-                if let Some(expr) = expr {
-                    let val = self.evaluate(expr)?;
-                    Ok((val, ControlFlow::Normal))
+            StmtKind::Return(expr) => {
+                let val = if let Some(expr) = expr {
+                    let (val, flow) = self.evaluate_with_flow(expr)?;
+                    if flow != ControlFlow::Normal {
+                        return Ok((val, flow));
+                    }
+                    val
                 } else {
-                    Ok((Value::None, ControlFlow::Normal))
-                }
+                    Value::None
+                };
+                // Unwinds through blocks and loops until the enclosing call catches it
+                Ok((Value::None, ControlFlow::Return(val)))
             }
-            Stmt::Break => {
+            StmtKind::Break => {
                 Ok((Value::None, ControlFlow::Break))
             }
-            Stmt::Continue => {
+            StmtKind::Continue => {
                 Ok((Value::None, ControlFlow::Continue))
             }
+            StmtKind::Import { .. } => {
+                // Linked by the module loader, which puts the imported
+                // modules' statements ahead of the program's
+                Ok((Value::None, ControlFlow::Continue))
+            }
+            StmtKind::Error => {
+                Err(TogError::RuntimeError("Cannot run a statement that failed to parse".to_string(), None))
+            }
         }
     }
     
     fn evaluate_block(&mut self, statements: &[Stmt]) -> Result<(Value, ControlFlow), TogError> {
-        let mut last_val = Value::None;
-        for stmt in statements {
-            let (val, control_flow) = self.execute_stmt(stmt)?;
-            last_val = val;
-            if control_flow != ControlFlow::Normal {
-                return Ok((last_val, control_flow));
+        self.with_scope(Vec::new(), |interp| {
+            let mut last_val = Value::None;
+            for stmt in statements {
+                let (val, control_flow) = interp.execute_stmt(stmt)?;
+                last_val = val;
+                if control_flow != ControlFlow::Normal {
+                    return Ok((last_val, control_flow));
+                }
+            }
+            Ok((last_val, ControlFlow::Normal))
+        })
+    }
+
+    /// Evaluates an expression that may contain `return`, `break` or `continue`
+    /// and reports how control left it. Only blocks, `if`, loops and `match`
+    /// can produce a non-normal flow; everything else defers to `evaluate`.
+    fn evaluate_with_flow(&mut self, expr: &Expr) -> Result<(Value, ControlFlow), TogError> {
+        let result = self.evaluate_with_flow_kind(expr);
+        if let Some(value) = self.early_return.take() {
+            return Ok((Value::None, ControlFlow::Return(value)));
+        }
+        result.map_err(|e| e.with_span(expr.span))
+    }
+
+    fn evaluate_with_flow_kind(&mut self, expr: &Expr) -> Result<(Value, ControlFlow), TogError> {
+        match &expr.kind {
+            ExprKind::Block(statements) => self.evaluate_block(statements),
+            ExprKind::If { condition, then_branch, else_branch } => {
+                let cond_val = self.evaluate(condition)?;
+                if is_truthy(&cond_val) {
+                    self.evaluate_with_flow(then_branch)
+                } else if let Some(else_expr) = else_branch {
+                    self.evaluate_with_flow(else_expr)
+                } else {
+                    Ok((Value::None, ControlFlow::Normal))
+                }
+            }
+            ExprKind::While { condition, body } => {
+                while is_truthy(&self.evaluate(condition)?) {
+                    let (val, flow) = self.evaluate_with_flow(body)?;
+                    match flow {
+                        ControlFlow::Break => break,
+                        ControlFlow::Continue | ControlFlow::Normal => {}
+                        ControlFlow::Return(_) => return Ok((val, flow)),
+                    }
+                }
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                let values = iteration_items(self.evaluate(iterable)?)?;
+
+                for val in values {
+                    let mut bindings = Vec::new();
+                    if !match_pattern(pattern, &val, &mut bindings)? {
+                        return Err(TogError::RuntimeError(
+                            format!("Loop item {} doesn't match the pattern", value_to_string(&val)),
+                            None
+                        ));
+                    }
+                    let (val, flow) = self.with_scope(bindings, |interp| interp.evaluate_with_flow(body))?;
+                    match flow {
+                        ControlFlow::Break => break,
+                        ControlFlow::Continue | ControlFlow::Normal => {}
+                        ControlFlow::Return(_) => return Ok((val, flow)),
+                    }
+                }
+                Ok((Value::None, ControlFlow::Normal))
+            }
+            ExprKind::Match { expr, arms } => {
+                let value = self.evaluate(expr)?;
+                for arm in arms {
+                    let mut bindings = Vec::new();
+                    if !match_pattern(&arm.pattern, &value, &mut bindings)? {
+                        continue;
+                    }
+                    // The guard sees the pattern's variables; if it fails, try the next arm
+                    let result = self.with_scope(bindings, |interp| {
+                        if let Some(guard) = &arm.guard {
+                            if !is_truthy(&interp.evaluate(guard)?) {
+                                return Ok(None);
+                            }
+                        }
+                        interp.evaluate_with_flow(&arm.body).map(Some)
+                    })?;
+                    if let Some(result) = result {
+                        return Ok(result);
+                    }
+                }
+                Err(TogError::RuntimeError(
+                    "No matching pattern in match expression".to_string(),
+                    None
+                ))
             }
+            _ => Ok((self.evaluate(expr)?, ControlFlow::Normal)),
         }
-        Ok((last_val, ControlFlow::Normal))
     }
 
+    /// Evaluates an expression in a position where control cannot leave it,
+    /// such as an argument or operand.
+    fn evaluate_normal(&mut self, expr: &Expr) -> Result<Value, TogError> {
+        let (val, flow) = self.evaluate_with_flow(expr)?;
+        match flow {
+            ControlFlow::Normal => Ok(val),
+            ControlFlow::Return(_) => Err(TogError::RuntimeError(
+                "'return' cannot be used inside this expression".to_string(),
+                None
+            )),
+            flow => Err(TogError::RuntimeError(format!("{:?} outside of loop", flow), None)),
+        }
+    }
+
+    /// Runs `call` with a frame for `function` on the call stack. An error
+    /// leaving the call gets the stack as it was when the error was raised.
+    fn with_frame<T>(
+        &mut self,
+        function: String,
+        receiver: Option<String>,
+        call: impl FnOnce(&mut Self) -> Result<T, TogError>,
+    ) -> Result<T, TogError> {
+        self.call_stack.push(StackFrame { function, receiver, call_site: self.call_site });
+        let result = call(self).map_err(|e| e.with_trace(&self.call_stack));
+        if let Some(frame) = self.call_stack.pop() {
+            // Calls made inside the callee moved it on
+            self.call_site = frame.call_site;
+        }
+        result
+    }
+
+    /// Runs a function or method body and catches the `return` that ends it.
+    fn call_body(&mut self, body: &Expr) -> Result<Value, TogError> {
+        let (val, flow) = self.evaluate_with_flow(body)?;
+        match flow {
+            ControlFlow::Normal => Ok(val),
+            ControlFlow::Return(ret) => Ok(ret),
+            flow => Err(TogError::RuntimeError(format!("{:?} outside of loop", flow), None)),
+        }
+    }
+
+    /// Evaluates an expression. Errors that don't have a location yet get the
+    /// span of the innermost expression that failed.
     fn evaluate(&mut self, expr: &Expr) -> Result<Value, TogError> {
-        match expr {
-            Expr::Literal(lit) => {
+        self.evaluate_kind(expr).map_err(|e| e.with_span(expr.span))
+    }
+
+    fn evaluate_kind(&mut self, expr: &Expr) -> Result<Value, TogError> {
+        match &expr.kind {
+            ExprKind::Literal(lit) => {
                 match lit {
                     Literal::Array(elems) => {
                         let mut values = Vec::new();
@@ -253,10 +614,23 @@ impl Interpreter {
                         }
                         Ok(Value::Array(values))
                     }
+                    Literal::Tuple(elems) => {
+                        let values: Result<Vec<Value>, TogError> = elems.iter().map(|elem| self.evaluate(elem)).collect();
+                        Ok(Value::Tuple(values?))
+                    }
+                    Literal::Map(entries) => {
+                        let mut map = BTreeMap::new();
+                        for (key, value) in entries {
+                            let key_val = self.evaluate(key)?;
+                            let key = MapKey::from_value(&key_val).map_err(|e| e.with_span(key.span))?;
+                            map.insert(key, self.evaluate(value)?);
+                        }
+                        Ok(Value::Map(map))
+                    }
                     _ => Ok(literal_to_value(lit)),
                 }
             }
-            Expr::StructLiteral { name, fields } => {
+            ExprKind::StructLiteral { name, fields } => {
                 let def = self.struct_defs.get(name).cloned()
                     .ok_or_else(|| TogError::RuntimeError(
                         format!("Unknown struct: {}", name),
@@ -284,12 +658,33 @@ impl Interpreter {
                     fields: map,
                 })
             }
-            Expr::EnumVariant { enum_name, variant_name, data } => {
-                // Validate that the enum exists
-                if !self.enum_defs.contains_key(enum_name) {
-                    return Err(TogError::RuntimeError(
+            ExprKind::EnumVariant { enum_name, variant_name, data } => {
+                // `Type::name(arg)` parses like a variant but may name an associated function
+                if !self.is_enum_variant(enum_name, variant_name) {
+                    if let Some(method) = self.find_method(enum_name, variant_name)? {
+                        let args = match data {
+                            Some(data_expr) => vec![self.evaluate(data_expr)?],
+                            None => Vec::new(),
+                        };
+                        return self.call_method(enum_name, &method, None, args);
+                    }
+                    let args = match data {
+                        Some(data_expr) => vec![self.evaluate(data_expr)?],
+                        None => Vec::new(),
+                    };
+                    if let Some(result) = self.call_builtin_associated(enum_name, variant_name, &args)? {
+                        return Ok(result);
+                    }
+                }
+                let variants = self.enum_defs.get(enum_name)
+                    .ok_or_else(|| TogError::RuntimeError(
                         format!("Unknown enum: {}", enum_name),
                         None
+                    ))?;
+                if !variants.iter().any(|v| v.name == *variant_name) {
+                    return Err(TogError::RuntimeError(
+                        format!("Enum '{}' has no variant or method '{}'", enum_name, variant_name),
+                        None
                     ));
                 }
                 
@@ -306,258 +701,140 @@ impl Interpreter {
                     data: data_value,
                 })
             }
-            Expr::Variable(name) => {
+            ExprKind::Variable(name) => {
                 // Builtin functions are handled in call expressions
-                self.environment.borrow().get(name)
+                let result = self.environment.borrow().get(name);
+                result.map_err(|e| {
+                    let names = self.environment.borrow().names();
+                    match did_you_mean(name, names.iter().map(String::as_str)) {
+                        Some(help) => e.with_help(help),
+                        None => e,
+                    }
+                })
             }
-            Expr::FieldAccess { object, field } => {
+            ExprKind::FieldAccess { object, field } => {
                 let obj_val = self.evaluate(object)?;
-                match obj_val {
-                    Value::Struct { fields, .. } => {
-                        fields.get(field)
-                            .cloned()
-                            .ok_or_else(|| TogError::RuntimeError(
-                                format!("Field '{}' not found", field),
-                                None
-                            ))
-                    }
-                    _ => Err(TogError::RuntimeError(
-                        "Field access on non-struct value".to_string(),
-                        None
-                    ))
-                }
+                field_of(&obj_val, field).cloned()
             }
-            Expr::BinaryOp { left, op, right } => {
+            ExprKind::BinaryOp { left, op, right } => {
                 let left_val = self.evaluate(left)?;
                 let right_val = self.evaluate(right)?;
-                self.evaluate_binary_op(&left_val, *op, &right_val)
+                evaluate_binary_op(&left_val, *op, &right_val)
             }
-            Expr::UnaryOp { op, expr } => {
+            ExprKind::UnaryOp { op, expr } => {
                 let val = self.evaluate(expr)?;
-                self.evaluate_unary_op(*op, &val)
+                evaluate_unary_op(*op, &val)
             }
-            Expr::Call { callee, args } => {
+            ExprKind::Call { callee, args } => {
                 // println!("[DEBUG] evaluate_call: callee: {:?}", callee); // Removed: causes infinite recursion with closures
                 let arg_values: Result<Vec<Value>, TogError> = 
                     args.iter().map(|arg| self.evaluate(arg)).collect();
                 let arg_values = arg_values?;
+                self.call_site = expr.span;
                 
-                // Method call: obj.method(...) or Struct.method(...)
-                if let Expr::FieldAccess { object, field: method_name } = callee.as_ref() {
-                    // Check for static method call: StructName.method()
-                    if let Expr::Variable(struct_name) = object.as_ref() {
-                        if self.struct_defs.contains_key(struct_name) {
-                            if let Some((_, methods)) = self.struct_defs.get(struct_name) {
-                                if let Some(method) = methods.iter().find(|m| m.name == *method_name).cloned() {
-                                    // Static method call. Execute in a new environment.
-                                    let old_env = Rc::clone(&self.environment);
-                                    self.environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&old_env)))));
-                                    for (param, arg_value) in method.params.iter().zip(arg_values.iter()) {
-                                        self.environment.borrow_mut().define(param.name.clone(), arg_value.clone());
-                                    }
-                                    let result = self.evaluate(&method.body);
-                                    self.environment = old_env;
-                                    return result;
-                                }
-                            }
+                // Method call: obj.method(...) or Type.method(...)
+                if let ExprKind::FieldAccess { object, field: method_name } = &callee.kind {
+                    // Check for static method call: TypeName.method()
+                    if let ExprKind::Variable(type_name) = &object.kind {
+                        if self.is_type_name(type_name) && self.environment.borrow().get(type_name).is_err() {
+                            let method = self.find_method(type_name, method_name)?
+                                .ok_or_else(|| TogError::RuntimeError(
+                                    format!("Unknown method '{}' on {}", method_name, type_name),
+                                    None
+                                ))?;
+                            return self.call_method(type_name, &method, None, arg_values);
                         }
                     }
 
                     let obj_val = self.evaluate(object)?;
-                    if let Value::Struct { name: struct_name, .. } = obj_val.clone() {
-                        if let Some((_, methods)) = self.struct_defs.get(&struct_name) {
-                            if let Some(method) = methods.iter().find(|m| m.name == *method_name).cloned() {
-                                
-                                let old_env = Rc::clone(&self.environment);
-                                // Create a new environment for the method call, enclosing the global scope.
-                                self.environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&old_env)))));
-                                self.environment.borrow_mut().define("self".to_string(), obj_val.clone());
-                                for (param, arg_value) in method.params.iter().zip(arg_values.iter()) {
-                                    self.environment.borrow_mut().define(param.name.clone(), arg_value.clone());
-                                }
-                                let result = self.evaluate(&method.body);
-                                self.environment = old_env;
-                                return result;
-                            }
+                    // Builtin types only have the methods of `impl Trait for int` etc.
+                    let builtin_type = match &obj_val {
+                        Value::Int(_) => Some("int"),
+                        Value::Float(_) => Some("float"),
+                        Value::String(_) => Some("string"),
+                        Value::Bool(_) => Some("bool"),
+                        _ => None,
+                    };
+                    if let Some(type_name) = builtin_type {
+                        if let Some(method) = self.find_method(type_name, method_name)? {
+                            return self.call_method_on(type_name, &method, object, obj_val, arg_values);
                         }
-                        return Err(TogError::RuntimeError(
-                            format!("Unknown method '{}' on struct {}", method_name, struct_name),
-                            None
-                        ));
                     }
-                }
-                
-                // Check for builtin functions first
-                if let Expr::Variable(name) = callee.as_ref() {
-                    match name.as_str() {
-                        "print" => {
-                            // print is now a builtin function
-                            for arg in &arg_values {
-                                print!("{}", value_to_string(arg));
-                            }
-                            println!(); // Newline after print
-                            return Ok(Value::None);
+                    let receiver_type = match &obj_val {
+                        Value::Struct { name, .. } => Some(name.clone()),
+                        Value::Enum { enum_name, .. } => Some(enum_name.clone()),
+                        _ => None,
+                    };
+                    if let Some(type_name) = receiver_type {
+                        if let Some(method) = self.find_method(&type_name, method_name)? {
+                            return self.call_method_on(&type_name, &method, object, obj_val, arg_values);
                         }
-                        _ => {
-                match crate::stdlib::call_builtin(name, &arg_values) {
-                                Ok(result) => return Ok(result),
-                                Err(TogError::RuntimeError(ref msg, _)) if msg.contains("Unknown builtin") => {
-                                    // Not a builtin, continue to normal evaluation
-                                }
-                                Err(e) => return Err(e), // Other error (wrong args, etc.)
-                            }
-                        }
-                    }
-                }
-                
-                let callee_val = self.evaluate(callee)?;
-                match callee_val {
-                    Value::Function { params, body, closure, bound_self, .. } => {
-                        if arg_values.len() != params.len() {
+                        // A struct field holding a function is called like a method
+                        let is_field = matches!(&obj_val, Value::Struct { fields, .. } if fields.contains_key(method_name));
+                        if !is_field {
                             return Err(TogError::RuntimeError(
-                                format!("Function expects {} arguments, got {}", params.len(), arg_values.len()),
+                                format!("Unknown method '{}' on {}", method_name, type_name),
                                 None
                             ));
                         }
-                        
-                        let old_env = Rc::clone(&self.environment);
-                        // The new environment encloses the function's definition environment (closure).
-                        self.environment = Rc::new(RefCell::new(Environment::new(Some(closure))));
-
-                        // If a method is bound, add 'self' to the new scope
-                        if let Some(self_val) = bound_self {
-                            self.environment.borrow_mut().define("self".to_string(), *self_val);
-                        }
-                        
-                        // Bind arguments to parameters in the new scope
-                        for (param, arg_val) in params.iter().zip(arg_values.iter()) {
-                            self.environment.borrow_mut().define(param.name.clone(), arg_val.clone());
-                        }
-
-                        let result = self.evaluate(&body);
-
-                        self.environment = old_env;
-                        return result;
-                    }
-                    _ => Err(TogError::TypeError(
-                        "Can only call functions".to_string(),
-                        None
-                    ))
-                }
-            }
-            Expr::Block(statements) => {
-                let (last_val, flow) = self.evaluate_block(statements)?;
-                if flow != ControlFlow::Normal {
-                     return Err(TogError::RuntimeError(format!("{:?} outside of loop", flow), None));
-                }
-                Ok(last_val)
-            }
-            Expr::If { condition, then_branch, else_branch } => {
-                let cond_val = self.evaluate(condition)?;
-                if is_truthy(&cond_val) {
-                    self.evaluate(then_branch)
-                } else if let Some(else_expr) = else_branch {
-                    self.evaluate(else_expr)
-                } else {
-                    Ok(Value::None)
-                }
-            }
-            Expr::While { condition, body } => {
-                while is_truthy(&self.evaluate(condition)?) {
-                    if let Expr::Block(statements) = body.as_ref() {
-                        let (_, flow) = self.evaluate_block(statements)?;
-                        match flow {
-                            ControlFlow::Break => break,
-                            ControlFlow::Continue => continue,
-                            ControlFlow::Normal => {}
-                        }
-                    } else {
-                        self.evaluate(body)?;
                     }
                 }
-                Ok(Value::None)
-            }
-            Expr::For { variable, iterable, body } => {
-                let iterable_val = self.evaluate(iterable)?;
-                let values = match iterable_val {
-                    Value::Array(arr) => arr,
-                    Value::String(s) => s.chars().map(|c| Value::String(c.to_string())).collect(),
-                    _ => return Err(TogError::TypeError("Expected iterable in for loop".to_string(), None)),
-                };
 
-                for val in values {
-                    let old_val = self.environment.borrow().get(variable).ok();
-                    self.environment.borrow_mut().define(variable.clone(), val);
-                    
-                    if let Expr::Block(statements) = body.as_ref() {
-                        let (_, flow) = self.evaluate_block(statements)?;
-                        match flow {
-                            ControlFlow::Break => break,
-                            ControlFlow::Continue => continue,
-                            ControlFlow::Normal => {}
+                // Associated function or multi-value path call: Type::name(a, b)
+                if let ExprKind::EnumVariant { enum_name, variant_name, data: None } = &callee.kind {
+                    if !self.is_enum_variant(enum_name, variant_name) {
+                        if let Some(result) = self.call_builtin_associated(enum_name, variant_name, &arg_values)? {
+                            return Ok(result);
                         }
-                    } else {
-                        self.evaluate(body)?;
+                        let method = self.find_method(enum_name, variant_name)?
+                            .ok_or_else(|| TogError::RuntimeError(
+                                format!("Unknown method '{}' on {}", variant_name, enum_name),
+                                None
+                            ))?;
+                        return self.call_method(enum_name, &method, None, arg_values);
                     }
-
-                    if let Some(old) = old_val {
-                        self.environment.borrow_mut().assign(variable, old)?;
-                    } else {
-                        self.environment.borrow_mut().remove(variable);
+                    if arg_values.is_empty() {
+                        return Err(TogError::RuntimeError(
+                            format!("Enum variant {}::{} needs a value inside the parentheses", enum_name, variant_name),
+                            None
+                        ).with_help(format!("write `{}::{}` for a variant without data", enum_name, variant_name)));
                     }
+                    // `Move(1, 2)` holds its values as a tuple
+                    return Ok(Value::Enum {
+                        enum_name: enum_name.clone(),
+                        variant_name: variant_name.clone(),
+                        data: Some(Box::new(Value::Tuple(arg_values))),
+                    });
                 }
-                Ok(Value::None)
-            }
-            Expr::Match { expr, arms } => {
-                let value = self.evaluate(expr)?;
-                for arm in arms {
-                    if self.match_pattern(&arm.pattern, &value)? {
-                        // Bind pattern variables to the matched value
-                        match &arm.pattern {
-                            Pattern::Variable(var_name) => {
-                                let old_val = self.environment.borrow().get(var_name).ok();
-                                self.environment.borrow_mut().define(var_name.clone(), value.clone());
-                                let result = self.evaluate(&arm.body);
-                                // Restore old value if it existed
-                                if let Some(old) = old_val {
-                                    self.environment.borrow_mut().assign(&var_name, old)?;
-                                } else {
-                                    self.environment.borrow_mut().remove(var_name);
-                                }
-                                return result;
-                            }
-                            Pattern::EnumVariant { binding, .. } => {
-                                // Bind the data from the enum variant
-                                if let Some(binding_name) = binding {
-                                    if let Value::Enum { data, .. } = &value {
-                                        if let Some(data_value) = data {
-                                            let old_val = self.environment.borrow().get(binding_name).ok();
-                                            self.environment.borrow_mut().define(binding_name.clone(), (**data_value).clone());
-                                            let result = self.evaluate(&arm.body);
-                                            // Restore old value if it existed
-                                            if let Some(old) = old_val {
-                                                self.environment.borrow_mut().assign(&binding_name, old)?;
-                                            } else {
-                                                self.environment.borrow_mut().remove(binding_name);
-                                            }
-                                            return result;
-                                        }
-                                    }
-                                }
-                                return self.evaluate(&arm.body);
+                
+                // Builtin functions, unless the program defines its own with the same name
+                if let ExprKind::Variable(name) = &callee.kind {
+                    let user_defined = matches!(self.environment.borrow().get(name), Ok(Value::Function { .. }));
+                    if !user_defined {
+                        if name == "print" {
+                            for arg in &arg_values {
+                                print!("{}", value_to_string(arg));
                             }
-                            _ => {
-                                return self.evaluate(&arm.body);
+                            println!(); // Newline after print
+                            return Ok(Value::None);
+                        }
+                        match crate::stdlib::call_builtin(self, name, &arg_values) {
+                            Ok(result) => return Ok(result),
+                            Err(TogError::RuntimeError(ref msg, _)) if msg.contains("Unknown builtin") => {
+                                // Not a builtin, continue to normal evaluation
                             }
+                            Err(e) => return Err(e), // Other error (wrong args, etc.)
                         }
                     }
                 }
-                Err(TogError::RuntimeError(
-                    "No matching pattern in match expression".to_string(),
-                    None
-                ))
+                
+                let callee_val = self.evaluate(callee)?;
+                self.call_function(&callee_val, arg_values)
+            }
+            ExprKind::Block(_) | ExprKind::If { .. } | ExprKind::While { .. } | ExprKind::For { .. } | ExprKind::Match { .. } => {
+                self.evaluate_normal(expr)
             }
-            Expr::Function { name, params, return_type: _, body } => {
+            ExprKind::Function { name, params, body, .. } => {
                 let func_value = Value::Function {
                     name: name.clone(),
                     params: params.clone(),
@@ -569,60 +846,221 @@ impl Interpreter {
                 self.environment.borrow_mut().define(name.clone(), func_value.clone());
                 Ok(func_value)
             }
-            Expr::Index { array, index } => {
-                let array_val = self.evaluate(array)?;
-                let index_val = self.evaluate(index)?;
-                
-                match (array_val, index_val) {
-                    (Value::Array(arr), Value::Int(idx)) => {
-                        if idx < 0 || idx as usize >= arr.len() {
-                            return Err(TogError::RuntimeError(
-                                format!("Array index {} out of bounds (length: {})", idx, arr.len()),
-                                None
-                            ));
+            ExprKind::Lambda { params, return_type: _, body } => {
+                Ok(Value::Function {
+                    name: "<lambda>".to_string(),
+                    params: params.clone(),
+                    body: Rc::new(*body.clone()),
+                    closure: Rc::clone(&self.environment), // Captured by reference
+                    bound_self: None,
+                })
+            }
+            ExprKind::InterpolatedString(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    match part {
+                        StringPart::Literal(text) => result.push_str(text),
+                        StringPart::Expr { expr, format } => {
+                            let value = self.evaluate(expr)?;
+                            match format {
+                                Some(spec) => result.push_str(&format_value(&value, spec)?),
+                                None => result.push_str(&value_to_string(&value)),
+                            }
                         }
-                        Ok(arr[idx as usize].clone())
                     }
-                    (Value::String(s), Value::Int(idx)) => {
-                        if idx < 0 || idx as usize >= s.len() {
-                            return Err(TogError::RuntimeError(
-                                format!("String index {} out of bounds (length: {})", idx, s.len()),
-                                None
-                            ));
-                        }
-                        Ok(Value::String(s.chars().nth(idx as usize).unwrap().to_string()))
+                }
+                Ok(Value::String(result))
+            }
+            ExprKind::Try(operand) => {
+                let value = self.evaluate(operand)?;
+                let Value::Enum { variant_name, data, .. } = &value else {
+                    return Err(TogError::RuntimeError(
+                        format!("The `?` operator needs a Result or Option, got {}", value_to_string(&value)),
+                        None
+                    ));
+                };
+                match variant_name.as_str() {
+                    "Ok" | "Some" => Ok(data.as_deref().cloned().unwrap_or(Value::None)),
+                    "Err" | "None" if self.call_stack.is_empty() => Err(TogError::RuntimeError(
+                        "The `?` operator can only be used inside a function".to_string(),
+                        None
+                    )),
+                    "Err" | "None" => {
+                        // Unwinds to the statement around it, which returns `value`
+                        self.early_return = Some(value);
+                        Err(TogError::RuntimeError("`?` returned from the function".to_string(), None))
                     }
-                    (arr, idx) => Err(TogError::RuntimeError(
-                        format!("Cannot index {:?} with {:?}", arr, idx),
+                    _ => Err(TogError::RuntimeError(
+                        format!("The `?` operator needs a Result or Option, got {}", value_to_string(&value)),
                         None
-                    ))
+                    )),
                 }
             }
+            ExprKind::Index { array, index } => {
+                let array_val = self.evaluate(array)?;
+                let index_val = self.evaluate(index)?;
+                index_of(&array_val, &index_val).map(Cow::into_owned)
+            }
         }
     }
 
-    fn set_struct_field(struct_val: Value, field: &str, new_val: Value) -> Result<Value, TogError> {
-        if let Value::Struct { name, mut fields } = struct_val {
-            fields.insert(field.to_string(), new_val);
-            Ok(Value::Struct { name, fields })
-        } else {
-            Err(TogError::RuntimeError(
-                format!("Cannot assign field '{}' to non-struct value", field),
-                None,
-            ))
+    fn is_type_name(&self, name: &str) -> bool {
+        self.struct_defs.contains_key(name)
+            || self.enum_defs.contains_key(name)
+            || self.inherent_impls.contains_key(name)
+    }
+
+    /// Calls an associated function of a builtin type, such as `Map::new()`,
+    /// unless the program declares a type of that name.
+    fn call_builtin_associated(&mut self, type_name: &str, name: &str, args: &[Value]) -> Result<Option<Value>, TogError> {
+        if self.is_type_name(type_name) {
+            return Ok(None);
+        }
+        match crate::stdlib::call_builtin(self, &format!("{}::{}", type_name, name), args) {
+            Ok(result) => Ok(Some(result)),
+            Err(TogError::RuntimeError(ref msg, _)) if msg.contains("Unknown builtin") => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_enum_variant(&self, enum_name: &str, variant_name: &str) -> bool {
+        self.enum_defs.get(enum_name)
+            .is_some_and(|variants| variants.iter().any(|v| v.name == variant_name))
+    }
+
+    /// Resolves `method_name` on `type_name`. Methods written inline in a struct
+    /// body win, then inherent `impl Type` blocks, then `impl Trait for Type`
+    /// blocks. Two traits providing the same name is an ambiguity error.
+    fn find_method(&self, type_name: &str, method_name: &str) -> Result<Option<MethodDecl>, TogError> {
+        if let Some((_, methods)) = self.struct_defs.get(type_name) {
+            if let Some(method) = methods.iter().find(|m| m.name == method_name) {
+                return Ok(Some(method.clone()));
+            }
+        }
+        if let Some(methods) = self.inherent_impls.get(type_name) {
+            if let Some(method) = methods.iter().find(|m| m.name == method_name) {
+                return Ok(Some(method.clone()));
+            }
+        }
+
+        let mut candidates: Vec<(&String, &MethodDecl)> = self.trait_impls.iter()
+            .filter(|((impl_type, _), _)| impl_type == type_name)
+            .filter_map(|((_, trait_name), methods)| {
+                methods.iter().find(|m| m.name == method_name).map(|m| (trait_name, m))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.cmp(b.0));
+
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [(_, method)] => Ok(Some((*method).clone())),
+            _ => {
+                let traits: Vec<&str> = candidates.iter().map(|(t, _)| t.as_str()).collect();
+                Err(TogError::RuntimeError(
+                    format!(
+                        "Ambiguous method '{}' on {}: provided by traits {}",
+                        method_name, type_name, traits.join(", ")
+                    ),
+                    None
+                ))
+            }
         }
     }
 
+    /// Calls a method on the value of `object`. If the method takes `&mut self`
+    /// and `object` is a place (a variable, or a field or index of one), the
+    /// method's final `self` is assigned back to it.
+    fn call_method_on(
+        &mut self,
+        type_name: &str,
+        method: &MethodDecl,
+        object: &Expr,
+        receiver: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, TogError> {
+        let (result, receiver) = self.invoke_method(type_name, method, Some(receiver), args)?;
+        if let Some(receiver) = receiver {
+            if is_place(object) {
+                self.assign_value_into(object, receiver)?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn call_method(
+        &mut self,
+        type_name: &str,
+        method: &MethodDecl,
+        receiver: Option<Value>,
+        args: Vec<Value>,
+    ) -> Result<Value, TogError> {
+        self.invoke_method(type_name, method, receiver, args).map(|(result, _)| result)
+    }
+
+    /// Invokes a method, returning its result and, for a `&mut self` method,
+    /// its final `self`. A leading `self` parameter is bound to `receiver`, or
+    /// to the first argument when called through the type (`Type::method(obj)`),
+    /// which a `&mut self` method doesn't allow: there is no place to write
+    /// its changes back to.
+    fn invoke_method(
+        &mut self,
+        type_name: &str,
+        method: &MethodDecl,
+        receiver: Option<Value>,
+        args: Vec<Value>,
+    ) -> Result<(Value, Option<Value>), TogError> {
+        let takes_self = method.params.first().is_some_and(|p| p.name == "self");
+        let by_ref = method.params.first().is_some_and(|p| p.by_ref);
+        let mut args = args.into_iter();
+        let self_val = match (takes_self, receiver) {
+            (true, Some(recv)) => Some(recv),
+            (true, None) if by_ref => return Err(mut_self_without_receiver(type_name, &method.name)),
+            (true, None) => Some(args.next().ok_or_else(|| TogError::RuntimeError(
+                format!("Method '{}' on {} needs a receiver", method.name, type_name),
+                None
+            ))?),
+            (false, _) => None,
+        };
+        let params = if takes_self { &method.params[1..] } else { &method.params[..] };
+        let args: Vec<Value> = args.collect();
+        if args.len() != params.len() {
+            return Err(TogError::RuntimeError(
+                format!("Method '{}' on {} expects {} arguments, got {}", method.name, type_name, params.len(), args.len()),
+                None
+            ));
+        }
+
+        let old_env = Rc::clone(&self.environment);
+        // Methods see globals, not the caller's locals
+        self.environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&self.globals)))));
+        if let Some(self_val) = self_val {
+            self.environment.borrow_mut().define("self".to_string(), self_val);
+        }
+        for (param, arg_value) in params.iter().zip(args) {
+            self.environment.borrow_mut().define(param.name.clone(), arg_value);
+        }
+        let result = self.with_frame(method.name.clone(), Some(type_name.to_string()), |interp| interp.call_body(&method.body));
+        let final_self = if by_ref { self.environment.borrow().get("self").ok() } else { None };
+        self.environment = old_env;
+        Ok((result?, final_self))
+    }
+
     fn assign_value_into(&mut self, target: &Expr, replacement: Value) -> Result<(), TogError> {
-        match target {
-            Expr::Variable(name) => {
+        match &target.kind {
+            ExprKind::Variable(name) => {
                 self.environment.borrow_mut().assign(name, replacement)?;
                 Ok(())
             }
-            Expr::FieldAccess { object, field } => {
-                let parent_val = self.evaluate(object)?;
-                let updated_parent = Self::set_struct_field(parent_val, field, replacement)?;
-                self.assign_value_into(object, updated_parent)
+            ExprKind::FieldAccess { object, field } => {
+                let mut parent_val = self.evaluate(object)?;
+                set_field(&mut parent_val, field, replacement)?;
+                self.assign_value_into(object, parent_val)
+            }
+            ExprKind::Index { array, index } => {
+                let index_val = self.evaluate(index)?;
+                let mut parent_val = self.evaluate(array)?;
+                set_index(&mut parent_val, index_val, replacement)?;
+                self.assign_value_into(array, parent_val)
             }
             _ => Err(TogError::RuntimeError(
                 "Invalid assignment target".to_string(),
@@ -631,124 +1069,617 @@ impl Interpreter {
         }
     }
 
-    fn assign_field_chain(&mut self, target: &Expr, field: &str, new_value: Value) -> Result<(), TogError> {
-        let obj_val = self.evaluate(target)?;
-        let updated_obj = Self::set_struct_field(obj_val, field, new_value)?;
-        self.assign_value_into(target, updated_obj)
-    }
-    
-    fn match_pattern(&self, pattern: &Pattern, value: &Value) -> Result<bool, TogError> {
-        match (pattern, value) {
-            (Pattern::Wildcard, _) => Ok(true),
-            (Pattern::Literal(lit), val) => {
-                let lit_val = literal_to_value(lit);
-                Ok(lit_val == *val)
+    /// The variable an assignment target such as `team.players[i]` starts
+    /// from and the steps after it, evaluating each index once, from the
+    /// root outwards.
+    fn place(&mut self, target: &Expr) -> Result<(String, Vec<PlaceStep>), TogError> {
+        match &target.kind {
+            ExprKind::Variable(name) => Ok((name.clone(), Vec::new())),
+            ExprKind::FieldAccess { object, field } => {
+                let (root, mut steps) = self.place(object)?;
+                steps.push(PlaceStep::Field(field.clone()));
+                Ok((root, steps))
+            }
+            ExprKind::Index { array, index } => {
+                let (root, mut steps) = self.place(array)?;
+                steps.push(PlaceStep::Index(self.evaluate(index)?));
+                Ok((root, steps))
             }
-            (Pattern::Variable(_), _) => Ok(true), // Always match variables
-            (Pattern::EnumVariant { enum_name, variant_name, .. }, Value::Enum { enum_name: val_enum, variant_name: val_variant, .. }) => {
-                // Match if enum name and variant name match
-                Ok(enum_name == val_enum && variant_name == val_variant)
+            _ => Err(TogError::RuntimeError(
+                "Invalid assignment target".to_string(),
+                None,
+            )),
+        }
+    }
+
+    /// Assigns `value` at the end of `steps` from the variable `root`. For
+    /// `op=` the value is combined with the one already there.
+    fn store_place(&mut self, root: &str, steps: &[PlaceStep], op: Option<BinaryOp>, value: Value) -> Result<(), TogError> {
+        let mut root_val = self.environment.borrow().get(root)?;
+        let (last, init) = steps.split_last().expect("place step");
+        let mut current = &mut root_val;
+        for step in init {
+            current = match step {
+                PlaceStep::Field(field) => field_mut(current, field)?,
+                PlaceStep::Index(index) => index_mut(current, index)?,
+            };
+        }
+        let value = match op {
+            Some(op) => {
+                let old = match last {
+                    PlaceStep::Field(field) => Cow::Borrowed(field_of(current, field)?),
+                    PlaceStep::Index(index) => index_of(current, index)?,
+                };
+                evaluate_binary_op(&old, op, &value)?
             }
-            (Pattern::EnumVariant { .. }, _) => Ok(false), // Enum pattern doesn't match non-enum value
+            None => value,
+        };
+        match last {
+            PlaceStep::Field(field) => set_field(current, field, value)?,
+            PlaceStep::Index(index) => set_index(current, index.clone(), value)?,
         }
+        self.environment.borrow_mut().assign(root, root_val)
     }
     
-    fn evaluate_binary_op(&self, left: &Value, op: BinaryOp, right: &Value) -> Result<Value, TogError> {
-        match (left, op, right) {
-            // Arithmetic
-            (Value::Int(a), BinaryOp::Add, Value::Int(b)) => Ok(Value::Int(a + b)),
-            (Value::Int(a), BinaryOp::Sub, Value::Int(b)) => Ok(Value::Int(a - b)),
-            (Value::Int(a), BinaryOp::Mul, Value::Int(b)) => Ok(Value::Int(a * b)),
-            (Value::Int(a), BinaryOp::Div, Value::Int(b)) => {
-                if *b == 0 {
-                    Err(TogError::RuntimeError("Division by zero".to_string(), None))
+    /// Runs `f` in a new scope holding `bindings`, so the variables of a
+    /// block, loop body or match arm don't outlive it. Each loop iteration
+    /// gets its own scope, which closures made in it keep.
+    fn with_scope<T>(
+        &mut self,
+        bindings: Vec<(String, Value)>,
+        f: impl FnOnce(&mut Self) -> Result<T, TogError>,
+    ) -> Result<T, TogError> {
+        let mut scope = Environment::new(Some(Rc::clone(&self.environment)));
+        for (name, value) in bindings {
+            scope.define(name, value);
+        }
+        let enclosing = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(scope)));
+        let result = f(self);
+        self.environment = enclosing;
+        result
+    }
+}
+
+impl FunctionCaller for Interpreter {
+    fn stack_trace(&self) -> String {
+        format_trace(&self.call_stack, self.call_site).trim_end().to_string()
+    }
+
+    fn share_function(&self, func: &Value) -> Result<SharedFunction, TogError> {
+        // Functions are keyed by their body so recursion and functions reached
+        // twice are only shared once. The queue is first-in first-out, so a
+        // function's index is its position in `functions`.
+        let mut indices: HashMap<*const Expr, usize> = HashMap::new();
+        let mut pending = VecDeque::from([func.clone()]);
+        let mut functions: Vec<SharedClosure> = Vec::new();
+        indices.insert(shared_key(func)?, 0);
+
+        while let Some(value) = pending.pop_front() {
+            let Value::Function { name, params, body, closure, bound_self } = &value else {
+                unreachable!("only function values are queued");
+            };
+            let captures = captured_variables(params, body);
+            if let Some(var) = captures.assigned.iter().next() {
+                return Err(TogError::RuntimeError(
+                    format!("Function '{}' cannot run in parallel: it assigns to captured variable '{}'", name, var),
+                    None
+                ));
+            }
+
+            let mut shared = SharedClosure {
+                name: name.clone(),
+                params: params.clone(),
+                body: (**body).clone(),
+                captures: Vec::new(),
+                captured_functions: Vec::new(),
+            };
+            for var in &captures.read {
+                let captured = match (var.as_str(), bound_self) {
+                    ("self", Some(self_val)) => (**self_val).clone(),
+                    // Builtins, type names and undefined names are resolved on the worker
+                    _ => match closure.borrow().get(var) {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    },
+                };
+                if let Value::Function { .. } = captured {
+                    let key = shared_key(&captured)?;
+                    let next = indices.len();
+                    let index = *indices.entry(key).or_insert_with(|| {
+                        pending.push_back(captured.clone());
+                        next
+                    });
+                    shared.captured_functions.push((var.clone(), index));
                 } else {
-                    Ok(Value::Int(a / b))
+                    shared.captures.push((var.clone(), SharedValue::from_value(&captured)?));
                 }
             }
-            (Value::Int(a), BinaryOp::Mod, Value::Int(b)) => Ok(Value::Int(a % b)),
-            
-            (Value::Float(a), BinaryOp::Add, Value::Float(b)) => Ok(Value::Float(a + b)),
-            (Value::Float(a), BinaryOp::Sub, Value::Float(b)) => Ok(Value::Float(a - b)),
-            (Value::Float(a), BinaryOp::Mul, Value::Float(b)) => Ok(Value::Float(a * b)),
-            (Value::Float(a), BinaryOp::Div, Value::Float(b)) => {
-                if *b == 0.0 {
-                    Err(TogError::RuntimeError("Division by zero".to_string(), None))
-                } else {
-                    Ok(Value::Float(a / b))
-                }
-            }
-            
-            // String concatenation (auto-convert numbers to strings)
-            (Value::String(a), BinaryOp::Add, Value::String(b)) => {
-                Ok(Value::String(format!("{}{}", a, b)))
-            }
-            (Value::String(a), BinaryOp::Add, Value::Int(b)) => {
-                Ok(Value::String(format!("{}{}", a, b)))
-            }
-            (Value::String(a), BinaryOp::Add, Value::Float(b)) => {
-                Ok(Value::String(format!("{}{}", a, b)))
-            }
-            (Value::Int(a), BinaryOp::Add, Value::String(b)) => {
-                Ok(Value::String(format!("{}{}", a, b)))
-            }
-            (Value::Float(a), BinaryOp::Add, Value::String(b)) => {
-                Ok(Value::String(format!("{}{}", a, b)))
-            }
-            
-            // Comparison
-            (Value::Int(a), BinaryOp::Eq, Value::Int(b)) => Ok(Value::Bool(a == b)),
-            (Value::Int(a), BinaryOp::Ne, Value::Int(b)) => Ok(Value::Bool(a != b)),
-            (Value::Int(a), BinaryOp::Lt, Value::Int(b)) => Ok(Value::Bool(a < b)),
-            (Value::Int(a), BinaryOp::Le, Value::Int(b)) => Ok(Value::Bool(a <= b)),
-            (Value::Int(a), BinaryOp::Gt, Value::Int(b)) => Ok(Value::Bool(a > b)),
-            (Value::Int(a), BinaryOp::Ge, Value::Int(b)) => Ok(Value::Bool(a >= b)),
-            
-            (Value::Bool(a), BinaryOp::And, Value::Bool(b)) => Ok(Value::Bool(*a && *b)),
-            (Value::Bool(a), BinaryOp::Or, Value::Bool(b)) => Ok(Value::Bool(*a || *b)),
-            
+            functions.push(shared);
+        }
+
+        Ok(SharedFunction {
+            definitions: Definitions {
+                struct_defs: self.struct_defs.clone(),
+                enum_defs: self.enum_defs.clone(),
+                trait_defs: self.trait_defs.clone(),
+                trait_impls: self.trait_impls.clone(),
+                inherent_impls: self.inherent_impls.clone(),
+            },
+            functions,
+        })
+    }
+
+
+    fn call_function(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, TogError> {
+        match func {
+            Value::Function { name, params, body, closure, bound_self, .. } => {
+                if args.len() != params.len() {
+                    return Err(TogError::RuntimeError(
+                        format!("Function expects {} arguments, got {}", params.len(), args.len()),
+                        None
+                    ));
+                }
+                
+                let old_env = Rc::clone(&self.environment);
+                // The new environment encloses the function's definition environment (closure).
+                self.environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(closure)))));
+
+                // If a method is bound, add 'self' to the new scope
+                if let Some(self_val) = bound_self {
+                    self.environment.borrow_mut().define("self".to_string(), (**self_val).clone());
+                }
+                
+                // Bind arguments to parameters in the new scope
+                for (param, arg_val) in params.iter().zip(args) {
+                    self.environment.borrow_mut().define(param.name.clone(), arg_val);
+                }
+
+                let result = self.with_frame(name.clone(), None, |interp| interp.call_body(body));
+
+                self.environment = old_env;
+                result
+            }
             _ => Err(TogError::TypeError(
-                format!("Invalid operation: {:?} {:?} {:?}", left, op, right),
+                "Can only call functions".to_string(),
                 None
             ))
         }
     }
-    
-    fn evaluate_unary_op(&self, op: UnaryOp, value: &Value) -> Result<Value, TogError> {
-        match (op, value) {
-            (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
-            (UnaryOp::Neg, Value::Int(n)) => Ok(Value::Int(-n)),
-            (UnaryOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
-            _ => Err(TogError::TypeError(
-                format!("Invalid unary operation: {:?} {:?}", op, value),
+}
+
+fn shared_key(func: &Value) -> Result<*const Expr, TogError> {
+    match func {
+        Value::Function { body, .. } => Ok(Rc::as_ptr(body)),
+        _ => Err(TogError::TypeError(
+            "Can only call functions".to_string(),
+            None
+        )),
+    }
+}
+
+/// Whether a `&mut self` method called on `expr` can write back to it: a
+/// variable, or a chain of fields and indexes rooted at one.
+fn is_place(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Variable(_) => true,
+        ExprKind::FieldAccess { object, .. } => is_place(object),
+        ExprKind::Index { array, .. } => is_place(array),
+        _ => false,
+    }
+}
+
+/// `Type::method(value)` on a `&mut self` method, which would change a copy.
+pub(crate) fn mut_self_without_receiver(type_name: &str, method: &str) -> TogError {
+    TogError::RuntimeError(
+        format!("Method '{}' on {} takes `&mut self` and must be called on a value", method, type_name),
+        None
+    ).with_help(format!("call it as `value.{}(...)`", method))
+}
+
+/// Reads `value.field`: a struct field, or a tuple element such as `pair.0`.
+pub(crate) fn field_of<'a>(value: &'a Value, field: &str) -> Result<&'a Value, TogError> {
+    match value {
+        Value::Struct { fields, .. } => {
+            fields.get(field).ok_or_else(|| {
+                let error = TogError::RuntimeError(format!("Field '{}' not found", field), None);
+                match did_you_mean(field, fields.keys().map(String::as_str)) {
+                    Some(help) => error.with_help(help),
+                    None => error,
+                }
+            })
+        }
+        Value::Tuple(elements) => {
+            let index = field.parse::<usize>().map_err(|_| TogError::RuntimeError(
+                format!("Tuple {} has no field '{}'", tuple_to_string(elements), field),
+                None
+            ).with_help("tuple elements are read by position: `pair.0`, `pair.1`"))?;
+            elements.get(index).ok_or_else(|| TogError::RuntimeError(
+                format!("Tuple index {} out of bounds for a tuple of {} elements", index, elements.len()),
                 None
             ))
         }
+        _ => Err(TogError::RuntimeError(
+            "Field access on non-struct value".to_string(),
+            None
+        ))
+    }
+}
+
+/// Reads `container[index]`. Array and map elements are borrowed; indexing
+/// a string makes a new one-character string.
+pub(crate) fn index_of<'a>(container: &'a Value, index: &Value) -> Result<Cow<'a, Value>, TogError> {
+    match (container, index) {
+        (Value::Array(arr), Value::Int(idx)) => {
+            if *idx < 0 || *idx as usize >= arr.len() {
+                return Err(TogError::RuntimeError(
+                    format!("Array index {} out of bounds (length: {})", idx, arr.len()),
+                    None
+                ));
+            }
+            Ok(Cow::Borrowed(&arr[*idx as usize]))
+        }
+        (Value::String(s), Value::Int(idx)) => {
+            if *idx < 0 || *idx as usize >= s.len() {
+                return Err(TogError::RuntimeError(
+                    format!("String index {} out of bounds (length: {})", idx, s.len()),
+                    None
+                ));
+            }
+            Ok(Cow::Owned(Value::String(s.chars().nth(*idx as usize).unwrap().to_string())))
+        }
+        (Value::Map(map), key) => {
+            let shown = match key {
+                Value::String(s) => format!("{:?}", s),
+                other => value_to_string(other),
+            };
+            map.get(&MapKey::from_value(key)?).map(Cow::Borrowed).ok_or_else(|| TogError::RuntimeError(
+                format!("Key {} not found in map", shown),
+                None
+            ).with_help("use `get(map, key)` for an Option, or `has(map, key)` to check first"))
+        }
+        (arr, idx) => Err(TogError::RuntimeError(
+            format!("Cannot index {:?} with {:?}", arr, idx),
+            None
+        ))
+    }
+}
+
+/// A step of an assignment target after its root variable, with its index
+/// already evaluated.
+enum PlaceStep {
+    Field(String),
+    Index(Value),
+}
+
+/// `target.field = new_val` on a struct value.
+pub(crate) fn set_field(target: &mut Value, field: &str, new_val: Value) -> Result<(), TogError> {
+    if let Value::Struct { fields, .. } = target {
+        fields.insert(field.to_string(), new_val);
+        Ok(())
+    } else {
+        Err(TogError::RuntimeError(
+            format!("Cannot assign field '{}' to non-struct value", field),
+            None,
+        ))
+    }
+}
+
+/// `target[index] = new_val` on a map or array value.
+pub(crate) fn set_index(target: &mut Value, index: Value, new_val: Value) -> Result<(), TogError> {
+    match (target, index) {
+        (Value::Map(map), key) => {
+            map.insert(MapKey::from_value(&key)?, new_val);
+            Ok(())
+        }
+        (Value::Array(arr), Value::Int(idx)) => {
+            if idx < 0 || idx as usize >= arr.len() {
+                return Err(TogError::RuntimeError(
+                    format!("Array index {} out of bounds (length: {})", idx, arr.len()),
+                    None
+                ));
+            }
+            arr[idx as usize] = new_val;
+            Ok(())
+        }
+        (container, index) => Err(TogError::RuntimeError(
+            format!("Cannot assign to index {} of {}", value_to_string(&index), value_to_string(container)),
+            None,
+        )),
+    }
+}
+
+/// The mutable counterpart of `field_of`, failing the same way.
+pub(crate) fn field_mut<'a>(value: &'a mut Value, field: &str) -> Result<&'a mut Value, TogError> {
+    field_of(value, field)?;
+    match value {
+        Value::Struct { fields, .. } => Ok(fields.get_mut(field).expect("checked field")),
+        Value::Tuple(elements) => Ok(&mut elements[field.parse::<usize>().expect("checked index")]),
+        _ => unreachable!("field_of accepted a non-aggregate"),
+    }
+}
+
+/// The mutable counterpart of `index_of`. Characters of a string can be read
+/// but not assigned through.
+pub(crate) fn index_mut<'a>(container: &'a mut Value, index: &Value) -> Result<&'a mut Value, TogError> {
+    index_of(container, index)?;
+    match (container, index) {
+        (Value::Array(items), Value::Int(i)) => Ok(&mut items[*i as usize]),
+        (Value::Map(map), key) => Ok(map.get_mut(&MapKey::from_value(key)?).expect("checked key")),
+        (container, index) => Err(TogError::RuntimeError(
+            format!("Cannot assign to index {} of {}", value_to_string(index), value_to_string(container)),
+            None,
+        )),
+    }
+}
+
+/// The items a `for` loop visits: array elements, the characters of a
+/// string, `(key, value)` tuples of a map, or the elements of a set.
+pub(crate) fn iteration_items(iterable: Value) -> Result<Vec<Value>, TogError> {
+    Ok(match iterable {
+        Value::Array(arr) => arr,
+        Value::String(s) => s.chars().map(|c| Value::String(c.to_string())).collect(),
+        // Maps give (key, value) tuples
+        Value::Map(map) => map.into_iter().map(|(k, v)| Value::Tuple(vec![k.to_value(), v])).collect(),
+        Value::Set(set) => set.iter().map(MapKey::to_value).collect(),
+        _ => return Err(TogError::TypeError("Expected iterable in for loop".to_string(), None)),
+    })
+}
+
+/// Whether `value` matches `pattern`, adding the variables it binds to
+/// `bindings`.
+pub(crate) fn match_pattern(pattern: &Pattern, value: &Value, bindings: &mut Vec<(String, Value)>) -> Result<bool, TogError> {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Ok(true),
+        (Pattern::Literal(lit), val) => {
+            let lit_val = literal_to_value(lit);
+            Ok(lit_val == *val)
+        }
+        (Pattern::Variable(name), val) => {
+            bindings.push((name.clone(), val.clone()));
+            Ok(true)
+        }
+        (Pattern::EnumVariant { enum_name, variant_name, inner }, Value::Enum { enum_name: val_enum, variant_name: val_variant, data }) => {
+            // A bare variant name (empty enum name) matches any enum's variant of that name
+            if (!enum_name.is_empty() && enum_name != val_enum) || variant_name != val_variant {
+                return Ok(false);
+            }
+            match (inner, data) {
+                (None, _) => Ok(true),
+                (Some(inner), Some(data)) => match_pattern(inner, data, bindings),
+                (Some(_), None) => Ok(false),
+            }
+        }
+        (Pattern::EnumVariant { .. }, _) => Ok(false), // Enum pattern doesn't match non-enum value
+        (Pattern::Struct { name, fields }, Value::Struct { name: val_name, fields: val_fields }) => {
+            if name != val_name {
+                return Ok(false);
+            }
+            for (field, field_pattern) in fields {
+                let Some(field_val) = val_fields.get(field) else {
+                    let error = TogError::RuntimeError(format!("Struct {} has no field '{}'", name, field), None);
+                    return Err(match did_you_mean(field, val_fields.keys().map(String::as_str)) {
+                        Some(help) => error.with_help(help),
+                        None => error,
+                    });
+                };
+                if !match_pattern(field_pattern, field_val, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        (Pattern::Struct { .. }, _) => Ok(false),
+        (Pattern::Array(elements), Value::Array(items)) => {
+            let Some(rest) = elements.iter().position(|p| matches!(p, Pattern::Rest(_))) else {
+                if elements.len() != items.len() {
+                    return Ok(false);
+                }
+                return match_all(elements, items, bindings);
+            };
+            // `[a, ..rest, z]`: the elements around the rest match the ends of the array
+            let after = elements.len() - rest - 1;
+            if items.len() < rest + after {
+                return Ok(false);
+            }
+            let rest_end = items.len() - after;
+            if !match_all(&elements[..rest], &items[..rest], bindings)?
+                || !match_all(&elements[rest + 1..], &items[rest_end..], bindings)? {
+                return Ok(false);
+            }
+            if let Pattern::Rest(Some(name)) = &elements[rest] {
+                bindings.push((name.clone(), Value::Array(items[rest..rest_end].to_vec())));
+            }
+            Ok(true)
+        }
+        (Pattern::Array(_), _) => Ok(false),
+        (Pattern::Tuple(elements), Value::Tuple(items)) => {
+            if elements.len() != items.len() {
+                return Ok(false);
+            }
+            match_all(elements, items, bindings)
+        }
+        (Pattern::Tuple(_), _) => Ok(false),
+        (Pattern::Rest(_), _) => Err(TogError::RuntimeError(
+            "'..' can only be used inside an array pattern".to_string(),
+            None
+        )),
+        (Pattern::Or(alternatives), val) => {
+            for alternative in alternatives {
+                let mut alternative_bindings = Vec::new();
+                if match_pattern(alternative, val, &mut alternative_bindings)? {
+                    bindings.extend(alternative_bindings);
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        (Pattern::Range { start, end, inclusive }, val) => {
+            // Ints compare exactly; anything involving a float compares as floats
+            Ok(match (val, literal_to_value(start), literal_to_value(end)) {
+                (Value::Int(v), Value::Int(start), Value::Int(end)) => {
+                    *v >= start && if *inclusive { *v <= end } else { *v < end }
+                }
+                (val, start, end) => match (as_float(val), as_float(&start), as_float(&end)) {
+                    (Some(v), Some(start), Some(end)) => v >= start && if *inclusive { v <= end } else { v < end },
+                    _ => false,
+                },
+            })
+        }
+    }
+}
+
+fn match_all(patterns: &[Pattern], values: &[Value], bindings: &mut Vec<(String, Value)>) -> Result<bool, TogError> {
+    for (pattern, value) in patterns.iter().zip(values) {
+        if !match_pattern(pattern, value, bindings)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn integer_overflow() -> TogError {
+    TogError::RuntimeError("Integer overflow".to_string(), None)
+}
+
+pub(crate) fn evaluate_binary_op(left: &Value, op: BinaryOp, right: &Value) -> Result<Value, TogError> {
+    match (left, op, right) {
+        // Arithmetic
+        (Value::Int(a), BinaryOp::Add, Value::Int(b)) => a.checked_add(*b).map(Value::Int).ok_or_else(integer_overflow),
+        (Value::Int(a), BinaryOp::Sub, Value::Int(b)) => a.checked_sub(*b).map(Value::Int).ok_or_else(integer_overflow),
+        (Value::Int(a), BinaryOp::Mul, Value::Int(b)) => a.checked_mul(*b).map(Value::Int).ok_or_else(integer_overflow),
+        (Value::Int(a), BinaryOp::Div, Value::Int(b)) => {
+            if *b == 0 {
+                Err(TogError::RuntimeError("Division by zero".to_string(), None))
+            } else {
+                Ok(Value::Int(a / b))
+            }
+        }
+        (Value::Int(a), BinaryOp::Mod, Value::Int(b)) => Ok(Value::Int(a % b)),
+        
+        (Value::Float(a), BinaryOp::Add, Value::Float(b)) => Ok(Value::Float(a + b)),
+        (Value::Float(a), BinaryOp::Sub, Value::Float(b)) => Ok(Value::Float(a - b)),
+        (Value::Float(a), BinaryOp::Mul, Value::Float(b)) => Ok(Value::Float(a * b)),
+        (Value::Float(a), BinaryOp::Div, Value::Float(b)) => {
+            if *b == 0.0 {
+                Err(TogError::RuntimeError("Division by zero".to_string(), None))
+            } else {
+                Ok(Value::Float(a / b))
+            }
+        }
+        
+        // String concatenation (auto-convert numbers to strings)
+        (Value::String(a), BinaryOp::Add, Value::String(b)) => {
+            Ok(Value::String(format!("{}{}", a, b)))
+        }
+        (Value::String(a), BinaryOp::Add, Value::Int(b)) => {
+            Ok(Value::String(format!("{}{}", a, b)))
+        }
+        (Value::String(a), BinaryOp::Add, Value::Float(b)) => {
+            Ok(Value::String(format!("{}{}", a, b)))
+        }
+        (Value::Int(a), BinaryOp::Add, Value::String(b)) => {
+            Ok(Value::String(format!("{}{}", a, b)))
+        }
+        (Value::Float(a), BinaryOp::Add, Value::String(b)) => {
+            Ok(Value::String(format!("{}{}", a, b)))
+        }
+        
+        // Comparison
+        (Value::Int(a), BinaryOp::Eq, Value::Int(b)) => Ok(Value::Bool(a == b)),
+        (Value::Int(a), BinaryOp::Ne, Value::Int(b)) => Ok(Value::Bool(a != b)),
+        (Value::Int(a), BinaryOp::Lt, Value::Int(b)) => Ok(Value::Bool(a < b)),
+        (Value::Int(a), BinaryOp::Le, Value::Int(b)) => Ok(Value::Bool(a <= b)),
+        (Value::Int(a), BinaryOp::Gt, Value::Int(b)) => Ok(Value::Bool(a > b)),
+        (Value::Int(a), BinaryOp::Ge, Value::Int(b)) => Ok(Value::Bool(a >= b)),
+        
+        (Value::Bool(a), BinaryOp::And, Value::Bool(b)) => Ok(Value::Bool(*a && *b)),
+        (Value::Bool(a), BinaryOp::Or, Value::Bool(b)) => Ok(Value::Bool(*a || *b)),
+        
+        _ => Err(TogError::TypeError(
+            format!("Invalid operation: {:?} {:?} {:?}", left, op, right),
+            None
+        ))
+    }
+}
+
+pub(crate) fn evaluate_unary_op(op: UnaryOp, value: &Value) -> Result<Value, TogError> {
+    match (op, value) {
+        (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (UnaryOp::Neg, Value::Int(n)) => Ok(Value::Int(-n)),
+        (UnaryOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+        _ => Err(TogError::TypeError(
+            format!("Invalid unary operation: {:?} {:?}", op, value),
+            None
+        ))
     }
 }
 
-fn literal_to_value(lit: &Literal) -> Value {
+pub(crate) fn literal_to_value(lit: &Literal) -> Value {
     match lit {
         Literal::Int(n) => Value::Int(*n),
         Literal::Float(n) => Value::Float(*n),
         Literal::String(s) => Value::String(s.clone()),
         Literal::Bool(b) => Value::Bool(*b),
-        Literal::Array(_) => {
-            // Arrays are handled in evaluate() directly
-            unreachable!("Arrays should be handled in evaluate()")
+        Literal::Array(_) | Literal::Tuple(_) | Literal::Map(_) => {
+            // Collections are handled in evaluate() directly
+            unreachable!("Collections should be handled in evaluate()")
         }
         Literal::None => Value::None,
     }
 }
 
-fn is_truthy(value: &Value) -> bool {
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_truthy(value: &Value) -> bool {
     match value {
         Value::Bool(false) | Value::None => false,
         _ => true,
     }
 }
 
-fn value_to_string(value: &Value) -> String {
+/// Formats a value for `{expr:spec}` in an interpolated string.
+pub(crate) fn format_value(value: &Value, spec: &FormatSpec) -> Result<String, TogError> {
+    let text = match (value, spec.precision) {
+        (Value::Float(f), Some(p)) => format!("{:.*}", p, f),
+        (Value::Int(i), Some(p)) => format!("{:.*}", p, *i as f64),
+        (Value::String(s), Some(p)) => s.chars().take(p).collect(),
+        (_, Some(_)) => {
+            return Err(TogError::RuntimeError(
+                format!("Precision in a format spec needs a number or string, got {}", value_to_string(value)),
+                None
+            ));
+        }
+        (_, None) => value_to_string(value),
+    };
+
+    let padding = spec.width.unwrap_or(0).saturating_sub(text.chars().count());
+    let numeric = matches!(value, Value::Int(_) | Value::Float(_));
+    if spec.zero {
+        if !numeric {
+            return Err(TogError::RuntimeError(
+                format!("The '0' flag in a format spec needs a number, got {}", value_to_string(value)),
+                None
+            ));
+        }
+        let (sign, digits) = text.split_at(if text.starts_with('-') { 1 } else { 0 });
+        return Ok(format!("{}{}{}", sign, "0".repeat(padding), digits));
+    }
+    let (left, right) = match spec.align.unwrap_or(if numeric { Align::Right } else { Align::Left }) {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    let fill = |n: usize| spec.fill.to_string().repeat(n);
+    Ok(format!("{}{}{}", fill(left), text, fill(right)))
+}
+
+pub(crate) fn value_to_string(value: &Value) -> String {
     match value {
         Value::Int(i) => i.to_string(),
         Value::Float(f) => f.to_string(),
@@ -758,6 +1689,17 @@ fn value_to_string(value: &Value) -> String {
             let elems: Vec<String> = arr.iter().map(value_to_string).collect();
             format!("[{}]", elems.join(", "))
         }
+        Value::Tuple(elements) => tuple_to_string(elements),
+        Value::Map(map) => {
+            let entries: Vec<String> = map.iter()
+                .map(|(k, v)| format!("{}: {}", value_to_string(&k.to_value()), value_to_string(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Set(set) => {
+            let elems: Vec<String> = set.iter().map(|e| value_to_string(&e.to_value())).collect();
+            format!("{{{}}}", elems.join(", "))
+        }
         Value::Struct { name, fields } => {
             let mut parts = Vec::new();
             for (k, v) in fields {
@@ -766,6 +1708,12 @@ fn value_to_string(value: &Value) -> String {
             format!("{} {{ {} }}", name, parts.join(", "))
         }
         Value::Enum { enum_name, variant_name, data } => {
+            // A variant holding several values prints as `Move(1, 2)`
+            if let Some(Value::Tuple(elements)) = data.as_deref() {
+                if elements.len() != 1 {
+                    return format!("{}::{}{}", enum_name, variant_name, tuple_to_string(elements));
+                }
+            }
             if let Some(d) = data {
                 format!("{}::{}({})", enum_name, variant_name, value_to_string(d))
             } else {
@@ -773,7 +1721,17 @@ fn value_to_string(value: &Value) -> String {
             }
         }
         Value::Function { name, .. } => format!("<fn {}>", name),
+        Value::Closure(closure) => format!("<fn {}>", closure.name()),
         Value::None => "none".to_string(),
     }
 }
 
+/// `(1, 2)`; a single element keeps its comma: `(1,)`.
+pub(crate) fn tuple_to_string(elements: &[Value]) -> String {
+    let elems: Vec<String> = elements.iter().map(value_to_string).collect();
+    match elems.as_slice() {
+        [single] => format!("({},)", single),
+        _ => format!("({})", elems.join(", ")),
+    }
+}
+
@@ -1,7 +1,14 @@
 use crate::error::TogError;
+use crate::span::{FileId, Span};
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Token {
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
     // Literals
     Int(i64),
     Float(f64),
@@ -19,6 +26,11 @@ pub enum Token {
     Star,
     Slash,
     Percent,
+    PlusEq,    // +=
+    MinusEq,   // -=
+    StarEq,    // *=
+    SlashEq,   // /=
+    PercentEq, // %=
     Eq,
     EqEq,
     Ne,
@@ -30,6 +42,11 @@ pub enum Token {
     Or,
     Not,
     Dot,
+    DotDot,   // ..
+    DotDotEq, // ..=
+    Pipe, // | around lambda parameters
+    Ampersand, // & in a `&mut self` receiver
+    Question, // postfix ? on a Result or Option
     
     // Delimiters
     LeftParen,
@@ -72,15 +89,28 @@ pub enum Keyword {
     String,
     Bool,
     Array,
+    Pub,
+    Import,
+    Use,
+    As,
+    Mut,
 }
 
-pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
-    let mut tokens = Vec::new();
+pub fn tokenize(source: &str, file: FileId) -> Result<Vec<Token>, TogError> {
+    tokenize_at(source, file, 1, 1)
+}
+
+/// Tokenizes `source` as if it started at `line`:`column` of `file`. Used for
+/// the expressions inside interpolated strings.
+pub fn tokenize_at(source: &str, file: FileId, line: usize, column: usize) -> Result<Vec<Token>, TogError> {
+    let mut kinds = Vec::new();
+    let mut spans = Vec::new();
     let mut chars = source.chars().peekable();
-    let mut line = 1;
-    let mut column = 1;
+    let mut line = line;
+    let mut column = column;
     
     while let Some(&ch) = chars.peek() {
+        let start = (line, column);
         match ch {
             // Whitespace
             ' ' | '\t' => {
@@ -113,6 +143,8 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 let _start_col = column;
                 let mut num_str = String::new();
                 let mut is_float = false;
+                // `pair.0.1` indexes twice rather than reading the float `0.1`
+                let is_index = kinds.last() == Some(&TokenKind::Dot);
                 
                 while let Some(&ch) = chars.peek() {
                     match ch {
@@ -121,7 +153,8 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                             chars.next();
                             column += 1;
                         }
-                        '.' if !is_float => {
+                        // `1..9` is a range, not the float `1.`
+                        '.' if !is_float && !is_index && chars.clone().nth(1) != Some('.') => {
                             is_float = true;
                             num_str.push(ch);
                             chars.next();
@@ -135,18 +168,16 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                     let num = num_str.parse::<f64>()
                         .map_err(|_| TogError::LexError(
                             format!("Invalid float: {}", num_str),
-                            line,
-                            _start_col
+                            Span::point(file, line, _start_col)
                         ))?;
-                    tokens.push(Token::Float(num));
+                    kinds.push(TokenKind::Float(num));
                 } else {
                     let num = num_str.parse::<i64>()
                         .map_err(|_| TogError::LexError(
                             format!("Invalid integer: {}", num_str),
-                            line,
-                            _start_col
+                            Span::point(file, line, _start_col)
                         ))?;
-                    tokens.push(Token::Int(num));
+                    kinds.push(TokenKind::Int(num));
                 }
             }
             
@@ -157,19 +188,59 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 column += 1;
                 let mut string = String::new();
                 let mut has_interpolation = false;
+                let mut depth = 0; // Nesting of {expr} parts
+                let mut open = (line, column); // Where the outermost {expr} starts
                 
                 while let Some(ch) = chars.next() {
                     column += 1;
                     match ch {
-                        '"' => break,
+                        '"' if depth == 0 => break,
+                        // An {expr} ends on its line, so a missing `}` doesn't swallow the file
+                        '\n' if depth > 0 => return Err(unclosed_interpolation(file, open)),
+                        '\n' => {
+                            string.push(ch);
+                            line += 1;
+                            column = 1;
+                        }
+                        '"' => {
+                            // A string inside {expr}: keep it raw, the parser lexes it again
+                            string.push(ch);
+                            while let Some(ch) = chars.next() {
+                                column += 1;
+                                string.push(ch);
+                                match ch {
+                                    '"' => break,
+                                    '\n' => return Err(unclosed_interpolation(file, open)),
+                                    '\\' => {
+                                        if let Some(escaped) = chars.next() {
+                                            column += 1;
+                                            string.push(escaped);
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
                         '{' => {
-                            // String interpolation: {expr}
+                            // String interpolation: {expr}, split up by the parser
                             has_interpolation = true;
-                            // For now, we'll handle this in the parser
-                            // Just mark it and continue
                             string.push(ch);
+                            if depth == 0 && matches!(chars.peek(), Some('{')) {
+                                // {{ escape
+                                string.push('{');
+                                chars.next();
+                                column += 1;
+                            } else {
+                                if depth == 0 {
+                                    open = (line, column - 1);
+                                }
+                                depth += 1;
+                            }
                         }
                         '}' => {
+                            if depth > 0 {
+                                depth -= 1;
+                            }
                             string.push(ch);
                         }
                         '\\' => {
@@ -192,8 +263,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                                 }
                                 _ => return Err(TogError::LexError(
                                     "Invalid escape sequence".to_string(),
-                                    line,
-                                    column
+                                    Span::point(file, line, column)
                                 )),
                             }
                         }
@@ -202,17 +272,23 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 }
                 
                 if has_interpolation {
-                    tokens.push(Token::InterpolatedString(string));
+                    kinds.push(TokenKind::InterpolatedString(string));
                 } else {
-                    tokens.push(Token::String(string));
+                    kinds.push(TokenKind::String(string));
                 }
             }
             
             // Operators and punctuation
             '+' => {
-                tokens.push(Token::Plus);
                 chars.next();
                 column += 1;
+                if matches!(chars.peek(), Some('=')) {
+                    chars.next();
+                    column += 1;
+                    kinds.push(TokenKind::PlusEq);
+                } else {
+                    kinds.push(TokenKind::Plus);
+                }
             }
             '-' => {
                 chars.next();
@@ -220,30 +296,64 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('>')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::Arrow);
+                    kinds.push(TokenKind::Arrow);
+                } else if matches!(chars.peek(), Some('=')) {
+                    chars.next();
+                    column += 1;
+                    kinds.push(TokenKind::MinusEq);
                 } else {
-                    tokens.push(Token::Minus);
+                    kinds.push(TokenKind::Minus);
                 }
             }
             '*' => {
-                tokens.push(Token::Star);
                 chars.next();
                 column += 1;
+                if matches!(chars.peek(), Some('=')) {
+                    chars.next();
+                    column += 1;
+                    kinds.push(TokenKind::StarEq);
+                } else {
+                    kinds.push(TokenKind::Star);
+                }
             }
             '/' => {
-                tokens.push(Token::Slash);
                 chars.next();
                 column += 1;
+                if matches!(chars.peek(), Some('=')) {
+                    chars.next();
+                    column += 1;
+                    kinds.push(TokenKind::SlashEq);
+                } else {
+                    kinds.push(TokenKind::Slash);
+                }
             }
             '%' => {
-                tokens.push(Token::Percent);
                 chars.next();
                 column += 1;
+                if matches!(chars.peek(), Some('=')) {
+                    chars.next();
+                    column += 1;
+                    kinds.push(TokenKind::PercentEq);
+                } else {
+                    kinds.push(TokenKind::Percent);
+                }
             }
             '.' => {
-                tokens.push(Token::Dot);
                 chars.next();
                 column += 1;
+                if matches!(chars.peek(), Some('.')) {
+                    chars.next();
+                    column += 1;
+                    if matches!(chars.peek(), Some('=')) {
+                        chars.next();
+                        column += 1;
+                        kinds.push(TokenKind::DotDotEq);
+                    } else {
+                        kinds.push(TokenKind::DotDot);
+                    }
+                } else {
+                    kinds.push(TokenKind::Dot);
+                }
             }
             '=' => {
                 chars.next();
@@ -251,13 +361,13 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('=')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::EqEq);
+                    kinds.push(TokenKind::EqEq);
                 } else if matches!(chars.peek(), Some('>')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::FatArrow);
+                    kinds.push(TokenKind::FatArrow);
                 } else {
-                    tokens.push(Token::Eq);
+                    kinds.push(TokenKind::Eq);
                 }
             }
             '!' => {
@@ -266,9 +376,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('=')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::Ne);
+                    kinds.push(TokenKind::Ne);
                 } else {
-                    tokens.push(Token::Not);
+                    kinds.push(TokenKind::Not);
                 }
             }
             '<' => {
@@ -277,9 +387,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('=')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::Le);
+                    kinds.push(TokenKind::Le);
                 } else {
-                    tokens.push(Token::Lt);
+                    kinds.push(TokenKind::Lt);
                 }
             }
             '>' => {
@@ -288,9 +398,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('=')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::Ge);
+                    kinds.push(TokenKind::Ge);
                 } else {
-                    tokens.push(Token::Gt);
+                    kinds.push(TokenKind::Gt);
                 }
             }
             '&' => {
@@ -299,13 +409,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('&')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::And);
+                    kinds.push(TokenKind::And);
                 } else {
-                    return Err(TogError::LexError(
-                        "Unexpected '&'".to_string(),
-                        line,
-                        column
-                    ));
+                    kinds.push(TokenKind::Ampersand);
                 }
             }
             '|' => {
@@ -314,52 +420,53 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some('|')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::Or);
+                    kinds.push(TokenKind::Or);
                 } else {
-                    return Err(TogError::LexError(
-                        "Unexpected '|'".to_string(),
-                        line,
-                        column
-                    ));
+                    kinds.push(TokenKind::Pipe);
                 }
             }
+            '?' => {
+                kinds.push(TokenKind::Question);
+                chars.next();
+                column += 1;
+            }
             '(' => {
-                tokens.push(Token::LeftParen);
+                kinds.push(TokenKind::LeftParen);
                 chars.next();
                 column += 1;
             }
             ')' => {
-                tokens.push(Token::RightParen);
+                kinds.push(TokenKind::RightParen);
                 chars.next();
                 column += 1;
             }
             '{' => {
-                tokens.push(Token::LeftBrace);
+                kinds.push(TokenKind::LeftBrace);
                 chars.next();
                 column += 1;
             }
             '}' => {
-                tokens.push(Token::RightBrace);
+                kinds.push(TokenKind::RightBrace);
                 chars.next();
                 column += 1;
             }
             '[' => {
-                tokens.push(Token::LeftBracket);
+                kinds.push(TokenKind::LeftBracket);
                 chars.next();
                 column += 1;
             }
             ']' => {
-                tokens.push(Token::RightBracket);
+                kinds.push(TokenKind::RightBracket);
                 chars.next();
                 column += 1;
             }
             ',' => {
-                tokens.push(Token::Comma);
+                kinds.push(TokenKind::Comma);
                 chars.next();
                 column += 1;
             }
             ';' => {
-                tokens.push(Token::Semicolon);
+                kinds.push(TokenKind::Semicolon);
                 chars.next();
                 column += 1;
             }
@@ -369,9 +476,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 if matches!(chars.peek(), Some(':')) {
                     chars.next();
                     column += 1;
-                    tokens.push(Token::ColonColon);
+                    kinds.push(TokenKind::ColonColon);
                 } else {
-                    tokens.push(Token::Colon);
+                    kinds.push(TokenKind::Colon);
                 }
             }
             
@@ -393,46 +500,63 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, TogError> {
                 
                 // Check if it's a keyword
                 match ident.as_str() {
-                    "fn" => tokens.push(Token::Keyword(Keyword::Fn)),
-                    "let" => tokens.push(Token::Keyword(Keyword::Let)),
-                    "struct" => tokens.push(Token::Keyword(Keyword::Struct)),
-                    "enum" => tokens.push(Token::Keyword(Keyword::Enum)),
-                    "trait" => tokens.push(Token::Keyword(Keyword::Trait)),
-                    "impl" => tokens.push(Token::Keyword(Keyword::Impl)),
-                    "if" => tokens.push(Token::Keyword(Keyword::If)),
-                    "else" => tokens.push(Token::Keyword(Keyword::Else)),
-                    "while" => tokens.push(Token::Keyword(Keyword::While)),
-                    "for" => tokens.push(Token::Keyword(Keyword::For)),
-                    "in" => tokens.push(Token::Keyword(Keyword::In)),
-                    "return" => tokens.push(Token::Keyword(Keyword::Return)),
-                    "match" => tokens.push(Token::Keyword(Keyword::Match)),
-                    "break" => tokens.push(Token::Keyword(Keyword::Break)),
-                    "continue" => tokens.push(Token::Keyword(Keyword::Continue)),
-                    "none" => tokens.push(Token::Keyword(Keyword::None)),
-                    "true" => tokens.push(Token::Bool(true)),
-                    "false" => tokens.push(Token::Bool(false)),
+                    "fn" => kinds.push(TokenKind::Keyword(Keyword::Fn)),
+                    "let" => kinds.push(TokenKind::Keyword(Keyword::Let)),
+                    "struct" => kinds.push(TokenKind::Keyword(Keyword::Struct)),
+                    "enum" => kinds.push(TokenKind::Keyword(Keyword::Enum)),
+                    "trait" => kinds.push(TokenKind::Keyword(Keyword::Trait)),
+                    "impl" => kinds.push(TokenKind::Keyword(Keyword::Impl)),
+                    "if" => kinds.push(TokenKind::Keyword(Keyword::If)),
+                    "else" => kinds.push(TokenKind::Keyword(Keyword::Else)),
+                    "while" => kinds.push(TokenKind::Keyword(Keyword::While)),
+                    "for" => kinds.push(TokenKind::Keyword(Keyword::For)),
+                    "in" => kinds.push(TokenKind::Keyword(Keyword::In)),
+                    "return" => kinds.push(TokenKind::Keyword(Keyword::Return)),
+                    "match" => kinds.push(TokenKind::Keyword(Keyword::Match)),
+                    "break" => kinds.push(TokenKind::Keyword(Keyword::Break)),
+                    "continue" => kinds.push(TokenKind::Keyword(Keyword::Continue)),
+                    "none" => kinds.push(TokenKind::Keyword(Keyword::None)),
+                    "true" => kinds.push(TokenKind::Bool(true)),
+                    "false" => kinds.push(TokenKind::Bool(false)),
                     // "print" is now a built-in function, not a keyword
-                    // "print" => tokens.push(Token::Keyword(Keyword::Print)),
-                    "int" => tokens.push(Token::Keyword(Keyword::Int)),
-                    "float" => tokens.push(Token::Keyword(Keyword::Float)),
-                    "string" => tokens.push(Token::Keyword(Keyword::String)),
-                    "bool" => tokens.push(Token::Keyword(Keyword::Bool)),
-                    "array" => tokens.push(Token::Keyword(Keyword::Array)),
-                    _ => tokens.push(Token::Identifier(ident)),
+                    // "print" => kinds.push(TokenKind::Keyword(Keyword::Print)),
+                    "int" => kinds.push(TokenKind::Keyword(Keyword::Int)),
+                    "float" => kinds.push(TokenKind::Keyword(Keyword::Float)),
+                    "string" => kinds.push(TokenKind::Keyword(Keyword::String)),
+                    "bool" => kinds.push(TokenKind::Keyword(Keyword::Bool)),
+                    "array" => kinds.push(TokenKind::Keyword(Keyword::Array)),
+                    "pub" => kinds.push(TokenKind::Keyword(Keyword::Pub)),
+                    "import" => kinds.push(TokenKind::Keyword(Keyword::Import)),
+                    "use" => kinds.push(TokenKind::Keyword(Keyword::Use)),
+                    "as" => kinds.push(TokenKind::Keyword(Keyword::As)),
+                    "mut" => kinds.push(TokenKind::Keyword(Keyword::Mut)),
+                    _ => kinds.push(TokenKind::Identifier(ident)),
                 }
             }
             
             _ => {
                 return Err(TogError::LexError(
                     format!("Unexpected character: {}", ch),
-                    line,
-                    column
+                    Span::point(file, line, column)
                 ));
             }
         }
+        
+        // Whitespace and comments don't produce a token
+        if kinds.len() > spans.len() {
+            spans.push(Span::new(file, start, (line, column)));
+        }
     }
     
-    tokens.push(Token::Eof);
-    Ok(tokens)
+    kinds.push(TokenKind::Eof);
+    spans.push(Span::point(file, line, column));
+    Ok(kinds.into_iter()
+        .zip(spans)
+        .map(|(kind, span)| Token { kind, span })
+        .collect())
 }
 
+fn unclosed_interpolation(file: FileId, open: (usize, usize)) -> TogError {
+    TogError::LexError("Unclosed '{' in interpolated string".to_string(), Span::point(file, open.0, open.1))
+        .with_help("close it with '}', or use '{{' for a literal brace")
+}
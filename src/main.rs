@@ -1,7 +1,9 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::IsTerminal;
 use std::fs;
 use std::path::PathBuf;
 
+mod span;
 mod lexer;
 mod parser;
 mod ast;
@@ -10,8 +12,17 @@ mod error;
 mod stdlib;
 mod compiler;
 mod type_checker;
+mod parallel;
+mod diagnostics;
+mod modules;
+mod exhaustiveness;
+mod inference;
+mod ownership;
+mod resolver;
+mod vm;
 
 use error::TogError;
+use diagnostics::Severity;
 
 #[derive(Parser)]
 #[command(name = "tog")]
@@ -20,6 +31,25 @@ use error::TogError;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// When to colour diagnostics
+    #[arg(long, value_enum, global = true, default_value = "auto")]
+    color: ColorChoice,
+    /// Don't load the prelude (`Option`, `Result`) before the program
+    #[arg(long, global = true)]
+    no_prelude: bool,
+    /// Another directory to look for imported modules in, after the
+    /// importing file's own (repeatable; TOG_PATH is searched after these)
+    #[arg(long, global = true, value_name = "DIR")]
+    module_path: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    /// Colour when stderr is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    /// Plain text, e.g. for CI logs
+    Never,
 }
 
 #[derive(Subcommand)]
@@ -28,6 +58,9 @@ enum Commands {
     Run {
         /// Path to the TOG source file
         file: PathBuf,
+        /// Run on the tree-walking interpreter instead of the bytecode VM
+        #[arg(long)]
+        tree_walk: bool,
     },
     /// Compile a TOG program
     Build {
@@ -46,34 +79,72 @@ enum Commands {
     Check {
         /// Path to the TOG source file
         file: PathBuf,
+        /// Stop after this many syntax errors
+        #[arg(long, default_value_t = 20)]
+        max_errors: usize,
     },
 }
 
-fn main() -> Result<(), TogError> {
+fn main() {
     let cli = Cli::parse();
 
+    let color = match cli.color {
+        ColorChoice::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    colored::control::set_override(color);
+
+    if let Err(e) = run(cli) {
+        eprint!("{}", diagnostics::render(&e, Severity::Error));
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), TogError> {
+    let mut search_path = cli.module_path;
+    if let Some(paths) = std::env::var_os("TOG_PATH") {
+        search_path.extend(std::env::split_paths(&paths));
+    }
+    let loader = modules::ModuleLoader::new(search_path);
+
     match cli.command {
-        Commands::Run { file } => {
+        Commands::Run { file, tree_walk } => {
             let source = fs::read_to_string(&file)
                 .map_err(|e| TogError::IoError(format!("Failed to read file: {}", e)))?;
             
             println!("Running TOG program: {}", file.display());
             
             // Lex
-            let tokens = lexer::tokenize(&source)?;
+            let file_id = span::add_file(file.display().to_string(), source.as_str());
+            let tokens = lexer::tokenize(&source, file_id)?;
             
             // Parse
             let ast = parser::Parser::parse(tokens)?;
+            let ast = loader.link(ast, &file)?;
+            
+            // Resolve scopes; unlike type errors, these stop the program
+            resolver::check_program(&ast, !cli.no_prelude)?;
             
             // Type check
             let mut type_checker = type_checker::TypeChecker::new();
-            if let Err(e) = type_checker.check_program(&ast) {
-                eprintln!("Type check warning: {}", e);
+            if !cli.no_prelude {
+                type_checker.load_prelude()?;
+            }
+            let checked = type_checker.check_program(&ast);
+            for warning in type_checker.take_warnings() {
+                eprint!("{}", diagnostics::render(&warning, Severity::Warning));
+            }
+            if let Err(e) = checked {
+                eprint!("{}", diagnostics::render(&e, Severity::Warning));
                 // Continue anyway (gradual typing)
             }
             
-            // Interpret
-            interpreter::Interpreter::interpret(ast)?;
+            if tree_walk {
+                interpreter::Interpreter::interpret(ast, !cli.no_prelude)?;
+            } else {
+                vm::run(ast, !cli.no_prelude)?;
+            }
             
             Ok(())
         }
@@ -84,10 +155,12 @@ fn main() -> Result<(), TogError> {
             println!("Building TOG program: {}", file.display());
             
             // Lex
-            let tokens = lexer::tokenize(&source)?;
+            let file_id = span::add_file(file.display().to_string(), source.as_str());
+            let tokens = lexer::tokenize(&source, file_id)?;
             
             // Parse
             let ast = parser::Parser::parse(tokens)?;
+            let ast = loader.link(ast, &file)?;
             
             // Compile using compiler backend
             let output_path = output.unwrap_or_else(|| {
@@ -111,7 +184,7 @@ fn main() -> Result<(), TogError> {
                 }
                 Err(e) => {
                     // Fallback message
-                    println!("Build error: {}", e);
+                    eprint!("{}", diagnostics::render(&e, Severity::Error));
                     println!("Note: For full native compilation, LLVM/Cranelift backends require additional dependencies.");
                 }
             }
@@ -123,21 +196,43 @@ fn main() -> Result<(), TogError> {
             println!("   (Formatter coming soon!)");
             Ok(())
         }
-        Commands::Check { file } => {
+        Commands::Check { file, max_errors } => {
             let source = fs::read_to_string(&file)
                 .map_err(|e| TogError::IoError(format!("Failed to read file: {}", e)))?;
             
             println!("Checking syntax: {}", file.display());
             
             // Lex
-            let tokens = lexer::tokenize(&source)?;
+            let file_id = span::add_file(file.display().to_string(), source.as_str());
+            let tokens = lexer::tokenize(&source, file_id)?;
+            
+            // Parse, reporting every syntax error up to the limit
+            let (ast, errors) = parser::Parser::parse_recovering(tokens, max_errors);
+            if !errors.is_empty() {
+                for error in &errors {
+                    eprint!("{}", diagnostics::render(error, Severity::Error));
+                }
+                let message = if errors.len() >= max_errors {
+                    format!("Stopped after {} syntax errors in {} (see --max-errors)", errors.len(), file.display())
+                } else {
+                    format!("Found {} syntax error(s) in {}", errors.len(), file.display())
+                };
+                return Err(TogError::ParseError(message, span::Span::default()));
+            }
+            let ast = loader.link(ast, &file)?;
             
-            // Parse
-            let ast = parser::Parser::parse(tokens)?;
+            resolver::check_program(&ast, !cli.no_prelude)?;
             
             // Type check
             let mut type_checker = type_checker::TypeChecker::new();
-            type_checker.check_program(&ast)?;
+            if !cli.no_prelude {
+                type_checker.load_prelude()?;
+            }
+            let checked = type_checker.check_program(&ast);
+            for warning in type_checker.take_warnings() {
+                eprint!("{}", diagnostics::render(&warning, Severity::Warning));
+            }
+            checked?;
             
             println!("Syntax and type check passed!");
             Ok(())
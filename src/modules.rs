@@ -0,0 +1,615 @@
+// Modules
+//
+// `import "utils.tog"` and `use math::vector` bring other files into a
+// program. The loader parses each module once and gives it a namespace named
+// after its file, then links everything into a single `Program`:
+//
+//   - a module's top-level items are renamed into its namespace, so `fn add`
+//     in utils.tog becomes `utils::add`
+//   - references are rewritten to the linked names: `utils::add(1, 2)` and,
+//     after `use utils::add`, `add(1, 2)` both call `utils::add`
+//
+// The interpreter, type checker and compiler then see one flat program.
+// Imported modules come first, dependencies before the modules that use them,
+// so their top-level code runs once, before the importing file's.
+//
+// Only `pub` items can be used from other modules. A module is looked up
+// relative to the importing file, then in each directory of the search path.
+
+use crate::ast::*;
+use crate::diagnostics::did_you_mean;
+use crate::error::TogError;
+use crate::resolver::collect_type_names;
+use crate::span::{self, Span};
+use crate::{lexer, parser, stdlib};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub struct ModuleLoader {
+    search_path: Vec<PathBuf>,
+    modules: HashMap<PathBuf, Rc<Module>>, // Linked modules, by canonical path
+    loading: Vec<(PathBuf, String)>, // Files being linked, importer first: (canonical path, display name)
+    namespaces: HashSet<String>,
+    statements: Vec<Stmt>, // Linked module statements, dependencies first
+    prelude_types: HashSet<String>, // `Option` and `Result`, which every file can name
+}
+
+struct Module {
+    namespace: String,
+    file: String, // For messages
+    items: HashMap<String, bool>, // Top-level item names, and whether they are `pub`
+}
+
+impl Module {
+    /// The linked name of an item used from another module.
+    fn item(&self, name: &str, span: Span) -> Result<String, TogError> {
+        match self.items.get(name) {
+            Some(true) => Ok(format!("{}::{}", self.namespace, name)),
+            Some(false) => Err(TogError::ParseError(
+                format!("`{}` is private to module `{}`", name, self.namespace),
+                span
+            ).with_help(format!("mark it `pub` in {} to use it here", self.file))),
+            None => {
+                let error = TogError::ParseError(
+                    format!("Module `{}` has no item `{}`", self.namespace, name),
+                    span
+                );
+                let public = self.items.iter().filter(|(_, public)| **public).map(|(n, _)| n.as_str());
+                match did_you_mean(name, public) {
+                    Some(help) => Err(error.with_help(help)),
+                    None => Err(error),
+                }
+            }
+        }
+    }
+}
+
+impl ModuleLoader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            namespaces: HashSet::new(),
+            statements: Vec::new(),
+            prelude_types: HashSet::new(),
+        }
+    }
+
+    /// Links `program`, parsed from `file`, with every module it imports.
+    /// The program's own items keep their names.
+    pub fn link(mut self, program: Program, file: &Path) -> Result<Program, TogError> {
+        let path = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        self.loading.push((path, file.display().to_string()));
+        for stmt in &stdlib::prelude()?.statements {
+            collect_type_names(stmt, &mut self.prelude_types);
+        }
+        let statements = self.resolve(program.statements, file, None)?;
+        self.statements.extend(statements);
+        Ok(Program { statements: self.statements })
+    }
+
+    /// Loads each module `statements` imports, then rewrites the statements to
+    /// use linked names. `namespace` is None for the program's own file.
+    fn resolve(&mut self, mut statements: Vec<Stmt>, file: &Path, namespace: Option<&str>) -> Result<Vec<Stmt>, TogError> {
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut renamer = Renamer { type_names: self.prelude_types.clone(), ..Renamer::default() };
+        for stmt in &statements {
+            collect_type_names(stmt, &mut renamer.type_names);
+        }
+
+        for stmt in &statements {
+            let StmtKind::Import { source, alias } = &stmt.kind else {
+                continue;
+            };
+            match self.import(source, &dir, stmt.span)? {
+                Imported::Module(module, name) => {
+                    renamer.modules.insert(alias.clone().unwrap_or(name), module);
+                }
+                Imported::Item(module, name) => {
+                    let linked = module.item(&name, stmt.span)?;
+                    renamer.names.insert(alias.clone().unwrap_or(name), linked);
+                }
+            }
+        }
+        // The file's own items shadow imported ones
+        for stmt in &statements {
+            if let Some(name) = item_name(stmt) {
+                let linked = match namespace {
+                    Some(namespace) => format!("{}::{}", namespace, name),
+                    None => name.to_string(),
+                };
+                renamer.names.insert(name.to_string(), linked);
+            }
+        }
+
+        for stmt in &mut statements {
+            renamer.stmt(stmt)?;
+        }
+        Ok(statements)
+    }
+
+    fn import(&mut self, source: &ImportSource, dir: &Path, span: Span) -> Result<Imported, TogError> {
+        match source {
+            ImportSource::File(path) => {
+                let found = self.find(Path::new(path), dir).ok_or_else(|| {
+                    self.not_found(&format!("\"{}\"", path), dir, span)
+                })?;
+                let name = Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                Ok(Imported::Module(self.load(&found, span)?, name))
+            }
+            ImportSource::Path(segments) => {
+                // `use a::b` is module a/b.tog, or else item `b` of module a.tog
+                let as_file = |segments: &[String]| PathBuf::from(format!("{}.tog", segments.join("/")));
+                let last = segments[segments.len() - 1].clone();
+                if let Some(found) = self.find(&as_file(segments), dir) {
+                    return Ok(Imported::Module(self.load(&found, span)?, last));
+                }
+                if segments.len() > 1 {
+                    if let Some(found) = self.find(&as_file(&segments[..segments.len() - 1]), dir) {
+                        return Ok(Imported::Item(self.load(&found, span)?, last));
+                    }
+                }
+                Err(self.not_found(&format!("`{}`", segments.join("::")), dir, span))
+            }
+        }
+    }
+
+    /// The first existing `path` relative to `dir`, then each search directory.
+    fn find(&self, path: &Path, dir: &Path) -> Option<PathBuf> {
+        std::iter::once(dir)
+            .chain(self.search_path.iter().map(|p| p.as_path()))
+            .map(|base| base.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn not_found(&self, module: &str, dir: &Path, span: Span) -> TogError {
+        let mut searched = vec![display_dir(dir)];
+        searched.extend(self.search_path.iter().map(|p| display_dir(p)));
+        TogError::ParseError(format!("Cannot find module {}", module), span)
+            .with_note(format!("searched in {}", searched.join(", ")))
+            .with_help("add a directory to the search path with --module-path or TOG_PATH")
+    }
+
+    /// Parses and links the module at `path`, once per program.
+    fn load(&mut self, path: &Path, span: Span) -> Result<Rc<Module>, TogError> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(module) = self.modules.get(&canonical) {
+            return Ok(Rc::clone(module));
+        }
+        let file = path.display().to_string();
+        if let Some(start) = self.loading.iter().position(|(p, _)| *p == canonical) {
+            let cycle: Vec<&str> = self.loading[start..].iter().map(|(_, name)| name.as_str()).collect();
+            return Err(TogError::ParseError(
+                format!("Import cycle: {} -> {}", cycle.join(" -> "), file),
+                span
+            ).with_note("modules can't import each other in a cycle; move the shared items into a module both import"));
+        }
+
+        let source = fs::read_to_string(path)
+            .map_err(|e| TogError::IoError(format!("Failed to read module {}: {}", file, e)))?;
+        let file_id = span::add_file(file.clone(), source.as_str());
+        let program = parser::Parser::parse(lexer::tokenize(&source, file_id)?)?;
+
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let namespace = self.unique_namespace(stem);
+        let items = program.statements.iter()
+            .filter_map(|stmt| item_name(stmt).map(|name| (name.to_string(), stmt.public)))
+            .collect();
+
+        self.loading.push((canonical.clone(), file.clone()));
+        let statements = self.resolve(program.statements, path, Some(&namespace));
+        self.loading.pop();
+        self.statements.extend(statements?);
+
+        let module = Rc::new(Module { namespace, file, items });
+        self.modules.insert(canonical, Rc::clone(&module));
+        Ok(module)
+    }
+
+    /// Modules are namespaced by file name; two files with the same name in
+    /// different directories get distinct namespaces.
+    fn unique_namespace(&mut self, stem: String) -> String {
+        let mut namespace = stem.clone();
+        let mut n = 2;
+        while !self.namespaces.insert(namespace.clone()) {
+            namespace = format!("{}{}", stem, n);
+            n += 1;
+        }
+        namespace
+    }
+}
+
+enum Imported {
+    Module(Rc<Module>, String), // The module and the name it's bound to
+    Item(Rc<Module>, String), // `use a::b` where b is an item in a
+}
+
+fn display_dir(dir: &Path) -> String {
+    if dir.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        dir.display().to_string()
+    }
+}
+
+/// The name a top-level statement declares, if it's an item.
+fn item_name(stmt: &Stmt) -> Option<&str> {
+    match &stmt.kind {
+        StmtKind::Expr(Expr { kind: ExprKind::Function { name, .. }, .. }) => Some(name),
+        StmtKind::Let { name, .. }
+        | StmtKind::StructDef { name, .. }
+        | StmtKind::EnumDef { name, .. }
+        | StmtKind::TraitDef { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// Rewrites one file's statements to use linked names.
+#[derive(Default)]
+struct Renamer {
+    names: HashMap<String, String>, // Top-level names in scope -> linked names
+    modules: HashMap<String, Rc<Module>>, // Module bindings: `utils` in `utils::add`
+    type_names: HashSet<String>, // Types the file declares or impls, and the prelude's
+    locals: Vec<HashSet<String>>, // Local scopes, innermost last; empty at the top level
+    span: Span, // Of the innermost expression or statement, for errors
+}
+
+impl Renamer {
+    fn stmt(&mut self, stmt: &mut Stmt) -> Result<(), TogError> {
+        self.span = stmt.span;
+        let top_level = self.locals.is_empty();
+        match &mut stmt.kind {
+            StmtKind::Expr(expr) => {
+                if let ExprKind::Function { name, .. } = &mut expr.kind {
+                    if top_level {
+                        self.rename_item(name);
+                    } else {
+                        self.declare(name);
+                    }
+                }
+                self.expr(expr)
+            }
+            StmtKind::Let { name, type_annotation, value } => {
+                self.expr(value)?;
+                if let Some(ty) = type_annotation {
+                    self.ty(ty)?;
+                }
+                if top_level {
+                    self.rename_item(name);
+                } else {
+                    self.declare(name);
+                }
+                Ok(())
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                self.expr(value)?;
+                for name in self.pattern(pattern)? {
+                    self.declare(&name);
+                }
+                Ok(())
+            }
+            StmtKind::Assign { name, value, .. } => {
+                self.expr(value)?;
+                self.variable(name);
+                Ok(())
+            }
+            StmtKind::AssignField { object, value, .. } => {
+                self.expr(object)?;
+                self.expr(value)
+            }
+            StmtKind::AssignIndex { object, index, value, .. } => {
+                self.expr(object)?;
+                self.expr(index)?;
+                self.expr(value)
+            }
+            StmtKind::StructDef { name, type_params, fields, methods } => {
+                if top_level {
+                    self.rename_item(name);
+                }
+                self.type_params(type_params)?;
+                for (_, ty) in fields.iter_mut() {
+                    if let Some(ty) = ty {
+                        self.ty(ty)?;
+                    }
+                }
+                for method in methods {
+                    self.function(&mut method.params, &mut method.return_type, &mut method.body)?;
+                }
+                Ok(())
+            }
+            StmtKind::EnumDef { name, type_params, variants } => {
+                if top_level {
+                    self.rename_item(name);
+                }
+                self.type_params(type_params)?;
+                for variant in variants {
+                    if let Some(ty) = &mut variant.data_type {
+                        self.ty(ty)?;
+                    }
+                }
+                Ok(())
+            }
+            StmtKind::TraitDef { name, type_params, methods } => {
+                if top_level {
+                    self.rename_item(name);
+                }
+                self.type_params(type_params)?;
+                for method in methods {
+                    for param in &mut method.params {
+                        if let Some(ty) = &mut param.type_annotation {
+                            self.ty(ty)?;
+                        }
+                    }
+                    if let Some(ty) = &mut method.return_type {
+                        self.ty(ty)?;
+                    }
+                    if let Some(body) = &mut method.default_body {
+                        self.scoped(method.params.iter().map(|p| p.name.clone()), |r| r.expr(body))?;
+                    }
+                }
+                Ok(())
+            }
+            StmtKind::ImplBlock { type_params, trait_name, type_name, methods } => {
+                self.type_params(type_params)?;
+                if let Some(trait_name) = trait_name {
+                    self.type_name(trait_name)?;
+                }
+                self.type_name(type_name)?;
+                for method in methods {
+                    self.function(&mut method.params, &mut method.return_type, &mut method.body)?;
+                }
+                Ok(())
+            }
+            StmtKind::Import { .. } if !top_level => Err(TogError::ParseError(
+                "Imports are only allowed at the top level of a file".to_string(),
+                stmt.span
+            )),
+            StmtKind::Return(Some(expr)) => self.expr(expr),
+            StmtKind::Import { .. } | StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Error => Ok(()),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> Result<(), TogError> {
+        self.span = expr.span;
+        match &mut expr.kind {
+            ExprKind::Literal(Literal::Array(elements) | Literal::Tuple(elements)) => {
+                for element in elements {
+                    self.expr(element)?;
+                }
+            }
+            ExprKind::Literal(Literal::Map(entries)) => {
+                for (key, value) in entries {
+                    self.expr(key)?;
+                    self.expr(value)?;
+                }
+            }
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) => self.variable(name),
+            ExprKind::StructLiteral { name, fields } => {
+                self.type_name(name)?;
+                for (_, value) in fields {
+                    self.expr(value)?;
+                }
+            }
+            ExprKind::FieldAccess { object, .. } => self.expr(object)?,
+            ExprKind::BinaryOp { left, right, .. } => {
+                self.expr(left)?;
+                self.expr(right)?;
+            }
+            ExprKind::UnaryOp { expr, .. } | ExprKind::Try(expr) => self.expr(expr)?,
+            ExprKind::Call { callee, args } => {
+                self.expr(callee)?;
+                for arg in args {
+                    self.expr(arg)?;
+                }
+            }
+            ExprKind::Block(statements) => {
+                self.scoped(std::iter::empty(), |r| {
+                    statements.iter_mut().try_for_each(|stmt| r.stmt(stmt))
+                })?;
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition)?;
+                self.expr(then_branch)?;
+                if let Some(else_branch) = else_branch {
+                    self.expr(else_branch)?;
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.expr(condition)?;
+                self.expr(body)?;
+            }
+            ExprKind::Match { expr, arms } => {
+                self.expr(expr)?;
+                for arm in arms {
+                    let bindings = self.pattern(&mut arm.pattern)?;
+                    self.scoped(bindings.into_iter(), |r| {
+                        if let Some(guard) = &mut arm.guard {
+                            r.expr(guard)?;
+                        }
+                        r.expr(&mut arm.body)
+                    })?;
+                }
+            }
+            ExprKind::Function { type_params, params, return_type, body, .. } => {
+                self.type_params(type_params)?;
+                self.function(params, return_type, body)?;
+            }
+            ExprKind::Lambda { params, return_type, body } => {
+                self.function(params, return_type, body)?;
+            }
+            ExprKind::Index { array, index } => {
+                self.expr(array)?;
+                self.expr(index)?;
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                self.expr(iterable)?;
+                let bindings = self.pattern(pattern)?;
+                self.scoped(bindings.into_iter(), |r| r.expr(body))?;
+            }
+            ExprKind::EnumVariant { enum_name, variant_name, data } => {
+                // `utils::add` names an item of module `utils`, not a variant
+                if let Some(module) = self.modules.get(enum_name.as_str()) {
+                    let callee = Expr::new(ExprKind::Variable(module.item(variant_name, expr.span)?), expr.span);
+                    expr.kind = match data.take() {
+                        Some(arg) => ExprKind::Call { callee: Box::new(callee), args: vec![*arg] },
+                        None => callee.kind,
+                    };
+                    return self.expr(expr);
+                }
+                self.qualifier(enum_name)?;
+                self.type_name(enum_name)?;
+                if let Some(data) = data {
+                    self.expr(data)?;
+                }
+            }
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr { expr, .. } = part {
+                        self.expr(expr)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, params: &mut [Param], return_type: &mut Option<Type>, body: &mut Expr) -> Result<(), TogError> {
+        for param in params.iter_mut() {
+            if let Some(ty) = &mut param.type_annotation {
+                self.ty(ty)?;
+            }
+        }
+        if let Some(ty) = return_type {
+            self.ty(ty)?;
+        }
+        self.scoped(params.iter().map(|p| p.name.clone()), |r| r.expr(body))
+    }
+
+    /// Resolves enum names in a pattern and returns the names it binds.
+    fn pattern(&mut self, pattern: &mut Pattern) -> Result<Vec<String>, TogError> {
+        self.pattern_names(pattern)?;
+        Ok(pattern.bindings())
+    }
+
+    fn pattern_names(&mut self, pattern: &mut Pattern) -> Result<(), TogError> {
+        match pattern {
+            // A bare variant name (`Some(x)`) has no enum name to resolve
+            Pattern::EnumVariant { enum_name, inner, .. } => {
+                if !enum_name.is_empty() {
+                    self.qualifier(enum_name)?;
+                    self.type_name(enum_name)?;
+                }
+                if let Some(inner) = inner {
+                    self.pattern_names(inner)?;
+                }
+                Ok(())
+            }
+            Pattern::Struct { name, fields } => {
+                self.type_name(name)?;
+                fields.iter_mut().try_for_each(|(_, field)| self.pattern_names(field))
+            }
+            Pattern::Array(elements) | Pattern::Tuple(elements) | Pattern::Or(elements) => {
+                elements.iter_mut().try_for_each(|element| self.pattern_names(element))
+            }
+            Pattern::Literal(_) | Pattern::Variable(_) | Pattern::Wildcard | Pattern::Rest(_) | Pattern::Range { .. } => Ok(()),
+        }
+    }
+
+    fn type_params(&mut self, type_params: &mut [TypeParam]) -> Result<(), TogError> {
+        for param in type_params {
+            for bound in &mut param.bounds {
+                self.type_name(bound)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ty(&mut self, ty: &mut Type) -> Result<(), TogError> {
+        match ty {
+            Type::Struct(name) | Type::Enum(name) => self.type_name(name),
+            Type::Generic { name, args } => {
+                self.type_name(name)?;
+                args.iter_mut().try_for_each(|arg| self.ty(arg))
+            }
+            Type::Array(inner) => self.ty(inner),
+            Type::Tuple(elements) => elements.iter_mut().try_for_each(|element| self.ty(element)),
+            Type::Map(key, value) => {
+                self.ty(key)?;
+                self.ty(value)
+            }
+            Type::Set(element) => self.ty(element),
+            Type::Function { params, return_type } => {
+                params.iter_mut().try_for_each(|param| self.ty(param))?;
+                self.ty(return_type)
+            }
+            Type::Int | Type::Float | Type::String | Type::Bool | Type::Param(_) | Type::None | Type::Infer => Ok(()),
+        }
+    }
+
+    /// Resolves a type or trait name, which may be qualified: `geometry::Point`.
+    fn type_name(&mut self, name: &mut String) -> Result<(), TogError> {
+        match name.split_once("::") {
+            Some((module, item)) => match self.modules.get(module) {
+                Some(module) => {
+                    *name = module.item(item, self.span)?;
+                    Ok(())
+                }
+                None => Err(self.not_imported(module)),
+            },
+            None => {
+                self.rename_item(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that the `Name` in `Name::item` is a type, if it isn't a
+    /// module: a qualified name can't start with anything else.
+    fn qualifier(&self, name: &str) -> Result<(), TogError> {
+        if name.contains("::") || self.names.contains_key(name) || self.type_names.contains(name) || stdlib::is_builtin_type(name) {
+            return Ok(());
+        }
+        Err(self.not_imported(name))
+    }
+
+    fn not_imported(&self, module: &str) -> TogError {
+        let error = TogError::ParseError(format!("Module `{}` is not imported", module), self.span);
+        match did_you_mean(module, self.modules.keys().map(|m| m.as_str())) {
+            Some(help) => error.with_help(help),
+            None => error.with_help(format!("import it first: `use {}`", module)),
+        }
+    }
+
+    fn variable(&mut self, name: &mut String) {
+        if !self.locals.iter().any(|scope| scope.contains(name.as_str())) {
+            self.rename_item(name);
+        }
+    }
+
+    fn rename_item(&self, name: &mut String) {
+        if let Some(linked) = self.names.get(name.as_str()) {
+            *name = linked.clone();
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.locals.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn scoped<T>(
+        &mut self,
+        names: impl Iterator<Item = String>,
+        f: impl FnOnce(&mut Self) -> Result<T, TogError>,
+    ) -> Result<T, TogError> {
+        self.locals.push(names.collect());
+        let result = f(self);
+        self.locals.pop();
+        result
+    }
+}
@@ -0,0 +1,582 @@
+// Ownership and moves
+//
+// Tracks who owns each value that isn't `Copy` (arrays, maps, sets, structs
+// and strings) through a program, including function and method bodies:
+//
+//   type error: Use of moved value: items
+//
+// `let b = a`, assigning `a` to a variable, field or index, putting it in an
+// array, tuple, map, struct or enum variant, and returning it all move `a`:
+// the new place owns the value, and `a` can't be used again until it's
+// assigned a new one. Passing `a` to a function, calling a method on it,
+// reading its fields and matching or looping over it only borrow it. Ints,
+// floats, bools, `none` and enums without data are copied instead.
+//
+// A value taken out of a place that keeps owning it (a parameter, a field or
+// element, a loop or match variable, or a variable of an enclosing function)
+// isn't moved: the new owner shares it. When the pass can't tell whether a
+// variable holds a `Copy` value (gradual typing), a later use turns its move
+// into a share rather than an error.
+//
+// Moves are tracked along every path: a value moved in one branch of an `if`
+// may have been moved after it, and one moved in a loop body is gone on the
+// next iteration.
+
+use crate::ast::*;
+use crate::error::TogError;
+use crate::span::Span;
+use crate::type_checker::EnumDef;
+use std::collections::HashMap;
+
+/// How an expression in a moving position hands its value over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transfer {
+    Move,  // The variable gives its value up
+    Copy,  // A cheap copy of a `Copy` value
+    Share, // Both places keep the value, counting references
+}
+
+/// Checks `program` for uses of moved values.
+pub fn analyze(program: &Program, enum_defs: &HashMap<String, EnumDef>) -> Result<(), TogError> {
+    let mut returns = HashMap::new();
+    for stmt in &program.statements {
+        if let StmtKind::Expr(Expr { kind: ExprKind::Function { name, return_type: Some(ty), .. }, .. }) = &stmt.kind {
+            returns.insert(name.clone(), ty.clone());
+        }
+    }
+    let mut checker = MoveChecker {
+        enum_defs,
+        returns,
+        vars: Vec::new(),
+        scopes: vec![Vec::new()],
+        function: 0,
+        states: HashMap::new(),
+        reachable: true,
+        loops: Vec::new(),
+        repeating: false,
+        transfers: HashMap::new(),
+    };
+    program.statements.iter().try_for_each(|stmt| checker.stmt(stmt))
+}
+
+// Whether values of a type are copied or moved
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Copy,
+    Owned(String), // What the value is, for diagnostics: "an array", "a Point"
+    Unknown,
+}
+
+impl Kind {
+    /// The kind of a value that holds values of `parts`.
+    fn holding(parts: impl IntoIterator<Item = Kind>, what: String) -> Kind {
+        let mut kind = Kind::Copy;
+        for part in parts {
+            match part {
+                Kind::Owned(_) => return Kind::Owned(what),
+                Kind::Unknown => kind = Kind::Unknown,
+                Kind::Copy => {}
+            }
+        }
+        kind
+    }
+
+    /// The kind of a value that is one of `self` or `other`.
+    fn join(self, other: Kind) -> Kind {
+        match (self, other) {
+            (Kind::Copy, Kind::Copy) => Kind::Copy,
+            (Kind::Owned(what), Kind::Owned(_)) => Kind::Owned(what),
+            _ => Kind::Unknown,
+        }
+    }
+}
+
+struct Var {
+    name: String,
+    kind: Kind,
+    owned: bool,     // False for parameters and loop and match variables, which borrow
+    function: usize, // How many functions deep it was declared
+}
+
+// Where a variable's value was moved. Variables that own their value have no state.
+#[derive(Debug, Clone, PartialEq)]
+struct Moved {
+    at: Vec<Span>,
+    maybe: bool, // Only on some paths
+}
+
+type States = HashMap<usize, Moved>;
+
+fn merge(a: &States, b: &States) -> States {
+    let mut merged = a.clone();
+    for (id, moved) in merged.iter_mut() {
+        if !b.contains_key(id) {
+            moved.maybe = true;
+        }
+    }
+    for (id, moved) in b {
+        match merged.get_mut(id) {
+            Some(existing) => {
+                existing.maybe |= moved.maybe;
+                for span in &moved.at {
+                    if !existing.at.contains(span) {
+                        existing.at.push(*span);
+                    }
+                }
+            }
+            None => {
+                merged.insert(*id, Moved { at: moved.at.clone(), maybe: true });
+            }
+        }
+    }
+    merged
+}
+
+/// The states where paths meet; None if none of them gets there.
+fn join(flows: impl IntoIterator<Item = Option<States>>) -> Option<States> {
+    flows.into_iter().flatten().reduce(|a, b| merge(&a, &b))
+}
+
+#[derive(Default)]
+struct LoopExits {
+    breaks: Vec<Option<States>>,
+    continues: Vec<Option<States>>,
+}
+
+struct MoveChecker<'a> {
+    enum_defs: &'a HashMap<String, EnumDef>,
+    returns: HashMap<String, Type>, // Declared return types of top-level functions
+    vars: Vec<Var>,
+    scopes: Vec<Vec<usize>>,
+    function: usize, // How many functions deep the walk is; 0 at the top level
+    states: States,
+    reachable: bool, // False after a return, break or continue
+    loops: Vec<LoopExits>,
+    repeating: bool, // Walking a loop body again, after the moves of an earlier iteration
+    transfers: HashMap<Span, Transfer>, // By the span of the variable, field, index or `?` moved from
+}
+
+impl MoveChecker<'_> {
+    fn flow(&self) -> Option<States> {
+        self.reachable.then(|| self.states.clone())
+    }
+
+    fn set_flow(&mut self, flow: Option<States>) {
+        match flow {
+            Some(states) => {
+                self.states = states;
+                self.reachable = true;
+            }
+            None => self.reachable = false,
+        }
+    }
+
+    fn declare(&mut self, name: &str, kind: Kind, owned: bool) {
+        let id = self.vars.len();
+        self.vars.push(Var { name: name.to_string(), kind, owned, function: self.function });
+        self.scopes.last_mut().expect("scope").push(id);
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|&id| self.vars[id].name == name)
+    }
+
+    fn kind_of_type(&self, ty: &Type) -> Kind {
+        match ty {
+            Type::Int | Type::Float | Type::Bool | Type::None => Kind::Copy,
+            Type::String => Kind::Owned("a string".to_string()),
+            Type::Array(_) => Kind::Owned("an array".to_string()),
+            Type::Map(..) => Kind::Owned("a map".to_string()),
+            Type::Set(_) => Kind::Owned("a set".to_string()),
+            Type::Tuple(elements) => Kind::holding(elements.iter().map(|e| self.kind_of_type(e)), "a tuple".to_string()),
+            Type::Struct(name) | Type::Enum(name) | Type::Generic { name, .. } => match self.enum_defs.get(name) {
+                Some((_, variants)) if variants.iter().all(|v| v.data_type.is_none()) => Kind::Copy,
+                Some(_) => Kind::Unknown,
+                None => Kind::Owned(format!("a {}", name)),
+            },
+            Type::Param(_) | Type::Function { .. } | Type::Infer => Kind::Unknown,
+        }
+    }
+
+    /// What an expression's value is, as far as its syntax and the variables
+    /// it names tell.
+    fn kind_of_expr(&self, expr: &Expr) -> Kind {
+        match &expr.kind {
+            ExprKind::Literal(lit) => match lit {
+                Literal::Int(_) | Literal::Float(_) | Literal::Bool(_) | Literal::None => Kind::Copy,
+                Literal::String(_) => Kind::Owned("a string".to_string()),
+                Literal::Array(_) => Kind::Owned("an array".to_string()),
+                Literal::Map(_) => Kind::Owned("a map".to_string()),
+                Literal::Tuple(elements) => Kind::holding(elements.iter().map(|e| self.kind_of_expr(e)), "a tuple".to_string()),
+            },
+            ExprKind::InterpolatedString(_) => Kind::Owned("a string".to_string()),
+            ExprKind::StructLiteral { name, .. } => Kind::Owned(format!("a {}", name)),
+            ExprKind::EnumVariant { enum_name, data, .. } => match (self.enum_defs.get(enum_name), data) {
+                (Some(_), Some(data)) => Kind::holding([self.kind_of_expr(data)], format!("a {}", enum_name)),
+                (Some(_), None) => self.kind_of_type(&Type::Enum(enum_name.clone())),
+                (None, _) => Kind::Unknown,
+            },
+            ExprKind::Call { callee, args } => match &callee.kind {
+                ExprKind::EnumVariant { enum_name, .. } => match enum_name.as_str() {
+                    "Map" => Kind::Owned("a map".to_string()),
+                    "Set" => Kind::Owned("a set".to_string()),
+                    _ if self.enum_defs.contains_key(enum_name) => {
+                        Kind::holding(args.iter().map(|a| self.kind_of_expr(a)), format!("a {}", enum_name))
+                    }
+                    _ => Kind::Unknown,
+                },
+                ExprKind::Variable(name) if self.lookup(name).is_none() => {
+                    self.returns.get(name).map_or(Kind::Unknown, |ty| self.kind_of_type(ty))
+                }
+                _ => Kind::Unknown,
+            },
+            ExprKind::Variable(name) => self.lookup(name).map_or(Kind::Unknown, |id| self.vars[id].kind.clone()),
+            ExprKind::BinaryOp { left, op: BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div, right } => {
+                match (self.kind_of_expr(left), self.kind_of_expr(right)) {
+                    (Kind::Copy, Kind::Copy) => Kind::Copy,
+                    // `"total: " + n` builds a new string
+                    (owned @ Kind::Owned(_), _) | (_, owned @ Kind::Owned(_)) => owned,
+                    _ => Kind::Unknown,
+                }
+            }
+            ExprKind::BinaryOp { .. } | ExprKind::UnaryOp { .. } | ExprKind::While { .. } | ExprKind::For { .. } => Kind::Copy,
+            ExprKind::If { then_branch, else_branch, .. } => {
+                let else_kind = else_branch.as_ref().map_or(Kind::Copy, |e| self.kind_of_expr(e));
+                self.kind_of_expr(then_branch).join(else_kind)
+            }
+            ExprKind::Block(statements) => match statements.last().map(|s| &s.kind) {
+                Some(StmtKind::Expr(tail)) => self.kind_of_expr(tail),
+                _ => Kind::Copy,
+            },
+            ExprKind::FieldAccess { .. } | ExprKind::Index { .. } | ExprKind::Try(_) | ExprKind::Match { .. }
+            | ExprKind::Function { .. } | ExprKind::Lambda { .. } => Kind::Unknown,
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.expr(expr, false),
+            StmtKind::Let { name, type_annotation, value } => {
+                let kind = match type_annotation {
+                    Some(ty) => self.kind_of_type(ty),
+                    None => self.kind_of_expr(value),
+                };
+                self.expr(value, true)?;
+                self.declare(name, kind, true);
+                Ok(())
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                self.expr(value, true)?;
+                for name in pattern.bindings() {
+                    self.declare(&name, Kind::Unknown, true);
+                }
+                Ok(())
+            }
+            StmtKind::Assign { name, op, value } => {
+                // `x += y` reads `x` first
+                if op.is_some() {
+                    self.use_variable(name, stmt.span)?;
+                }
+                self.expr(value, true)?;
+                // The variable owns a value again
+                if let Some(id) = self.lookup(name) {
+                    if self.reachable && self.vars[id].function == self.function {
+                        self.states.remove(&id);
+                    }
+                }
+                Ok(())
+            }
+            StmtKind::AssignField { object, value, .. } => {
+                self.expr(value, true)?;
+                self.expr(object, false)
+            }
+            StmtKind::AssignIndex { object, index, value, .. } => {
+                self.expr(value, true)?;
+                self.expr(index, false)?;
+                self.expr(object, false)
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value, true)?;
+                }
+                self.reachable = false;
+                Ok(())
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let flow = self.flow();
+                if let Some(exits) = self.loops.last_mut() {
+                    match stmt.kind {
+                        StmtKind::Break => exits.breaks.push(flow),
+                        _ => exits.continues.push(flow),
+                    }
+                }
+                self.reachable = false;
+                Ok(())
+            }
+            StmtKind::StructDef { methods, .. } | StmtKind::ImplBlock { methods, .. } => {
+                for method in methods {
+                    self.function_body(&method.params, &method.body)?;
+                }
+                Ok(())
+            }
+            StmtKind::TraitDef { methods, .. } => {
+                for method in methods {
+                    if let Some(body) = &method.default_body {
+                        self.function_body(&method.params, body)?;
+                    }
+                }
+                Ok(())
+            }
+            StmtKind::EnumDef { .. } | StmtKind::Import { .. } | StmtKind::Error => Ok(()),
+        }
+    }
+
+    /// Walks `expr`. A `consumed` value moves into wherever it's going;
+    /// otherwise it's only borrowed.
+    fn expr(&mut self, expr: &Expr, consumed: bool) -> Result<(), TogError> {
+        match &expr.kind {
+            ExprKind::Variable(name) if consumed => self.move_variable(name, expr.span),
+            ExprKind::Variable(name) => self.use_variable(name, expr.span),
+            ExprKind::Literal(Literal::Array(elements) | Literal::Tuple(elements)) => {
+                elements.iter().try_for_each(|e| self.expr(e, true))
+            }
+            ExprKind::Literal(Literal::Map(entries)) => {
+                entries.iter().try_for_each(|(key, value)| {
+                    self.expr(key, true)?;
+                    self.expr(value, true)
+                })
+            }
+            ExprKind::StructLiteral { fields, .. } => fields.iter().try_for_each(|(_, value)| self.expr(value, true)),
+            ExprKind::EnumVariant { enum_name, data: Some(data), .. } => {
+                // `Set::from(items)` parses like a variant, but only reads `items`
+                let stored = self.enum_defs.contains_key(enum_name);
+                self.expr(data, stored)
+            }
+            ExprKind::Call { callee, args } => {
+                if let ExprKind::EnumVariant { enum_name, data: None, .. } = &callee.kind {
+                    if self.enum_defs.contains_key(enum_name) {
+                        return args.iter().try_for_each(|arg| self.expr(arg, true));
+                    }
+                }
+                for arg in args {
+                    self.expr(arg, false)?;
+                }
+                match &callee.kind {
+                    ExprKind::FieldAccess { object, .. } => self.expr(object, false),
+                    _ => self.expr(callee, false),
+                }
+            }
+            ExprKind::FieldAccess { .. } | ExprKind::Index { .. } | ExprKind::Try(_) => {
+                for child in expr.children() {
+                    self.expr(child, false)?;
+                }
+                if consumed && self.reachable && root_variable(expr).is_some_and(|name| self.lookup(name).is_some()) {
+                    // The variable keeps its part of the value
+                    let transfer = match self.kind_of_expr(expr) {
+                        Kind::Copy => Transfer::Copy,
+                        _ => Transfer::Share,
+                    };
+                    self.transfers.insert(expr.span, transfer);
+                }
+                Ok(())
+            }
+            ExprKind::Block(statements) => self.block(statements, consumed),
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition, false)?;
+                let entry = self.flow();
+                self.expr(then_branch, consumed)?;
+                let after_then = self.flow();
+                self.set_flow(entry);
+                if let Some(else_branch) = else_branch {
+                    self.expr(else_branch, consumed)?;
+                }
+                let after_else = self.flow();
+                self.set_flow(join([after_then, after_else]));
+                Ok(())
+            }
+            ExprKind::Match { expr: subject, arms } => {
+                self.expr(subject, false)?;
+                let entry = self.flow();
+                let mut exits = Vec::new();
+                for arm in arms {
+                    self.set_flow(entry.clone());
+                    self.scopes.push(Vec::new());
+                    for name in arm.pattern.bindings() {
+                        self.declare(&name, Kind::Unknown, false);
+                    }
+                    let result = match &arm.guard {
+                        Some(guard) => self.expr(guard, false),
+                        None => Ok(()),
+                    }.and_then(|_| self.expr(&arm.body, consumed));
+                    self.scopes.pop();
+                    result?;
+                    exits.push(self.flow());
+                }
+                self.set_flow(join(exits));
+                Ok(())
+            }
+            ExprKind::While { condition, body } => self.walk_loop(None, Some(condition), body),
+            ExprKind::For { pattern, iterable, body } => {
+                self.expr(iterable, false)?;
+                self.walk_loop(Some(pattern), None, body)
+            }
+            ExprKind::Function { name, params, body, .. } => {
+                let declared = self.scopes.last().expect("scope").iter().any(|&id| self.vars[id].name == *name);
+                if !declared {
+                    self.declare(name, Kind::Copy, true);
+                }
+                self.function_body(params, body)
+            }
+            ExprKind::Lambda { params, body, .. } => self.function_body(params, body),
+            _ => expr.children().into_iter().try_for_each(|child| self.expr(child, false)),
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt], consumed: bool) -> Result<(), TogError> {
+        self.scopes.push(Vec::new());
+        let result = statements.iter().enumerate().try_for_each(|(i, stmt)| match &stmt.kind {
+            // The block's value
+            StmtKind::Expr(tail) if consumed && i + 1 == statements.len() => self.expr(tail, true),
+            _ => self.stmt(stmt),
+        });
+        self.scopes.pop();
+        result
+    }
+
+    /// Walks a function, lambda or method body. Parameters borrow their
+    /// arguments, and the body's value is returned.
+    fn function_body(&mut self, params: &[Param], body: &Expr) -> Result<(), TogError> {
+        let loops = std::mem::take(&mut self.loops);
+        let reachable = std::mem::replace(&mut self.reachable, true);
+        let repeating = std::mem::replace(&mut self.repeating, false);
+        self.function += 1;
+        self.scopes.push(Vec::new());
+        for param in params {
+            let kind = param.type_annotation.as_ref().map_or(Kind::Unknown, |ty| self.kind_of_type(ty));
+            self.declare(&param.name, kind, false);
+        }
+        let result = self.expr(body, true);
+        self.scopes.pop();
+        self.function -= 1;
+        self.loops = loops;
+        self.reachable = reachable;
+        self.repeating = repeating;
+        result
+    }
+
+    /// Walks a `while` or `for` loop. If the body moves a variable from
+    /// outside the loop, it's walked again to catch uses of it on the next
+    /// iteration.
+    fn walk_loop(&mut self, pattern: Option<&Pattern>, condition: Option<&Expr>, body: &Expr) -> Result<(), TogError> {
+        let entry = self.flow();
+        let (back, breaks) = self.iteration(pattern, condition, body)?;
+        let head = join([entry.clone(), back]);
+        if head == entry {
+            self.set_flow(join(std::iter::once(entry).chain(breaks)));
+            return Ok(());
+        }
+        self.set_flow(head.clone());
+        let repeating = std::mem::replace(&mut self.repeating, true);
+        let result = self.iteration(pattern, condition, body);
+        self.repeating = repeating;
+        let (back, breaks) = result?;
+        self.set_flow(join([head, back].into_iter().chain(breaks)));
+        Ok(())
+    }
+
+    /// Walks one iteration from the current states. Returns the states that
+    /// go round again and the ones that break out.
+    fn iteration(&mut self, pattern: Option<&Pattern>, condition: Option<&Expr>, body: &Expr) -> Result<(Option<States>, Vec<Option<States>>), TogError> {
+        if let Some(condition) = condition {
+            self.expr(condition, false)?;
+        }
+        self.loops.push(LoopExits::default());
+        self.scopes.push(Vec::new());
+        for name in pattern.map(Pattern::bindings).unwrap_or_default() {
+            self.declare(&name, Kind::Unknown, false);
+        }
+        let result = self.expr(body, false);
+        self.scopes.pop();
+        let exits = self.loops.pop().expect("loop");
+        result?;
+        let back = join(std::iter::once(self.flow()).chain(exits.continues));
+        Ok((back, exits.breaks))
+    }
+
+    /// Reads or borrows a variable, which must still own its value.
+    fn use_variable(&mut self, name: &str, span: Span) -> Result<(), TogError> {
+        let Some(id) = self.lookup(name) else {
+            return Ok(()); // A builtin or type name
+        };
+        if !self.reachable {
+            return Ok(());
+        }
+        let Some(moved) = self.states.get(&id) else {
+            return Ok(());
+        };
+        let Kind::Owned(what) = &self.vars[id].kind else {
+            // It may hold a `Copy` value, so its moves become shares
+            for at in &moved.at {
+                self.transfers.insert(*at, Transfer::Share);
+            }
+            self.states.remove(&id);
+            return Ok(());
+        };
+        let message = if moved.maybe {
+            format!("Use of a value that may have been moved: {}", name)
+        } else {
+            format!("Use of moved value: {}", name)
+        };
+        let label = if self.repeating {
+            "value moved here, in the previous iteration of the loop"
+        } else {
+            "value moved here"
+        };
+        let mut error = TogError::TypeError(message, Some(span));
+        for at in &moved.at {
+            error = error.with_label(*at, label);
+        }
+        Err(error
+            .with_note(format!("`{}` holds {}, which moves rather than being copied", name, what))
+            .with_help(format!("use `{}` before it moves, or assign it a new value first", name)))
+    }
+
+    /// Moves a variable's value into wherever it's going.
+    fn move_variable(&mut self, name: &str, span: Span) -> Result<(), TogError> {
+        self.use_variable(name, span)?;
+        let Some(id) = self.lookup(name) else {
+            return Ok(());
+        };
+        if !self.reachable {
+            return Ok(());
+        }
+        let var = &self.vars[id];
+        let transfer = if var.kind == Kind::Copy {
+            Transfer::Copy
+        } else if !var.owned || var.function != self.function {
+            Transfer::Share
+        } else {
+            // A move that a later use turned into a share stays one
+            *self.transfers.get(&span).unwrap_or(&Transfer::Move)
+        };
+        self.transfers.insert(span, transfer);
+        if transfer == Transfer::Move {
+            self.states.insert(id, Moved { at: vec![span], maybe: false });
+        }
+        Ok(())
+    }
+}
+
+/// The variable a field, index or `?` chain starts from.
+fn root_variable(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Variable(name) => Some(name),
+        ExprKind::FieldAccess { object: inner, .. } | ExprKind::Index { array: inner, .. } | ExprKind::Try(inner) => {
+            root_variable(inner)
+        }
+        _ => None,
+    }
+}
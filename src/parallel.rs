@@ -0,0 +1,559 @@
+// Parallel execution of TOG functions
+//
+// The interpreter's `Value` holds `Rc`/`RefCell` environments and cannot cross
+// threads. Parallel builtins therefore work on snapshots:
+// 1. Data is converted to `SharedValue`, a plain-data mirror of `Value`
+// 2. Functions are packaged as a `SharedFunction`: the function, every
+//    function it reaches by name, the values it captures, and the program's
+//    type definitions. Capture analysis rejects functions that assign to a
+//    captured variable, since each worker only sees its own copy.
+// 3. Each worker thread builds its own interpreter from the snapshot and
+//    pulls chunks of work from a work-stealing pool.
+//
+// Chunk boundaries depend only on the input length, never on the thread
+// count, and chunk results are combined in order. Reductions with an
+// associative function therefore give the same result as a sequential fold,
+// however many threads run and whichever thread finishes first.
+
+use crate::ast::*;
+use crate::error::TogError;
+use crate::interpreter::{is_truthy, Definitions, Interpreter, MapKey, Value};
+use crate::stdlib::FunctionCaller;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Inputs are cut into at most this many chunks so idle workers have
+// something to steal without paying per-element scheduling costs
+const MAX_CHUNKS: usize = 64;
+
+/// Thread-safe snapshot of a data `Value`. Functions are not data and are
+/// shared through `SharedFunction` instead.
+#[derive(Debug, Clone)]
+pub enum SharedValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Array(Vec<SharedValue>),
+    Tuple(Vec<SharedValue>),
+    Map(BTreeMap<MapKey, SharedValue>),
+    Set(BTreeSet<MapKey>),
+    Struct {
+        name: String,
+        fields: HashMap<String, SharedValue>,
+    },
+    Enum {
+        enum_name: String,
+        variant_name: String,
+        data: Option<Box<SharedValue>>,
+    },
+    None,
+}
+
+impl SharedValue {
+    pub fn from_value(value: &Value) -> Result<Self, TogError> {
+        Ok(match value {
+            Value::Int(n) => SharedValue::Int(*n),
+            Value::Float(n) => SharedValue::Float(*n),
+            Value::String(s) => SharedValue::String(s.clone()),
+            Value::Bool(b) => SharedValue::Bool(*b),
+            Value::Array(arr) => SharedValue::Array(
+                arr.iter().map(SharedValue::from_value).collect::<Result<_, _>>()?
+            ),
+            Value::Tuple(elements) => SharedValue::Tuple(
+                elements.iter().map(SharedValue::from_value).collect::<Result<_, _>>()?
+            ),
+            Value::Map(map) => SharedValue::Map(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), SharedValue::from_value(v)?)))
+                    .collect::<Result<_, TogError>>()?
+            ),
+            Value::Set(set) => SharedValue::Set(set.clone()),
+            Value::Struct { name, fields } => SharedValue::Struct {
+                name: name.clone(),
+                fields: fields.iter()
+                    .map(|(k, v)| Ok((k.clone(), SharedValue::from_value(v)?)))
+                    .collect::<Result<_, TogError>>()?,
+            },
+            Value::Enum { enum_name, variant_name, data } => SharedValue::Enum {
+                enum_name: enum_name.clone(),
+                variant_name: variant_name.clone(),
+                data: match data {
+                    Some(d) => Some(Box::new(SharedValue::from_value(d)?)),
+                    None => None,
+                },
+            },
+            Value::Function { name, .. } => {
+                return Err(TogError::RuntimeError(
+                    format!("Function '{}' cannot be sent between threads as data", name),
+                    None
+                ));
+            }
+            Value::Closure(closure) => {
+                return Err(TogError::RuntimeError(
+                    format!("Function '{}' cannot be sent between threads as data", closure.name()),
+                    None
+                ));
+            }
+            Value::None => SharedValue::None,
+        })
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            SharedValue::Int(n) => Value::Int(n),
+            SharedValue::Float(n) => Value::Float(n),
+            SharedValue::String(s) => Value::String(s),
+            SharedValue::Bool(b) => Value::Bool(b),
+            SharedValue::Array(arr) => Value::Array(arr.into_iter().map(SharedValue::into_value).collect()),
+            SharedValue::Tuple(elements) => Value::Tuple(elements.into_iter().map(SharedValue::into_value).collect()),
+            SharedValue::Map(map) => Value::Map(map.into_iter().map(|(k, v)| (k, v.into_value())).collect()),
+            SharedValue::Set(set) => Value::Set(set),
+            SharedValue::Struct { name, fields } => Value::Struct {
+                name,
+                fields: fields.into_iter().map(|(k, v)| (k, v.into_value())).collect(),
+            },
+            SharedValue::Enum { enum_name, variant_name, data } => Value::Enum {
+                enum_name,
+                variant_name,
+                data: data.map(|d| Box::new(d.into_value())),
+            },
+            SharedValue::None => Value::None,
+        }
+    }
+}
+
+/// A function with everything it captures, ready to be rebuilt on a worker.
+#[derive(Debug, Clone)]
+pub struct SharedClosure {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Expr,
+    pub captures: Vec<(String, SharedValue)>,
+    // Captured functions, as indices into `SharedFunction::functions`
+    pub captured_functions: Vec<(String, usize)>,
+}
+
+/// A pure TOG function packaged for worker threads. `functions[0]` is the
+/// function being called; the rest are functions it reaches by name.
+#[derive(Debug, Clone)]
+pub struct SharedFunction {
+    pub definitions: Definitions,
+    pub functions: Vec<SharedClosure>,
+}
+
+impl SharedFunction {
+    /// Builds a fresh interpreter for one worker and returns it together with
+    /// the callable function value.
+    fn instantiate(&self) -> (Interpreter, Value) {
+        Interpreter::for_shared_function(self)
+    }
+}
+
+/// Variables a function body reads from its enclosing scopes, and the ones
+/// among them that it assigns to.
+#[derive(Debug, Default)]
+pub struct Captures {
+    pub read: BTreeSet<String>,
+    pub assigned: BTreeSet<String>,
+}
+
+/// Finds the free variables of a function: the names it uses that no scope
+/// inside it declares. Scopes follow the resolver: parameters, blocks, `for`
+/// loops and match arms each open one, and a variable is only visible after
+/// its declaration, except to nested functions.
+pub fn captured_variables(params: &[Param], body: &Expr) -> Captures {
+    let mut scan = CaptureScan {
+        scopes: Vec::new(),
+        depth: 0,
+        captures: Captures::default(),
+    };
+    scan.scoped(params.iter().map(|param| param.name.clone()), body);
+    scan.captures
+}
+
+struct CaptureScan {
+    scopes: Vec<CaptureScope>,
+    depth: usize, // Functions nested inside the scanned one
+    captures: Captures,
+}
+
+struct CaptureScope {
+    depth: usize,
+    names: HashMap<String, bool>, // Whether each declared name is defined yet
+}
+
+impl CaptureScan {
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| match scope.names.get(name) {
+            Some(defined) => *defined || scope.depth < self.depth,
+            None => false,
+        })
+    }
+
+    fn push_scope(&mut self, names: impl IntoIterator<Item = (String, bool)>) {
+        self.scopes.push(CaptureScope { depth: self.depth, names: names.into_iter().collect() });
+    }
+
+    fn bind(&mut self, names: impl IntoIterator<Item = String>) {
+        let scope = self.scopes.last_mut().expect("scope");
+        scope.names.extend(names.into_iter().map(|name| (name, true)));
+    }
+
+    fn read(&mut self, name: &str) {
+        if !self.is_local(name) {
+            self.captures.read.insert(name.to_string());
+        }
+    }
+
+    fn assign(&mut self, name: &str) {
+        if !self.is_local(name) {
+            self.captures.assigned.insert(name.to_string());
+        }
+    }
+
+    /// Scans `expr` in a new scope holding `names`.
+    fn scoped(&mut self, names: impl IntoIterator<Item = String>, expr: &Expr) {
+        self.push_scope(names.into_iter().map(|name| (name, true)));
+        self.expr(expr);
+        self.scopes.pop();
+    }
+
+    /// Scans a nested function's parameters and body.
+    fn function(&mut self, params: &[Param], body: &Expr) {
+        self.depth += 1;
+        self.scoped(params.iter().map(|param| param.name.clone()), body);
+        self.depth -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Let { name, value, .. } => {
+                self.expr(value);
+                self.bind([name.clone()]);
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                self.expr(value);
+                self.bind(pattern.bindings());
+            }
+            StmtKind::Assign { name, value, .. } => {
+                self.read(name);
+                self.assign(name);
+                self.expr(value);
+            }
+            StmtKind::AssignField { object, value, .. } => {
+                if let Some(root) = root_variable(object) {
+                    self.assign(root);
+                }
+                self.expr(object);
+                self.expr(value);
+            }
+            StmtKind::AssignIndex { object, index, value, .. } => {
+                if let Some(root) = root_variable(object) {
+                    self.assign(root);
+                }
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            StmtKind::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            }
+            // Nested type declarations carry their own method bodies
+            StmtKind::StructDef { .. } | StmtKind::EnumDef { .. } | StmtKind::TraitDef { .. } | StmtKind::ImplBlock { .. } => {}
+            StmtKind::Break | StmtKind::Continue | StmtKind::Import { .. } | StmtKind::Error => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(Literal::Array(elems) | Literal::Tuple(elems)) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+            }
+            ExprKind::Literal(Literal::Map(entries)) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) => self.read(name),
+            ExprKind::StructLiteral { fields, .. } => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::FieldAccess { object, .. } => self.expr(object),
+            ExprKind::BinaryOp { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::UnaryOp { expr, .. } | ExprKind::Try(expr) => self.expr(expr),
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Block(statements) => {
+                // Nested functions see the block's later declarations too
+                let declared = statements.iter().flat_map(|stmt| match &stmt.kind {
+                    StmtKind::Let { name, .. } => vec![name.clone()],
+                    StmtKind::LetPattern { pattern, .. } => pattern.bindings(),
+                    StmtKind::Expr(Expr { kind: ExprKind::Function { name, .. }, .. }) => vec![name.clone()],
+                    _ => Vec::new(),
+                });
+                self.push_scope(declared.map(|name| (name, false)).collect::<Vec<_>>());
+                for stmt in statements {
+                    self.stmt(stmt);
+                }
+                self.scopes.pop();
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition);
+                self.expr(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(else_branch);
+                }
+            }
+            ExprKind::While { condition, body } => {
+                self.expr(condition);
+                self.expr(body);
+            }
+            ExprKind::Match { expr, arms } => {
+                self.expr(expr);
+                for arm in arms {
+                    self.push_scope(arm.pattern.bindings().into_iter().map(|name| (name, true)));
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    self.expr(&arm.body);
+                    self.scopes.pop();
+                }
+            }
+            ExprKind::Function { name, params, body, .. } => {
+                self.bind([name.clone()]);
+                self.function(params, body);
+            }
+            ExprKind::Lambda { params, body, .. } => self.function(params, body),
+            ExprKind::Index { array, index } => {
+                self.expr(array);
+                self.expr(index);
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                self.expr(iterable);
+                self.scoped(pattern.bindings(), body);
+            }
+            ExprKind::EnumVariant { data, .. } => {
+                if let Some(data) = data {
+                    self.expr(data);
+                }
+            }
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr { expr, .. } = part {
+                        self.expr(expr);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn root_variable(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Variable(name) => Some(name),
+        ExprKind::FieldAccess { object, .. } | ExprKind::Index { array: object, .. } => root_variable(object),
+        _ => None,
+    }
+}
+
+/// Number of worker threads: `TOG_THREADS` if set, otherwise one per core.
+pub fn thread_count() -> Result<usize, TogError> {
+    match std::env::var("TOG_THREADS") {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(TogError::RuntimeError(
+                format!("TOG_THREADS must be a positive integer, got '{}'", value),
+                None
+            )),
+        },
+        Err(_) => Ok(thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+    }
+}
+
+fn chunk_size_for(len: usize) -> usize {
+    len.div_ceil(MAX_CHUNKS).max(1)
+}
+
+/// Runs `task` for every chunk index in `0..chunk_count` on a work-stealing
+/// pool and returns the results in chunk order. Each worker owns a deque of
+/// chunk indices, starting with a contiguous block; it pops from the front of
+/// its own deque and, once that is empty, steals from the back of the others.
+/// `init` builds per-worker state on the worker's own thread.
+fn run_chunks<W, T, I, F>(chunk_count: usize, init: I, task: F) -> Result<Vec<T>, TogError>
+where
+    I: Fn() -> W + Sync,
+    F: Fn(&mut W, usize) -> Result<T, TogError> + Sync,
+    T: Send,
+{
+    if chunk_count == 0 {
+        return Ok(Vec::new());
+    }
+    let threads = thread_count()?.min(chunk_count);
+    if threads == 1 {
+        let mut worker = init();
+        return (0..chunk_count).map(|chunk| task(&mut worker, chunk)).collect();
+    }
+
+    let per_thread = chunk_count.div_ceil(threads);
+    let queues: Vec<Mutex<VecDeque<usize>>> = (0..threads)
+        .map(|t| {
+            let start = (t * per_thread).min(chunk_count);
+            let end = ((t + 1) * per_thread).min(chunk_count);
+            Mutex::new((start..end).collect())
+        })
+        .collect();
+    let failed = AtomicBool::new(false);
+
+    let next_chunk = |id: usize| -> Option<usize> {
+        if let Some(chunk) = queues[id].lock().unwrap().pop_front() {
+            return Some(chunk);
+        }
+        (1..threads)
+            .map(|offset| (id + offset) % threads)
+            .find_map(|victim| queues[victim].lock().unwrap().pop_back())
+    };
+
+    let mut finished: Vec<(usize, Result<T, TogError>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|id| {
+                let (init, task, failed, next_chunk) = (&init, &task, &failed, &next_chunk);
+                scope.spawn(move || {
+                    let mut worker = init();
+                    let mut done = Vec::new();
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some(chunk) = next_chunk(id) else { break };
+                        let result = task(&mut worker, chunk);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        done.push((chunk, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        let mut all = Vec::new();
+        for handle in handles {
+            match handle.join() {
+                Ok(done) => all.extend(done),
+                Err(_) => all.push((0, Err(TogError::RuntimeError(
+                    "Parallel worker thread panicked".to_string(),
+                    None
+                )))),
+            }
+        }
+        all
+    });
+
+    finished.sort_by_key(|(chunk, _)| *chunk);
+    finished.into_iter().map(|(_, result)| result).collect()
+}
+
+fn share_all(items: &[Value]) -> Result<Vec<SharedValue>, TogError> {
+    items.iter().map(SharedValue::from_value).collect()
+}
+
+/// Applies `func` to every element, preserving order.
+pub fn map(items: &[Value], func: &SharedFunction, chunk_size: Option<usize>) -> Result<Vec<Value>, TogError> {
+    let items = share_all(items)?;
+    let chunk_size = chunk_size.unwrap_or_else(|| chunk_size_for(items.len()));
+    let chunks = run_chunks(items.len().div_ceil(chunk_size), || func.instantiate(), |(interp, f), chunk| {
+        let start = chunk * chunk_size;
+        let end = (start + chunk_size).min(items.len());
+        items[start..end].iter()
+            .map(|item| SharedValue::from_value(&interp.call_function(f, vec![item.clone().into_value()])?))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(chunks.into_iter().flatten().map(SharedValue::into_value).collect())
+}
+
+/// Keeps the elements for which `func` returns a truthy value, preserving order.
+pub fn filter(items: &[Value], func: &SharedFunction) -> Result<Vec<Value>, TogError> {
+    let shared = share_all(items)?;
+    let chunk_size = chunk_size_for(shared.len());
+    let keep = run_chunks(shared.len().div_ceil(chunk_size), || func.instantiate(), |(interp, f), chunk| {
+        let start = chunk * chunk_size;
+        let end = (start + chunk_size).min(shared.len());
+        shared[start..end].iter()
+            .map(|item| {
+                let verdict = interp.call_function(f, vec![item.clone().into_value()])?;
+                Ok(is_truthy(&verdict))
+            })
+            .collect::<Result<Vec<bool>, TogError>>()
+    })?;
+    Ok(items.iter()
+        .zip(keep.into_iter().flatten())
+        .filter(|(_, keep)| *keep)
+        .map(|(item, _)| item.clone())
+        .collect())
+}
+
+/// Folds each chunk on a worker, starting from the chunk's first element,
+/// then folds `initial` with the chunk results in order on the calling thread.
+pub fn reduce(
+    caller: &mut dyn FunctionCaller,
+    items: &[Value],
+    initial: Value,
+    func: &Value,
+    shared: &SharedFunction,
+) -> Result<Value, TogError> {
+    let items = share_all(items)?;
+    let chunk_size = chunk_size_for(items.len());
+    let partials = run_chunks(items.len().div_ceil(chunk_size), || shared.instantiate(), |(interp, f), chunk| {
+        let start = chunk * chunk_size;
+        let end = (start + chunk_size).min(items.len());
+        let mut acc = items[start].clone().into_value();
+        for item in &items[start + 1..end] {
+            acc = interp.call_function(f, vec![acc, item.clone().into_value()])?;
+        }
+        SharedValue::from_value(&acc)
+    })?;
+
+    let mut acc = initial;
+    for partial in partials {
+        acc = caller.call_function(func, vec![acc, partial.into_value()])?;
+    }
+    Ok(acc)
+}
+
+/// Sums a numeric array across threads. Chunk sums are added in chunk order,
+/// so the floating-point result does not depend on scheduling.
+pub fn sum(items: &[Value]) -> Result<f64, TogError> {
+    let numbers: Vec<f64> = items.iter()
+        .map(|v| match v {
+            Value::Int(i) => Ok(*i as f64),
+            Value::Float(f) => Ok(*f),
+            other => Err(TogError::TypeError(
+                format!("parallel_sum() expects numbers, got {:?}", other),
+                None
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+    let chunk_size = chunk_size_for(numbers.len());
+    let partials = run_chunks(numbers.len().div_ceil(chunk_size), || (), |_, chunk| {
+        let start = chunk * chunk_size;
+        let end = (start + chunk_size).min(numbers.len());
+        Ok(numbers[start..end].iter().sum::<f64>())
+    })?;
+    Ok(partials.into_iter().sum())
+}
@@ -1,302 +1,541 @@
 use crate::ast::*;
 use crate::error::TogError;
-use crate::lexer::{Token, Keyword};
+use crate::lexer::{tokenize_at, Token, TokenKind, Keyword};
+use crate::span::Span;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<TogError>,
+    max_errors: usize,
+    generic_scope: Vec<String>, // Type parameters of the declarations being parsed
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self { tokens, current: 0, errors: Vec::new(), max_errors: 1, generic_scope: Vec::new() }
     }
     
+    /// Parses a whole program, stopping at the first syntax error.
     pub fn parse(tokens: Vec<Token>) -> Result<Program, TogError> {
+        let (program, mut errors) = Self::parse_recovering(tokens, 1);
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Parses a whole program, recovering from syntax errors so that one run
+    /// reports up to `max_errors` of them. Statements that failed to parse are
+    /// kept in the program as `StmtKind::Error`.
+    pub fn parse_recovering(tokens: Vec<Token>, max_errors: usize) -> (Program, Vec<TogError>) {
         let mut parser = Self::new(tokens);
+        parser.max_errors = max_errors.max(1);
         let mut statements = Vec::new();
         
         while !parser.is_at_end() {
-            statements.push(parser.declaration()?);
+            let start = parser.current;
+            match parser.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => match parser.recover(e, start) {
+                    Ok(stmt) => {
+                        statements.push(stmt);
+                        // Recovery stops before a '}' in case it closes a block,
+                        // but there is no block to close at the top level
+                        while parser.check(&TokenKind::RightBrace) {
+                            parser.advance();
+                        }
+                    }
+                    Err(e) => {
+                        parser.errors.push(e);
+                        break;
+                    }
+                },
+            }
         }
         
-        Ok(Program { statements })
+        (Program { statements }, parser.errors)
+    }
+
+    /// Records `error` and skips to the next statement, returning an error node
+    /// for the statement that started at token `start`. Once the error limit is
+    /// reached the error is returned instead, to abandon the parse.
+    fn recover(&mut self, error: TogError, start: usize) -> Result<Stmt, TogError> {
+        if self.errors.len() + 1 >= self.max_errors {
+            return Err(error);
+        }
+        self.errors.push(error);
+        // Always skip the token the statement failed on, so the parse moves on
+        if self.current == start {
+            self.advance();
+        }
+        self.synchronize();
+        let start_span = self.tokens[start].span;
+        let span = if self.current > start { start_span.to(self.previous_span()) } else { start_span };
+        Ok(Stmt::new(StmtKind::Error, span))
+    }
+
+    /// Panic-mode recovery: skips tokens until the start of a new line or a
+    /// declaration keyword, stepping over bracketed groups. Stops before a '}'
+    /// that closes the enclosing block.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        while !self.is_at_end() {
+            if depth == 0 && self.current > 0 && self.peek_span().line > self.previous_span().line {
+                return;
+            }
+            match self.peek() {
+                TokenKind::LeftBrace | TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                TokenKind::RightBrace if depth == 0 => return,
+                TokenKind::RightBrace | TokenKind::RightParen | TokenKind::RightBracket => {
+                    depth = depth.saturating_sub(1);
+                }
+                TokenKind::Keyword(Keyword::Fn | Keyword::Struct | Keyword::Enum | Keyword::Trait | Keyword::Impl | Keyword::Let
+                    | Keyword::Pub | Keyword::Import | Keyword::Use)
+                    if depth == 0 => return,
+                _ => {}
+            }
+            self.advance();
+        }
     }
     
     fn declaration(&mut self) -> Result<Stmt, TogError> {
-        if self.match_token(&[Token::Keyword(Keyword::Let)]) {
+        if self.match_token(&[TokenKind::Keyword(Keyword::Pub)]) {
+            return self.public_declaration();
+        }
+        if self.match_token(&[TokenKind::Keyword(Keyword::Import)]) {
+            return self.import_declaration();
+        }
+        if self.match_token(&[TokenKind::Keyword(Keyword::Use)]) {
+            return self.use_declaration();
+        }
+        if self.match_token(&[TokenKind::Keyword(Keyword::Let)]) {
             self.variable_declaration()
-        } else if self.match_token(&[Token::Keyword(Keyword::Struct)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Struct)]) {
             self.struct_declaration()
-        } else if self.match_token(&[Token::Keyword(Keyword::Enum)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Enum)]) {
             self.enum_declaration()
-        } else if self.match_token(&[Token::Keyword(Keyword::Trait)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Trait)]) {
             self.trait_declaration()
-        } else if self.match_token(&[Token::Keyword(Keyword::Impl)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Impl)]) {
             self.impl_block()
-        } else if self.match_token(&[Token::Keyword(Keyword::Fn)]) {
+        } else if self.check(&TokenKind::Keyword(Keyword::Fn)) && !self.check_ahead(1, &TokenKind::LeftParen) {
+            // `fn(` starts a lambda expression instead
+            self.advance();
             self.function_declaration()
         } else {
             self.statement()
         }
     }
 
+    /// A declaration after `pub`, visible to modules that import this one.
+    fn public_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
+        let declares_item = matches!(
+            self.peek(),
+            TokenKind::Keyword(Keyword::Fn | Keyword::Struct | Keyword::Enum | Keyword::Trait | Keyword::Let)
+        );
+        if !declares_item || self.check_ahead(1, &TokenKind::LeftParen) {
+            return Err(TogError::ParseError(
+                "Expected 'fn', 'struct', 'enum', 'trait' or 'let' after 'pub'".to_string(),
+                self.peek_span()
+            ));
+        }
+        let mut stmt = self.declaration()?;
+        stmt.span = start.to(stmt.span);
+        stmt.public = true;
+        Ok(stmt)
+    }
+
+    /// `import "path/to/file.tog"`, optionally `as name`.
+    fn import_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
+        let path = match self.peek() {
+            TokenKind::String(path) => path.clone(),
+            _ => return Err(TogError::ParseError(
+                "Expected a file path string after 'import'".to_string(),
+                self.peek_span()
+            )),
+        };
+        self.advance();
+        let alias = self.import_alias()?;
+        Ok(Stmt::new(StmtKind::Import { source: ImportSource::File(path), alias }, self.span_from(start)))
+    }
+
+    /// `use math::vector` or `use math::vector::dot`, optionally `as name`.
+    fn use_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
+        let mut segments = vec![self.consume_identifier()?];
+        while self.match_token(&[TokenKind::ColonColon]) {
+            segments.push(self.consume_identifier()?);
+        }
+        let alias = self.import_alias()?;
+        Ok(Stmt::new(StmtKind::Import { source: ImportSource::Path(segments), alias }, self.span_from(start)))
+    }
+
+    fn import_alias(&mut self) -> Result<Option<String>, TogError> {
+        if self.match_token(&[TokenKind::Keyword(Keyword::As)]) {
+            Ok(Some(self.consume_identifier()?))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn struct_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let name = self.consume_identifier()?;
-        self.consume(&Token::LeftBrace, "Expected '{' after struct name")?;
-        let mut fields = Vec::new();
-        let mut methods = Vec::new();
+        let type_params = self.type_parameters()?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after struct name")?;
 
-        // Parse fields
-        while !self.check(&Token::RightBrace) && !self.check(&Token::Keyword(Keyword::Fn)) && !self.is_at_end() {
-            let field_name = self.consume_identifier()?;
-            self.consume(&Token::Colon, "Expected ':' after field name")?;
-            let field_type = self.parse_type()?;
-            fields.push((field_name, Some(field_type)));
+        let (fields, methods) = self.in_generic_scope(&type_params, |parser| {
+            let mut fields = Vec::new();
+            let mut methods = Vec::new();
 
-            // If there's no comma, it must be the end of fields (or start of methods/end of struct)
-            if !self.match_token(&[Token::Comma]) {
-                break;
-            }
-        }
+            // Parse fields
+            while !parser.check(&TokenKind::RightBrace) && !parser.check(&TokenKind::Keyword(Keyword::Fn)) && !parser.is_at_end() {
+                let field_name = parser.consume_identifier()?;
+                parser.consume(&TokenKind::Colon, "Expected ':' after field name")?;
+                let field_type = parser.parse_type()?;
+                fields.push((field_name, Some(field_type)));
 
-        // Now, parse methods
-        while self.match_token(&[Token::Keyword(Keyword::Fn)]) {
-            let method_name = self.consume_identifier()?;
-            self.consume(&Token::LeftParen, "Expected '(' after method name")?;
-            let mut params = Vec::new();
-            if !self.check(&Token::RightParen) {
-                loop {
-                    let param_name = self.consume_identifier()?;
-                    let param_type = if self.match_token(&[Token::Colon]) {
-                        Some(self.parse_type()?)
-                    } else {
-                        None
-                    };
-                    params.push(Param {
-                        name: param_name,
-                        type_annotation: param_type,
-                    });
-                    
-                    if !self.match_token(&[Token::Comma]) {
-                        break;
-                    }
+                // If there's no comma, it must be the end of fields (or start of methods/end of struct)
+                if !parser.match_token(&[TokenKind::Comma]) {
+                    break;
                 }
             }
-            self.consume(&Token::RightParen, "Expected ')' after parameters")?;
-            
-            let return_type = if self.match_token(&[Token::Arrow]) {
-                Some(self.parse_type()?)
-            } else {
-                None
-            };
-            
-            self.consume(&Token::LeftBrace, "Expected '{' after method signature")?;
-            let body = self.block_with_brace_consumed()?;
-            
-            methods.push(MethodDecl {
-                name: method_name,
-                params,
-                return_type,
-                body,
-            });
-        }
 
-        self.consume(&Token::RightBrace, "Expected '}' after struct body")?;
+            // Now, parse methods
+            while parser.match_token(&[TokenKind::Keyword(Keyword::Fn)]) {
+                methods.push(parser.method_declaration()?);
+            }
+            Ok((fields, methods))
+        })?;
+
+        self.consume(&TokenKind::RightBrace, "Expected '}' after struct body")?;
 
-        Ok(Stmt::StructDef {
+        Ok(Stmt::new(StmtKind::StructDef {
             name,
+            type_params,
             fields,
             methods,
-        })
+        }, self.span_from(start)))
     }
 
     fn enum_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let name = self.consume_identifier()?;
-        self.consume(&Token::LeftBrace, "Expected '{' after enum name")?;
-        let mut variants = Vec::new();
+        let type_params = self.type_parameters()?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after enum name")?;
 
-        // Parse enum variants
-        while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            let variant_name = self.consume_identifier()?;
-            
-            // Check for associated data type
-            let data_type = if self.match_token(&[Token::LeftParen]) {
-                let ty = self.parse_type()?;
-                self.consume(&Token::RightParen, "Expected ')' after enum variant type")?;
-                Some(ty)
-            } else {
-                None
-            };
-            
-            variants.push(EnumVariant {
-                name: variant_name,
-                data_type,
-            });
-            
-            // Comma is optional for the last variant
-            if !self.match_token(&[Token::Comma]) {
-                break;
+        let variants = self.in_generic_scope(&type_params, |parser| {
+            let mut variants = Vec::new();
+
+            // Parse enum variants
+            while !parser.check(&TokenKind::RightBrace) && !parser.is_at_end() {
+                let variant_name = parser.consume_identifier()?;
+                
+                // Check for associated data type; Move(int, int) holds a tuple
+                let data_type = if parser.match_token(&[TokenKind::LeftParen]) {
+                    let mut types = vec![parser.parse_type()?];
+                    while parser.match_token(&[TokenKind::Comma]) {
+                        types.push(parser.parse_type()?);
+                    }
+                    parser.consume(&TokenKind::RightParen, "Expected ')' after enum variant type")?;
+                    if types.len() == 1 {
+                        types.pop()
+                    } else {
+                        Some(Type::Tuple(types))
+                    }
+                } else {
+                    None
+                };
+                
+                variants.push(EnumVariant {
+                    name: variant_name,
+                    data_type,
+                });
+                
+                // Comma is optional for the last variant
+                if !parser.match_token(&[TokenKind::Comma]) {
+                    break;
+                }
             }
-        }
+            Ok(variants)
+        })?;
 
-        self.consume(&Token::RightBrace, "Expected '}' after enum body")?;
+        self.consume(&TokenKind::RightBrace, "Expected '}' after enum body")?;
 
-        Ok(Stmt::EnumDef { name, variants })
+        Ok(Stmt::new(StmtKind::EnumDef { name, type_params, variants }, self.span_from(start)))
     }
 
     fn trait_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let name = self.consume_identifier()?;
-        self.consume(&Token::LeftBrace, "Expected '{' after trait name")?;
-        let mut methods = Vec::new();
-
-        // Parse trait method signatures
-        while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            self.consume(&Token::Keyword(Keyword::Fn), "Expected 'fn' in trait method")?;
-            let method_name = self.consume_identifier()?;
-            self.consume(&Token::LeftParen, "Expected '(' after method name")?;
-            
-            let mut params = Vec::new();
-            if !self.check(&Token::RightParen) {
-                loop {
-                    let param_name = self.consume_identifier()?;
-                    let param_type = if self.match_token(&[Token::Colon]) {
-                        Some(self.parse_type()?)
-                    } else {
-                        None
-                    };
-                    params.push(Param {
-                        name: param_name,
-                        type_annotation: param_type,
-                    });
-                    
-                    if !self.match_token(&[Token::Comma]) {
-                        break;
-                    }
-                }
+        let type_params = self.type_parameters()?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after trait name")?;
+
+        let methods = self.in_generic_scope(&type_params, |parser| {
+            let mut methods = Vec::new();
+
+            // Parse trait method signatures and default methods
+            while !parser.check(&TokenKind::RightBrace) && !parser.is_at_end() {
+                parser.consume(&TokenKind::Keyword(Keyword::Fn), "Expected 'fn' in trait method")?;
+                let method_name = parser.consume_identifier()?;
+                parser.consume(&TokenKind::LeftParen, "Expected '(' after method name")?;
+                let params = parser.method_parameters()?;
+                parser.consume(&TokenKind::RightParen, "Expected ')' after parameters")?;
+                
+                let return_type = if parser.match_token(&[TokenKind::Arrow]) {
+                    Some(parser.parse_type()?)
+                } else {
+                    None
+                };
+                
+                // A body makes this a default method; otherwise it's just a signature
+                let default_body = if parser.match_token(&[TokenKind::LeftBrace]) {
+                    Some(parser.block_with_brace_consumed()?)
+                } else {
+                    // Optionally consume semicolon
+                    parser.match_token(&[TokenKind::Semicolon]);
+                    None
+                };
+                
+                methods.push(TraitMethod {
+                    name: method_name,
+                    params,
+                    return_type,
+                    default_body,
+                });
             }
-            self.consume(&Token::RightParen, "Expected ')' after parameters")?;
-            
-            let return_type = if self.match_token(&[Token::Arrow]) {
-                Some(self.parse_type()?)
-            } else {
-                None
-            };
-            
-            // Trait methods don't have bodies, just signatures
-            // Optionally consume semicolon
-            self.match_token(&[Token::Semicolon]);
-            
-            methods.push(TraitMethod {
-                name: method_name,
-                params,
-                return_type,
-            });
-        }
+            Ok(methods)
+        })?;
 
-        self.consume(&Token::RightBrace, "Expected '}' after trait body")?;
+        self.consume(&TokenKind::RightBrace, "Expected '}' after trait body")?;
 
-        Ok(Stmt::TraitDef { name, methods })
+        Ok(Stmt::new(StmtKind::TraitDef { name, type_params, methods }, self.span_from(start)))
     }
 
     fn impl_block(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         // impl TraitName for TypeName { ... }
         // or
         // impl TypeName { ... } (inherent impl)
+        // Either can be generic: impl<T> Show for Box<T> { ... }
         
-        let first_name = self.consume_identifier()?;
+        let type_params = self.type_parameters()?;
+        self.in_generic_scope(&type_params.clone(), |parser| {
+            let first_name = parser.impl_type_name()?;
+            
+            let (trait_name, type_name) = if parser.match_token(&[TokenKind::Keyword(Keyword::For)]) {
+                // impl TraitName for TypeName
+                let type_name = parser.impl_type_name()?;
+                (Some(first_name), type_name)
+            } else {
+                // impl TypeName (inherent impl)
+                (None, first_name)
+            };
+            
+            parser.consume(&TokenKind::LeftBrace, "Expected '{' after impl declaration")?;
+            let mut methods = Vec::new();
+
+            // Parse method implementations
+            while parser.match_token(&[TokenKind::Keyword(Keyword::Fn)]) {
+                methods.push(parser.method_declaration()?);
+            }
+
+            parser.consume(&TokenKind::RightBrace, "Expected '}' after impl body")?;
+
+            Ok(Stmt::new(StmtKind::ImplBlock {
+                type_params,
+                trait_name,
+                type_name,
+                methods,
+            }, parser.span_from(start)))
+        })
+    }
+
+    /// A type or trait name in an impl header. Type arguments are dropped, as
+    /// the impl applies to every instance of a generic type. Builtin types can
+    /// implement traits too: `impl Display for int`.
+    fn impl_type_name(&mut self) -> Result<String, TogError> {
+        let primitive = match self.peek() {
+            TokenKind::Keyword(Keyword::Int) => Some("int"),
+            TokenKind::Keyword(Keyword::Float) => Some("float"),
+            TokenKind::Keyword(Keyword::String) => Some("string"),
+            TokenKind::Keyword(Keyword::Bool) => Some("bool"),
+            _ => None,
+        };
+        if let Some(name) = primitive {
+            self.advance();
+            return Ok(name.to_string());
+        }
+        let name = self.path()?;
+        if self.check(&TokenKind::Lt) {
+            self.type_arguments()?;
+        }
+        Ok(name)
+    }
+
+    /// A method in a struct body or impl block, after the `fn`.
+    fn method_declaration(&mut self) -> Result<MethodDecl, TogError> {
+        let method_name = self.consume_identifier()?;
+        self.consume(&TokenKind::LeftParen, "Expected '(' after method name")?;
+        let params = self.method_parameters()?;
+        self.consume(&TokenKind::RightParen, "Expected ')' after parameters")?;
         
-        let (trait_name, type_name) = if self.match_token(&[Token::Keyword(Keyword::For)]) {
-            // impl TraitName for TypeName
-            let type_name = self.consume_identifier()?;
-            (Some(first_name), type_name)
+        let return_type = if self.match_token(&[TokenKind::Arrow]) {
+            Some(self.parse_type()?)
         } else {
-            // impl TypeName (inherent impl)
-            (None, first_name)
+            None
         };
         
-        self.consume(&Token::LeftBrace, "Expected '{' after impl declaration")?;
-        let mut methods = Vec::new();
-
-        // Parse method implementations
-        while self.match_token(&[Token::Keyword(Keyword::Fn)]) {
-            let method_name = self.consume_identifier()?;
-            self.consume(&Token::LeftParen, "Expected '(' after method name")?;
-            let mut params = Vec::new();
-            if !self.check(&Token::RightParen) {
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after method signature")?;
+        let body = self.block_with_brace_consumed()?;
+        
+        Ok(MethodDecl {
+            name: method_name,
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    /// Optional `<T, U: Bound + Other>` after a declaration's name.
+    fn type_parameters(&mut self) -> Result<Vec<TypeParam>, TogError> {
+        let mut params: Vec<TypeParam> = Vec::new();
+        if !self.match_token(&[TokenKind::Lt]) {
+            return Ok(params);
+        }
+        loop {
+            let name_span = self.peek_span();
+            let name = self.consume_identifier()?;
+            if params.iter().any(|p| p.name == name) {
+                return Err(TogError::ParseError(
+                    format!("Type parameter '{}' is declared twice", name),
+                    name_span
+                ));
+            }
+            let mut bounds = Vec::new();
+            if self.match_token(&[TokenKind::Colon]) {
                 loop {
-                    let param_name = self.consume_identifier()?;
-                    let param_type = if self.match_token(&[Token::Colon]) {
-                        Some(self.parse_type()?)
-                    } else {
-                        None
-                    };
-                    params.push(Param {
-                        name: param_name,
-                        type_annotation: param_type,
-                    });
-                    
-                    if !self.match_token(&[Token::Comma]) {
+                    bounds.push(self.path()?);
+                    if !self.match_token(&[TokenKind::Plus]) {
                         break;
                     }
                 }
             }
-            self.consume(&Token::RightParen, "Expected ')' after parameters")?;
-            
-            let return_type = if self.match_token(&[Token::Arrow]) {
-                Some(self.parse_type()?)
-            } else {
-                None
-            };
-            
-            self.consume(&Token::LeftBrace, "Expected '{' after method signature")?;
-            let body = self.block_with_brace_consumed()?;
-            
-            methods.push(MethodDecl {
-                name: method_name,
-                params,
-                return_type,
-                body,
-            });
+            params.push(TypeParam { name, bounds });
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
         }
+        self.consume(&TokenKind::Gt, "Expected '>' after type parameters")?;
+        Ok(params)
+    }
 
-        self.consume(&Token::RightBrace, "Expected '}' after impl body")?;
+    /// `<int, T>` after a generic type's name.
+    fn type_arguments(&mut self) -> Result<Vec<Type>, TogError> {
+        self.consume(&TokenKind::Lt, "Expected '<'")?;
+        let mut args = Vec::new();
+        loop {
+            args.push(self.parse_type()?);
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenKind::Gt, "Expected '>' after type arguments")?;
+        Ok(args)
+    }
 
-        Ok(Stmt::ImplBlock {
-            trait_name,
-            type_name,
-            methods,
-        })
+    /// Runs `parse` with `type_params` in scope, so `parse_type` reads their
+    /// names as `Type::Param`.
+    fn in_generic_scope<T>(
+        &mut self,
+        type_params: &[TypeParam],
+        parse: impl FnOnce(&mut Self) -> Result<T, TogError>,
+    ) -> Result<T, TogError> {
+        let outer = self.generic_scope.len();
+        self.generic_scope.extend(type_params.iter().map(|p| p.name.clone()));
+        let result = parse(self);
+        self.generic_scope.truncate(outer);
+        result
     }
     
     fn variable_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
+        let plain_name = matches!(self.peek(), TokenKind::Identifier(_))
+            && (self.check_ahead(1, &TokenKind::Eq) || self.check_ahead(1, &TokenKind::Colon));
+        if !plain_name {
+            // Destructuring: let Point { x, y } = p
+            let pattern = self.parse_pattern()?;
+            self.consume(&TokenKind::Eq, "Expected '=' after pattern")?;
+            let value = self.expression()?;
+            return Ok(Stmt::new(StmtKind::LetPattern { pattern, value }, self.span_from(start)));
+        }
         let name = self.consume_identifier()?;
         
-        let type_annotation = if self.match_token(&[Token::Colon]) {
+        let type_annotation = if self.match_token(&[TokenKind::Colon]) {
             Some(self.parse_type()?)
         } else {
             None
         };
         
-        self.consume(&Token::Eq, "Expected '=' after variable name")?;
+        self.consume(&TokenKind::Eq, "Expected '=' after variable name")?;
         let value = self.expression()?;
         
-        Ok(Stmt::Let {
+        Ok(Stmt::new(StmtKind::Let {
             name,
             type_annotation,
             value,
-        })
+        }, self.span_from(start)))
     }
     
     fn function_declaration(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let name = self.consume_identifier()?;
-        self.consume(&Token::LeftParen, "Expected '(' after function name")?;
+        let type_params = self.type_parameters()?;
+        let (params, return_type, body) = self.in_generic_scope(&type_params, |parser| {
+            parser.consume(&TokenKind::LeftParen, "Expected '(' after function name")?;
+            let params = parser.parameters(&TokenKind::RightParen)?;
+            parser.consume(&TokenKind::RightParen, "Expected ')' after parameters")?;
+            
+            let return_type = if parser.match_token(&[TokenKind::Arrow]) {
+                Some(parser.parse_type()?)
+            } else {
+                None
+            };
+            
+            // Functions must have braces for now
+            parser.consume(&TokenKind::LeftBrace, "Expected '{' after function signature")?;
+            let body = parser.block_with_brace_consumed()?;
+            Ok((params, return_type, body))
+        })?;
         
+        let span = self.span_from(start);
+        Ok(Stmt::new(StmtKind::Expr(Expr::new(ExprKind::Function {
+            name,
+            type_params,
+            params,
+            return_type,
+            body: Box::new(body),
+        }, span)), span))
+    }
+    
+    /// Comma-separated parameters with optional type annotations, up to but
+    /// not including `end`.
+    fn parameters(&mut self, end: &TokenKind) -> Result<Vec<Param>, TogError> {
         let mut params = Vec::new();
-        if !self.check(&Token::RightParen) {
+        if !self.check(end) {
             loop {
+                if self.check(&TokenKind::Ampersand) {
+                    return Err(TogError::ParseError(
+                        "`&mut` can only take a method's first parameter, `self`".to_string(),
+                        self.peek_span()
+                    ));
+                }
                 let param_name = self.consume_identifier()?;
-                let param_type = if self.match_token(&[Token::Colon]) {
+                let param_type = if self.match_token(&[TokenKind::Colon]) {
                     Some(self.parse_type()?)
                 } else {
                     None
@@ -304,60 +543,146 @@ impl Parser {
                 params.push(Param {
                     name: param_name,
                     type_annotation: param_type,
+                    by_ref: false,
                 });
                 
-                if !self.match_token(&[Token::Comma]) {
+                if !self.match_token(&[TokenKind::Comma]) {
                     break;
                 }
             }
         }
-        
-        self.consume(&Token::RightParen, "Expected ')' after parameters")?;
-        
-        let return_type = if self.match_token(&[Token::Arrow]) {
+        Ok(params)
+    }
+
+    /// A method's parameters, which may start with a `&mut self` receiver.
+    fn method_parameters(&mut self) -> Result<Vec<Param>, TogError> {
+        if !self.match_token(&[TokenKind::Ampersand]) {
+            return self.parameters(&TokenKind::RightParen);
+        }
+        let start = self.previous_span();
+        self.consume(&TokenKind::Keyword(Keyword::Mut), "Expected 'mut' after '&'")?;
+        let name = self.consume_identifier()?;
+        if name != "self" {
+            return Err(TogError::ParseError(
+                format!("Only `self` can be taken as `&mut`, not '{}'", name),
+                self.span_from(start)
+            ).with_help("other parameters are passed by value; return the new value instead"));
+        }
+        let mut params = vec![Param { name, type_annotation: None, by_ref: true }];
+        if self.match_token(&[TokenKind::Comma]) {
+            params.extend(self.parameters(&TokenKind::RightParen)?);
+        }
+        Ok(params)
+    }
+
+    /// `fn(params) -> T { body }`, after the `fn`.
+    fn fn_lambda(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span(); // The keyword
+        self.consume(&TokenKind::LeftParen, "Expected '(' after 'fn'")?;
+        let params = self.parameters(&TokenKind::RightParen)?;
+        self.consume(&TokenKind::RightParen, "Expected ')' after parameters")?;
+        let return_type = if self.match_token(&[TokenKind::Arrow]) {
             Some(self.parse_type()?)
         } else {
             None
         };
-        
-        // Functions must have braces for now
-        self.consume(&Token::LeftBrace, "Expected '{' after function signature")?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after lambda signature")?;
         let body = self.block_with_brace_consumed()?;
-        
-        Ok(Stmt::Expr(Expr::Function {
-            name,
+        Ok(Expr::new(ExprKind::Lambda {
             params,
             return_type,
             body: Box::new(body),
-        }))
+        }, self.span_from(start)))
     }
-    
+
+    /// `|params| expr` or `|params| -> T { body }`, after the opening `|`.
+    /// `||` has already been read as a single token when there are no parameters.
+    fn pipe_lambda(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span();
+        let params = if matches!(self.previous(), TokenKind::Or) {
+            Vec::new()
+        } else {
+            let params = self.parameters(&TokenKind::Pipe)?;
+            self.consume(&TokenKind::Pipe, "Expected '|' after lambda parameters")?;
+            params
+        };
+        let return_type = if self.match_token(&[TokenKind::Arrow]) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        let body = if return_type.is_some() || self.check(&TokenKind::LeftBrace) {
+            // A return type needs a block body, as in Rust
+            self.consume(&TokenKind::LeftBrace, "Expected '{' after lambda return type")?;
+            self.block_with_brace_consumed()?
+        } else {
+            self.expression()?
+        };
+        Ok(Expr::new(ExprKind::Lambda {
+            params,
+            return_type,
+            body: Box::new(body),
+        }, self.span_from(start)))
+    }
+
     fn parse_type(&mut self) -> Result<Type, TogError> {
-        if self.match_token(&[Token::Keyword(Keyword::Int)]) {
+        if self.match_token(&[TokenKind::Keyword(Keyword::Int)]) {
             Ok(Type::Int)
-        } else if self.match_token(&[Token::Keyword(Keyword::Float)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Float)]) {
             Ok(Type::Float)
-        } else if self.match_token(&[Token::Keyword(Keyword::String)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::String)]) {
             Ok(Type::String)
-        } else if self.match_token(&[Token::Keyword(Keyword::Bool)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Bool)]) {
             Ok(Type::Bool)
-        } else if self.match_token(&[Token::Keyword(Keyword::Array)]) {
-            self.consume(&Token::LeftBracket, "Expected '[' after 'array'")?;
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Array)]) {
+            self.consume(&TokenKind::LeftBracket, "Expected '[' after 'array'")?;
             let inner_type = self.parse_type()?;
-            self.consume(&Token::RightBracket, "Expected ']' after array type")?;
+            self.consume(&TokenKind::RightBracket, "Expected ']' after array type")?;
             Ok(Type::Array(Box::new(inner_type)))
-        } else if let Token::Identifier(name) = self.peek() {
+        } else if self.match_token(&[TokenKind::LeftParen]) {
+            // A tuple type: (int, string), or (int,) with a single element
+            let mut elements = Vec::new();
+            while !self.check(&TokenKind::RightParen) {
+                elements.push(self.parse_type()?);
+                if !self.match_token(&[TokenKind::Comma]) {
+                    break;
+                }
+            }
+            self.consume(&TokenKind::RightParen, "Expected ')' after tuple type")?;
+            Ok(Type::Tuple(elements))
+        } else if matches!(self.peek(), TokenKind::Identifier(name) if name == "map") && self.check_ahead(1, &TokenKind::LeftBracket) {
+            // `map` and `set` stay usable as names; only `map[` starts a type
+            self.advance();
+            self.advance();
+            let key_type = self.parse_type()?;
+            self.consume(&TokenKind::Comma, "Expected ',' between map key and value types")?;
+            let value_type = self.parse_type()?;
+            self.consume(&TokenKind::RightBracket, "Expected ']' after map type")?;
+            Ok(Type::Map(Box::new(key_type), Box::new(value_type)))
+        } else if matches!(self.peek(), TokenKind::Identifier(name) if name == "set") && self.check_ahead(1, &TokenKind::LeftBracket) {
+            self.advance();
+            self.advance();
+            let element_type = self.parse_type()?;
+            self.consume(&TokenKind::RightBracket, "Expected ']' after set type")?;
+            Ok(Type::Set(Box::new(element_type)))
+        } else if let TokenKind::Identifier(_) = self.peek() {
+            let name = self.path()?;
+            if self.generic_scope.contains(&name) {
+                return Ok(Type::Param(name));
+            }
+            if self.check(&TokenKind::Lt) {
+                let args = self.type_arguments()?;
+                return Ok(Type::Generic { name, args });
+            }
             // Struct or Enum type name
             // We can't distinguish here, so we'll treat both as custom types
             // The type checker will validate later
-            let name = name.clone();
-            self.advance();
             // For now, assume it's a struct. The interpreter will handle enums.
             Ok(Type::Struct(name))
         } else {
             Err(TogError::ParseError(
                 "Expected type".to_string(),
-                0, 0
+                self.peek_span()
             ))
         }
     }
@@ -367,127 +692,132 @@ impl Parser {
         if self.is_at_end() {
             return Err(TogError::ParseError(
                 "Unexpected end of file".to_string(),
-                0, 0
+                self.peek_span()
             ));
         }
         
-        // Check for assignment: identifier = expression or field_access = expression
-        let current_pos = self.current;
-        if current_pos + 1 < self.tokens.len() {
-            if let Token::Identifier(_) = self.peek() {
-                // Check for simple assignment: identifier = ...
-                if matches!(&self.tokens[current_pos + 1], Token::Eq) {
-                    if let Token::Identifier(name) = self.peek() {
-                        let var_name = name.clone();
-                        self.advance(); // consume identifier
-                        self.advance(); // consume =
-                        let value = self.expression()?;
-                        return Ok(Stmt::Assign {
-                            name: var_name,
-                            value,
-                        });
-                    }
-                }
-                // Check for field assignment: identifier.field = ...
-                else if current_pos + 3 < self.tokens.len() {
-                    if matches!(&self.tokens[current_pos + 1], Token::Dot) {
-                        if let Token::Identifier(_) = &self.tokens[current_pos + 2] {
-                            if matches!(&self.tokens[current_pos + 3], Token::Eq) {
-                                // Parse: identifier.field = value
-                                let obj_name = if let Token::Identifier(name) = self.peek() {
-                                    name.clone()
-                                } else {
-                                    return Err(TogError::ParseError("Expected identifier".to_string(), 0, 0));
-                                };
-                                self.advance(); // consume identifier
-                                self.consume(&Token::Dot, "Expected '.'")?;
-                                let field_name = self.consume_identifier()?;
-                                self.consume(&Token::Eq, "Expected '='")?;
-                                let value = self.expression()?;
-                                return Ok(Stmt::AssignField {
-                                    object: Box::new(Expr::Variable(obj_name)),
-                                    field: field_name,
-                                    value,
-                                });
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        let start = self.peek_span();
         
         // print is now a function call, not a statement
-        if self.match_token(&[Token::Keyword(Keyword::Return)]) {
-            let value = if !self.check(&Token::Semicolon) && !self.is_at_end() {
+        if self.match_token(&[TokenKind::Keyword(Keyword::Return)]) {
+            let value = if !self.check(&TokenKind::Semicolon) && !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
                 Some(self.expression()?)
             } else {
                 None
             };
-            Ok(Stmt::Return(value))
-        } else if self.match_token(&[Token::Keyword(Keyword::Break)]) {
-            Ok(Stmt::Break)
-        } else if self.match_token(&[Token::Keyword(Keyword::Continue)]) {
-            Ok(Stmt::Continue)
-        } else if self.match_token(&[Token::LeftBrace]) {
-            Ok(Stmt::Expr(self.block()?))
-        } else if self.match_token(&[Token::Keyword(Keyword::If)]) {
+            Ok(Stmt::new(StmtKind::Return(value), self.span_from(start)))
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Break)]) {
+            Ok(Stmt::new(StmtKind::Break, start))
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::Continue)]) {
+            Ok(Stmt::new(StmtKind::Continue, start))
+        } else if self.match_token(&[TokenKind::LeftBrace]) {
+            let block = self.block_with_brace_consumed()?;
+            Ok(Stmt::new(StmtKind::Expr(block), self.span_from(start)))
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::If)]) {
             self.if_statement()
-        } else if self.match_token(&[Token::Keyword(Keyword::While)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::While)]) {
             self.while_statement()
-        } else if self.match_token(&[Token::Keyword(Keyword::For)]) {
+        } else if self.match_token(&[TokenKind::Keyword(Keyword::For)]) {
             self.for_statement()
         } else {
             let expr = self.expression()?;
-            Ok(Stmt::Expr(expr))
+            if self.match_token(&[
+                TokenKind::Eq,
+                TokenKind::PlusEq,
+                TokenKind::MinusEq,
+                TokenKind::StarEq,
+                TokenKind::SlashEq,
+                TokenKind::PercentEq,
+            ]) {
+                let op = match self.previous() {
+                    TokenKind::PlusEq => Some(BinaryOp::Add),
+                    TokenKind::MinusEq => Some(BinaryOp::Sub),
+                    TokenKind::StarEq => Some(BinaryOp::Mul),
+                    TokenKind::SlashEq => Some(BinaryOp::Div),
+                    TokenKind::PercentEq => Some(BinaryOp::Mod),
+                    _ => None,
+                };
+                let value = self.expression()?;
+                return self.assignment_statement(expr, op, value, start);
+            }
+            let span = expr.span;
+            Ok(Stmt::new(StmtKind::Expr(expr), span))
         }
     }
     
+    /// Build the statement for `target = value`, or `target op= value`. The
+    /// target can be a variable, a field or an index, and fields and indexes
+    /// can be chained (`obj.items[i].x = y`).
+    fn assignment_statement(&self, target: Expr, op: Option<BinaryOp>, value: Expr, start: Span) -> Result<Stmt, TogError> {
+        let kind = match target.kind {
+            ExprKind::Variable(name) => StmtKind::Assign { name, op, value },
+            ExprKind::FieldAccess { object, field } => StmtKind::AssignField { object, field, op, value },
+            ExprKind::Index { array, index } => StmtKind::AssignIndex {
+                object: array,
+                index: *index,
+                op,
+                value,
+            },
+            _ => {
+                return Err(TogError::ParseError("Invalid assignment target".to_string(), target.span)
+                    .with_help("only variables, fields and indexes can be assigned to, e.g. `x = 1`, `p.x = 1` or `items[0] = 1`"));
+            }
+        };
+        Ok(Stmt::new(kind, self.span_from(start)))
+    }
+    
     fn if_statement(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let condition = self.expression()?;
         let then_branch = Box::new(self.block()?);
         
-        let else_branch = if self.match_token(&[Token::Keyword(Keyword::Else)]) {
+        let else_branch = if self.match_token(&[TokenKind::Keyword(Keyword::Else)]) {
             Some(Box::new(self.block()?))
         } else {
             None
         };
         
-        Ok(Stmt::Expr(Expr::If {
+        let span = self.span_from(start);
+        Ok(Stmt::new(StmtKind::Expr(Expr::new(ExprKind::If {
             condition: Box::new(condition),
             then_branch,
             else_branch,
-        }))
+        }, span)), span))
     }
     
     fn while_statement(&mut self) -> Result<Stmt, TogError> {
+        let start = self.previous_span(); // The keyword
         let condition = self.expression()?;
         let body = Box::new(self.block()?);
         
-        Ok(Stmt::Expr(Expr::While {
+        let span = self.span_from(start);
+        Ok(Stmt::new(StmtKind::Expr(Expr::new(ExprKind::While {
             condition: Box::new(condition),
             body,
-        }))
+        }, span)), span))
     }
     
     fn for_statement(&mut self) -> Result<Stmt, TogError> {
-        // Parse: for variable in iterable { body }
-        let variable = self.consume_identifier()?;
+        let start = self.previous_span(); // The keyword
+        // Parse: for pattern in iterable { body }
+        let pattern = self.parse_pattern()?;
         
         // Expect 'in' keyword
-        self.consume(&Token::Keyword(Keyword::In), "Expected 'in' after loop variable")?;
+        self.consume(&TokenKind::Keyword(Keyword::In), "Expected 'in' after loop variable")?;
         
         let iterable = Box::new(self.expression()?);
         let body = Box::new(self.block()?);
         
-        Ok(Stmt::Expr(Expr::For {
-            variable,
+        let span = self.span_from(start);
+        Ok(Stmt::new(StmtKind::Expr(Expr::new(ExprKind::For {
+            pattern,
             iterable,
             body,
-        }))
+        }, span)), span))
     }
     
     fn block(&mut self) -> Result<Expr, TogError> {
-        if !self.match_token(&[Token::LeftBrace]) {
+        if !self.match_token(&[TokenKind::LeftBrace]) {
             // Single expression (no braces)
             return Ok(self.expression()?);
         }
@@ -496,15 +826,21 @@ impl Parser {
     }
     
     fn block_with_brace_consumed(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span(); // The '{'
         let mut statements = Vec::new();
         
-        while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            let stmt_start = self.current;
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => statements.push(self.recover(e, stmt_start)?),
+            }
         }
         
-        self.consume(&Token::RightBrace, "Expected '}' after block")?;
+        self.consume(&TokenKind::RightBrace, "Expected '}' after block")
+            .map_err(|e| e.with_label(start, "block opened here"))?;
         
-        Ok(Expr::Block(statements))
+        Ok(Expr::new(ExprKind::Block(statements), self.span_from(start)))
     }
     
     fn expression(&mut self) -> Result<Expr, TogError> {
@@ -518,14 +854,15 @@ impl Parser {
     fn or(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.and()?;
         
-        while self.match_token(&[Token::Or]) {
+        while self.match_token(&[TokenKind::Or]) {
             let op = BinaryOp::Or;
             let right = self.and()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
@@ -534,14 +871,15 @@ impl Parser {
     fn and(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.equality()?;
         
-        while self.match_token(&[Token::And]) {
+        while self.match_token(&[TokenKind::And]) {
             let op = BinaryOp::And;
             let right = self.equality()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
@@ -550,18 +888,19 @@ impl Parser {
     fn equality(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.comparison()?;
         
-        while self.match_token(&[Token::EqEq, Token::Ne]) {
+        while self.match_token(&[TokenKind::EqEq, TokenKind::Ne]) {
             let op = match self.previous().clone() {
-                Token::EqEq => BinaryOp::Eq,
-                Token::Ne => BinaryOp::Ne,
+                TokenKind::EqEq => BinaryOp::Eq,
+                TokenKind::Ne => BinaryOp::Ne,
                 _ => unreachable!(),
             };
             let right = self.comparison()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
@@ -570,20 +909,21 @@ impl Parser {
     fn comparison(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.term()?;
         
-        while self.match_token(&[Token::Gt, Token::Ge, Token::Lt, Token::Le]) {
+        while self.match_token(&[TokenKind::Gt, TokenKind::Ge, TokenKind::Lt, TokenKind::Le]) {
             let op = match self.previous().clone() {
-                Token::Gt => BinaryOp::Gt,
-                Token::Ge => BinaryOp::Ge,
-                Token::Lt => BinaryOp::Lt,
-                Token::Le => BinaryOp::Le,
+                TokenKind::Gt => BinaryOp::Gt,
+                TokenKind::Ge => BinaryOp::Ge,
+                TokenKind::Lt => BinaryOp::Lt,
+                TokenKind::Le => BinaryOp::Le,
                 _ => unreachable!(),
             };
             let right = self.term()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
@@ -592,18 +932,19 @@ impl Parser {
     fn term(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.factor()?;
         
-        while self.match_token(&[Token::Plus, Token::Minus]) {
+        while self.match_token(&[TokenKind::Plus, TokenKind::Minus]) {
             let op = match self.previous().clone() {
-                Token::Plus => BinaryOp::Add,
-                Token::Minus => BinaryOp::Sub,
+                TokenKind::Plus => BinaryOp::Add,
+                TokenKind::Minus => BinaryOp::Sub,
                 _ => unreachable!(),
             };
             let right = self.factor()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
@@ -612,36 +953,39 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.unary()?;
         
-        while self.match_token(&[Token::Star, Token::Slash, Token::Percent]) {
+        while self.match_token(&[TokenKind::Star, TokenKind::Slash, TokenKind::Percent]) {
             let op = match self.previous().clone() {
-                Token::Star => BinaryOp::Mul,
-                Token::Slash => BinaryOp::Div,
-                Token::Percent => BinaryOp::Mod,
+                TokenKind::Star => BinaryOp::Mul,
+                TokenKind::Slash => BinaryOp::Div,
+                TokenKind::Percent => BinaryOp::Mod,
                 _ => unreachable!(),
             };
             let right = self.unary()?;
-            expr = Expr::BinaryOp {
+            let span = expr.span.to(right.span);
+            expr = Expr::new(ExprKind::BinaryOp {
                 left: Box::new(expr),
                 op,
                 right: Box::new(right),
-            };
+            }, span);
         }
         
         Ok(expr)
     }
     
     fn unary(&mut self) -> Result<Expr, TogError> {
-        if self.match_token(&[Token::Not, Token::Minus]) {
+        if self.match_token(&[TokenKind::Not, TokenKind::Minus]) {
+            let start = self.previous_span();
             let op = match self.previous().clone() {
-                Token::Not => UnaryOp::Not,
-                Token::Minus => UnaryOp::Neg,
+                TokenKind::Not => UnaryOp::Not,
+                TokenKind::Minus => UnaryOp::Neg,
                 _ => unreachable!(),
             };
             let expr = self.unary()?;
-            return Ok(Expr::UnaryOp {
+            let span = start.to(expr.span);
+            return Ok(Expr::new(ExprKind::UnaryOp {
                 op,
                 expr: Box::new(expr),
-            });
+            }, span));
         }
         
         self.call()
@@ -651,76 +995,140 @@ impl Parser {
         // Note: match keyword was already consumed if called from statement()
         // But if called from unary(), it was also consumed
         // Parse the expression being matched (use call() to avoid struct literal parsing)
+        let start = self.previous_span();
         let expr = Box::new(self.call()?);
 
-        self.consume(&Token::LeftBrace, "Expected '{' after match expression")?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after match expression")?;
         
         let mut arms = Vec::new();
-        while !self.check(&Token::RightBrace) && !self.is_at_end() {
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            let arm_start = self.peek_span();
             let pattern = self.parse_pattern()?;
-            self.consume(&Token::FatArrow, "Expected '=>' after pattern")?;
-            let body = self.expression()?;
+            let guard = if self.match_token(&[TokenKind::Keyword(Keyword::If)]) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            let arm_span = self.span_from(arm_start);
+            self.consume(&TokenKind::FatArrow, "Expected '=>' after pattern")?;
+            // Arm bodies may be a braced block so they can hold `return` and friends
+            let body = self.block()?;
             
-            // Optional comma between arms
-            let _ = self.match_token(&[Token::Comma]);
+            // Arms are separated by a comma or a new line
+            if !self.match_token(&[TokenKind::Comma]) && !self.check(&TokenKind::RightBrace) && !self.at_line_start() {
+                self.consume(&TokenKind::Comma, "Expected ',' or a new line between match arms")?;
+            }
             
-            arms.push(MatchArm { pattern, body });
+            arms.push(MatchArm { pattern, guard, body, span: arm_span });
         }
         
-        self.consume(&Token::RightBrace, "Expected '}' after match arms")?;
+        self.consume(&TokenKind::RightBrace, "Expected '}' after match arms")?;
         
-        Ok(Expr::Match { expr, arms })
+        Ok(Expr::new(ExprKind::Match { expr, arms }, self.span_from(start)))
     }
     
+    /// A pattern, possibly with alternatives: `1 | 2`, `Some(x) | None`.
     fn parse_pattern(&mut self) -> Result<Pattern, TogError> {
-        if self.match_token(&[Token::Keyword(Keyword::None)]) {
+        let start = self.peek_span();
+        let first = self.single_pattern()?;
+        if !self.check(&TokenKind::Pipe) {
+            return Ok(first);
+        }
+        let mut alternatives = vec![first];
+        while self.match_token(&[TokenKind::Pipe]) {
+            alternatives.push(self.single_pattern()?);
+        }
+        // Each alternative must bind the same variables, so the arm body can use them
+        let mut expected = alternatives[0].bindings();
+        expected.sort();
+        for alternative in &alternatives[1..] {
+            let mut names = alternative.bindings();
+            names.sort();
+            if names != expected {
+                let missing = expected.iter().chain(&names)
+                    .find(|n| !expected.contains(n) || !names.contains(n))
+                    .cloned()
+                    .unwrap_or_default();
+                return Err(TogError::ParseError(
+                    format!("Variable '{}' is not bound in every alternative of this pattern", missing),
+                    self.span_from(start)
+                ));
+            }
+        }
+        Ok(Pattern::Or(alternatives))
+    }
+
+    fn single_pattern(&mut self) -> Result<Pattern, TogError> {
+        if self.match_token(&[TokenKind::Keyword(Keyword::None)]) {
             return Ok(Pattern::Literal(Literal::None));
         }
-        if self.check(&Token::Bool(true)) {
+        if self.check(&TokenKind::Bool(true)) {
             self.advance();
             return Ok(Pattern::Literal(Literal::Bool(true)));
         }
-        if self.check(&Token::Bool(false)) {
+        if self.check(&TokenKind::Bool(false)) {
             self.advance();
             return Ok(Pattern::Literal(Literal::Bool(false)));
         }
-        if let Token::Int(val) = self.peek() {
-            let val = *val;
-            self.advance();
-            return Ok(Pattern::Literal(Literal::Int(val)));
-        }
-        if let Token::Float(val) = self.peek() {
-            let val = *val;
-            self.advance();
-            return Ok(Pattern::Literal(Literal::Float(val)));
-        }
-        if let Token::String(val) = self.peek() {
+        if let TokenKind::String(val) = self.peek() {
             let val = val.clone();
             self.advance();
             return Ok(Pattern::Literal(Literal::String(val)));
         }
-        if let Token::Identifier(name) = self.peek() {
+        if matches!(self.peek(), TokenKind::Int(_) | TokenKind::Float(_) | TokenKind::Minus) {
+            // A number, or a range of numbers: 1..=9
+            let start = self.number_pattern()?;
+            let inclusive = self.check(&TokenKind::DotDotEq);
+            if !self.match_token(&[TokenKind::DotDot, TokenKind::DotDotEq]) {
+                return Ok(Pattern::Literal(start));
+            }
+            let end = self.number_pattern()?;
+            return Ok(Pattern::Range { start, end, inclusive });
+        }
+        if self.match_token(&[TokenKind::LeftBracket]) {
+            return self.array_pattern();
+        }
+        if self.match_token(&[TokenKind::LeftParen]) {
+            // A tuple pattern, or a parenthesized one: (x, y), (x,), (x)
+            let (mut elements, trailing_comma) = self.pattern_list()?;
+            self.consume(&TokenKind::RightParen, "Expected ')' after tuple pattern")?;
+            if elements.len() == 1 && !trailing_comma {
+                return Ok(elements.remove(0));
+            }
+            return Ok(Pattern::Tuple(elements));
+        }
+        if let TokenKind::Identifier(name) = self.peek() {
             let name = name.clone();
-            
-            // Check for enum variant pattern: EnumName::VariantName or EnumName::VariantName(binding)
-            if self.check_ahead(1, &Token::ColonColon) {
-                self.advance(); // consume enum name
-                self.consume(&Token::ColonColon, "Expected '::'")?;
-                let variant_name = self.consume_identifier()?;
-                
-                // Check for binding: VariantName(var)
-                let binding = if self.match_token(&[Token::LeftParen]) {
-                    let binding_name = self.consume_identifier()?;
-                    self.consume(&Token::RightParen, "Expected ')' after binding")?;
-                    Some(binding_name)
+
+            // A struct pattern: Point { x, y: 0 }, or geometry::Point { x, y }
+            if self.check_ahead(self.path_len(), &TokenKind::LeftBrace) {
+                let name = self.path()?;
+                return self.struct_pattern(name);
+            }
+
+            // Check for enum variant pattern: EnumName::VariantName or EnumName::VariantName(pattern),
+            // where the enum can be qualified by its module: shapes::Shape::Circle(r).
+            // A bare VariantName(pattern) matches that variant of any enum: Some(Ok(v))
+            if self.check_ahead(1, &TokenKind::ColonColon) || self.check_ahead(1, &TokenKind::LeftParen) {
+                let (enum_name, variant_name) = split_path(&self.path()?);
+                let inner = if self.match_token(&[TokenKind::LeftParen]) {
+                    // Move(x, y) destructures a variant holding a tuple
+                    let (mut elements, _) = self.pattern_list()?;
+                    self.consume(&TokenKind::RightParen, "Expected ')' after variant pattern")?;
+                    let inner = if elements.len() == 1 {
+                        elements.remove(0)
+                    } else {
+                        Pattern::Tuple(elements)
+                    };
+                    Some(Box::new(inner))
                 } else {
                     None
                 };
                 
                 return Ok(Pattern::EnumVariant {
-                    enum_name: name,
+                    enum_name,
                     variant_name,
-                    binding,
+                    inner,
                 });
             }
             
@@ -733,31 +1141,137 @@ impl Parser {
         
         Err(TogError::ParseError(
             "Expected pattern".to_string(),
-            0, 0
+            self.peek_span()
         ))
     }
+
+    /// Comma-separated patterns up to a ')', and whether a trailing comma ended them.
+    fn pattern_list(&mut self) -> Result<(Vec<Pattern>, bool), TogError> {
+        let mut elements = Vec::new();
+        let mut trailing_comma = false;
+        while !self.check(&TokenKind::RightParen) && !self.is_at_end() {
+            elements.push(self.parse_pattern()?);
+            trailing_comma = self.match_token(&[TokenKind::Comma]);
+            if !trailing_comma {
+                break;
+            }
+        }
+        Ok((elements, trailing_comma))
+    }
+
+    /// An int or float literal in a pattern, optionally negative.
+    fn number_pattern(&mut self) -> Result<Literal, TogError> {
+        let negative = self.match_token(&[TokenKind::Minus]);
+        let literal = match self.peek() {
+            TokenKind::Int(val) if negative => Literal::Int(-*val),
+            TokenKind::Int(val) => Literal::Int(*val),
+            TokenKind::Float(val) if negative => Literal::Float(-*val),
+            TokenKind::Float(val) => Literal::Float(*val),
+            _ => return Err(TogError::ParseError(
+                "Expected a number in pattern".to_string(),
+                self.peek_span()
+            )),
+        };
+        self.advance();
+        Ok(literal)
+    }
+
+    /// `[a, b, ..rest]` after the '['.
+    fn array_pattern(&mut self) -> Result<Pattern, TogError> {
+        let open = self.previous_span();
+        let mut elements = Vec::new();
+        let mut rest_span: Option<Span> = None;
+        while !self.check(&TokenKind::RightBracket) && !self.is_at_end() {
+            if self.match_token(&[TokenKind::DotDot]) {
+                let span = self.previous_span();
+                if let Some(first) = rest_span {
+                    return Err(TogError::ParseError(
+                        "Only one '..' is allowed in an array pattern".to_string(),
+                        span
+                    ).with_label(first, "first '..' here"));
+                }
+                rest_span = Some(span);
+                let binding = match self.peek() {
+                    TokenKind::Identifier(_) => Some(self.consume_identifier()?),
+                    _ => None,
+                };
+                elements.push(Pattern::Rest(binding));
+            } else {
+                elements.push(self.parse_pattern()?);
+            }
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenKind::RightBracket, "Expected ']' after array pattern")
+            .map_err(|e| e.with_label(open, "array pattern opened here"))?;
+        Ok(Pattern::Array(elements))
+    }
+
+    /// `{ x, y: 0, .. }` after a struct name. A field without a pattern binds
+    /// a variable of the same name.
+    fn struct_pattern(&mut self, name: String) -> Result<Pattern, TogError> {
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after struct name")?;
+        let mut fields = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            if self.match_token(&[TokenKind::DotDot]) {
+                // Unlisted fields are ignored anyway; `..` just says so
+                break;
+            }
+            let field = self.consume_identifier()?;
+            let pattern = if self.match_token(&[TokenKind::Colon]) {
+                self.parse_pattern()?
+            } else {
+                Pattern::Variable(field.clone())
+            };
+            fields.push((field, pattern));
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenKind::RightBrace, "Expected '}' after struct pattern")?;
+        Ok(Pattern::Struct { name, fields })
+    }
     
+
     fn call(&mut self) -> Result<Expr, TogError> {
         let mut expr = self.primary()?;
         
         loop {
-            if self.match_token(&[Token::LeftParen]) {
+            // A '(' or '[' starting a line begins a new expression, such as a
+            // tuple or array that ends a block, rather than calling or indexing
+            if self.at_line_start() && (self.check(&TokenKind::LeftParen) || self.check(&TokenKind::LeftBracket)) {
+                break;
+            }
+            if self.match_token(&[TokenKind::LeftParen]) {
                 expr = self.finish_call(expr)?;
-            } else if self.match_token(&[Token::LeftBracket]) {
+            } else if self.match_token(&[TokenKind::LeftBracket]) {
                 // Array indexing
                 let index = self.expression()?;
-                self.consume(&Token::RightBracket, "Expected ']' after index")?;
-                expr = Expr::Index {
+                self.consume(&TokenKind::RightBracket, "Expected ']' after index")?;
+                let span = self.span_from(expr.span);
+                expr = Expr::new(ExprKind::Index {
                     array: Box::new(expr),
                     index: Box::new(index),
+                }, span);
+            } else if self.match_token(&[TokenKind::Dot]) {
+                // Field access, or tuple indexing: pair.0
+                let field_name = match self.peek() {
+                    TokenKind::Int(index) => {
+                        let index = index.to_string();
+                        self.advance();
+                        index
+                    }
+                    _ => self.consume_identifier()?,
                 };
-            } else if self.match_token(&[Token::Dot]) {
-                // Field access
-                let field_name = self.consume_identifier()?;
-                expr = Expr::FieldAccess {
+                let span = self.span_from(expr.span);
+                expr = Expr::new(ExprKind::FieldAccess {
                     object: Box::new(expr),
                     field: field_name,
-                };
+                }, span);
+            } else if self.match_token(&[TokenKind::Question]) {
+                let span = self.span_from(expr.span);
+                expr = Expr::new(ExprKind::Try(Box::new(expr)), span);
             } else {
                 break;
             }
@@ -767,67 +1281,79 @@ impl Parser {
     }
     
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, TogError> {
+        let open = self.previous_span(); // The '('
         let mut args = Vec::new();
         
-        if !self.check(&Token::RightParen) {
+        if !self.check(&TokenKind::RightParen) {
             loop {
                 args.push(self.expression()?);
-                if !self.match_token(&[Token::Comma]) {
+                if !self.match_token(&[TokenKind::Comma]) {
                     break;
                 }
             }
         }
         
-        self.consume(&Token::RightParen, "Expected ')' after arguments")?;
+        self.consume(&TokenKind::RightParen, "Expected ')' after arguments")
+            .map_err(|e| e.with_label(open, "argument list opened here"))?;
         
-        Ok(Expr::Call {
+        let span = self.span_from(callee.span);
+        Ok(Expr::new(ExprKind::Call {
             callee: Box::new(callee),
             args,
-        })
+        }, span))
     }
     
     fn primary(&mut self) -> Result<Expr, TogError> {
-        if self.match_token(&[Token::Keyword(Keyword::None)]) {
-            return Ok(Expr::Literal(Literal::None));
+        if self.match_token(&[TokenKind::Keyword(Keyword::None)]) {
+            return Ok(Expr::new(ExprKind::Literal(Literal::None), self.previous_span()));
         }
-        if self.match_token(&[Token::Keyword(Keyword::Match)]) {
+        if self.match_token(&[TokenKind::Keyword(Keyword::Match)]) {
             return self.match_expression();
         }
+        if self.match_token(&[TokenKind::Keyword(Keyword::Fn)]) {
+            return self.fn_lambda();
+        }
+        if self.match_token(&[TokenKind::Pipe, TokenKind::Or]) {
+            return self.pipe_lambda();
+        }
 
         if let Some(token) = self.tokens.get(self.current) {
-            match token.clone() {
-                Token::Int(val) => {
+            let span = token.span;
+            match token.kind.clone() {
+                TokenKind::Int(val) => {
                     self.advance();
-                    return Ok(Expr::Literal(Literal::Int(val)));
+                    return Ok(Expr::new(ExprKind::Literal(Literal::Int(val)), span));
                 },
-                Token::Float(val) => {
+                TokenKind::Float(val) => {
                     self.advance();
-                    return Ok(Expr::Literal(Literal::Float(val)));
+                    return Ok(Expr::new(ExprKind::Literal(Literal::Float(val)), span));
                 },
-                Token::String(val) => {
+                TokenKind::String(val) => {
                     self.advance();
-                    return Ok(Expr::Literal(Literal::String(val)));
+                    return Ok(Expr::new(ExprKind::Literal(Literal::String(val)), span));
                 },
-                Token::InterpolatedString(val) => {
+                TokenKind::InterpolatedString(val) => {
                     self.advance();
-                    return Ok(Expr::Literal(Literal::String(val)));
+                    return interpolated_string(&val, span);
                 },
-                Token::Bool(val) => {
+                TokenKind::Bool(val) => {
                     self.advance();
-                    return Ok(Expr::Literal(Literal::Bool(val)));
+                    return Ok(Expr::new(ExprKind::Literal(Literal::Bool(val)), span));
                 },
-                Token::Identifier(name) => {
+                TokenKind::Identifier(name) => {
                     // Check for struct literal: Point { field: value, ... }
                     // We need to distinguish from match expressions: match x { ... }
                     // A struct literal has the pattern: Identifier { Identifier : ...
                     // So we check ahead for { followed by an identifier and then :
-                    if self.check_ahead(1, &Token::LeftBrace) {
+                    // The name can be qualified by a module: geometry::Point { ... }
+                    let path_len = self.path_len();
+                    if self.check_ahead(path_len, &TokenKind::LeftBrace) {
                         // Look further ahead to see if this is really a struct literal
                         // Check if after { there's an identifier followed by :
-                        if self.current + 2 < self.tokens.len() {
-                            if let Token::Identifier(_) = &self.tokens[self.current + 2] {
-                                if self.current + 3 < self.tokens.len() {
-                                    if matches!(&self.tokens[self.current + 3], Token::Colon) {
+                        if self.current + path_len + 1 < self.tokens.len() {
+                            if let TokenKind::Identifier(_) = &self.tokens[self.current + path_len + 1].kind {
+                                if self.current + path_len + 2 < self.tokens.len() {
+                                    if matches!(&self.tokens[self.current + path_len + 2].kind, TokenKind::Colon) {
                                         // Definitely a struct literal: Name { field: ...
                                         return self.struct_literal();
                                     }
@@ -839,30 +1365,32 @@ impl Parser {
                     }
                     
                     // Check for enum variant: EnumName::VariantName or EnumName::VariantName(data)
-                    if self.check_ahead(1, &Token::ColonColon) {
+                    if self.check_ahead(1, &TokenKind::ColonColon) {
                         return self.enum_variant_construction();
                     }
 
                     if name == "_" {
                         // A wildcard `_` is not a valid expression on its own.
                         // It's only valid as a pattern in a match arm.
-                        return Err(TogError::ParseError("Wildcard `_` can only be used as a pattern in a match arm.".to_string(), 0, 0));
+                        return Err(TogError::ParseError("Wildcard `_` can only be used as a pattern in a match arm.".to_string(), self.peek_span()));
                     } else {
                         self.advance();
-                        return Ok(Expr::Variable(name.clone()));
+                        return Ok(Expr::new(ExprKind::Variable(name.clone()), span));
                     }
                 },
-                Token::LeftBracket => {
+                TokenKind::LeftBracket => {
                     self.advance(); // consume '['
                     let elements = self.array()?;
                     // array() already consumes the ']'
                     return Ok(elements);
                 },
-                Token::LeftParen => {
+                TokenKind::LeftParen => {
                     self.advance(); // consume '('
-                    let expr = self.expression()?;
-                    self.consume(&Token::RightParen, "Expected ')' after expression")?;
-                    return Ok(expr);
+                    return self.paren_or_tuple();
+                },
+                TokenKind::LeftBrace => {
+                    self.advance(); // consume '{'
+                    return self.map_literal();
                 },
                 _ => {}
             }
@@ -870,69 +1398,135 @@ impl Parser {
         
         Err(TogError::ParseError(
             "Expected expression".to_string(),
-            0, 0
+            self.peek_span()
         ))
     }
     
+    /// A parenthesized expression, or a tuple: `()`, `(a,)`, `(a, b)`.
+    fn paren_or_tuple(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span(); // The '('
+        let mut elements = Vec::new();
+        let mut trailing_comma = false;
+        while !self.check(&TokenKind::RightParen) && !self.is_at_end() {
+            elements.push(self.expression()?);
+            trailing_comma = self.match_token(&[TokenKind::Comma]);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.consume(&TokenKind::RightParen, "Expected ')' after expression")
+            .map_err(|e| e.with_label(start, "parenthesis opened here"))?;
+        if elements.len() == 1 && !trailing_comma {
+            return Ok(elements.remove(0));
+        }
+        Ok(Expr::new(ExprKind::Literal(Literal::Tuple(elements)), self.span_from(start)))
+    }
+
+    /// A map literal after its '{': `{"a": 1, "b": 2}`. Blocks never reach
+    /// here, since statements and bodies parse their braces themselves.
+    fn map_literal(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span(); // The '{'
+        let mut entries = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            let key = self.expression()?;
+            self.consume(&TokenKind::Colon, "Expected ':' after map key")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+        self.consume(&TokenKind::RightBrace, "Expected '}' after map entries")
+            .map_err(|e| e.with_label(start, "map opened here"))?;
+        Ok(Expr::new(ExprKind::Literal(Literal::Map(entries)), self.span_from(start)))
+    }
+
     fn array(&mut self) -> Result<Expr, TogError> {
+        let start = self.previous_span(); // The '['
         let mut elements = Vec::new();
         
-        if !self.check(&Token::RightBracket) {
+        if !self.check(&TokenKind::RightBracket) {
             loop {
                 elements.push(self.expression()?);
-                if !self.match_token(&[Token::Comma]) {
+                if !self.match_token(&[TokenKind::Comma]) {
                     break;
                 }
             }
         }
         
-        self.consume(&Token::RightBracket, "Expected ']' after array")?;
+        self.consume(&TokenKind::RightBracket, "Expected ']' after array")
+            .map_err(|e| e.with_label(start, "array opened here"))?;
         
-        Ok(Expr::Literal(Literal::Array(elements)))
+        Ok(Expr::new(ExprKind::Literal(Literal::Array(elements)), self.span_from(start)))
     }
     
     fn struct_literal(&mut self) -> Result<Expr, TogError> {
-        let name = self.consume_identifier()?;
-        self.consume(&Token::LeftBrace, "Expected '{' after struct name")?;
+        let start = self.peek_span();
+        let name = self.path()?;
+        self.consume(&TokenKind::LeftBrace, "Expected '{' after struct name")?;
         let mut fields = Vec::new();
-        while !self.check(&Token::RightBrace) && !self.is_at_end() {
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
             let field_name = self.consume_identifier()?;
-            self.consume(&Token::Colon, "Expected ':' after field name in struct literal")?;
+            self.consume(&TokenKind::Colon, "Expected ':' after field name in struct literal")?;
             let field_value = self.expression()?;
             fields.push((field_name, field_value));
-            if !self.match_token(&[Token::Comma]) {
+            if !self.match_token(&[TokenKind::Comma]) {
                 break;
             }
         }
-        self.consume(&Token::RightBrace, "Expected '}' after struct literal")?;
+        self.consume(&TokenKind::RightBrace, "Expected '}' after struct literal")?;
 
-        Ok(Expr::StructLiteral { name, fields })
+        Ok(Expr::new(ExprKind::StructLiteral { name, fields }, self.span_from(start)))
     }
     
     fn enum_variant_construction(&mut self) -> Result<Expr, TogError> {
-        // Parse: EnumName::VariantName or EnumName::VariantName(data)
-        let enum_name = self.consume_identifier()?;
-        self.consume(&Token::ColonColon, "Expected '::' after enum name")?;
-        let variant_name = self.consume_identifier()?;
+        // Parse: EnumName::VariantName or EnumName::VariantName(data). Module
+        // items parse the same way: utils::add(1, 2), shapes::Shape::Circle(2)
+        let start = self.peek_span();
+        let (enum_name, variant_name) = split_path(&self.path()?);
+        let path_span = self.span_from(start);
         
         // Check if there's associated data: VariantName(data)
-        let data = if self.match_token(&[Token::LeftParen]) {
-            let data_expr = self.expression()?;
-            self.consume(&Token::RightParen, "Expected ')' after enum variant data")?;
-            Some(Box::new(data_expr))
-        } else {
-            None
-        };
+        if self.match_token(&[TokenKind::LeftParen]) {
+            let mut args = Vec::new();
+            if !self.check(&TokenKind::RightParen) {
+                loop {
+                    args.push(self.expression()?);
+                    if !self.match_token(&[TokenKind::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(&TokenKind::RightParen, "Expected ')' after enum variant data")?;
+            
+            if args.len() == 1 {
+                return Ok(Expr::new(ExprKind::EnumVariant {
+                    enum_name,
+                    variant_name,
+                    data: Some(Box::new(args.remove(0))),
+                }, self.span_from(start)));
+            }
+            // Anything else is an associated function call such as
+            // `Point::new(1, 2)` or `Config::default()`
+            return Ok(Expr::new(ExprKind::Call {
+                callee: Box::new(Expr::new(ExprKind::EnumVariant {
+                    enum_name,
+                    variant_name,
+                    data: None,
+                }, path_span)),
+                args,
+            }, self.span_from(start)));
+        }
         
-        Ok(Expr::EnumVariant {
+        Ok(Expr::new(ExprKind::EnumVariant {
             enum_name,
             variant_name,
-            data,
-        })
+            data: None,
+        }, path_span))
     }
 
     // Helper methods
-    fn match_token(&mut self, tokens: &[Token]) -> bool {
+    fn match_token(&mut self, tokens: &[TokenKind]) -> bool {
         for token in tokens {
             if self.check(token) {
                 self.advance();
@@ -943,52 +1537,56 @@ impl Parser {
     }
     
     
-    fn check(&self, token: &Token) -> bool {
+    fn check(&self, token: &TokenKind) -> bool {
         if self.is_at_end() {
             return false;
         }
-        match (token, &self.tokens[self.current]) {
-            (Token::Int(_), Token::Int(_)) => true,
-            (Token::Float(_), Token::Float(_)) => true,
-            (Token::String(_), Token::String(_)) => true,
-            (Token::InterpolatedString(_), Token::InterpolatedString(_)) => true,
-            (Token::String(_), Token::InterpolatedString(_)) => true, // Allow matching
-            (Token::InterpolatedString(_), Token::String(_)) => true, // Allow matching
-            (Token::Bool(a), Token::Bool(b)) => a == b,
-            (Token::Keyword(k1), Token::Keyword(k2)) => k1 == k2,
-            (Token::Identifier(_), Token::Identifier(_)) => true,
+        match (token, &self.tokens[self.current].kind) {
+            (TokenKind::Int(_), TokenKind::Int(_)) => true,
+            (TokenKind::Float(_), TokenKind::Float(_)) => true,
+            (TokenKind::String(_), TokenKind::String(_)) => true,
+            (TokenKind::InterpolatedString(_), TokenKind::InterpolatedString(_)) => true,
+            (TokenKind::String(_), TokenKind::InterpolatedString(_)) => true, // Allow matching
+            (TokenKind::InterpolatedString(_), TokenKind::String(_)) => true, // Allow matching
+            (TokenKind::Bool(a), TokenKind::Bool(b)) => a == b,
+            (TokenKind::Keyword(k1), TokenKind::Keyword(k2)) => k1 == k2,
+            (TokenKind::Identifier(_), TokenKind::Identifier(_)) => true,
             (t1, t2) => std::mem::discriminant(t1) == std::mem::discriminant(t2),
         }
     }
     
-    fn advance(&mut self) -> &Token {
+    fn advance(&mut self) -> &TokenKind {
         if !self.is_at_end() {
             self.current += 1;
         }
-        &self.tokens[self.current - 1]
+        &self.tokens[self.current - 1].kind
     }
     
     fn is_at_end(&self) -> bool {
-        matches!(self.peek(), Token::Eof)
+        matches!(self.peek(), TokenKind::Eof)
     }
     
-    fn check_ahead(&self, distance: usize, token: &Token) -> bool {
+    fn check_ahead(&self, distance: usize, token: &TokenKind) -> bool {
         if self.current + distance >= self.tokens.len() {
             return false;
         }
-        let future_token = &self.tokens[self.current + distance];
+        let future_token = &self.tokens[self.current + distance].kind;
         match (token, future_token) {
-            (Token::Int(_), Token::Int(_)) => true,
-            (Token::Float(_), Token::Float(_)) => true,
-            (Token::String(_), Token::String(_)) => true,
-            (Token::InterpolatedString(_), Token::InterpolatedString(_)) => true,
-            (Token::Identifier(_), Token::Identifier(_)) => true,
-            (Token::ColonColon, Token::ColonColon) => true,
+            (TokenKind::Int(_), TokenKind::Int(_)) => true,
+            (TokenKind::Float(_), TokenKind::Float(_)) => true,
+            (TokenKind::String(_), TokenKind::String(_)) => true,
+            (TokenKind::InterpolatedString(_), TokenKind::InterpolatedString(_)) => true,
+            (TokenKind::Identifier(_), TokenKind::Identifier(_)) => true,
+            (TokenKind::ColonColon, TokenKind::ColonColon) => true,
             _ => token == future_token,
         }
     }
 
-    fn peek(&self) -> &Token {
+    fn peek(&self) -> &TokenKind {
+        &self.peek_token().kind
+    }
+
+    fn peek_token(&self) -> &Token {
         if self.current >= self.tokens.len() {
             &self.tokens[self.tokens.len() - 1] // Return last token (should be Eof)
         } else {
@@ -996,33 +1594,245 @@ impl Parser {
         }
     }
     
-    fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
+    fn previous(&self) -> &TokenKind {
+        &self.tokens[self.current - 1].kind
+    }
+
+    fn peek_span(&self) -> Span {
+        self.peek_token().span
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.current.saturating_sub(1)].span
+    }
+
+    /// Whether the next token is the first on its line.
+    fn at_line_start(&self) -> bool {
+        self.current > 0 && self.peek_span().line > self.previous_span().end_line
+    }
+
+    /// The span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous_span())
     }
     
-    fn consume(&mut self, token: &Token, message: &str) -> Result<(), TogError> {
+    fn consume(&mut self, token: &TokenKind, message: &str) -> Result<(), TogError> {
         if self.check(token) {
             self.advance();
             Ok(())
         } else {
             Err(TogError::ParseError(
                 format!("{}: expected {:?}", message, token),
-                0, 0
+                self.peek_span()
             ))
         }
     }
     
+    /// A name that may be qualified by modules: `Point`, `geometry::Point`.
+    fn path(&mut self) -> Result<String, TogError> {
+        let mut path = self.consume_identifier()?;
+        while self.check(&TokenKind::ColonColon) && self.check_ahead(1, &TokenKind::Identifier(String::new())) {
+            self.advance();
+            path.push_str("::");
+            path.push_str(&self.consume_identifier()?);
+        }
+        Ok(path)
+    }
+
+    /// The number of tokens in the path starting at the current token.
+    fn path_len(&self) -> usize {
+        let mut len = 1;
+        while self.check_ahead(len, &TokenKind::ColonColon)
+            && self.check_ahead(len + 1, &TokenKind::Identifier(String::new())) {
+            len += 2;
+        }
+        len
+    }
+
     fn consume_identifier(&mut self) -> Result<String, TogError> {
-        if let Token::Identifier(name) = self.peek() {
+        if let TokenKind::Identifier(name) = self.peek() {
             let name = name.clone();
             self.advance();
             Ok(name)
         } else {
             Err(TogError::ParseError(
                 "Expected identifier".to_string(),
-                0, 0
+                self.peek_span()
             ))
         }
     }
 }
 
+/// Splits `a::b::c` into `("a::b", "c")`.
+fn split_path(path: &str) -> (String, String) {
+    match path.rsplit_once("::") {
+        Some((prefix, last)) => (prefix.to_string(), last.to_string()),
+        None => (String::new(), path.to_string()),
+    }
+}
+
+/// Splits the raw text of an interpolated string into literal parts and
+/// parsed `{expr}` / `{expr:spec}` parts. `{{` and `}}` are literal braces.
+/// `span` is the string token's span, used to place the inner expressions.
+fn interpolated_string(raw: &str, span: Span) -> Result<Expr, TogError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = raw.chars().peekable();
+    // Position of the next character, just past the opening quote to start
+    let mut pos = (span.line, span.column + 1);
+    let step = |pos: &mut (usize, usize), c: char| {
+        if c == '\n' {
+            *pos = (pos.0 + 1, 1);
+        } else {
+            pos.1 += 1;
+        }
+    };
+
+    while let Some(ch) = chars.next() {
+        step(&mut pos, ch);
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                step(&mut pos, '{');
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                step(&mut pos, '}');
+                literal.push('}');
+            }
+            '{' => {
+                let inner_pos = pos;
+                let mut inner = String::new();
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        Some('}') if depth == 0 => {
+                            step(&mut pos, '}');
+                            break;
+                        }
+                        Some(c) => {
+                            step(&mut pos, c);
+                            match c {
+                                '{' | '(' | '[' => depth += 1,
+                                '}' | ')' | ']' => depth -= 1,
+                                _ => {}
+                            }
+                            inner.push(c);
+                        }
+                        None => return Err(TogError::ParseError(
+                            "Unclosed '{' in interpolated string".to_string(),
+                            Span::point(span.file, inner_pos.0, inner_pos.1 - 1)
+                        )),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(StringPart::Literal(std::mem::take(&mut literal)));
+                }
+                let inner_span = Span::new(span.file, inner_pos, pos);
+                parts.push(interpolation(&inner, inner_span)?);
+            }
+            '}' => {
+                return Err(TogError::ParseError(
+                    format!("Unmatched '}}' in interpolated string \"{}\" (use '}}}}' for a literal brace)", raw),
+                    span
+                ));
+            }
+            _ => literal.push(ch),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(StringPart::Literal(literal));
+    }
+
+    // Only escaped braces: this is just a plain string
+    let kind = match parts.as_slice() {
+        [] => ExprKind::Literal(Literal::String(String::new())),
+        [StringPart::Literal(text)] => ExprKind::Literal(Literal::String(text.clone())),
+        _ => ExprKind::InterpolatedString(parts),
+    };
+    Ok(Expr::new(kind, span))
+}
+
+/// Parses the text between `{` and `}`: an expression, optionally followed
+/// by `:` and a format spec. `::` paths are part of the expression. `span`
+/// starts at the first character of `text`.
+fn interpolation(text: &str, span: Span) -> Result<StringPart, TogError> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut spec_start = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b':' if bytes.get(i + 1) == Some(&b':') => i += 1,
+            b':' if depth == 0 => {
+                spec_start = Some(i);
+                break;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    let (expr_text, format) = match spec_start {
+        Some(i) => (&text[..i], Some(format_spec(&text[i + 1..], span)?)),
+        None => (text, None),
+    };
+
+    if expr_text.trim().is_empty() {
+        return Err(TogError::ParseError(
+            "Empty '{}' in interpolated string (use '{{' for a literal brace)".to_string(),
+            span
+        ));
+    }
+    let mut parser = Parser::new(tokenize_at(expr_text, span.file, span.line, span.column)?);
+    let expr = parser.expression()?;
+    if !parser.is_at_end() {
+        return Err(TogError::ParseError(
+            format!("Unexpected {:?} in interpolation '{{{}}}'", parser.peek(), text),
+            parser.peek_span()
+        ));
+    }
+    Ok(StringPart::Expr { expr: Box::new(expr), format })
+}
+
+/// Parses `[[fill]align][0][width][.precision]`.
+fn format_spec(spec: &str, span: Span) -> Result<FormatSpec, TogError> {
+    let invalid = || TogError::ParseError(format!("Invalid format spec '{}'", spec), span);
+    let to_align = |c: char| match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+
+    let chars: Vec<char> = spec.chars().collect();
+    let (fill, align, mut rest) = match chars.as_slice() {
+        [fill, a, ..] if to_align(*a).is_some() => (*fill, to_align(*a), &chars[2..]),
+        [a, ..] if to_align(*a).is_some() => (' ', to_align(*a), &chars[1..]),
+        _ => (' ', None, &chars[..]),
+    };
+
+    let number = |rest: &mut &[char]| -> Option<usize> {
+        let digits: String = rest.iter().take_while(|c| c.is_ascii_digit()).collect();
+        *rest = &rest[digits.len()..];
+        digits.parse().ok()
+    };
+    let zero = rest.first() == Some(&'0');
+    if zero {
+        rest = &rest[1..];
+    }
+    let width = number(&mut rest);
+    let precision = match rest.first() {
+        Some('.') => {
+            rest = &rest[1..];
+            Some(number(&mut rest).ok_or_else(invalid)?)
+        }
+        _ => None,
+    };
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+    Ok(FormatSpec { fill, align, zero, width, precision })
+}
@@ -0,0 +1,486 @@
+// Scope resolution
+//
+// Runs over the whole program before it executes and decides which
+// declaration every variable refers to:
+//
+//   type error: Variable used before its definition: total
+//   type error: `count` is already defined in this scope
+//
+// Scopes are lexical. A function's parameters form one scope, and every
+// block, `for` loop and match arm opens another inside it. `let`, `fn` and
+// `let` patterns declare into the innermost scope, so a variable declared in
+// an `if` or a loop body is gone after it. Declaring a name twice in one scope
+// is an error; shadowing a name from an outer scope is fine.
+//
+// Within a function a variable can only be used after its declaration. A
+// nested function may name a variable declared later around it, since it
+// usually runs once that variable is defined (and local functions can call
+// each other). Methods see their parameters and the globals, nothing else.
+//
+// Each declaration gets a slot in its function's frame, and each use becomes a
+// `(depth, slot)` pair: how many functions out the declaration is, and where.
+// Top-level `let`s and `fn`s are globals. The bytecode compiler builds its
+// variable accesses from the result rather than looking names up.
+
+use crate::ast::*;
+use crate::diagnostics::did_you_mean;
+use crate::error::TogError;
+use crate::span::Span;
+use crate::stdlib;
+use std::collections::{HashMap, HashSet};
+
+/// What a variable refers to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    /// Slot `slot` of the function `depth` levels out from the use (0: the
+    /// function the use is in)
+    Local { depth: u32, slot: u32 },
+    Global,
+}
+
+/// The variables of one function, or of the top-level code, by slot.
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub slot_names: Vec<String>, // Parameters first, in order
+    pub captured: HashSet<u32>,  // Slots that nested functions use
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    uses: HashMap<Span, Binding>, // By the span of the variable, or of the assignment to it
+    // By the span of the `let`, `fn`, `for` or match arm that declares the name
+    declarations: HashMap<(Span, String), Binding>,
+    blocks: HashMap<Span, Vec<u32>>, // The slots declared directly in each block
+    frames: HashMap<Span, Frame>,    // By function body
+    pub script: Frame,
+}
+
+impl Resolution {
+    /// What the variable read or assigned at `span` refers to. None for
+    /// builtin functions and type names, which aren't variables.
+    pub fn binding(&self, span: Span) -> Option<Binding> {
+        self.uses.get(&span).copied()
+    }
+
+    /// Where the `let`, `fn`, `for` or match arm at `span` puts `name`.
+    pub fn declaration(&self, span: Span, name: &str) -> Binding {
+        self.declarations[&(span, name.to_string())]
+    }
+
+    pub fn block_slots(&self, span: Span) -> &[u32] {
+        self.blocks.get(&span).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn frame(&self, body: Span) -> &Frame {
+        &self.frames[&body]
+    }
+}
+
+/// Resolves `statements`, the whole program as it runs.
+pub fn resolve(statements: &[Stmt]) -> Result<Resolution, TogError> {
+    resolve_parts(&[statements])
+}
+
+/// Checks a program's scopes as it will run: after the prelude, unless told not to.
+pub fn check_program(program: &Program, load_prelude: bool) -> Result<(), TogError> {
+    let prelude = if load_prelude { stdlib::prelude()?.statements } else { Vec::new() };
+    resolve_parts(&[&prelude, &program.statements]).map(|_| ())
+}
+
+fn resolve_parts(parts: &[&[Stmt]]) -> Result<Resolution, TogError> {
+    let statements: Vec<&Stmt> = parts.iter().flat_map(|part| part.iter()).collect();
+    let mut resolver = Resolver {
+        functions: vec![Function::new(true)],
+        type_names: HashSet::new(),
+        resolution: Resolution::default(),
+    };
+    for stmt in &statements {
+        collect_type_names(stmt, &mut resolver.type_names);
+    }
+    for stmt in &statements {
+        resolver.predeclare(stmt)?;
+    }
+    for stmt in &statements {
+        resolver.stmt(stmt)?;
+    }
+    let script = resolver.functions.pop().expect("script scope");
+    resolver.resolution.script = script.frame;
+    Ok(resolver.resolution)
+}
+
+struct Declared {
+    binding: Binding,
+    span: Span,
+    defined: bool, // False until execution reaches the declaration
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, Declared>,
+    slots: Vec<u32>,
+}
+
+struct Function {
+    scopes: Vec<Scope>,
+    frame: Frame,
+    enclosed: bool, // Sees the variables of the function around it; methods don't
+}
+
+impl Function {
+    fn new(enclosed: bool) -> Self {
+        Function { scopes: vec![Scope::default()], frame: Frame::default(), enclosed }
+    }
+}
+
+/// How a variable is used, which decides what a name that isn't a variable means.
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    Value,
+    Callee,   // `name(args)`, which may call a builtin
+    Receiver, // `name.method(args)`, which may call a static method of a type
+}
+
+enum Lookup {
+    Found(Binding),
+    Later(Span), // Only declared further on in the same function
+    Missing,
+}
+
+struct Resolver {
+    functions: Vec<Function>, // The script first, then the functions being resolved
+    type_names: HashSet<String>,
+    resolution: Resolution,
+}
+
+impl Resolver {
+    fn function(&mut self) -> &mut Function {
+        self.functions.last_mut().expect("function")
+    }
+
+    fn push_scope(&mut self) {
+        self.function().scopes.push(Scope::default());
+    }
+
+    fn pop_scope(&mut self) -> Scope {
+        self.function().scopes.pop().expect("scope")
+    }
+
+    /// Adds `name` to the innermost scope, not yet defined. The outermost
+    /// scope of the top-level code holds the globals.
+    fn declare(&mut self, name: &str, span: Span) -> Result<(), TogError> {
+        let global = self.functions.len() == 1 && self.functions[0].scopes.len() == 1;
+        let function = self.function();
+        let scope = function.scopes.last_mut().expect("scope");
+        if let Some(previous) = scope.names.get(name) {
+            return Err(TogError::TypeError(format!("`{}` is already defined in this scope", name), Some(span))
+                .with_label(previous.span, "first defined here")
+                .with_help(format!("assign with `{} = ...` to change it, or pick another name", name)));
+        }
+        let binding = if global {
+            Binding::Global
+        } else {
+            let slot = function.frame.slot_names.len() as u32;
+            function.frame.slot_names.push(name.to_string());
+            scope.slots.push(slot);
+            Binding::Local { depth: 0, slot }
+        };
+        scope.names.insert(name.to_string(), Declared { binding, span, defined: false });
+        self.resolution.declarations.insert((span, name.to_string()), binding);
+        Ok(())
+    }
+
+    /// Marks `name` in the innermost scope as defined, so uses after this point can see it.
+    fn define(&mut self, name: &str) {
+        if let Some(declared) = self.function().scopes.last_mut().expect("scope").names.get_mut(name) {
+            declared.defined = true;
+        }
+    }
+
+    /// Declares the variables a statement in a block will define, so nested
+    /// functions earlier in the block can refer to them.
+    fn predeclare(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Let { name, .. } => self.declare(name, stmt.span),
+            StmtKind::LetPattern { pattern, .. } => {
+                for name in pattern.bindings() {
+                    self.declare(&name, stmt.span)?;
+                }
+                Ok(())
+            }
+            StmtKind::Expr(expr @ Expr { kind: ExprKind::Function { name, .. }, .. }) => self.declare(name, expr.span),
+            _ => Ok(()),
+        }
+    }
+
+    /// Finds the declaration `name` refers to from the current position:
+    /// the innermost defined one in this function, then in the functions
+    /// around it, then the globals.
+    fn lookup(&mut self, name: &str) -> Lookup {
+        let current = self.functions.len() - 1;
+        let mut later = None;
+        let mut index = current;
+        let mut globals_only = false;
+        let found = loop {
+            let function = &self.functions[index];
+            let scopes = if globals_only { &function.scopes[..1] } else { &function.scopes[..] };
+            let declared = scopes.iter().rev()
+                .filter_map(|scope| scope.names.get(name))
+                .find(|declared| {
+                    if index == current && !declared.defined {
+                        later.get_or_insert(declared.span);
+                        return false;
+                    }
+                    true
+                });
+            if let Some(declared) = declared {
+                break Some((index, declared.binding));
+            }
+            if index == 0 {
+                break None;
+            }
+            if !function.enclosed {
+                globals_only = true;
+                index = 0;
+            } else {
+                index -= 1;
+            }
+        };
+        match (found, later) {
+            (Some((index, Binding::Local { slot, .. })), _) => {
+                let depth = current - index;
+                if depth > 0 {
+                    self.functions[index].frame.captured.insert(slot);
+                }
+                Lookup::Found(Binding::Local { depth: depth as u32, slot })
+            }
+            (Some((_, Binding::Global)), _) => Lookup::Found(Binding::Global),
+            (None, Some(span)) => Lookup::Later(span),
+            (None, None) => Lookup::Missing,
+        }
+    }
+
+    /// Every variable name visible from the current position, for "did you mean".
+    fn visible_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut index = self.functions.len() - 1;
+        let mut globals_only = false;
+        loop {
+            let function = &self.functions[index];
+            let scopes = if globals_only { &function.scopes[..1] } else { &function.scopes[..] };
+            names.extend(scopes.iter().flat_map(|scope| scope.names.keys().map(String::as_str)));
+            if index == 0 {
+                return names;
+            }
+            if !function.enclosed {
+                globals_only = true;
+                index = 0;
+            } else {
+                index -= 1;
+            }
+        }
+    }
+
+    fn variable(&mut self, name: &str, span: Span, role: Role) -> Result<(), TogError> {
+        match self.lookup(name) {
+            Lookup::Found(binding) => {
+                self.resolution.uses.insert(span, binding);
+                Ok(())
+            }
+            Lookup::Missing if role == Role::Callee && stdlib::is_builtin(name) => Ok(()),
+            Lookup::Missing if role == Role::Receiver && self.type_names.contains(name) => Ok(()),
+            Lookup::Later(declared) => Err(used_before_definition(name, span, declared)),
+            Lookup::Missing => {
+                let error = TogError::TypeError(format!("Undefined variable: {}", name), Some(span));
+                Err(match did_you_mean(name, self.visible_names()) {
+                    Some(help) => error.with_help(help),
+                    None => error,
+                })
+            }
+        }
+    }
+
+    fn assign(&mut self, name: &str, span: Span) -> Result<(), TogError> {
+        match self.lookup(name) {
+            Lookup::Found(binding) => {
+                self.resolution.uses.insert(span, binding);
+                Ok(())
+            }
+            Lookup::Later(declared) => Err(used_before_definition(name, span, declared)),
+            Lookup::Missing => {
+                let error = TogError::TypeError(format!("Cannot assign to undefined variable: {}", name), Some(span));
+                Err(match did_you_mean(name, self.visible_names()) {
+                    Some(help) => error.with_help(help),
+                    None => error.with_help(format!("declare it with `let {} = ...`", name)),
+                })
+            }
+        }
+    }
+
+    /// Resolves a function, lambda or method body in a frame of its own. The
+    /// parameters take the first slots.
+    fn function_body(&mut self, params: &[Param], body: &Expr, enclosed: bool) -> Result<(), TogError> {
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].iter().any(|p| p.name == param.name) {
+                return Err(TogError::TypeError(format!("Parameter `{}` is declared twice", param.name), Some(body.span)));
+            }
+        }
+        self.functions.push(Function::new(enclosed));
+        for param in params {
+            self.declare(&param.name, body.span)?;
+            self.define(&param.name);
+        }
+        self.expr(body)?;
+        let function = self.functions.pop().expect("function");
+        self.resolution.frames.insert(body.span, function.frame);
+        Ok(())
+    }
+
+    fn methods<'m>(&mut self, methods: impl IntoIterator<Item = (&'m [Param], &'m Expr)>) -> Result<(), TogError> {
+        for (params, body) in methods {
+            self.function_body(params, body, false)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves `scope_span`'s pattern variables, then `body` with them in scope.
+    fn pattern_scope(&mut self, pattern: &Pattern, scope_span: Span, body: impl FnOnce(&mut Self) -> Result<(), TogError>) -> Result<(), TogError> {
+        self.push_scope();
+        for name in pattern.bindings() {
+            self.declare(&name, scope_span)?;
+            self.define(&name);
+        }
+        let result = body(self);
+        self.pop_scope();
+        result
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => self.expr(expr),
+            StmtKind::Let { name, value, .. } => {
+                self.expr(value)?;
+                self.define(name);
+                Ok(())
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                self.expr(value)?;
+                for name in pattern.bindings() {
+                    self.define(&name);
+                }
+                Ok(())
+            }
+            StmtKind::Assign { name, value, .. } => {
+                self.expr(value)?;
+                self.assign(name, stmt.span)
+            }
+            StmtKind::AssignField { object, value, .. } => {
+                self.expr(value)?;
+                self.expr(object)
+            }
+            StmtKind::AssignIndex { object, index, value, .. } => {
+                self.expr(value)?;
+                self.expr(index)?;
+                self.expr(object)
+            }
+            StmtKind::StructDef { methods, .. } | StmtKind::ImplBlock { methods, .. } => {
+                self.methods(methods.iter().map(|m| (&m.params[..], &m.body)))
+            }
+            StmtKind::TraitDef { methods, .. } => {
+                self.methods(methods.iter().filter_map(|m| Some((&m.params[..], m.default_body.as_ref()?))))
+            }
+            StmtKind::EnumDef { .. } | StmtKind::Import { .. } | StmtKind::Return(None)
+            | StmtKind::Break | StmtKind::Continue | StmtKind::Error => Ok(()),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), TogError> {
+        match &expr.kind {
+            ExprKind::Variable(name) => self.variable(name, expr.span, Role::Value),
+            ExprKind::Call { callee, args } => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                match &callee.kind {
+                    ExprKind::Variable(name) => self.variable(name, callee.span, Role::Callee),
+                    ExprKind::FieldAccess { object, .. } => match &object.kind {
+                        ExprKind::Variable(name) => self.variable(name, object.span, Role::Receiver),
+                        _ => self.expr(object),
+                    },
+                    _ => self.expr(callee),
+                }
+            }
+            ExprKind::Block(statements) => {
+                self.push_scope();
+                let result = statements.iter().try_for_each(|stmt| self.predeclare(stmt))
+                    .and_then(|_| statements.iter().try_for_each(|stmt| self.stmt(stmt)));
+                let scope = self.pop_scope();
+                self.resolution.blocks.insert(expr.span, scope.slots);
+                result
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                self.expr(iterable)?;
+                self.pattern_scope(pattern, expr.span, |resolver| resolver.expr(body))
+            }
+            ExprKind::Match { expr: subject, arms } => {
+                self.expr(subject)?;
+                for arm in arms {
+                    self.pattern_scope(&arm.pattern, arm.span, |resolver| {
+                        if let Some(guard) = &arm.guard {
+                            resolver.expr(guard)?;
+                        }
+                        resolver.expr(&arm.body)
+                    })?;
+                }
+                Ok(())
+            }
+            ExprKind::Function { name, params, body, .. } => {
+                // A `fn` inside an expression is declared where it appears
+                if !self.resolution.declarations.contains_key(&(expr.span, name.clone())) {
+                    self.declare(name, expr.span)?;
+                }
+                self.define(name);
+                self.function_body(params, body, true)
+            }
+            ExprKind::Lambda { params, body, .. } => self.function_body(params, body, true),
+            _ => expr.children().into_iter().try_for_each(|child| self.expr(child)),
+        }
+    }
+}
+
+fn used_before_definition(name: &str, span: Span, declared: Span) -> TogError {
+    TogError::TypeError(format!("Variable used before its definition: {}", name), Some(span))
+        .with_label(declared, "defined here")
+}
+
+/// Adds the names of the structs, enums and impl'd types declared in `stmt`,
+/// including inside function bodies.
+pub(crate) fn collect_type_names(stmt: &Stmt, names: &mut HashSet<String>) {
+    match &stmt.kind {
+        StmtKind::StructDef { name, .. } | StmtKind::EnumDef { name, .. } => {
+            names.insert(name.clone());
+        }
+        StmtKind::ImplBlock { type_name, .. } => {
+            names.insert(type_name.clone());
+        }
+        _ => {
+            for expr in stmt.expressions() {
+                collect_nested_type_names(expr, names);
+            }
+        }
+    }
+}
+
+fn collect_nested_type_names(expr: &Expr, names: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::Block(statements) => {
+            for stmt in statements {
+                collect_type_names(stmt, names);
+            }
+        }
+        _ => {
+            for child in expr.children() {
+                collect_nested_type_names(child, names);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Source locations
+//
+// Every token, expression and statement carries a `Span` so errors can point
+// at the code that caused them. Spans refer to files through a `FileId`; the
+// file names and sources live in a process-wide source map so an error can be
+// displayed without threading the map through every call.
+
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(usize);
+
+/// A range of source text. Lines and columns start at 1; `end_column` is one
+/// past the last character. A span with line 0 has no known location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: (usize, usize), end: (usize, usize)) -> Self {
+        Span {
+            file,
+            line: start.0,
+            column: start.1,
+            end_line: end.0,
+            end_column: end.1,
+        }
+    }
+
+    /// A one-character span.
+    pub fn point(file: FileId, line: usize, column: usize) -> Self {
+        Span::new(file, (line, column), (line, column + 1))
+    }
+
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        if !self.is_known() {
+            return other;
+        }
+        if !other.is_known() {
+            return self;
+        }
+        Span::new(self.file, (self.line, self.column), (other.end_line, other.end_column))
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", file_name(self.file), self.line, self.column)
+    }
+}
+
+struct SourceFile {
+    name: String,
+    source: String,
+}
+
+// Indexed by `FileId`
+static SOURCE_MAP: Mutex<Vec<SourceFile>> = Mutex::new(Vec::new());
+
+/// Registers a source file and returns the id its spans will use.
+pub fn add_file(name: impl Into<String>, source: impl Into<String>) -> FileId {
+    let mut files = SOURCE_MAP.lock().unwrap();
+    files.push(SourceFile { name: name.into(), source: source.into() });
+    FileId(files.len() - 1)
+}
+
+pub fn file_name(file: FileId) -> String {
+    SOURCE_MAP.lock().unwrap()
+        .get(file.0)
+        .map(|f| f.name.clone())
+        .unwrap_or_else(|| "<unknown>".to_string())
+}
+
+/// The text of a 1-based line, without its line ending.
+pub fn source_line(file: FileId, line: usize) -> Option<String> {
+    let files = SOURCE_MAP.lock().unwrap();
+    let source = &files.get(file.0)?.source;
+    source.lines().nth(line.checked_sub(1)?).map(|l| l.trim_end_matches('\r').to_string())
+}
+
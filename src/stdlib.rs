@@ -1,16 +1,63 @@
 // Standard library functions for TOG
-use crate::interpreter::{Value, Interpreter};
+use crate::interpreter::{is_truthy, tuple_to_string, MapKey, Value, Interpreter};
 use crate::error::TogError;
+use crate::parallel::{self, SharedFunction};
+use crate::ast::Program;
+use crate::{lexer, parser, span};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::Path;
 
+/// Source of `stdlib/prelude.tog`, which defines `Option` and `Result`.
+/// Programs see its declarations unless run with `--no-prelude`; their own
+/// declarations of the same names replace the prelude's.
+pub const PRELUDE: &str = include_str!("../stdlib/prelude.tog");
+
+pub fn prelude() -> Result<Program, TogError> {
+    let file = span::add_file("<prelude>", PRELUDE);
+    parser::Parser::parse(lexer::tokenize(PRELUDE, file)?)
+}
+
 #[allow(dead_code)] // Reserved for future eager registration of built-ins
 pub fn register_builtins(_interpreter: &mut Interpreter) {
     // Built-in functions are now dynamically called, no need to register them beforehand.
     // This function can be used in the future if we need eager registration.
 }
 
-pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
+/// Lets builtins such as `map` and `filter` call a TOG function value.
+/// Implemented by whatever is executing the program.
+pub trait FunctionCaller {
+    fn call_function(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, TogError>;
+
+    /// Packages a function so the parallel builtins can run it on worker threads.
+    /// Fails if the function assigns to a variable it captures.
+    fn share_function(&self, func: &Value) -> Result<SharedFunction, TogError>;
+
+    /// The current call stack, formatted like an error's traceback.
+    fn stack_trace(&self) -> String;
+}
+
+/// The functions `call_builtin` provides, plus `print`, which the engines
+/// handle themselves. A call to one of these names needs no variable.
+const BUILTINS: &[&str] = &[
+    "print", "len", "to_string", "range", "map", "filter", "reduce", "sort_by", "any", "all", "find",
+    "for_each", "split", "join", "contains", "substring", "push", "pop", "reverse", "append", "min",
+    "max", "abs", "sqrt", "pow", "read_file", "write_file", "gpu_sum", "gpu_product", "gpu_mean",
+    "parallel_sum", "stack_trace", "batch_size", "parallel_map", "parallel_filter", "parallel_reduce",
+    "batch_process", "first", "last", "slice", "flatten", "unique", "sort", "keys", "values", "get",
+    "insert", "remove", "has", "unwrap", "unwrap_or", "expect", "is_ok", "is_err", "is_some", "is_none",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+/// Builtin types with associated functions in `call_builtin`, like `Map::new`.
+pub fn is_builtin_type(name: &str) -> bool {
+    matches!(name, "Map" | "Set")
+}
+
+pub fn call_builtin(caller: &mut dyn FunctionCaller, name: &str, args: &[Value]) -> Result<Value, TogError> {
     match name {
         "len" => {
             if args.len() != 1 {
@@ -22,8 +69,10 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
             match &args[0] {
                 Value::String(s) => Ok(Value::Int(s.len() as i64)),
                 Value::Array(arr) => Ok(Value::Int(arr.len() as i64)),
+                Value::Map(map) => Ok(Value::Int(map.len() as i64)),
+                Value::Set(set) => Ok(Value::Int(set.len() as i64)),
                 _ => Err(TogError::TypeError(
-                    "len() expects string or array".to_string(),
+                    "len() expects string, array, map or set".to_string(),
                     None
                 ))
             }
@@ -75,83 +124,106 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
                 ))
             }
         }
+        // Higher-order functions: these call back into the program
         "map" => {
-            if args.len() != 2 {
-                return Err(TogError::RuntimeError(
-                    format!("map() expects 2 arguments (array, function), got {}", args.len()),
-                    None
-                ));
+            let (arr, func) = array_and_function("map", args, 2, 1)?;
+            let mut result = Vec::with_capacity(arr.len());
+            for item in arr {
+                result.push(caller.call_function(func, vec![item.clone()])?);
             }
-            match (&args[0], &args[1]) {
-                (Value::Array(_arr), Value::Function { params, .. }) => {
-                    if params.len() != 1 {
-                        return Err(TogError::RuntimeError(
-                            "map() function must take exactly 1 argument".to_string(),
-                            None
-                        ));
-                    }
-                    Err(TogError::RuntimeError(
-                        "map() requires interpreter context - use array comprehension instead".to_string(),
-                        None
-                    ))
+            Ok(Value::Array(result))
+        }
+        "filter" => {
+            let (arr, func) = array_and_function("filter", args, 2, 1)?;
+            let mut result = Vec::new();
+            for item in arr {
+                if is_truthy(&caller.call_function(func, vec![item.clone()])?) {
+                    result.push(item.clone());
                 }
-                _ => Err(TogError::TypeError(
-                    "map() expects (array, function)".to_string(),
-                    None
-                ))
             }
+            Ok(Value::Array(result))
         }
-        "filter" => {
-            if args.len() != 2 {
-                return Err(TogError::RuntimeError(
-                    format!("filter() expects 2 arguments (array, function), got {}", args.len()),
-                    None
-                ));
+        "reduce" => {
+            // reduce(array, initial, function(accumulator, element))
+            let (arr, func) = array_and_function("reduce", args, 3, 2)?;
+            let mut acc = args[1].clone();
+            for item in arr {
+                acc = caller.call_function(func, vec![acc, item.clone()])?;
             }
-            match (&args[0], &args[1]) {
-                (Value::Array(_arr), Value::Function { params, .. }) => {
-                    if params.len() != 1 {
-                        return Err(TogError::RuntimeError(
-                            "filter() function must take exactly 1 argument".to_string(),
+            Ok(acc)
+        }
+        "sort_by" => {
+            // sort_by(array, function(a, b)) - the function returns a negative
+            // int, zero or a positive int like a three-way comparison
+            let (arr, func) = array_and_function("sort_by", args, 2, 2)?;
+            let mut sorted = arr.to_vec();
+            let mut failure = None;
+            sorted.sort_by(|a, b| {
+                if failure.is_some() {
+                    return std::cmp::Ordering::Equal;
+                }
+                match caller.call_function(func, vec![a.clone(), b.clone()]) {
+                    Ok(Value::Int(n)) => n.cmp(&0),
+                    Ok(other) => {
+                        failure = Some(TogError::TypeError(
+                            format!("sort_by() comparator must return int, got {}", value_to_string(&other)),
                             None
                         ));
+                        std::cmp::Ordering::Equal
+                    }
+                    Err(e) => {
+                        failure = Some(e);
+                        std::cmp::Ordering::Equal
                     }
-                    Err(TogError::RuntimeError(
-                        "filter() requires interpreter context - use array comprehension instead".to_string(),
-                        None
-                    ))
                 }
-                _ => Err(TogError::TypeError(
-                    "filter() expects (array, function)".to_string(),
-                    None
-                ))
+            });
+            match failure {
+                Some(e) => Err(e),
+                None => Ok(Value::Array(sorted)),
             }
         }
-        "reduce" => {
-            if args.len() != 3 {
-                return Err(TogError::RuntimeError(
-                    format!("reduce() expects 3 arguments (array, initial, function), got {}", args.len()),
-                    None
-                ));
+        "any" => {
+            let (arr, func) = array_and_function("any", args, 2, 1)?;
+            for item in arr {
+                if is_truthy(&caller.call_function(func, vec![item.clone()])?) {
+                    return Ok(Value::Bool(true));
+                }
             }
-            match (&args[0], &args[2]) {
-                (Value::Array(_arr), Value::Function { params, .. }) => {
-                    if params.len() != 2 {
-                        return Err(TogError::RuntimeError(
-                            "reduce() function must take exactly 2 arguments (accumulator, element)".to_string(),
-                            None
-                        ));
-                    }
-                    Err(TogError::RuntimeError(
-                        "reduce() requires interpreter context - use loop instead".to_string(),
-                        None
-                    ))
+            Ok(Value::Bool(false))
+        }
+        "all" => {
+            let (arr, func) = array_and_function("all", args, 2, 1)?;
+            for item in arr {
+                if !is_truthy(&caller.call_function(func, vec![item.clone()])?) {
+                    return Ok(Value::Bool(false));
                 }
-                _ => Err(TogError::TypeError(
-                    "reduce() expects (array, initial_value, function)".to_string(),
-                    None
-                ))
             }
+            Ok(Value::Bool(true))
+        }
+        "find" => {
+            // find(array, predicate) - Option::Some(first match) or Option::None
+            let (arr, func) = array_and_function("find", args, 2, 1)?;
+            for item in arr {
+                if is_truthy(&caller.call_function(func, vec![item.clone()])?) {
+                    return Ok(Value::Enum {
+                        enum_name: "Option".to_string(),
+                        variant_name: "Some".to_string(),
+                        data: Some(Box::new(item.clone())),
+                    });
+                }
+            }
+            Ok(Value::Enum {
+                enum_name: "Option".to_string(),
+                variant_name: "None".to_string(),
+                data: None,
+            })
+        }
+        "for_each" => {
+            let (arr, func) = array_and_function("for_each", args, 2, 1)?;
+            for item in arr {
+                caller.call_function(func, vec![item.clone()])?;
+            }
+            Ok(Value::None)
         }
         // String operations
         "split" => {
@@ -454,7 +526,7 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
             }
         }
         "parallel_sum" => {
-            // Parallel sum using rayon-style processing
+            // Chunk sums run on the worker pool and are added in chunk order
             if args.len() != 1 {
                 return Err(TogError::RuntimeError(
                     format!("parallel_sum() expects 1 argument, got {}", args.len()),
@@ -462,110 +534,53 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
                 ));
             }
             match &args[0] {
-                Value::Array(arr) => {
-                    // Use chunks for parallel processing simulation
-                    let chunk_size = (arr.len() / 4).max(1);
-                    let sum = arr.chunks(chunk_size)
-                        .map(|chunk| {
-                            chunk.iter().fold(0.0, |acc, v| {
-                                acc + match v {
-                                    Value::Int(i) => *i as f64,
-                                    Value::Float(f) => *f,
-                                    _ => 0.0,
-                                }
-                            })
-                        })
-                        .sum::<f64>();
-                    Ok(Value::Float(sum))
-                }
+                Value::Array(arr) => Ok(Value::Float(parallel::sum(arr)?)),
                 _ => Err(TogError::TypeError("parallel_sum() expects array".to_string(), None))
             }
         }
-        "batch_size" => {
-            // Returns optimal batch size for the system
-            // For now, return a reasonable default
-            Ok(Value::Int(1024))
-        }
-        "map" => {
-            // map(array, function) - applies function to each element
-            // Note: Function application needs to be handled by interpreter
-            if args.len() != 2 {
-                return Err(TogError::RuntimeError(
-                    format!("map() expects 2 arguments (array, function), got {}", args.len()),
-                    None
-                ));
-            }
-            // For now, return the array as-is
-            // The interpreter will need to handle function application
-            match &args[0] {
-                Value::Array(_) => Ok(args[0].clone()),
-                _ => Err(TogError::TypeError("map() expects array as first argument".to_string(), None))
-            }
-        }
-        "filter" => {
-            // filter(array, predicate) - keeps elements where predicate is true
-            if args.len() != 2 {
+        "stack_trace" => {
+            if !args.is_empty() {
                 return Err(TogError::RuntimeError(
-                    format!("filter() expects 2 arguments (array, predicate), got {}", args.len()),
+                    format!("stack_trace() expects 0 arguments, got {}", args.len()),
                     None
                 ));
             }
-            match &args[0] {
-                Value::Array(_) => Ok(args[0].clone()),
-                _ => Err(TogError::TypeError("filter() expects array as first argument".to_string(), None))
-            }
+            Ok(Value::String(caller.stack_trace()))
         }
-        "reduce" => {
-            // reduce(array, initial, function) - reduces array to single value
-            if args.len() != 3 {
-                return Err(TogError::RuntimeError(
-                    format!("reduce() expects 3 arguments (array, initial, function), got {}", args.len()),
-                    None
-                ));
-            }
-            match &args[0] {
-                Value::Array(_) => Ok(args[1].clone()), // Return initial for now
-                _ => Err(TogError::TypeError("reduce() expects array as first argument".to_string(), None))
-            }
+        "batch_size" => {
+            // Returns optimal batch size for the system
+            // For now, return a reasonable default
+            Ok(Value::Int(1024))
         }
         "parallel_map" => {
-            // Parallel version of map
-            if args.len() != 2 {
-                return Err(TogError::RuntimeError(
-                    format!("parallel_map() expects 2 arguments, got {}", args.len()),
-                    None
-                ));
-            }
-            match &args[0] {
-                Value::Array(_) => Ok(args[0].clone()),
-                _ => Err(TogError::TypeError("parallel_map() expects array".to_string(), None))
-            }
+            let (arr, func) = array_and_function("parallel_map", args, 2, 1)?;
+            let shared = caller.share_function(func)?;
+            parallel_map(arr, &shared)
         }
         "parallel_filter" => {
-            // Parallel version of filter
-            if args.len() != 2 {
-                return Err(TogError::RuntimeError(
-                    format!("parallel_filter() expects 2 arguments, got {}", args.len()),
-                    None
-                ));
-            }
-            match &args[0] {
-                Value::Array(_) => Ok(args[0].clone()),
-                _ => Err(TogError::TypeError("parallel_filter() expects array".to_string(), None))
-            }
+            let (arr, func) = array_and_function("parallel_filter", args, 2, 1)?;
+            let shared = caller.share_function(func)?;
+            Ok(Value::Array(parallel::filter(arr, &shared)?))
         }
         "parallel_reduce" => {
-            // Parallel version of reduce
-            if args.len() != 3 {
-                return Err(TogError::RuntimeError(
-                    format!("parallel_reduce() expects 3 arguments, got {}", args.len()),
+            // parallel_reduce(array, initial, fn(acc, x)); the function must be
+            // associative, and `initial` is folded in once
+            let (arr, func) = array_and_function("parallel_reduce", args, 3, 2)?;
+            let shared = caller.share_function(func)?;
+            parallel::reduce(caller, arr, args[1].clone(), func, &shared)
+        }
+        "batch_process" => {
+            // batch_process(array, batch_size, fn(x)) - each batch is one unit of work
+            let (arr, func) = array_and_function("batch_process", args, 3, 1)?;
+            let batch_size = match &args[1] {
+                Value::Int(n) if *n > 0 => *n as usize,
+                _ => return Err(TogError::RuntimeError(
+                    "batch_size must be greater than 0".to_string(),
                     None
-                ));
-            }
-            match &args[0] {
-                Value::Array(_) => Ok(args[1].clone()),
-                _ => Err(TogError::TypeError("parallel_reduce() expects array".to_string(), None))
-            }
+                )),
+            };
+            let shared = caller.share_function(func)?;
+            batch_process(arr, batch_size, &shared)
         }
         // Additional array operations
         "first" => {
@@ -702,7 +717,104 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
                 _ => Err(TogError::TypeError("sort() expects array".to_string(), None))
             }
         }
-        
+
+        // Map and set operations. Like the array operations, the ones that
+        // change a collection return a new one: `m = insert(m, "k", 1)`
+        "Map::new" => {
+            expect_args("Map::new", args, 0)?;
+            Ok(Value::Map(BTreeMap::new()))
+        }
+        "Set::new" => {
+            expect_args("Set::new", args, 0)?;
+            Ok(Value::Set(BTreeSet::new()))
+        }
+        "Set::from" => {
+            // Set::from(array) - the distinct elements of an array
+            expect_args("Set::from", args, 1)?;
+            match &args[0] {
+                Value::Array(arr) => Ok(Value::Set(arr.iter().map(MapKey::from_value).collect::<Result<_, _>>()?)),
+                _ => Err(TogError::TypeError("Set::from() expects array".to_string(), None))
+            }
+        }
+        "keys" => {
+            expect_args("keys", args, 1)?;
+            match &args[0] {
+                Value::Map(map) => Ok(Value::Array(map.keys().map(MapKey::to_value).collect())),
+                _ => Err(TogError::TypeError("keys() expects map".to_string(), None))
+            }
+        }
+        "values" => {
+            expect_args("values", args, 1)?;
+            match &args[0] {
+                Value::Map(map) => Ok(Value::Array(map.values().cloned().collect())),
+                Value::Set(set) => Ok(Value::Array(set.iter().map(MapKey::to_value).collect())),
+                _ => Err(TogError::TypeError("values() expects map or set".to_string(), None))
+            }
+        }
+        "get" => {
+            // get(map, key) - Option::Some(value) or Option::None
+            expect_args("get", args, 2)?;
+            match &args[0] {
+                Value::Map(map) => Ok(match map.get(&MapKey::from_value(&args[1])?) {
+                    Some(value) => Value::Enum {
+                        enum_name: "Option".to_string(),
+                        variant_name: "Some".to_string(),
+                        data: Some(Box::new(value.clone())),
+                    },
+                    None => Value::Enum {
+                        enum_name: "Option".to_string(),
+                        variant_name: "None".to_string(),
+                        data: None,
+                    },
+                }),
+                _ => Err(TogError::TypeError("get() expects (map, key)".to_string(), None))
+            }
+        }
+        "insert" => {
+            // insert(map, key, value) or insert(set, element)
+            match (args.first(), args.len()) {
+                (Some(Value::Map(map)), 3) => {
+                    let mut new_map = map.clone();
+                    new_map.insert(MapKey::from_value(&args[1])?, args[2].clone());
+                    Ok(Value::Map(new_map))
+                }
+                (Some(Value::Set(set)), 2) => {
+                    let mut new_set = set.clone();
+                    new_set.insert(MapKey::from_value(&args[1])?);
+                    Ok(Value::Set(new_set))
+                }
+                _ => Err(TogError::TypeError("insert() expects (map, key, value) or (set, element)".to_string(), None))
+            }
+        }
+        "remove" => {
+            // remove(map, key) or remove(set, element); missing keys are fine
+            expect_args("remove", args, 2)?;
+            let key = MapKey::from_value(&args[1])?;
+            match &args[0] {
+                Value::Map(map) => {
+                    let mut new_map = map.clone();
+                    new_map.remove(&key);
+                    Ok(Value::Map(new_map))
+                }
+                Value::Set(set) => {
+                    let mut new_set = set.clone();
+                    new_set.remove(&key);
+                    Ok(Value::Set(new_set))
+                }
+                _ => Err(TogError::TypeError("remove() expects map or set".to_string(), None))
+            }
+        }
+        "has" => {
+            // has(map, key) or has(set, element)
+            expect_args("has", args, 2)?;
+            let key = MapKey::from_value(&args[1])?;
+            match &args[0] {
+                Value::Map(map) => Ok(Value::Bool(map.contains_key(&key))),
+                Value::Set(set) => Ok(Value::Bool(set.contains(&key))),
+                _ => Err(TogError::TypeError("has() expects map or set".to_string(), None))
+            }
+        }
+
         // Result helper methods
         "unwrap" => {
             if args.len() != 1 {
@@ -948,6 +1060,57 @@ pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, TogError> {
     }
 }
 
+fn expect_args(name: &str, args: &[Value], count: usize) -> Result<(), TogError> {
+    if args.len() != count {
+        return Err(TogError::RuntimeError(
+            format!("{}() expects {} argument{}, got {}", name, count, if count == 1 { "" } else { "s" }, args.len()),
+            None
+        ));
+    }
+    Ok(())
+}
+
+/// Validates the `(array, ..., function)` shape shared by the higher-order
+/// builtins and returns the array and the function. The function is always
+/// the last argument and must take `func_arity` parameters.
+fn array_and_function<'a>(
+    name: &str,
+    args: &'a [Value],
+    arg_count: usize,
+    func_arity: usize,
+) -> Result<(&'a [Value], &'a Value), TogError> {
+    if args.len() != arg_count {
+        return Err(TogError::RuntimeError(
+            format!("{}() expects {} arguments, got {}", name, arg_count, args.len()),
+            None
+        ));
+    }
+    match (&args[0], &args[arg_count - 1]) {
+        (Value::Array(arr), func @ Value::Function { params, .. }) => {
+            if params.len() != func_arity {
+                return Err(TogError::RuntimeError(
+                    format!("{}() function must take exactly {} argument(s), got {}", name, func_arity, params.len()),
+                    None
+                ));
+            }
+            Ok((arr, func))
+        }
+        (Value::Array(arr), func @ Value::Closure(closure)) => {
+            if closure.arity() != func_arity {
+                return Err(TogError::RuntimeError(
+                    format!("{}() function must take exactly {} argument(s), got {}", name, func_arity, closure.arity()),
+                    None
+                ));
+            }
+            Ok((arr, func))
+        }
+        _ => Err(TogError::TypeError(
+            format!("{}() expects an array and a function", name),
+            None
+        ))
+    }
+}
+
 fn value_to_string(value: &Value) -> String {
     match value {
         Value::Int(n) => n.to_string(),
@@ -958,6 +1121,17 @@ fn value_to_string(value: &Value) -> String {
             let elems: Vec<String> = arr.iter().map(value_to_string).collect();
             format!("[{}]", elems.join(", "))
         }
+        Value::Tuple(elements) => tuple_to_string(elements),
+        Value::Map(map) => {
+            let entries: Vec<String> = map.iter()
+                .map(|(k, v)| format!("{}: {}", value_to_string(&k.to_value()), value_to_string(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Set(set) => {
+            let elems: Vec<String> = set.iter().map(|e| value_to_string(&e.to_value())).collect();
+            format!("{{{}}}", elems.join(", "))
+        }
         Value::Struct { name, fields } => {
             let mut parts: Vec<String> = Vec::new();
             for (k, v) in fields {
@@ -966,6 +1140,11 @@ fn value_to_string(value: &Value) -> String {
             format!("{} {{ {} }}", name, parts.join(", "))
         }
         Value::Enum { enum_name, variant_name, data } => {
+            if let Some(Value::Tuple(elements)) = data.as_deref() {
+                if elements.len() != 1 {
+                    return format!("{}::{}{}", enum_name, variant_name, tuple_to_string(elements));
+                }
+            }
             if let Some(d) = data {
                 format!("{}::{}({})", enum_name, variant_name, value_to_string(d))
             } else {
@@ -973,6 +1152,7 @@ fn value_to_string(value: &Value) -> String {
             }
         }
         Value::Function { name, .. } => format!("<function {}>", name),
+        Value::Closure(closure) => format!("<function {}>", closure.name()),
         Value::None => "none".to_string(),
     }
 }
@@ -981,34 +1161,22 @@ fn value_to_string(value: &Value) -> String {
 // GPU and Parallel Processing Functions
 // ============================================================================
 
-/// Parallel map - applies a function to each element in parallel
+/// Parallel map - applies a function to each element on the worker pool
 /// Usage: parallel_map(array, function)
-#[allow(dead_code)]
-pub fn parallel_map(array: &[Value], _func: &Value) -> Result<Value, TogError> {
-    // For now, this is a placeholder that does sequential processing
-    // In the future, this will use rayon or GPU acceleration
-    // The interpreter will need to handle function application
-    Ok(Value::Array(array.to_vec()))
+pub fn parallel_map(array: &[Value], func: &SharedFunction) -> Result<Value, TogError> {
+    Ok(Value::Array(parallel::map(array, func, None)?))
 }
 
-/// Batch process - processes array in batches for better cache locality
+/// Batch process - applies a function to each element, one batch per unit of work
 /// Usage: batch_process(array, batch_size, function)
-#[allow(dead_code)]
-pub fn batch_process(array: &[Value], batch_size: usize, _func: &Value) -> Result<Value, TogError> {
+pub fn batch_process(array: &[Value], batch_size: usize, func: &SharedFunction) -> Result<Value, TogError> {
     if batch_size == 0 {
         return Err(TogError::RuntimeError(
             "batch_size must be greater than 0".to_string(),
             None
         ));
     }
-    
-    // Process in batches for better cache performance
-    let mut result = Vec::new();
-    for chunk in array.chunks(batch_size) {
-        result.extend_from_slice(chunk);
-    }
-    
-    Ok(Value::Array(result))
+    Ok(Value::Array(parallel::map(array, func, Some(batch_size))?))
 }
 
 /// GPU-accelerated array operations
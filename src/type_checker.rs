@@ -5,11 +5,41 @@
 
 use crate::ast::*;
 use crate::error::TogError;
-use std::collections::HashMap;
+use crate::diagnostics::did_you_mean;
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+
+// Struct definition: type parameters, declared fields and methods written
+// inline in the struct body
+pub(crate) type StructDef = (Vec<TypeParam>, Vec<(String, Option<Type>)>, Vec<MethodDecl>);
+
+// Enum definition: type parameters and variants
+pub(crate) type EnumDef = (Vec<TypeParam>, Vec<EnumVariant>);
+
+// A named function's signature, for checking calls to it. Unannotated
+// parameters and return types have the types `inference` found, which are
+// `Type::Infer` where only the running program knows.
+#[derive(Clone)]
+struct FunctionSig {
+    type_params: Vec<TypeParam>,
+    param_names: Vec<String>,
+    params: Vec<Type>,
+    inferred_at: Vec<Option<Span>>, // Where an unannotated parameter's type was inferred from
+    return_type: Type,
+}
 
 pub struct TypeChecker {
     environment: HashMap<String, Type>,
-    struct_defs: HashMap<String, (Vec<(String, Option<Type>)>, Vec<MethodDecl>)>,
+    struct_defs: HashMap<String, StructDef>,
+    enum_defs: HashMap<String, EnumDef>,
+    trait_defs: HashMap<String, Vec<TraitMethod>>,
+    trait_impls: HashSet<(String, String)>, // (type name, trait name)
+    functions: HashMap<String, FunctionSig>,
+    inferred: HashMap<String, crate::inference::Signature>, // Signatures of the program's top-level functions
+    warnings: Vec<TogError>, // Problems that don't stop the program, such as unreachable match arms
+    // The declared return type of the function being checked, `Infer` if it
+    // has none; None at the top level
+    return_type: Option<Type>,
 }
 
 impl TypeChecker {
@@ -17,26 +47,205 @@ impl TypeChecker {
         Self {
             environment: HashMap::new(),
             struct_defs: HashMap::new(),
+            enum_defs: HashMap::new(),
+            trait_defs: HashMap::new(),
+            trait_impls: HashSet::new(),
+            functions: HashMap::new(),
+            inferred: HashMap::new(),
+            warnings: Vec::new(),
+            return_type: None,
         }
     }
     
+    /// Makes the prelude's declarations known, as `Interpreter::interpret`
+    /// does before running a program.
+    pub fn load_prelude(&mut self) -> Result<(), TogError> {
+        self.check_program(&crate::stdlib::prelude()?)
+    }
+
     pub fn check_program(&mut self, program: &Program) -> Result<(), TogError> {
+        // Declarations first, so uses may come before definitions
+        for stmt in &program.statements {
+            self.declare(stmt);
+        }
+        // Then the types of the functions' unannotated parameters and results
+        self.inferred = crate::inference::infer_program(program, &self.struct_defs, &self.enum_defs, &self.trait_impls)?;
+        for stmt in &program.statements {
+            self.declare(stmt);
+        }
         for stmt in &program.statements {
             self.check_statement(stmt)?;
         }
+        // Matches everywhere, function bodies included
+        crate::exhaustiveness::check_program(program, &self.enum_defs, &self.struct_defs, &mut self.warnings)?;
+        // Uses of moved values
+        crate::ownership::analyze(program, &self.enum_defs)
+    }
+
+    /// Warnings found by `check_program` so far, removed from the checker.
+    pub fn take_warnings(&mut self) -> Vec<TogError> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Records a top-level type, trait, impl or function declaration.
+    fn declare(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::StructDef { name, type_params, fields, methods } => {
+                self.struct_defs.insert(name.clone(), (type_params.clone(), fields.clone(), methods.clone()));
+            }
+            StmtKind::EnumDef { name, type_params, variants } => {
+                self.enum_defs.insert(name.clone(), (type_params.clone(), variants.clone()));
+            }
+            StmtKind::TraitDef { name, methods, .. } => {
+                self.trait_defs.insert(name.clone(), methods.clone());
+            }
+            StmtKind::ImplBlock { trait_name: Some(trait_name), type_name, .. } => {
+                self.trait_impls.insert((type_name.clone(), trait_name.clone()));
+            }
+            StmtKind::Expr(Expr { kind: ExprKind::Function { name, type_params, params, return_type, .. }, .. }) => {
+                let inferred = self.inferred.get(name);
+                let mut sig = FunctionSig {
+                    type_params: type_params.clone(),
+                    param_names: params.iter().map(|p| p.name.clone()).collect(),
+                    params: params.iter()
+                        .map(|p| p.type_annotation.clone().unwrap_or(Type::Infer))
+                        .collect(),
+                    inferred_at: vec![None; params.len()],
+                    return_type: return_type.clone().unwrap_or(Type::Infer),
+                };
+                if let Some(inferred) = inferred {
+                    for (i, param) in params.iter().enumerate() {
+                        if param.type_annotation.is_none() {
+                            sig.params[i] = inferred.params[i].clone();
+                            sig.inferred_at[i] = inferred.inferred_at[i];
+                        }
+                    }
+                    if return_type.is_none() {
+                        sig.return_type = inferred.return_type.clone();
+                    }
+                }
+                self.functions.insert(name.clone(), sig);
+            }
+            _ => {}
+        }
+    }
+
+    /// A checker that starts with everything this one knows; changes to it
+    /// don't leak back.
+    fn child_scope(&self) -> TypeChecker {
+        TypeChecker {
+            environment: self.environment.clone(),
+            struct_defs: self.struct_defs.clone(),
+            enum_defs: self.enum_defs.clone(),
+            trait_defs: self.trait_defs.clone(),
+            trait_impls: self.trait_impls.clone(),
+            functions: self.functions.clone(),
+            inferred: HashMap::new(),
+            warnings: Vec::new(),
+            return_type: self.return_type.clone(),
+        }
+    }
+
+    /// Checks that every bound names a known trait.
+    fn check_type_params(&self, type_params: &[TypeParam]) -> Result<(), TogError> {
+        for param in type_params {
+            for bound in &param.bounds {
+                if !self.trait_defs.contains_key(bound) {
+                    let error = TogError::TypeError(
+                        format!("Unknown trait '{}' in bound on type parameter {}", bound, param.name),
+                        None
+                    );
+                    return Err(match did_you_mean(bound, self.trait_defs.keys().map(String::as_str)) {
+                        Some(help) => error.with_help(help),
+                        None => error,
+                    });
+                }
+            }
+        }
         Ok(())
     }
+
+    /// Checks that generic types in an annotation get the right number of
+    /// type arguments.
+    fn check_type(&self, ty: &Type) -> Result<(), TogError> {
+        match ty {
+            Type::Generic { name, args } => {
+                let expected = self.type_params_of(name).map(|params| params.len());
+                match expected {
+                    Some(count) if count != args.len() => {
+                        return Err(TogError::TypeError(
+                            format!("{} expects {} type argument(s), got {}", name, count, args.len()),
+                            None
+                        ));
+                    }
+                    _ => {}
+                }
+                args.iter().try_for_each(|arg| self.check_type(arg))
+            }
+            Type::Array(inner) => self.check_type(inner),
+            Type::Tuple(elements) => elements.iter().try_for_each(|e| self.check_type(e)),
+            Type::Map(key, value) => {
+                self.check_type(key)?;
+                self.check_type(value)
+            }
+            Type::Set(element) => self.check_type(element),
+            Type::Function { params, return_type } => {
+                params.iter().try_for_each(|p| self.check_type(p))?;
+                self.check_type(return_type)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn type_params_of(&self, type_name: &str) -> Option<&[TypeParam]> {
+        self.struct_defs.get(type_name).map(|(params, _, _)| params.as_slice())
+            .or_else(|| self.enum_defs.get(type_name).map(|(params, _)| params.as_slice()))
+    }
+
+    /// Checks a call to a named function: argument count, argument types and
+    /// the bounds on type parameters. Returns the return type with the type
+    /// parameters the arguments determined filled in.
+    fn check_call(&self, name: &str, sig: &FunctionSig, args: &[Expr]) -> Result<Type, TogError> {
+        if args.len() != sig.params.len() {
+            return Err(TogError::TypeError(
+                format!("Function '{}' expects {} arguments, got {}", name, sig.params.len(), args.len()),
+                None
+            ));
+        }
+        let mut bindings = HashMap::new();
+        for (i, (param, arg)) in sig.params.iter().zip(args).enumerate() {
+            let arg_type = self.infer_expression_type(arg)?;
+            if !bind_type_params(param, &arg_type, &mut bindings) {
+                let param_type = substitute(param, &bindings);
+                let error = TogError::TypeError(
+                    format!("Argument to '{}' has type {}, but the parameter has type {}", name, arg_type, param_type),
+                    None
+                ).with_span(arg.span);
+                return Err(match sig.inferred_at[i] {
+                    Some(span) => error.with_label(span, format!("`{}` is inferred to be {} here", sig.param_names[i], param_type)),
+                    None => error,
+                });
+            }
+        }
+        check_bounds(&self.trait_impls, &[], &sig.type_params, &bindings, &format!("function '{}'", name))?;
+        Ok(substitute(&sig.return_type, &bindings))
+    }
     
     fn check_statement(&mut self, stmt: &Stmt) -> Result<(), TogError> {
-        match stmt {
-            Stmt::Let { name, type_annotation, value } => {
+        self.check_statement_kind(stmt).map_err(|e| e.with_span(stmt.span))
+    }
+
+    fn check_statement_kind(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::Let { name, type_annotation, value } => {
                 let value_type = self.infer_expression_type(value)?;
                 
                 if let Some(annotated_type) = type_annotation {
+                    self.check_type(annotated_type)?;
                     // Check type compatibility
                     if !types_compatible(&value_type, annotated_type) {
                         return Err(TogError::TypeError(
-                            format!("Type mismatch: expected {:?}, got {:?}", annotated_type, value_type),
+                            format!("Type mismatch: expected {}, got {}", annotated_type, value_type),
                             None
                         ));
                     }
@@ -46,7 +255,11 @@ impl TypeChecker {
                     self.environment.insert(name.clone(), value_type);
                 }
             }
-            Stmt::Assign { name, value } => {
+            StmtKind::LetPattern { pattern, value } => {
+                let value_type = self.infer_expression_type(value)?;
+                self.bind_pattern(pattern, &value_type)?;
+            }
+            StmtKind::Assign { name, op, value } => {
                 // Check if variable exists
                 if !self.environment.contains_key(name) {
                     return Err(TogError::TypeError(
@@ -54,76 +267,450 @@ impl TypeChecker {
                         None
                     ));
                 }
-                let value_type = self.infer_expression_type(value)?;
                 let var_type = self.environment.get(name).unwrap();
+                let value_type = self.assigned_type(var_type.clone(), *op, value)?;
                 
                 // Check type compatibility
                 if !types_compatible(&value_type, var_type) {
                     return Err(TogError::TypeError(
-                        format!("Type mismatch in assignment: variable '{}' has type {:?}, but assigned value has type {:?}", name, var_type, value_type),
+                        format!("Type mismatch in assignment: variable '{}' has type {}, but assigned value has type {}", name, var_type, value_type),
                         None
                     ));
                 }
             }
-            Stmt::AssignField { object, field, value } => {
+            StmtKind::AssignField { object, field, op, value } => {
                 // Check object type and field existence
                 let obj_type = self.infer_expression_type(object)?;
-                if let Type::Struct(struct_name) = obj_type {
-                    if let Some((fields, _)) = self.struct_defs.get(&struct_name) {
+                if let Type::Struct(struct_name) | Type::Generic { name: struct_name, .. } = obj_type {
+                    if let Some((_, fields, _)) = self.struct_defs.get(&struct_name) {
                         if let Some((_, field_type_opt)) = fields.iter().find(|(fname, _)| fname == field) {
-                            let value_type = self.infer_expression_type(value)?;
+                            let current = Expr::new(ExprKind::FieldAccess { object: object.clone(), field: field.clone() }, stmt.span);
+                            let value_type = self.assigned_type(self.infer_expression_type(&current)?, *op, value)?;
                             if let Some(field_type) = field_type_opt {
                                 if !types_compatible(&value_type, field_type) {
                                     return Err(TogError::TypeError(
-                                        format!("Type mismatch in field assignment: field '{}' has type {:?}, but assigned value has type {:?}", field, field_type, value_type),
+                                        format!("Type mismatch in field assignment: field '{}' has type {}, but assigned value has type {}", field, field_type, value_type),
                                         None
                                     ));
                                 }
                             }
                         } else {
-                            return Err(TogError::TypeError(
+                            let error = TogError::TypeError(
                                 format!("Struct '{}' has no field '{}'", struct_name, field),
                                 None
-                            ));
+                            );
+                            return Err(match did_you_mean(field, fields.iter().map(|(name, _)| name.as_str())) {
+                                Some(help) => error.with_help(help),
+                                None => error,
+                            });
                         }
                     }
                 } else {
                     return Err(TogError::TypeError(
-                        format!("Cannot assign field to non-struct type {:?}", obj_type),
+                        format!("Cannot assign field to non-struct type {}", obj_type),
                         None
                     ));
                 }
             }
-            Stmt::StructDef { name, fields, methods } => {
-                self.struct_defs.insert(name.clone(), (fields.clone(), methods.clone()));
+            StmtKind::AssignIndex { object, index, op, value } => {
+                let obj_type = self.infer_expression_type(object)?;
+                let index_type = self.infer_expression_type(index)?;
+                let current_type = match op {
+                    Some(_) => self.infer_expression_type(&Expr::new(ExprKind::Index { array: object.clone(), index: Box::new(index.clone()) }, stmt.span))?,
+                    None => Type::Infer,
+                };
+                let value_type = self.assigned_type(current_type, *op, value)?;
+                let (expected_index, element_type) = match &obj_type {
+                    Type::Map(key, value) => ((**key).clone(), (**value).clone()),
+                    Type::Array(element) => (Type::Int, (**element).clone()),
+                    Type::Infer | Type::Param(_) => (Type::Infer, Type::Infer),
+                    _ => return Err(TogError::TypeError(
+                        format!("Cannot assign to an index of type {}", obj_type),
+                        None
+                    )),
+                };
+                if !types_compatible(&index_type, &expected_index) {
+                    return Err(TogError::TypeError(
+                        format!("Index into {} must be {}, got {}", obj_type, expected_index, index_type),
+                        Some(index.span)
+                    ));
+                }
+                if !types_compatible(&value_type, &element_type) {
+                    return Err(TogError::TypeError(
+                        format!("Type mismatch in index assignment: {} holds {}, but assigned value has type {}", obj_type, element_type, value_type),
+                        None
+                    ));
+                }
+            }
+            StmtKind::StructDef { type_params, fields, methods, .. } => {
+                self.declare(stmt);
+                self.check_type_params(type_params)?;
+                for (_, field_type) in fields.iter() {
+                    if let Some(field_type) = field_type {
+                        self.check_type(field_type)?;
+                    }
+                }
+                for method in methods {
+                    self.check_method(&method.name, &method.params, method.return_type.as_ref(), &method.body)?;
+                }
             }
-            Stmt::EnumDef { .. } => {
-                // Enum definitions - no type checking needed here
+            StmtKind::EnumDef { type_params, variants, .. } => {
+                self.declare(stmt);
+                self.check_type_params(type_params)?;
+                for variant in variants {
+                    if let Some(data_type) = &variant.data_type {
+                        self.check_type(data_type)?;
+                    }
+                }
+            }
+            StmtKind::TraitDef { type_params, methods, .. } => {
+                self.declare(stmt);
+                self.check_type_params(type_params)?;
+                for method in methods {
+                    if let Some(body) = &method.default_body {
+                        self.check_method(&method.name, &method.params, method.return_type.as_ref(), body)?;
+                    }
+                }
             }
-            Stmt::TraitDef { .. } => {
-                // Trait definitions - no type checking needed here
+            StmtKind::ImplBlock { type_params, trait_name: Some(trait_name), type_name, methods } => {
+                self.declare(stmt);
+                self.check_type_params(type_params)?;
+                let trait_methods = self.trait_defs.get(trait_name)
+                    .ok_or_else(|| TogError::TypeError(
+                        format!("Unknown trait '{}' in impl for {}", trait_name, type_name),
+                        None
+                    ))?;
+                check_trait_impl(trait_name, trait_methods, type_name, methods)?;
+                for method in methods {
+                    self.check_method(&method.name, &method.params, method.return_type.as_ref(), &method.body)?;
+                }
             }
-            Stmt::ImplBlock { .. } => {
-                // Impl blocks - type checking will be added later
+            StmtKind::ImplBlock { type_params, trait_name: None, methods, .. } => {
+                // Inherent impls have no contract to check
+                self.check_type_params(type_params)?;
+                for method in methods {
+                    self.check_method(&method.name, &method.params, method.return_type.as_ref(), &method.body)?;
+                }
             }
-            Stmt::Return(expr) => {
+            StmtKind::Return(expr) => {
                 if let Some(expr) = expr {
                     self.infer_expression_type(expr)?;
                 }
             }
-            Stmt::Break | Stmt::Continue => {
-                // No type checking needed for break/continue
+            StmtKind::Break | StmtKind::Continue | StmtKind::Import { .. } | StmtKind::Error => {
+                // No type checking needed for break/continue, imports (the
+                // module loader has linked them) or broken statements
             }
-            Stmt::Expr(expr) => {
+            StmtKind::Expr(expr) => {
+                if let ExprKind::Function { type_params, params, return_type, body, .. } = &expr.kind {
+                    self.declare(stmt);
+                    self.check_type_params(type_params)?;
+                    for param in params {
+                        if let Some(param_type) = &param.type_annotation {
+                            self.check_type(param_type)?;
+                        }
+                    }
+                    if let Some(return_type) = return_type {
+                        self.check_type(return_type)?;
+                    }
+                    self.check_try_operators(params, return_type.as_ref(), body)?;
+                }
                 self.infer_expression_type(expr)?;
             }
         }
         Ok(())
     }
     
+    /// The type of the value `target op= value` stores, where the target
+    /// holds `current`; just `value`'s for a plain assignment.
+    fn assigned_type(&self, current: Type, op: Option<BinaryOp>, value: &Expr) -> Result<Type, TogError> {
+        let value_type = self.infer_expression_type(value)?;
+        match op {
+            Some(op) => binary_type(current, op, value_type),
+            None => Ok(value_type),
+        }
+    }
+
+    /// Infers an expression's type. Errors point at the innermost expression
+    /// that failed.
+    /// The type of a function body. Unlike `infer_expression_type` on a block,
+    /// this checks `let` statements so later statements can use the variables.
+    fn infer_body_type(&mut self, body: &Expr) -> Result<Type, TogError> {
+        let ExprKind::Block(statements) = &body.kind else {
+            return self.infer_expression_type(body);
+        };
+        let mut last_type = Type::None;
+        for stmt in statements {
+            match &stmt.kind {
+                StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) => {
+                    last_type = self.infer_expression_type(expr)?;
+                }
+                _ => self.check_statement(stmt)?,
+            }
+        }
+        Ok(last_type)
+    }
+
+    fn check_method(&mut self, name: &str, params: &[Param], return_type: Option<&Type>, body: &Expr) -> Result<(), TogError> {
+        self.check_try_operators(params, return_type, body)?;
+        if params.first().is_some_and(|p| p.name == "self" && !p.by_ref) {
+            let mut receiver = ReceiverUse::default();
+            receiver.expr(body);
+            if let (Some(span), false) = (receiver.assigned, receiver.escapes) {
+                self.warnings.push(TogError::TypeError(
+                    format!("Method '{}' changes `self`, which it takes by value, so the change is lost when it returns", name),
+                    Some(span)
+                ).with_help("take `&mut self` to change the value the method is called on, or return `self`"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the `?` operators in a function body against the function's
+    /// return type. This checker doesn't otherwise walk bodies (`inference`
+    /// checks the calls, values and bounds in them), so an operand whose type
+    /// depends on local variables counts as unknown.
+    fn check_try_operators(&self, params: &[Param], return_type: Option<&Type>, body: &Expr) -> Result<(), TogError> {
+        let mut scope = self.child_scope();
+        for param in params {
+            scope.environment.insert(param.name.clone(), param.type_annotation.clone().unwrap_or(Type::Infer));
+        }
+        scope.return_type = Some(return_type.cloned().unwrap_or(Type::Infer));
+        scope.check_try_sites(body)
+    }
+
+    fn check_try_sites(&self, expr: &Expr) -> Result<(), TogError> {
+        match &expr.kind {
+            // Nested functions and lambdas return on their own account
+            ExprKind::Function { params, return_type, body, .. } | ExprKind::Lambda { params, return_type, body } => {
+                return self.check_try_operators(params, return_type.as_ref(), body);
+            }
+            ExprKind::Try(operand) => {
+                let operand_type = self.infer_expression_type(operand).unwrap_or(Type::Infer);
+                self.try_type(&operand_type).map_err(|e| e.with_span(expr.span))?;
+            }
+            _ => {}
+        }
+        for child in expr.children() {
+            self.check_try_sites(child)?;
+        }
+        Ok(())
+    }
+
+    /// The type of `operand?`, the `Ok` or `Some` data. Checks that the
+    /// enclosing function can return the `Err` or `None` instead.
+    fn try_type(&self, operand_type: &Type) -> Result<Type, TogError> {
+        let Some(return_type) = &self.return_type else {
+            return Err(TogError::TypeError(
+                "The `?` operator can only be used inside a function".to_string(),
+                None
+            ));
+        };
+        let (name, _) = named_type(operand_type);
+        let (kind, described, data_variant, early) = match name.as_deref() {
+            Some("Result") => ("Result", "a Result", "Ok", "the Err"),
+            Some("Option") => ("Option", "an Option", "Some", "None"),
+            _ if matches!(operand_type, Type::Infer | Type::Param(_)) => {
+                // Whichever it is, the function has to return one of them
+                let (return_name, _) = named_type(return_type);
+                if *return_type != Type::Infer && !matches!(return_name.as_deref(), Some("Result" | "Option")) {
+                    return Err(TogError::TypeError(
+                        format!("The `?` operator can only return early from a function that returns a Result or Option, but this one returns {}", return_type),
+                        None
+                    ));
+                }
+                return Ok(Type::Infer);
+            }
+            _ => {
+                return Err(TogError::TypeError(
+                    format!("The `?` operator needs a Result or Option, got {}", operand_type),
+                    None
+                ));
+            }
+        };
+
+        if *return_type != Type::Infer {
+            let (return_name, _) = named_type(return_type);
+            if return_name.as_deref() != Some(kind) {
+                return Err(TogError::TypeError(
+                    format!("The `?` operator returns {} from this function, but it is declared to return {}", early, return_type),
+                    None
+                ).with_help(format!("declare the function to return {}, or use a match to handle {}", described, early)));
+            }
+            if kind == "Result" {
+                let error_type = self.variant_data_type(operand_type, "Err").unwrap_or(Type::Infer);
+                let return_error = self.variant_data_type(return_type, "Err").unwrap_or(Type::Infer);
+                if !types_compatible(&error_type, &return_error) {
+                    return Err(TogError::TypeError(
+                        format!("The `?` operator returns {} errors from a function whose errors are {}", error_type, return_error),
+                        None
+                    ));
+                }
+            }
+        }
+        Ok(self.variant_data_type(operand_type, data_variant).unwrap_or(Type::Infer))
+    }
+
+    /// The type of a call to an associated function of a builtin type, such
+    /// as `Map::new()`, or None if `type_name::name` isn't one.
+    fn builtin_associated_type(&self, type_name: &str, name: &str, args: &[Expr]) -> Result<Option<Type>, TogError> {
+        if self.struct_defs.contains_key(type_name) || self.enum_defs.contains_key(type_name) {
+            return Ok(None);
+        }
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.infer_expression_type(arg)).collect::<Result<_, _>>()?;
+        Ok(match (type_name, name, arg_types.as_slice()) {
+            ("Map", "new", []) => Some(Type::Map(Box::new(Type::Infer), Box::new(Type::Infer))),
+            ("Set", "new", []) => Some(Type::Set(Box::new(Type::Infer))),
+            ("Set", "from", [Type::Array(element)]) => Some(Type::Set(element.clone())),
+            ("Set", "from", [Type::Infer | Type::Param(_)]) => Some(Type::Set(Box::new(Type::Infer))),
+            ("Set", "from", [other]) => {
+                return Err(TogError::TypeError(format!("Set::from() expects array, got {}", other), None));
+            }
+            _ => None,
+        })
+    }
+
+    /// The data type of `variant` in the enum type `ty`, with the type
+    /// arguments filled in.
+    fn variant_data_type(&self, ty: &Type, variant: &str) -> Option<Type> {
+        let (name, args) = named_type(ty);
+        let (type_params, variants) = self.enum_defs.get(&name?)?;
+        let data_type = variants.iter().find(|v| v.name == variant)?.data_type.as_ref()?;
+        Some(substitute(data_type, &type_arguments(type_params, args)))
+    }
+
+    /// Checks `pattern` against a value of type `ty` and binds the variables
+    /// it introduces. Parts whose type isn't known are bound as `Infer`.
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: &Type) -> Result<(), TogError> {
+        match pattern {
+            Pattern::Variable(name) => {
+                self.environment.insert(name.clone(), ty.clone());
+            }
+            Pattern::Wildcard | Pattern::Rest(None) => {}
+            Pattern::Rest(Some(name)) => {
+                // Only reached inside an array pattern, where `ty` is the array
+                self.environment.insert(name.clone(), ty.clone());
+            }
+            Pattern::Literal(lit) | Pattern::Range { start: lit, .. } => {
+                let lit_type = literal_type(lit);
+                if !types_compatible(&lit_type, ty) {
+                    return Err(TogError::TypeError(
+                        format!("Mismatched pattern: expected {}, found {}", ty, lit_type),
+                        None
+                    ));
+                }
+            }
+            Pattern::EnumVariant { enum_name, variant_name, inner } => {
+                let (name, args) = named_type(ty);
+                let enum_name = if enum_name.is_empty() { name.unwrap_or_default() } else { enum_name.clone() };
+                let Some((type_params, variants)) = self.enum_defs.get(&enum_name) else {
+                    // A bare variant of an unknown enum, or a type we can't see
+                    if let Some(inner) = inner {
+                        self.bind_pattern(inner, &Type::Infer)?;
+                    }
+                    return Ok(());
+                };
+                let Some(variant) = variants.iter().find(|v| v.name == *variant_name) else {
+                    let error = TogError::TypeError(
+                        format!("Enum {} has no variant {}", enum_name, variant_name),
+                        None
+                    );
+                    return Err(match did_you_mean(variant_name, variants.iter().map(|v| v.name.as_str())) {
+                        Some(help) => error.with_help(help),
+                        None => error,
+                    });
+                };
+                let Some(inner) = inner else {
+                    return Ok(());
+                };
+                let Some(data_type) = &variant.data_type else {
+                    return Err(TogError::TypeError(
+                        format!("Variant {}::{} has no data to match", enum_name, variant_name),
+                        None
+                    ).with_help(format!("use `{}::{}` without a pattern", enum_name, variant_name)));
+                };
+                let bindings = type_arguments(type_params, args);
+                let data_type = substitute(data_type, &bindings);
+                self.bind_pattern(inner, &data_type)?;
+            }
+            Pattern::Struct { name, fields } => {
+                let Some((type_params, declared, _)) = self.struct_defs.get(name) else {
+                    return Err(TogError::TypeError(format!("Unknown struct: {}", name), None));
+                };
+                let (_, args) = named_type(ty);
+                let bindings = type_arguments(type_params, args);
+                let mut field_types = Vec::new();
+                for (field, _) in fields {
+                    match declared.iter().find(|(declared_name, _)| declared_name == field) {
+                        Some((_, field_type)) => {
+                            let field_type = field_type.as_ref().map_or(Type::Infer, |t| substitute(t, &bindings));
+                            field_types.push(field_type);
+                        }
+                        None => {
+                            let error = TogError::TypeError(
+                                format!("Struct {} has no field '{}'", name, field),
+                                None
+                            );
+                            return Err(match did_you_mean(field, declared.iter().map(|(n, _)| n.as_str())) {
+                                Some(help) => error.with_help(help),
+                                None => error,
+                            });
+                        }
+                    }
+                }
+                for ((_, field_pattern), field_type) in fields.iter().zip(&field_types) {
+                    self.bind_pattern(field_pattern, field_type)?;
+                }
+            }
+            Pattern::Array(elements) => {
+                let element_type = match ty {
+                    Type::Array(inner) => (**inner).clone(),
+                    Type::Infer | Type::Param(_) => Type::Infer,
+                    _ => return Err(TogError::TypeError(
+                        format!("Array pattern can't match a value of type {}", ty),
+                        None
+                    )),
+                };
+                for element in elements {
+                    match element {
+                        Pattern::Rest(_) => self.bind_pattern(element, &Type::Array(Box::new(element_type.clone())))?,
+                        _ => self.bind_pattern(element, &element_type)?,
+                    }
+                }
+            }
+            Pattern::Tuple(elements) => {
+                let element_types = match ty {
+                    Type::Tuple(types) if types.len() == elements.len() => types.clone(),
+                    Type::Tuple(types) => return Err(TogError::TypeError(
+                        format!("Tuple pattern has {} elements, but the value {} has {}", elements.len(), ty, types.len()),
+                        None
+                    )),
+                    Type::Infer | Type::Param(_) => vec![Type::Infer; elements.len()],
+                    _ => return Err(TogError::TypeError(
+                        format!("Tuple pattern can't match a value of type {}", ty),
+                        None
+                    )),
+                };
+                for (element, element_type) in elements.iter().zip(&element_types) {
+                    self.bind_pattern(element, element_type)?;
+                }
+            }
+            Pattern::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.bind_pattern(alternative, ty)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn infer_expression_type(&self, expr: &Expr) -> Result<Type, TogError> {
-        match expr {
-            Expr::Literal(lit) => {
+        self.infer_expression_kind(expr).map_err(|e| e.with_span(expr.span))
+    }
+
+    fn infer_expression_kind(&self, expr: &Expr) -> Result<Type, TogError> {
+        match &expr.kind {
+            ExprKind::Literal(lit) => {
                 Ok(match lit {
                     Literal::Int(_) => Type::Int,
                     Literal::Float(_) => Type::Float,
@@ -137,67 +724,104 @@ impl TypeChecker {
                             Type::Array(Box::new(first_type))
                         }
                     }
+                    Literal::Tuple(elems) => {
+                        let types: Result<Vec<Type>, TogError> = elems.iter().map(|e| self.infer_expression_type(e)).collect();
+                        Type::Tuple(types?)
+                    }
+                    Literal::Map(entries) => match entries.first() {
+                        Some((key, value)) => {
+                            for (key, value) in &entries[1..] {
+                                self.infer_expression_type(key)?;
+                                self.infer_expression_type(value)?;
+                            }
+                            Type::Map(Box::new(self.infer_expression_type(key)?), Box::new(self.infer_expression_type(value)?))
+                        }
+                        None => Type::Map(Box::new(Type::Infer), Box::new(Type::Infer)),
+                    },
                     Literal::None => Type::None,
                 })
             }
-            Expr::StructLiteral { name, .. } => {
-                Ok(Type::Struct(name.clone()))
+            ExprKind::StructLiteral { name, fields } => {
+                let Some((type_params, declared, _)) = self.struct_defs.get(name) else {
+                    return Ok(Type::Struct(name.clone()));
+                };
+                if type_params.is_empty() {
+                    return Ok(Type::Struct(name.clone()));
+                }
+                // The field values determine the type arguments
+                let mut bindings = HashMap::new();
+                for (field, value) in fields {
+                    let value_type = self.infer_expression_type(value)?;
+                    let Some((_, Some(field_type))) = declared.iter().find(|(n, _)| n == field) else {
+                        continue;
+                    };
+                    if !bind_type_params(field_type, &value_type, &mut bindings) {
+                        return Err(TogError::TypeError(
+                            format!("Field '{}' of {} has type {}, but the value has type {}", field, name, substitute(field_type, &bindings), value_type),
+                            None
+                        ).with_span(value.span));
+                    }
+                }
+                check_bounds(&self.trait_impls, &[], type_params, &bindings, &format!("struct {}", name))?;
+                Ok(instantiate(name, type_params, &bindings))
             }
-            Expr::EnumVariant { enum_name, .. } => {
-                Ok(Type::Enum(enum_name.clone()))
+            ExprKind::EnumVariant { enum_name, variant_name, data } => {
+                // `Set::from(items)` parses like a variant holding `items`
+                let args = data.as_deref().map(std::slice::from_ref).unwrap_or_default();
+                if let Some(ty) = self.builtin_associated_type(enum_name, variant_name, args)? {
+                    return Ok(ty);
+                }
+                let Some((type_params, variants)) = self.enum_defs.get(enum_name) else {
+                    return Ok(Type::Enum(enum_name.clone()));
+                };
+                let mut bindings = HashMap::new();
+                let data_type = variants.iter()
+                    .find(|v| &v.name == variant_name)
+                    .and_then(|v| v.data_type.as_ref());
+                if let (Some(data), Some(data_type)) = (data, data_type) {
+                    let value_type = self.infer_expression_type(data)?;
+                    if !bind_type_params(data_type, &value_type, &mut bindings) {
+                        return Err(TogError::TypeError(
+                            format!("{}::{} holds {}, but the value has type {}", enum_name, variant_name, substitute(data_type, &bindings), value_type),
+                            None
+                        ).with_span(data.span));
+                    }
+                }
+                if type_params.is_empty() {
+                    return Ok(Type::Enum(enum_name.clone()));
+                }
+                check_bounds(&self.trait_impls, &[], type_params, &bindings, &format!("enum {}", enum_name))?;
+                Ok(instantiate(enum_name, type_params, &bindings))
+            }
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr { expr, .. } = part {
+                        self.infer_expression_type(expr)?;
+                    }
+                }
+                Ok(Type::String)
             }
-            Expr::Variable(name) => {
+            ExprKind::Variable(name) => {
                 self.environment.get(name)
                     .cloned()
-                    .ok_or_else(|| TogError::TypeError(
-                        format!("Undefined variable: {}", name),
-                        None
-                    ))
+                    .ok_or_else(|| {
+                        let error = TogError::TypeError(format!("Undefined variable: {}", name), None);
+                        match did_you_mean(name, self.environment.keys().map(String::as_str)) {
+                            Some(help) => error.with_help(help),
+                            None => error,
+                        }
+                    })
             }
-            Expr::BinaryOp { left, op, right } => {
+            ExprKind::BinaryOp { left, op, right } => {
                 let left_type = self.infer_expression_type(left)?;
                 let right_type = self.infer_expression_type(right)?;
-                
-                match op {
-                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
-                        // Arithmetic operations
-                        let left_clone = left_type.clone();
-                        let right_clone = right_type.clone();
-                        match (left_type, right_type) {
-                            (Type::Int, Type::Int) => Ok(Type::Int),
-                            (Type::Float, _) | (_, Type::Float) => Ok(Type::Float),
-                            (Type::String, _) | (_, Type::String) => Ok(Type::String),
-                            _ => Err(TogError::TypeError(
-                                format!("Invalid operation: {:?} {:?} {:?}", left_clone, op, right_clone),
-                                None
-                            )),
-                        }
-                    }
-                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | 
-                    BinaryOp::Gt | BinaryOp::Ge => {
-                        Ok(Type::Bool)
-                    }
-                    BinaryOp::And | BinaryOp::Or => {
-                        if left_type == Type::Bool && right_type == Type::Bool {
-                            Ok(Type::Bool)
-                        } else {
-                            Err(TogError::TypeError("Logical operations require bool operands".to_string(), None))
-                        }
-                    }
-                    BinaryOp::Mod => {
-                        if left_type == Type::Int && right_type == Type::Int {
-                            Ok(Type::Int)
-                        } else {
-                            Err(TogError::TypeError("Modulo requires int operands".to_string(), None))
-                        }
-                    }
-                }
+                binary_type(left_type, *op, right_type)
             }
-            Expr::UnaryOp { op, expr } => {
+            ExprKind::UnaryOp { op, expr } => {
                 let expr_type = self.infer_expression_type(expr)?;
                 match op {
                     UnaryOp::Not => {
-                        if expr_type == Type::Bool {
+                        if types_compatible(&expr_type, &Type::Bool) {
                             Ok(Type::Bool)
                         } else {
                             Err(TogError::TypeError("Not operator requires bool operand".to_string(), None))
@@ -205,46 +829,79 @@ impl TypeChecker {
                     }
                     UnaryOp::Neg => {
                         match expr_type {
-                            Type::Int | Type::Float => Ok(expr_type),
+                            Type::Int | Type::Float | Type::Infer => Ok(expr_type),
                             _ => Err(TogError::TypeError("Negation requires numeric operand".to_string(), None)),
                         }
                     }
                 }
             }
-            Expr::Call { callee, args } => {
+            ExprKind::Call { callee, args } => {
+                // `Move(1, 2)` holds its values as a tuple
+                if let ExprKind::EnumVariant { enum_name, variant_name, data: None } = &callee.kind {
+                    if let Some(ty) = self.builtin_associated_type(enum_name, variant_name, args)? {
+                        return Ok(ty);
+                    }
+                    let is_variant = self.enum_defs.get(enum_name)
+                        .is_some_and(|(_, variants)| variants.iter().any(|v| v.name == *variant_name));
+                    if is_variant && !args.is_empty() {
+                        let data = Expr::new(ExprKind::Literal(Literal::Tuple(args.clone())), expr.span);
+                        return self.infer_expression_type(&Expr::new(ExprKind::EnumVariant {
+                            enum_name: enum_name.clone(),
+                            variant_name: variant_name.clone(),
+                            data: Some(Box::new(data)),
+                        }, expr.span));
+                    }
+                }
                 // For builtin functions
-                if let Expr::Variable(name) = callee.as_ref() {
+                if let ExprKind::Variable(name) = &callee.kind {
                     match name.as_str() {
                         "print" => {
+                            for arg in args {
+                                self.infer_expression_type(arg)?;
+                            }
                             // print returns None
                             Ok(Type::None)
                         }
                         "len" => {
                             if args.len() == 1 {
+                                self.infer_expression_type(&args[0])?;
                                 Ok(Type::Int)
                             } else {
                                 Err(TogError::TypeError("len() expects 1 argument".to_string(), None))
                             }
                         }
                         _ => {
-                            // TODO: Look up function definition
-                            Ok(Type::Infer)
+                            // Variables holding lambdas know their return type
+                            match self.environment.get(name) {
+                                Some(Type::Function { return_type, .. }) => Ok((**return_type).clone()),
+                                Some(_) => Ok(Type::Infer),
+                                None => match self.functions.get(name) {
+                                    Some(sig) => self.check_call(name, sig, args),
+                                    None => {
+                                        // Another builtin
+                                        for arg in args {
+                                            self.infer_expression_type(arg)?;
+                                        }
+                                        Ok(Type::Infer)
+                                    }
+                                },
+                            }
                         }
                     }
                 } else {
                     Ok(Type::Infer)
                 }
             }
-            Expr::Block(statements) => {
+            ExprKind::Block(statements) => {
                 let mut last_type = Type::None;
                 for stmt in statements {
-                    match stmt {
-                        Stmt::Return(expr) => {
+                    match &stmt.kind {
+                        StmtKind::Return(expr) => {
                             if let Some(expr) = expr {
                                 last_type = self.infer_expression_type(expr)?;
                             }
                         }
-                        Stmt::Expr(expr) => {
+                        StmtKind::Expr(expr) => {
                             last_type = self.infer_expression_type(expr)?;
                         }
                         _ => {}
@@ -252,7 +909,7 @@ impl TypeChecker {
                 }
                 Ok(last_type)
             }
-            Expr::If { then_branch, else_branch, .. } => {
+            ExprKind::If { then_branch, else_branch, .. } => {
                 let then_type = self.infer_expression_type(then_branch)?;
                 if let Some(else_expr) = else_branch {
                     let else_type = self.infer_expression_type(else_expr)?;
@@ -266,27 +923,87 @@ impl TypeChecker {
                     Ok(Type::None)
                 }
             }
-            Expr::While { .. } => {
+            ExprKind::While { .. } => {
                 Ok(Type::None)
             }
-            Expr::For { .. } => {
+            ExprKind::For { .. } => {
                 Ok(Type::None)
             }
-            Expr::Match { .. } => {
-                // TODO: Infer from match arms
-                Ok(Type::Infer)
+            ExprKind::Match { expr, arms } => {
+                let scrutinee = self.infer_expression_type(expr)?;
+                let mut arm_types = Vec::new();
+                for arm in arms {
+                    let mut scope = self.child_scope();
+                    scope.bind_pattern(&arm.pattern, &scrutinee)?;
+                    if let Some(guard) = &arm.guard {
+                        let guard_type = scope.infer_expression_type(guard)?;
+                        if !types_compatible(&guard_type, &Type::Bool) {
+                            return Err(TogError::TypeError(
+                                format!("Match guard must be a bool, got {}", guard_type),
+                                Some(guard.span)
+                            ));
+                        }
+                    }
+                    arm_types.push(scope.infer_body_type(&arm.body)?);
+                }
+                // The arms' common type, if they agree
+                let known = arm_types.iter().find(|ty| **ty != Type::Infer).cloned().unwrap_or(Type::Infer);
+                match arm_types.iter().all(|ty| types_compatible(ty, &known)) {
+                    true => Ok(known),
+                    false => Ok(Type::Infer),
+                }
             }
-            Expr::Function { return_type, .. } => {
+            ExprKind::Function { return_type, .. } => {
                 Ok(return_type.clone().unwrap_or(Type::Infer))
             }
-            Expr::Index { array, index } => {
+            ExprKind::Lambda { params, return_type, body } => {
+                let param_types: Vec<Type> = params.iter()
+                    .map(|p| p.type_annotation.clone().unwrap_or(Type::Infer))
+                    .collect();
+                // The body sees the enclosing variables plus the parameters
+                let mut scope = self.child_scope();
+                for (param, ty) in params.iter().zip(&param_types) {
+                    scope.environment.insert(param.name.clone(), ty.clone());
+                }
+                scope.return_type = Some(return_type.clone().unwrap_or(Type::Infer));
+                let body_type = scope.infer_body_type(body)?;
+                let return_type = match return_type {
+                    Some(annotated) if !types_compatible(&body_type, annotated) => {
+                        return Err(TogError::TypeError(
+                            format!("Lambda is annotated to return {}, but its body has type {}", annotated, body_type),
+                            None
+                        ));
+                    }
+                    Some(annotated) => annotated.clone(),
+                    None => body_type,
+                };
+                Ok(Type::Function {
+                    params: param_types,
+                    return_type: Box::new(return_type),
+                })
+            }
+            ExprKind::Try(operand) => {
+                let operand_type = self.infer_expression_type(operand)?;
+                self.try_type(&operand_type)
+            }
+            ExprKind::Index { array, index } => {
                 let array_type = self.infer_expression_type(array)?;
                 let index_type = self.infer_expression_type(index)?;
+
+                if let Type::Map(key_type, value_type) = array_type {
+                    if !types_compatible(&index_type, &key_type) {
+                        return Err(TogError::TypeError(
+                            format!("Map key must be {}, got {}", key_type, index_type),
+                            Some(index.span)
+                        ));
+                    }
+                    return Ok(*value_type);
+                }
                 
                 // Index must be Int
                 if index_type != Type::Int {
                     return Err(TogError::TypeError(
-                        format!("Array index must be Int, got {:?}", index_type),
+                        format!("Array index must be int, got {}", index_type),
                         None
                     ));
                 }
@@ -296,16 +1013,16 @@ impl TypeChecker {
                     Type::Array(elem_type) => Ok(*elem_type),
                     Type::String => Ok(Type::String), // String indexing returns String (char)
                     _ => Err(TogError::TypeError(
-                        format!("Cannot index type {:?}", array_type),
+                        format!("Cannot index type {}", array_type),
                         None
                     ))
                 }
             }
-            Expr::FieldAccess { object, field } => {
+            ExprKind::FieldAccess { object, field } => {
                 let obj_type = self.infer_expression_type(object)?;
                 match obj_type {
                     Type::Struct(name) => {
-                        if let Some((fields, _)) = self.struct_defs.get(&name) {
+                        if let Some((_, fields, _)) = self.struct_defs.get(&name) {
                             if let Some((_, ty)) = fields.iter().find(|(fname, _)| fname == field) {
                                 if let Some(t) = ty {
                                     return Ok(t.clone());
@@ -314,6 +1031,30 @@ impl TypeChecker {
                         }
                         Ok(Type::Infer)
                     }
+                    Type::Generic { name, args } => {
+                        // Fill the struct's type parameters in from the arguments
+                        let Some((type_params, fields, _)) = self.struct_defs.get(&name) else {
+                            return Ok(Type::Infer);
+                        };
+                        let bindings = type_arguments(type_params, &args);
+                        match fields.iter().find(|(fname, _)| fname == field) {
+                            Some((_, Some(ty))) => Ok(substitute(ty, &bindings)),
+                            _ => Ok(Type::Infer),
+                        }
+                    }
+                    Type::Tuple(elements) => {
+                        let Ok(index) = field.parse::<usize>() else {
+                            return Err(TogError::TypeError(
+                                format!("Tuple {} has no field '{}'", Type::Tuple(elements), field),
+                                None
+                            ).with_help("tuple elements are read by position: `pair.0`, `pair.1`"));
+                        };
+                        let len = elements.len();
+                        elements.into_iter().nth(index).ok_or_else(|| TogError::TypeError(
+                            format!("Tuple index {} out of bounds for a tuple of {} elements", index, len),
+                            None
+                        ))
+                    }
                     _ => Ok(Type::Infer),
                 }
             }
@@ -321,10 +1062,391 @@ impl TypeChecker {
     }
 }
 
+/// The type of `left op right`.
+fn binary_type(left_type: Type, op: BinaryOp, right_type: Type) -> Result<Type, TogError> {
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+            // Arithmetic operations
+            let left_clone = left_type.clone();
+            let right_clone = right_type.clone();
+            match (left_type, right_type) {
+                (Type::Int, Type::Int) => Ok(Type::Int),
+                (Type::Float, _) | (_, Type::Float) => Ok(Type::Float),
+                (Type::String, _) | (_, Type::String) => Ok(Type::String),
+                // e.g. an unannotated lambda parameter
+                (Type::Infer, _) | (_, Type::Infer) => Ok(Type::Infer),
+                _ => Err(TogError::TypeError(
+                    format!("Invalid operation: {} {:?} {}", left_clone, op, right_clone),
+                    None
+                )),
+            }
+        }
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | 
+        BinaryOp::Gt | BinaryOp::Ge => {
+            Ok(Type::Bool)
+        }
+        BinaryOp::And | BinaryOp::Or => {
+            if types_compatible(&left_type, &Type::Bool) && types_compatible(&right_type, &Type::Bool) {
+                Ok(Type::Bool)
+            } else {
+                Err(TogError::TypeError("Logical operations require bool operands".to_string(), None))
+            }
+        }
+        BinaryOp::Mod => {
+            if types_compatible(&left_type, &Type::Int) && types_compatible(&right_type, &Type::Int) {
+                Ok(Type::Int)
+            } else {
+                Err(TogError::TypeError("Modulo requires int operands".to_string(), None))
+            }
+        }
+    }
+}
+
+fn literal_type(lit: &Literal) -> Type {
+    match lit {
+        Literal::Int(_) => Type::Int,
+        Literal::Float(_) => Type::Float,
+        Literal::String(_) => Type::String,
+        Literal::Bool(_) => Type::Bool,
+        Literal::Array(_) => Type::Array(Box::new(Type::Infer)),
+        Literal::Tuple(elems) => Type::Tuple(vec![Type::Infer; elems.len()]),
+        Literal::Map(_) => Type::Map(Box::new(Type::Infer), Box::new(Type::Infer)),
+        Literal::None => Type::None,
+    }
+}
+
+/// The struct or enum a type names, and its type arguments.
+fn named_type(ty: &Type) -> (Option<String>, &[Type]) {
+    match ty {
+        Type::Struct(name) | Type::Enum(name) => (Some(name.clone()), &[]),
+        Type::Generic { name, args } => (Some(name.clone()), args),
+        _ => (None, &[]),
+    }
+}
+
+/// Binds a definition's type parameters to the arguments of one of its
+/// instances. Parameters without an argument are left unbound.
+fn type_arguments(type_params: &[TypeParam], args: &[Type]) -> HashMap<String, Type> {
+    type_params.iter().map(|p| p.name.clone()).zip(args.iter().cloned()).collect()
+}
+
 fn types_compatible(t1: &Type, t2: &Type) -> bool {
     match (t1, t2) {
         (Type::Infer, _) | (_, Type::Infer) => true, // Infer is compatible with anything
+        (Type::Param(_), _) | (_, Type::Param(_)) => true, // Checked where the generic is used
+        (Type::Array(a), Type::Array(b)) => types_compatible(a, b),
+        (Type::Tuple(a), Type::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| types_compatible(a, b))
+        }
+        (Type::Map(k1, v1), Type::Map(k2, v2)) => types_compatible(k1, k2) && types_compatible(v1, v2),
+        (Type::Set(a), Type::Set(b)) => types_compatible(a, b),
+        (Type::Generic { name: n1, args: a1 }, Type::Generic { name: n2, args: a2 }) => {
+            n1 == n2 && a1.len() == a2.len() && a1.iter().zip(a2).all(|(a, b)| types_compatible(a, b))
+        }
+        // Annotations can't tell structs from enums, and may leave out type arguments
+        (Type::Struct(n1) | Type::Enum(n1) | Type::Generic { name: n1, .. },
+         Type::Struct(n2) | Type::Enum(n2) | Type::Generic { name: n2, .. }) => n1 == n2,
+        (Type::Function { params: p1, return_type: r1 }, Type::Function { params: p2, return_type: r2 }) => {
+            p1.len() == p2.len()
+                && p1.iter().zip(p2).all(|(a, b)| types_compatible(a, b))
+                && types_compatible(r1, r2)
+        }
         (a, b) => a == b,
     }
 }
 
+/// Matches `expected` (which may mention type parameters) against `actual`,
+/// recording what each parameter stands for. Fails on a mismatch, including a
+/// parameter that would have to be two different types.
+fn bind_type_params(expected: &Type, actual: &Type, bindings: &mut HashMap<String, Type>) -> bool {
+    match (expected, actual) {
+        (_, Type::Infer) => true,
+        (Type::Param(name), _) => match bindings.get(name) {
+            Some(Type::Infer) | None => {
+                bindings.insert(name.clone(), actual.clone());
+                true
+            }
+            Some(bound) => types_compatible(bound, actual),
+        },
+        (Type::Array(e), Type::Array(a)) => bind_type_params(e, a, bindings),
+        (Type::Tuple(e), Type::Tuple(a)) => {
+            e.len() == a.len() && e.iter().zip(a).all(|(e, a)| bind_type_params(e, a, bindings))
+        }
+        (Type::Map(ek, ev), Type::Map(ak, av)) => bind_type_params(ek, ak, bindings) && bind_type_params(ev, av, bindings),
+        (Type::Set(e), Type::Set(a)) => bind_type_params(e, a, bindings),
+        (Type::Generic { name: n1, args: e }, Type::Generic { name: n2, args: a }) => {
+            n1 == n2 && e.len() == a.len() && e.iter().zip(a).all(|(e, a)| bind_type_params(e, a, bindings))
+        }
+        (Type::Function { params: ep, return_type: er }, Type::Function { params: ap, return_type: ar }) => {
+            ep.len() == ap.len()
+                && ep.iter().zip(ap).all(|(e, a)| bind_type_params(e, a, bindings))
+                && bind_type_params(er, ar, bindings)
+        }
+        _ => types_compatible(expected, actual),
+    }
+}
+
+/// Replaces type parameters with what they were bound to, or `Infer`.
+fn substitute(ty: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match ty {
+        Type::Param(name) => bindings.get(name).cloned().unwrap_or(Type::Infer),
+        Type::Array(inner) => Type::Array(Box::new(substitute(inner, bindings))),
+        Type::Tuple(elements) => Type::Tuple(elements.iter().map(|e| substitute(e, bindings)).collect()),
+        Type::Map(key, value) => Type::Map(Box::new(substitute(key, bindings)), Box::new(substitute(value, bindings))),
+        Type::Set(element) => Type::Set(Box::new(substitute(element, bindings))),
+        Type::Generic { name, args } => Type::Generic {
+            name: name.clone(),
+            args: args.iter().map(|a| substitute(a, bindings)).collect(),
+        },
+        Type::Function { params, return_type } => Type::Function {
+            params: params.iter().map(|p| substitute(p, bindings)).collect(),
+            return_type: Box::new(substitute(return_type, bindings)),
+        },
+        other => other.clone(),
+    }
+}
+
+/// The type of a generic struct or enum value, e.g. `Option<int>`.
+fn instantiate(name: &str, type_params: &[TypeParam], bindings: &HashMap<String, Type>) -> Type {
+    Type::Generic {
+        name: name.to_string(),
+        args: type_params.iter()
+            .map(|p| bindings.get(&p.name).cloned().unwrap_or(Type::Infer))
+            .collect(),
+    }
+}
+
+/// Whether `ty` has an impl of `trait_name`. A type parameter in `in_scope`
+/// has only the traits its bounds name. Types that aren't known yet are
+/// given the benefit of the doubt.
+fn implements(trait_impls: &HashSet<(String, String)>, in_scope: &[TypeParam], ty: &Type, trait_name: &str) -> bool {
+    let type_name = match ty {
+        Type::Param(name) => {
+            return match in_scope.iter().find(|param| &param.name == name) {
+                Some(param) => param.bounds.iter().any(|bound| bound == trait_name),
+                None => true,
+            };
+        }
+        Type::Infer => return true,
+        Type::Int => "int",
+        Type::Float => "float",
+        Type::String => "string",
+        Type::Bool => "bool",
+        Type::Struct(name) | Type::Enum(name) | Type::Generic { name, .. } => name,
+        Type::Array(_) | Type::Tuple(_) | Type::Map(..) | Type::Set(_) | Type::Function { .. } | Type::None => return false,
+    };
+    trait_impls.contains(&(type_name.to_string(), trait_name.to_string()))
+}
+
+/// Checks that every type parameter bound to a type satisfies its bounds.
+/// `in_scope` holds the type parameters of the function the check is in.
+/// `context` names what declared the parameters, e.g. "function 'show'".
+pub(crate) fn check_bounds(
+    trait_impls: &HashSet<(String, String)>,
+    in_scope: &[TypeParam],
+    type_params: &[TypeParam],
+    bindings: &HashMap<String, Type>,
+    context: &str,
+) -> Result<(), TogError> {
+    for param in type_params {
+        let Some(ty) = bindings.get(&param.name) else {
+            continue;
+        };
+        for bound in &param.bounds {
+            if !implements(trait_impls, in_scope, ty, bound) {
+                let error = TogError::TypeError(
+                    format!("Type {} does not implement trait {}", ty, bound),
+                    None
+                )
+                .with_note(format!("required by the bound `{}: {}` on {}", param.name, bound, context));
+                // Builtin collections and functions can't have impls
+                return Err(match ty {
+                    Type::Param(name) => error.with_help(format!("add the bound `{}: {}`", name, bound)),
+                    Type::Array(_) | Type::Tuple(_) | Type::Map(..) | Type::Set(_) | Type::Function { .. } | Type::None => error,
+                    _ => error.with_help(format!("add `impl {} for {}`", bound, ty)),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that an `impl Trait for Type` block conforms to the trait: every
+/// method without a default body is provided, no extra methods are added, and
+/// parameter counts and annotated types agree with the trait's signatures.
+/// `Self` in a trait signature stands for the implementing type.
+pub fn check_trait_impl(
+    trait_name: &str,
+    trait_methods: &[TraitMethod],
+    type_name: &str,
+    impl_methods: &[MethodDecl],
+) -> Result<(), TogError> {
+    let context = format!("impl {} for {}", trait_name, type_name);
+
+    for method in impl_methods {
+        if !trait_methods.iter().any(|m| m.name == method.name) {
+            return Err(TogError::TypeError(
+                format!("Method '{}' in {} is not a member of trait {}", method.name, context, trait_name),
+                None
+            ));
+        }
+    }
+
+    for required in trait_methods {
+        let Some(method) = impl_methods.iter().find(|m| m.name == required.name) else {
+            if required.default_body.is_none() {
+                let params: Vec<&str> = required.params.iter().map(|p| p.name.as_str()).collect();
+                return Err(TogError::TypeError(
+                    format!("Missing method '{}' in {}", required.name, context),
+                    None
+                )
+                .with_note(format!("trait {} requires `{}` and has no default for it", trait_name, required.name))
+                .with_help(format!("add `fn {}({})` to the impl", required.name, params.join(", "))));
+            }
+            continue;
+        };
+
+        if method.params.len() != required.params.len() {
+            return Err(TogError::TypeError(
+                format!(
+                    "Method '{}' in {} takes {} parameters, but the trait declares {}",
+                    method.name, context, method.params.len(), required.params.len()
+                ),
+                None
+            ));
+        }
+
+        let by_ref = |params: &[Param]| params.first().is_some_and(|p| p.by_ref);
+        if by_ref(&method.params) != by_ref(&required.params) {
+            let receiver = |by_ref: bool| if by_ref { "`&mut self`" } else { "`self`" };
+            return Err(TogError::TypeError(
+                format!(
+                    "Method '{}' in {} takes {}, but the trait declares {}",
+                    method.name, context, receiver(by_ref(&method.params)), receiver(by_ref(&required.params))
+                ),
+                None
+            ));
+        }
+
+        for (param, expected) in method.params.iter().zip(&required.params) {
+            if let (Some(actual_ty), Some(expected_ty)) = (&param.type_annotation, &expected.type_annotation) {
+                if !signature_types_match(actual_ty, expected_ty, type_name) {
+                    return Err(TogError::TypeError(
+                        format!(
+                            "Method '{}' in {}: parameter '{}' has type {}, but the trait declares {}",
+                            method.name, context, param.name, actual_ty, expected_ty
+                        ),
+                        None
+                    ));
+                }
+            }
+        }
+
+        if let (Some(actual_ty), Some(expected_ty)) = (&method.return_type, &required.return_type) {
+            if !signature_types_match(actual_ty, expected_ty, type_name) {
+                return Err(TogError::TypeError(
+                    format!(
+                        "Method '{}' in {} returns {}, but the trait declares {}",
+                        method.name, context, actual_ty, expected_ty
+                    ),
+                    None
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn signature_types_match(actual: &Type, expected: &Type, self_type: &str) -> bool {
+    match (actual, expected) {
+        (_, Type::Struct(name)) if name == "Self" => {
+            matches!(actual, Type::Struct(n) | Type::Enum(n) if n == self_type || n == "Self")
+        }
+        (Type::Array(a), Type::Array(b)) => signature_types_match(a, b, self_type),
+        (Type::Tuple(a), Type::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| signature_types_match(a, b, self_type))
+        }
+        (Type::Map(k1, v1), Type::Map(k2, v2)) => {
+            signature_types_match(k1, k2, self_type) && signature_types_match(v1, v2, self_type)
+        }
+        (Type::Set(a), Type::Set(b)) => signature_types_match(a, b, self_type),
+        (_, Type::Param(_)) => true, // A trait's own type parameter
+        (a, b) => a == b,
+    }
+}
+
+/// How a method body uses a by-value `self`: where it first assigns through
+/// it, and whether `self` itself goes anywhere (returned, passed or stored)
+/// that could carry the change out of the method.
+#[derive(Default)]
+struct ReceiverUse {
+    assigned: Option<Span>,
+    escapes: bool,
+}
+
+impl ReceiverUse {
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Variable(name) if name == "self" => self.escapes = true,
+            ExprKind::FieldAccess { .. } | ExprKind::Index { .. } if rooted_at_self(expr) => self.place(expr),
+            ExprKind::Block(statements) => {
+                for stmt in statements {
+                    self.stmt(stmt);
+                }
+            }
+            _ => {
+                for child in expr.children() {
+                    self.expr(child);
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign { name, value, .. } if name == "self" => {
+                self.assigned.get_or_insert(stmt.span);
+                self.expr(value);
+            }
+            StmtKind::AssignField { object, value, .. } if rooted_at_self(object) => {
+                self.assigned.get_or_insert(stmt.span);
+                self.place(object);
+                self.expr(value);
+            }
+            StmtKind::AssignIndex { object, index, value, .. } if rooted_at_self(object) => {
+                self.assigned.get_or_insert(stmt.span);
+                self.place(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            _ => {
+                for expr in stmt.expressions() {
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    /// A field or index chain read or assigned through `self`; only its
+    /// index expressions are uses.
+    fn place(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::FieldAccess { object, .. } => self.place(object),
+            ExprKind::Index { array, index } => {
+                self.place(array);
+                self.expr(index);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn rooted_at_self(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Variable(name) => name == "self",
+        ExprKind::FieldAccess { object, .. } => rooted_at_self(object),
+        ExprKind::Index { array, .. } => rooted_at_self(array),
+        _ => false,
+    }
+}
@@ -0,0 +1,176 @@
+// Bytecode format
+//
+// Each function compiles to a `Proto`: a flat list of instructions plus the
+// constants, names and patterns they refer to by index. Instructions are
+// small `Copy` values, so the machine can fetch one without borrowing the
+// function. Every instruction has the span of the expression or statement it
+// came from, which errors raised by that instruction point at.
+
+use crate::ast::{BinaryOp, Expr, FormatSpec, Param, Pattern, UnaryOp};
+use crate::interpreter::Value;
+use crate::span::Span;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+/// A variable captured by a closure. Empty until its `let` runs.
+pub type Cell = Rc<RefCell<Option<Value>>>;
+
+/// Where a variable lives, decided when its function is compiled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var {
+    Local(u32),   // A slot in the current frame
+    Cell(u32),    // A frame slot holding a cell, because a closure uses the variable
+    Upvalue(u32), // A cell the running closure captured
+    Global(u32),  // An entry in the global table
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Const(u32),
+    Pop,
+    Dup,
+    Load(Var),
+    Define(Var), // `let`: sets the variable whether or not it was defined
+    Bind(Var),   // A pattern variable: like Define, but a cell variable gets a fresh cell
+    Store(Var),  // Assignment: the variable must already be defined
+    NewCell(u32), // Gives a captured slot a new, empty cell, on entry to its block
+    // Reads or assigns through a chain of fields and indexes rooted at a
+    // variable, without copying the variable. Pops the path's index values
+    // (and, for StorePath, the value below them). UpdatePath is StorePath for
+    // `+=` and friends: it combines the value with the one already there.
+    LoadPath(Var, u32),
+    StorePath(Var, u32),
+    UpdatePath(Var, u32, BinaryOp),
+    Field(u32), // Name index
+    Index,
+    Binary(BinaryOp),
+    Unary(UnaryOp),
+    Jump(u32),
+    JumpIfFalse(u32), // Pops the condition
+    LoopEnter,        // Records the stack height that `break` and `continue` unwind to
+    LoopExit,
+    Unwind,
+    IntoIter(u32),      // Pops an iterable into the slot and the slot after it (the position)
+    IterNext(u32, u32), // Pushes the next item of the iterator in the slot, or jumps when done
+    Match(u32),         // Pops a value; pushes whether it matches the pattern, binding its variables
+    Destructure(u32),   // Pops a value and binds the pattern's variables; a mismatch is an error
+    MakeArray(u32),
+    MakeTuple(u32),
+    MakeMap(u32), // Number of key/value pairs
+    MakeStruct(u32),
+    ToString,
+    Format(u32),
+    Concat(u32),
+    Closure(u32),
+    Call(u32), // The callee is above its arguments
+    // `name(args)`: calls the variable if it holds a function, otherwise the builtin
+    CallName { var: Var, callee: u32, argc: u32 },
+    CallMethod { name: u32, argc: u32 }, // The receiver is above the arguments
+    // `place.method(args)`: the receiver is read through the path, whose index
+    // values are above the arguments, and a `&mut self` method's final `self`
+    // is written back through it when the method returns
+    CallMethodAt { name: u32, argc: u32, var: Var, path: u32 },
+    CallStatic { type_name: u32, method: u32, argc: u32 }, // `Type.method(args)`
+    CallPath { type_name: u32, name: u32, argc: u32 },     // `Type::name(args)`
+    EnumPath { type_name: u32, name: u32, has_data: bool }, // `Type::name` or `Type::name(data)`
+    Try,
+    Return,
+    Fail(u32), // Raises the message at this name index
+}
+
+/// How a closure fills one of its upvalues when it is created.
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    Local(u32),   // A cell slot of the enclosing frame
+    Upvalue(u32), // An upvalue of the enclosing closure
+}
+
+#[derive(Debug)]
+pub enum Step {
+    Field(String),
+    Index, // Takes the next index value from the stack
+}
+
+#[derive(Debug)]
+pub struct Path {
+    pub root_span: Span,
+    pub steps: Vec<(Step, Span)>, // Outermost last, each with the span of its access
+    pub indexes: usize,
+}
+
+/// Why a pattern is matched, which decides what a mismatch means.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternUse {
+    Match, // A match arm: try the next one
+    Let,
+    Loop,
+}
+
+#[derive(Debug)]
+pub struct PatternInfo {
+    pub pattern: Pattern,
+    pub bindings: Vec<(String, Var)>,
+    pub usage: PatternUse,
+}
+
+#[derive(Debug)]
+pub struct StructShape {
+    pub name: String,
+    pub fields: Vec<String>, // In the order their values are pushed
+}
+
+/// A compiled function, or the program's top-level code.
+#[derive(Debug, Default)]
+pub struct Proto {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Option<Rc<Expr>>, // The source, for running the function on worker threads
+    pub code: Vec<Op>,
+    pub spans: Vec<Span>,
+    pub consts: Vec<Value>,
+    pub names: Vec<String>,
+    pub callees: Vec<(String, Span)>, // The name called by each CallName, with its span
+    pub paths: Vec<Path>,
+    pub patterns: Vec<PatternInfo>,
+    pub structs: Vec<StructShape>,
+    pub formats: Vec<FormatSpec>,
+    pub functions: Vec<Rc<Proto>>,
+    pub slot_count: usize,
+    pub slot_names: Vec<String>, // For "did you mean" suggestions
+    pub cell_slots: Vec<u32>,    // Slots that start the call holding a cell: captured parameters and locals
+    pub captures: Vec<Capture>,
+    pub upvalue_names: Vec<String>,
+}
+
+/// A function value: compiled code plus the variables it captured.
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub upvalues: Vec<Cell>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        &self.proto.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.proto.params.len()
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.proto.name)
+    }
+}
+
+/// The methods of one type, kept apart by where they were declared so
+/// lookups can apply the same precedence as the tree-walker.
+#[derive(Debug, Default)]
+pub struct TypeMethods {
+    pub inline: HashMap<String, Rc<Closure>>,   // Written in the struct body
+    pub inherent: HashMap<String, Rc<Closure>>, // From `impl Type`
+    pub traits: BTreeMap<String, HashMap<String, Rc<Closure>>>, // From `impl Trait for Type`, by trait
+}
@@ -0,0 +1,807 @@
+// Compiles the AST to bytecode
+//
+// Variables come from the resolver (resolver.rs), which has already given
+// each one a slot in its function's frame:
+// - a slot that nested functions use holds a cell, which closures share.
+//   Blocks give their captured variables new cells on entry, so closures
+//   made in different loop iterations don't share them.
+// - a variable of an enclosing function is reached through the closure's
+//   upvalues, one per variable, threaded through the functions in between.
+// - top-level `let`s and `fn`s, and names that aren't variables (builtins),
+//   are globals.
+// Type declarations are registered before any code is compiled, and methods
+// are compiled into per-type tables.
+
+use super::bytecode::*;
+use crate::ast::*;
+use crate::error::TogError;
+use crate::interpreter::{literal_to_value, Definitions, Value};
+use crate::resolver::{Binding, Frame, Resolution};
+use crate::span::Span;
+use crate::type_checker::check_trait_impl;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A compiled program: its top-level code plus the tables the machine runs it with.
+pub struct Compiled {
+    pub script: Rc<Proto>,
+    pub global_names: Vec<String>,
+    pub definitions: Definitions,
+    pub methods: HashMap<String, TypeMethods>,
+}
+
+pub fn compile(statements: &[Stmt], resolution: &Resolution) -> Result<Compiled, TogError> {
+    let mut compiler = Compiler {
+        resolution,
+        functions: Vec::new(),
+        globals: HashMap::new(),
+        global_names: Vec::new(),
+        definitions: Definitions::default(),
+        methods: HashMap::new(),
+    };
+
+    let mut declarations = Vec::new();
+    for stmt in statements {
+        collect_declarations(stmt, &mut declarations);
+    }
+    // Impls can name traits declared after them
+    for stmt in declarations.iter().filter(|s| !matches!(s.kind, StmtKind::ImplBlock { .. })) {
+        compiler.declare_type(stmt)?;
+    }
+    for stmt in declarations.iter().filter(|s| matches!(s.kind, StmtKind::ImplBlock { .. })) {
+        compiler.declare_type(stmt)?;
+    }
+
+    compiler.functions.push(FunctionState::new("<script>", &resolution.script));
+    for stmt in statements {
+        compiler.statement(stmt, false);
+    }
+    let end = statements.last().map_or(Span::default(), |s| s.span);
+    compiler.constant(Value::None, end);
+    compiler.emit(Op::Return, end);
+    let script = compiler.functions.pop().expect("script state").proto;
+
+    Ok(Compiled {
+        script: Rc::new(script),
+        global_names: compiler.global_names,
+        definitions: compiler.definitions,
+        methods: compiler.methods,
+    })
+}
+
+struct Loop {
+    start: usize, // Where `continue` jumps
+    breaks: Vec<usize>,
+}
+
+struct FunctionState<'r> {
+    proto: Proto,
+    frame: &'r Frame,
+    upvalues: Vec<(u32, u32)>, // The (depth, slot) each upvalue captures
+    loops: Vec<Loop>,
+}
+
+impl<'r> FunctionState<'r> {
+    fn new(name: &str, frame: &'r Frame) -> Self {
+        Self {
+            proto: Proto {
+                name: name.to_string(),
+                slot_count: frame.slot_names.len(),
+                slot_names: frame.slot_names.clone(),
+                ..Proto::default()
+            },
+            frame,
+            upvalues: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+}
+
+struct Compiler<'r> {
+    resolution: &'r Resolution,
+    functions: Vec<FunctionState<'r>>,
+    globals: HashMap<String, u32>,
+    global_names: Vec<String>,
+    definitions: Definitions,
+    methods: HashMap<String, TypeMethods>,
+}
+
+enum StepRef<'e> {
+    Field(&'e str),
+    Index(&'e Expr),
+}
+
+impl<'r> Compiler<'r> {
+    fn state(&mut self) -> &mut FunctionState<'r> {
+        self.functions.last_mut().expect("function state")
+    }
+
+    fn proto(&mut self) -> &mut Proto {
+        &mut self.state().proto
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        let proto = self.proto();
+        proto.code.push(op);
+        proto.spans.push(span);
+        proto.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.proto().code.len() as u32;
+        match &mut self.proto().code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::IterNext(_, to) => *to = target,
+            op => unreachable!("patching {:?}", op),
+        }
+    }
+
+    fn here(&mut self) -> usize {
+        self.proto().code.len()
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let proto = self.proto();
+        proto.consts.push(value);
+        let index = proto.consts.len() as u32 - 1;
+        self.emit(Op::Const(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let names = &mut self.proto().names;
+        match names.iter().position(|n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                names.push(name.to_string());
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    fn global(&mut self, name: &str) -> u32 {
+        if let Some(&index) = self.globals.get(name) {
+            return index;
+        }
+        let index = self.global_names.len() as u32;
+        self.global_names.push(name.to_string());
+        self.globals.insert(name.to_string(), index);
+        index
+    }
+
+    // --- Variables ---
+
+    /// A slot for the compiler's own use, such as a loop's iterator.
+    fn hidden_slot(&mut self) -> u32 {
+        let proto = self.proto();
+        proto.slot_count += 1;
+        proto.slot_names.push(String::new());
+        proto.slot_count as u32 - 1
+    }
+
+    /// A slot of the current function.
+    fn local(&mut self, slot: u32) -> Var {
+        if self.state().frame.captured.contains(&slot) { Var::Cell(slot) } else { Var::Local(slot) }
+    }
+
+    fn var(&mut self, binding: Binding, name: &str) -> Var {
+        match binding {
+            Binding::Local { depth: 0, slot } => self.local(slot),
+            Binding::Local { depth, slot } => Var::Upvalue(self.upvalue(self.functions.len() - 1, depth, slot)),
+            Binding::Global => Var::Global(self.global(name)),
+        }
+    }
+
+    /// The variable read or assigned at `span`. A name that isn't a variable
+    /// is a builtin, which `CallName` finds through its global.
+    fn use_var(&mut self, span: Span, name: &str) -> Var {
+        match self.resolution.binding(span) {
+            Some(binding) => self.var(binding, name),
+            None => Var::Global(self.global(name)),
+        }
+    }
+
+    /// The variable that the `let`, `fn`, `for` or match arm at `span` declares.
+    fn declared(&mut self, span: Span, name: &str) -> Var {
+        let binding = self.resolution.declaration(span, name);
+        self.var(binding, name)
+    }
+
+    /// The upvalue of `self.functions[function]` that reaches slot `slot` of
+    /// the function `depth` levels out, adding it (and any the functions in
+    /// between need) if this is its first use.
+    fn upvalue(&mut self, function: usize, depth: u32, slot: u32) -> u32 {
+        if let Some(index) = self.functions[function].upvalues.iter().position(|&u| u == (depth, slot)) {
+            return index as u32;
+        }
+        let capture = if depth == 1 {
+            Capture::Local(slot)
+        } else {
+            Capture::Upvalue(self.upvalue(function - 1, depth - 1, slot))
+        };
+        let name = self.functions[function - depth as usize].proto.slot_names[slot as usize].clone();
+        let state = &mut self.functions[function];
+        state.upvalues.push((depth, slot));
+        state.proto.captures.push(capture);
+        state.proto.upvalue_names.push(name);
+        state.upvalues.len() as u32 - 1
+    }
+
+    fn is_type_name(&self, name: &str) -> bool {
+        self.definitions.struct_defs.contains_key(name)
+            || self.definitions.enum_defs.contains_key(name)
+            || self.definitions.inherent_impls.contains_key(name)
+    }
+
+    /// Compiles a pattern whose variables are bound by `Match` or
+    /// `Destructure`. `span` is the `let`, loop or arm that declares them.
+    fn pattern(&mut self, pattern: &Pattern, usage: PatternUse, span: Span) -> u32 {
+        let bindings = pattern.bindings().into_iter()
+            .map(|name| {
+                let var = self.declared(span, &name);
+                (name, var)
+            })
+            .collect();
+        let patterns = &mut self.proto().patterns;
+        patterns.push(PatternInfo { pattern: pattern.clone(), bindings, usage });
+        patterns.len() as u32 - 1
+    }
+
+    // --- Declarations ---
+
+    fn declare_type(&mut self, stmt: &Stmt) -> Result<(), TogError> {
+        match &stmt.kind {
+            StmtKind::StructDef { name, fields, methods, .. } => {
+                self.definitions.struct_defs.insert(name.clone(), (fields.clone(), methods.clone()));
+                let mut inline = HashMap::new();
+                for method in methods {
+                    let compiled = self.method(method);
+                    inline.entry(method.name.clone()).or_insert(compiled);
+                }
+                self.methods.entry(name.clone()).or_default().inline = inline;
+            }
+            StmtKind::EnumDef { name, variants, .. } => {
+                self.definitions.enum_defs.insert(name.clone(), variants.clone());
+            }
+            StmtKind::TraitDef { name, methods, .. } => {
+                self.definitions.trait_defs.insert(name.clone(), methods.clone());
+            }
+            StmtKind::ImplBlock { trait_name: Some(trait_name), type_name, methods, .. } => {
+                let trait_methods = self.definitions.trait_defs.get(trait_name)
+                    .ok_or_else(|| TogError::RuntimeError(
+                        format!("Unknown trait '{}' in impl for {}", trait_name, type_name),
+                        Some(stmt.span)
+                    ))?;
+                check_trait_impl(trait_name, trait_methods, type_name, methods).map_err(|e| e.with_span(stmt.span))?;
+
+                let mut all_methods = methods.clone();
+                for trait_method in trait_methods {
+                    if let Some(body) = &trait_method.default_body {
+                        if !methods.iter().any(|m| m.name == trait_method.name) {
+                            all_methods.push(MethodDecl {
+                                name: trait_method.name.clone(),
+                                params: trait_method.params.clone(),
+                                return_type: trait_method.return_type.clone(),
+                                body: body.clone(),
+                            });
+                        }
+                    }
+                }
+                let mut compiled = HashMap::new();
+                for method in &all_methods {
+                    let closure = self.method(method);
+                    compiled.entry(method.name.clone()).or_insert(closure);
+                }
+                self.methods.entry(type_name.clone()).or_default().traits.insert(trait_name.clone(), compiled);
+                self.definitions.trait_impls.insert((type_name.clone(), trait_name.clone()), all_methods);
+            }
+            StmtKind::ImplBlock { trait_name: None, type_name, methods, .. } => {
+                for method in methods {
+                    let closure = self.method(method);
+                    self.methods.entry(type_name.clone()).or_default()
+                        .inherent.entry(method.name.clone()).or_insert(closure);
+                }
+                self.definitions.inherent_impls.entry(type_name.clone()).or_default().extend(methods.iter().cloned());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Methods see globals, not the variables around their declaration.
+    fn method(&mut self, method: &MethodDecl) -> Rc<Closure> {
+        let proto = self.function(&method.name, &method.params, &method.body);
+        Rc::new(Closure { proto, upvalues: Vec::new() })
+    }
+
+    fn function(&mut self, name: &str, params: &[Param], body: &Expr) -> Rc<Proto> {
+        let mut state = FunctionState::new(name, self.resolution.frame(body.span));
+        state.proto.params = params.to_vec();
+        state.proto.body = Some(Rc::new(body.clone()));
+        // Arguments arrive in the first slots
+        state.proto.cell_slots = (0..params.len() as u32).filter(|slot| state.frame.captured.contains(slot)).collect();
+        self.functions.push(state);
+
+        self.expr(body);
+        self.emit(Op::Return, body.span);
+        Rc::new(self.functions.pop().expect("function state").proto)
+    }
+
+    // --- Statements ---
+
+    /// Compiles a statement, leaving its value on the stack if `keep` is set.
+    fn statement(&mut self, stmt: &Stmt, keep: bool) {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                if !keep {
+                    self.emit(Op::Pop, span);
+                }
+            }
+            StmtKind::Let { name, value, .. } => {
+                self.expr(value);
+                if keep {
+                    self.emit(Op::Dup, span);
+                }
+                let var = self.declared(span, name);
+                self.emit(Op::Define(var), span);
+            }
+            StmtKind::LetPattern { pattern, value } => {
+                self.expr(value);
+                if keep {
+                    self.emit(Op::Dup, span);
+                }
+                let pattern = self.pattern(pattern, PatternUse::Let, span);
+                self.emit(Op::Destructure(pattern), span);
+            }
+            StmtKind::Assign { name, op, value } => {
+                if let Some(op) = op {
+                    let var = self.use_var(span, name);
+                    self.emit(Op::Load(var), span);
+                    self.expr(value);
+                    self.emit(Op::Binary(*op), span);
+                } else {
+                    self.expr(value);
+                }
+                if keep {
+                    self.emit(Op::Dup, span);
+                }
+                let var = self.use_var(span, name);
+                self.emit(Op::Store(var), span);
+            }
+            StmtKind::AssignField { object, field, op, value } => {
+                self.expr(value);
+                self.store_path(object, StepRef::Field(field), *op, span);
+                if keep {
+                    self.constant(Value::None, span);
+                }
+            }
+            StmtKind::AssignIndex { object, index, op, value } => {
+                self.expr(value);
+                self.store_path(object, StepRef::Index(index), *op, span);
+                if keep {
+                    self.constant(Value::None, span);
+                }
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.constant(Value::None, span),
+                }
+                self.emit(Op::Return, span);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let is_break = matches!(stmt.kind, StmtKind::Break);
+                if self.state().loops.is_empty() {
+                    let message = if is_break { "Break outside of loop" } else { "Continue outside of loop" };
+                    let message = self.name(message);
+                    self.emit(Op::Fail(message), span);
+                } else {
+                    self.emit(Op::Unwind, span);
+                    if is_break {
+                        let jump = self.emit(Op::Jump(0), span);
+                        self.state().loops.last_mut().expect("loop").breaks.push(jump);
+                    } else {
+                        let start = self.state().loops.last().expect("loop").start;
+                        self.emit(Op::Jump(start as u32), span);
+                    }
+                }
+                if keep {
+                    self.constant(Value::None, span);
+                }
+            }
+            StmtKind::Error => {
+                let message = self.name("Cannot run a statement that failed to parse");
+                self.emit(Op::Fail(message), span);
+                if keep {
+                    self.constant(Value::None, span);
+                }
+            }
+            // Registered before the program runs; imports are linked by the module loader
+            StmtKind::StructDef { .. } | StmtKind::EnumDef { .. } | StmtKind::TraitDef { .. }
+            | StmtKind::ImplBlock { .. } | StmtKind::Import { .. } => {
+                if keep {
+                    self.constant(Value::None, span);
+                }
+            }
+        }
+    }
+
+    /// Assigns the value on the stack through `object` followed by `last`,
+    /// combined with the value already there when `op` is set.
+    fn store_path(&mut self, object: &Expr, last: StepRef, op: Option<BinaryOp>, span: Span) {
+        let mut steps = Vec::new();
+        let Some((root, root_span)) = access_path(object, &mut steps) else {
+            self.emit(Op::Pop, span);
+            let message = self.name("Invalid assignment target");
+            self.emit(Op::Fail(message), span);
+            return;
+        };
+        steps.push((last, span));
+        let var = self.use_var(root_span, root);
+        let path = self.path(root_span, steps);
+        match op {
+            Some(op) => self.emit(Op::UpdatePath(var, path, op), span),
+            None => self.emit(Op::StorePath(var, path), span),
+        };
+    }
+
+    /// Compiles a path's index expressions and records its steps.
+    fn path(&mut self, root_span: Span, steps: Vec<(StepRef, Span)>) -> u32 {
+        let mut compiled = Vec::new();
+        let mut indexes = 0;
+        for (step, span) in steps {
+            match step {
+                StepRef::Field(field) => compiled.push((Step::Field(field.to_string()), span)),
+                StepRef::Index(index) => {
+                    self.expr(index);
+                    indexes += 1;
+                    compiled.push((Step::Index, span));
+                }
+            }
+        }
+        let paths = &mut self.proto().paths;
+        paths.push(Path { root_span, steps: compiled, indexes });
+        paths.len() as u32 - 1
+    }
+
+    // --- Expressions ---
+
+    /// Compiles an expression, leaving its value on the stack.
+    fn expr(&mut self, expr: &Expr) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Literal(lit) => match lit {
+                Literal::Array(elements) => {
+                    for element in elements {
+                        self.expr(element);
+                    }
+                    self.emit(Op::MakeArray(elements.len() as u32), span);
+                }
+                Literal::Tuple(elements) => {
+                    for element in elements {
+                        self.expr(element);
+                    }
+                    self.emit(Op::MakeTuple(elements.len() as u32), span);
+                }
+                Literal::Map(entries) => {
+                    for (key, value) in entries {
+                        self.expr(key);
+                        self.expr(value);
+                    }
+                    self.emit(Op::MakeMap(entries.len() as u32), span);
+                }
+                _ => self.constant(literal_to_value(lit), span),
+            },
+            ExprKind::Variable(name) => {
+                let var = self.use_var(span, name);
+                self.emit(Op::Load(var), span);
+            }
+            ExprKind::StructLiteral { name, fields } => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+                let shape = StructShape {
+                    name: name.clone(),
+                    fields: fields.iter().map(|(field, _)| field.clone()).collect(),
+                };
+                let structs = &mut self.proto().structs;
+                structs.push(shape);
+                let index = structs.len() as u32 - 1;
+                self.emit(Op::MakeStruct(index), span);
+            }
+            ExprKind::FieldAccess { object, field } => {
+                let mut steps = Vec::new();
+                if let Some((root, root_span)) = access_path(expr, &mut steps) {
+                    let var = self.use_var(root_span, root);
+                    let path = self.path(root_span, steps);
+                    self.emit(Op::LoadPath(var, path), span);
+                } else {
+                    self.expr(object);
+                    let field = self.name(field);
+                    self.emit(Op::Field(field), span);
+                }
+            }
+            ExprKind::Index { array, index } => {
+                let mut steps = Vec::new();
+                if let Some((root, root_span)) = access_path(expr, &mut steps) {
+                    let var = self.use_var(root_span, root);
+                    let path = self.path(root_span, steps);
+                    self.emit(Op::LoadPath(var, path), span);
+                } else {
+                    self.expr(array);
+                    self.expr(index);
+                    self.emit(Op::Index, span);
+                }
+            }
+            ExprKind::BinaryOp { left, op, right } => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Op::Binary(*op), span);
+            }
+            ExprKind::UnaryOp { op, expr: operand } => {
+                self.expr(operand);
+                self.emit(Op::Unary(*op), span);
+            }
+            ExprKind::Call { callee, args } => self.call(callee, args, span),
+            ExprKind::Block(statements) => {
+                for &slot in self.resolution.block_slots(span) {
+                    if let Var::Cell(slot) = self.local(slot) {
+                        self.emit(Op::NewCell(slot), span);
+                    }
+                }
+                if statements.is_empty() {
+                    self.constant(Value::None, span);
+                }
+                for (i, stmt) in statements.iter().enumerate() {
+                    self.statement(stmt, i + 1 == statements.len());
+                }
+            }
+            ExprKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0), span);
+                self.expr(then_branch);
+                let to_end = self.emit(Op::Jump(0), span);
+                self.patch(to_else);
+                match else_branch {
+                    Some(else_branch) => self.expr(else_branch),
+                    None => self.constant(Value::None, span),
+                }
+                self.patch(to_end);
+            }
+            ExprKind::While { condition, body } => {
+                self.emit(Op::LoopEnter, span);
+                let start = self.here();
+                self.expr(condition);
+                let exit = self.emit(Op::JumpIfFalse(0), span);
+                self.loop_body(start, body);
+                self.emit(Op::Jump(start as u32), span);
+                self.patch(exit);
+                self.end_loop(span);
+            }
+            ExprKind::For { pattern, iterable, body } => {
+                self.expr(iterable);
+                let iterator = self.hidden_slot();
+                self.hidden_slot(); // The position
+                self.emit(Op::IntoIter(iterator), span);
+                self.emit(Op::LoopEnter, span);
+                let start = self.here();
+                let exit = self.emit(Op::IterNext(iterator, 0), span);
+                self.bind_pattern(pattern, PatternUse::Loop, span);
+                self.loop_body(start, body);
+                self.emit(Op::Jump(start as u32), span);
+                self.patch(exit);
+                self.end_loop(span);
+            }
+            ExprKind::Match { expr: subject, arms } => {
+                self.expr(subject);
+                let slot = self.hidden_slot();
+                self.emit(Op::Define(Var::Local(slot)), span);
+                let mut to_end = Vec::new();
+                for arm in arms {
+                    let mut to_next = Vec::new();
+                    if arm.pattern != Pattern::Wildcard {
+                        self.emit(Op::Load(Var::Local(slot)), arm.span);
+                        let pattern = self.pattern(&arm.pattern, PatternUse::Match, arm.span);
+                        self.emit(Op::Match(pattern), arm.span);
+                        to_next.push(self.emit(Op::JumpIfFalse(0), arm.span));
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                        to_next.push(self.emit(Op::JumpIfFalse(0), arm.span));
+                    }
+                    self.expr(&arm.body);
+                    to_end.push(self.emit(Op::Jump(0), arm.span));
+                    for jump in to_next {
+                        self.patch(jump);
+                    }
+                }
+                let message = self.name("No matching pattern in match expression");
+                self.emit(Op::Fail(message), span);
+                for jump in to_end {
+                    self.patch(jump);
+                }
+            }
+            ExprKind::Function { name, params, body, .. } => {
+                let proto = self.function(name, params, body);
+                self.closure(proto, span);
+                self.emit(Op::Dup, span);
+                let var = self.declared(span, name);
+                self.emit(Op::Define(var), span);
+            }
+            ExprKind::Lambda { params, body, .. } => {
+                let proto = self.function("<lambda>", params, body);
+                self.closure(proto, span);
+            }
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    match part {
+                        StringPart::Literal(text) => self.constant(Value::String(text.clone()), span),
+                        StringPart::Expr { expr: inner, format } => {
+                            self.expr(inner);
+                            match format {
+                                Some(spec) => {
+                                    let formats = &mut self.proto().formats;
+                                    formats.push(spec.clone());
+                                    let index = formats.len() as u32 - 1;
+                                    self.emit(Op::Format(index), inner.span);
+                                }
+                                None => {
+                                    self.emit(Op::ToString, inner.span);
+                                }
+                            }
+                        }
+                    }
+                }
+                self.emit(Op::Concat(parts.len() as u32), span);
+            }
+            ExprKind::Try(operand) => {
+                self.expr(operand);
+                self.emit(Op::Try, span);
+            }
+            ExprKind::EnumVariant { enum_name, variant_name, data } => {
+                if let Some(data) = data {
+                    self.expr(data);
+                }
+                let type_name = self.name(enum_name);
+                let name = self.name(variant_name);
+                self.emit(Op::EnumPath { type_name, name, has_data: data.is_some() }, span);
+            }
+        }
+    }
+
+    /// Binds a loop item on the stack to `pattern`.
+    fn bind_pattern(&mut self, pattern: &Pattern, usage: PatternUse, span: Span) {
+        match pattern {
+            Pattern::Variable(name) => {
+                let var = self.declared(span, name);
+                self.emit(Op::Bind(var), span);
+            }
+            pattern => {
+                let pattern = self.pattern(pattern, usage, span);
+                self.emit(Op::Destructure(pattern), span);
+            }
+        }
+    }
+
+    fn loop_body(&mut self, start: usize, body: &Expr) {
+        self.state().loops.push(Loop { start, breaks: Vec::new() });
+        self.expr(body);
+        self.emit(Op::Pop, body.span);
+    }
+
+    /// Closes the innermost loop: `break`s land here, and the loop's value is none.
+    fn end_loop(&mut self, span: Span) {
+        let finished = self.state().loops.pop().expect("loop");
+        for jump in finished.breaks {
+            self.patch(jump);
+        }
+        self.emit(Op::LoopExit, span);
+        self.constant(Value::None, span);
+    }
+
+    fn closure(&mut self, proto: Rc<Proto>, span: Span) {
+        let functions = &mut self.proto().functions;
+        functions.push(proto);
+        let index = functions.len() as u32 - 1;
+        self.emit(Op::Closure(index), span);
+    }
+
+    /// Calls follow the tree-walker's dispatch: `Type.method()` on a type
+    /// name, `obj.method()`, `Type::name()`, a variable or builtin by name,
+    /// and otherwise any expression that evaluates to a function.
+    fn call(&mut self, callee: &Expr, args: &[Expr], span: Span) {
+        for arg in args {
+            self.expr(arg);
+        }
+        let argc = args.len() as u32;
+        match &callee.kind {
+            ExprKind::FieldAccess { object, field } => {
+                let method = self.name(field);
+                if let ExprKind::Variable(type_name) = &object.kind {
+                    if self.is_type_name(type_name) && self.resolution.binding(object.span).is_none() {
+                        let type_name = self.name(type_name);
+                        self.emit(Op::CallStatic { type_name, method, argc }, span);
+                        return;
+                    }
+                }
+                let mut steps = Vec::new();
+                if let Some((root, root_span)) = access_path(object, &mut steps) {
+                    let var = self.use_var(root_span, root);
+                    let path = self.path(root_span, steps);
+                    self.emit(Op::CallMethodAt { name: method, argc, var, path }, span);
+                    return;
+                }
+                self.expr(object);
+                self.emit(Op::CallMethod { name: method, argc }, span);
+            }
+            ExprKind::EnumVariant { enum_name, variant_name, data: None } => {
+                let type_name = self.name(enum_name);
+                let name = self.name(variant_name);
+                self.emit(Op::CallPath { type_name, name, argc }, span);
+            }
+            ExprKind::Variable(name) => {
+                let var = self.use_var(callee.span, name);
+                let callees = &mut self.proto().callees;
+                callees.push((name.clone(), callee.span));
+                let callee = callees.len() as u32 - 1;
+                self.emit(Op::CallName { var, callee, argc }, span);
+            }
+            _ => {
+                self.expr(callee);
+                self.emit(Op::Call(argc), span);
+            }
+        }
+    }
+}
+
+/// Splits `a.b[i].c` into the variable it starts from, with its span, and
+/// the accesses after it. Returns None unless the chain starts at a variable
+/// and has at least one access.
+fn access_path<'e>(expr: &'e Expr, steps: &mut Vec<(StepRef<'e>, Span)>) -> Option<(&'e str, Span)> {
+    let root = match &expr.kind {
+        ExprKind::Variable(name) => return Some((name, expr.span)),
+        ExprKind::FieldAccess { object, field } => {
+            let root = access_path(object, steps)?;
+            steps.push((StepRef::Field(field), expr.span));
+            root
+        }
+        ExprKind::Index { array, index } => {
+            let root = access_path(array, steps)?;
+            steps.push((StepRef::Index(index), expr.span));
+            root
+        }
+        _ => return None,
+    };
+    Some(root)
+}
+
+/// Collects type declarations in source order, including those inside
+/// function bodies.
+fn collect_declarations<'s>(stmt: &'s Stmt, out: &mut Vec<&'s Stmt>) {
+    match &stmt.kind {
+        StmtKind::StructDef { .. } | StmtKind::EnumDef { .. } | StmtKind::TraitDef { .. } | StmtKind::ImplBlock { .. } => {
+            out.push(stmt);
+        }
+        _ => {
+            for expr in stmt.expressions() {
+                collect_nested_declarations(expr, out);
+            }
+        }
+    }
+}
+
+fn collect_nested_declarations<'s>(expr: &'s Expr, out: &mut Vec<&'s Stmt>) {
+    match &expr.kind {
+        ExprKind::Block(statements) => {
+            for stmt in statements {
+                collect_declarations(stmt, out);
+            }
+        }
+        _ => {
+            for child in expr.children() {
+                collect_nested_declarations(child, out);
+            }
+        }
+    }
+}
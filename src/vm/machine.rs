@@ -0,0 +1,1066 @@
+// Runs compiled bytecode
+//
+// All frames share one operand stack and one array of variable slots; a
+// frame is an offset into each. Calls from TOG code to TOG code push a frame
+// and keep going in the same loop, so deep recursion doesn't grow the Rust
+// stack. Builtins that take a function, such as `map`, call back through
+// `FunctionCaller`, which runs the callee in a nested loop until it returns.
+
+use super::bytecode::*;
+use super::compiler::Compiled;
+use crate::ast::BinaryOp;
+use crate::diagnostics::{did_you_mean, format_trace};
+use crate::error::{StackFrame, TogError};
+use crate::interpreter::{
+    evaluate_binary_op, evaluate_unary_op, field_mut, field_of, format_value, index_mut, index_of, is_truthy,
+    iteration_items, match_pattern, mut_self_without_receiver, set_field, set_index, value_to_string, Definitions,
+    MapKey, Value,
+};
+use crate::parallel::{captured_variables, SharedClosure, SharedFunction, SharedValue};
+use crate::span::Span;
+use crate::stdlib::{self, FunctionCaller};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::mem;
+use std::rc::Rc;
+
+#[derive(Default)]
+enum Slot {
+    #[default]
+    Empty, // Declared but not yet assigned
+    Value(Value),
+    Cell(Cell),
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,       // First slot of the frame
+    stack_base: usize, // Operand stack height when the call started
+    loop_base: usize,
+    traced: bool, // Has an entry on the call stack; the top-level code doesn't
+    write_back: Option<WriteBack>,
+}
+
+/// Where a `&mut self` method's final `self` goes: a place in the caller's
+/// frame, with the index values its path was read with.
+struct WriteBack {
+    var: Var,
+    path: u32,
+    indexes: Vec<Value>,
+}
+
+/// What the machine does after an instruction.
+enum Flow {
+    Next,
+    Call, // A frame was pushed
+    Return(Value),
+}
+
+pub struct Machine {
+    stack: Vec<Value>,
+    slots: Vec<Slot>,
+    frames: Vec<Frame>,
+    loops: Vec<usize>, // Stack heights that `break` and `continue` unwind to
+    globals: Vec<Option<Value>>,
+    global_names: Vec<String>,
+    definitions: Definitions,
+    methods: HashMap<String, TypeMethods>,
+    call_stack: Vec<StackFrame>,
+    call_site: Span, // The call being dispatched, recorded in the callee's frame
+}
+
+impl Machine {
+    pub fn new(compiled: &Compiled) -> Self {
+        Self {
+            stack: Vec::new(),
+            slots: Vec::new(),
+            frames: Vec::new(),
+            loops: Vec::new(),
+            globals: vec![None; compiled.global_names.len()],
+            global_names: compiled.global_names.clone(),
+            definitions: compiled.definitions.clone(),
+            methods: HashMap::new(),
+            call_stack: Vec::new(),
+            call_site: Span::default(),
+        }
+    }
+
+    /// Runs the top-level code, then `main` if the program defines one.
+    pub fn run(mut self, compiled: Compiled) -> Result<(), TogError> {
+        self.methods = compiled.methods;
+        let script = Rc::new(Closure { proto: compiled.script, upvalues: Vec::new() });
+        self.enter(script, 0, None, None);
+        self.execute(0)?;
+
+        let main = self.global_names.iter().position(|name| name == "main")
+            .and_then(|index| match &self.globals[index] {
+                Some(Value::Closure(main)) => Some(Rc::clone(main)),
+                _ => None,
+            });
+        if let Some(main) = main {
+            let frame = StackFrame { function: "main".to_string(), receiver: None, call_site: Span::default() };
+            self.enter(main, 0, None, Some(frame));
+            self.execute(0)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes a frame for `closure`, taking its last `argc` arguments from the
+    /// stack. `receiver`, if given, becomes the first parameter.
+    fn enter(&mut self, closure: Rc<Closure>, argc: usize, receiver: Option<Value>, frame: Option<StackFrame>) {
+        let base = self.slots.len();
+        if let Some(receiver) = receiver {
+            self.slots.push(Slot::Value(receiver));
+        }
+        let first = self.stack.len() - argc;
+        self.slots.extend(self.stack.drain(first..).map(Slot::Value));
+        self.slots.resize_with(base + closure.proto.slot_count, Slot::default);
+        for &slot in &closure.proto.cell_slots {
+            let slot = &mut self.slots[base + slot as usize];
+            let value = match mem::take(slot) {
+                Slot::Value(value) => Some(value),
+                _ => None,
+            };
+            *slot = Slot::Cell(Rc::new(RefCell::new(value)));
+        }
+        let traced = frame.is_some();
+        self.call_stack.extend(frame);
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+            stack_base: self.stack.len(),
+            loop_base: self.loops.len(),
+            traced,
+            write_back: None,
+        });
+    }
+
+    fn leave(&mut self, frame: &Frame) {
+        self.slots.truncate(frame.base);
+        self.stack.truncate(frame.stack_base);
+        self.loops.truncate(frame.loop_base);
+        if frame.traced {
+            if let Some(entry) = self.call_stack.pop() {
+                // Calls made inside the callee moved it on
+                self.call_site = entry.call_site;
+            }
+        }
+    }
+
+    /// Runs until the frame count drops back to `stop`, returning the value
+    /// of the last frame to return. On an error, every frame above `stop` is
+    /// dropped.
+    fn execute(&mut self, stop: usize) -> Result<Value, TogError> {
+        let result = self.dispatch(stop);
+        if result.is_err() {
+            while self.frames.len() > stop {
+                let frame = self.frames.pop().expect("frame");
+                self.leave(&frame);
+            }
+        }
+        result
+    }
+
+    fn dispatch(&mut self, stop: usize) -> Result<Value, TogError> {
+        let top = self.frames.last().expect("frame");
+        let mut closure = Rc::clone(&top.closure);
+        let mut ip = top.ip;
+        let mut base = top.base;
+        loop {
+            let at = ip;
+            let op = closure.proto.code[at];
+            ip += 1;
+            if self.quick(op, &closure, base, &mut ip) {
+                continue;
+            }
+            let flow = match self.step(op, &closure, base, &mut ip) {
+                Ok(flow) => flow,
+                Err(e) => {
+                    return Err(e.with_span(closure.proto.spans[at]).with_trace(&self.call_stack));
+                }
+            };
+            match flow {
+                Flow::Next => {}
+                Flow::Call => {
+                    let depth = self.frames.len();
+                    self.frames[depth - 2].ip = ip;
+                    let top = &self.frames[depth - 1];
+                    closure = Rc::clone(&top.closure);
+                    ip = 0;
+                    base = top.base;
+                }
+                Flow::Return(value) => {
+                    let mut frame = self.frames.pop().expect("frame");
+                    let write_back = frame.write_back.take().map(|to| (to, self.take_receiver(&frame)));
+                    self.leave(&frame);
+                    if self.frames.len() == stop {
+                        return Ok(value);
+                    }
+                    let top = self.frames.last().expect("frame");
+                    closure = Rc::clone(&top.closure);
+                    ip = top.ip;
+                    base = top.base;
+                    if let Some((to, Some(receiver))) = write_back {
+                        if let Err(e) = self.write_back(to, receiver, &closure, base) {
+                            return Err(e.with_span(closure.proto.spans[ip - 1]).with_trace(&self.call_stack));
+                        }
+                    }
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let start = self.stack.len() - n as usize;
+        self.stack.split_off(start)
+    }
+
+    /// Runs the ops a loop spends most of its time in, returning false if
+    /// `op` needs `step`. `step` is too big to inline, and calling it and
+    /// matching its `Result<Flow>` costs more than these ops do.
+    #[inline(always)]
+    fn quick(&mut self, op: Op, closure: &Closure, base: usize, ip: &mut usize) -> bool {
+        match op {
+            Op::Const(index) => self.stack.push(closure.proto.consts[index as usize].clone()),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Dup => {
+                let top = self.stack.last().expect("operand stack underflow").clone();
+                self.stack.push(top);
+            }
+            Op::Load(Var::Local(slot)) => match &self.slots[base + slot as usize] {
+                Slot::Value(value) => {
+                    let value = value.clone();
+                    self.stack.push(value);
+                }
+                _ => return false,
+            },
+            Op::Store(Var::Local(slot)) => match &mut self.slots[base + slot as usize] {
+                Slot::Value(target) => *target = self.stack.pop().expect("operand stack underflow"),
+                _ => return false,
+            },
+            Op::Binary(op) => {
+                let top = self.stack.len() - 2;
+                let value = match &self.stack[top..] {
+                    [Value::Int(a), Value::Int(b)] => int_binary(*a, op, *b),
+                    _ => None,
+                };
+                let Some(value) = value else {
+                    return false;
+                };
+                self.stack.truncate(top);
+                self.stack.push(value);
+            }
+            Op::Jump(target) => *ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !is_truthy(&self.pop()) {
+                    *ip = target as usize;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn step(&mut self, op: Op, closure: &Rc<Closure>, base: usize, ip: &mut usize) -> Result<Flow, TogError> {
+        let proto = &*closure.proto;
+        match op {
+            Op::Const(_) | Op::Pop | Op::Dup | Op::Jump(_) | Op::JumpIfFalse(_) => unreachable!("run by `quick`"),
+            Op::Load(var) => {
+                let value = self.with_var(var, closure, base, Value::clone)
+                    .ok_or_else(|| self.undefined(var, proto))?;
+                self.stack.push(value);
+            }
+            Op::Define(var) => {
+                let value = self.pop();
+                self.define(var, value, closure, base);
+            }
+            Op::Bind(var) => {
+                let value = self.pop();
+                self.bind(var, value, closure, base);
+            }
+            Op::Store(var) => {
+                let value = self.pop();
+                self.store(var, value, closure, base)?;
+            }
+            Op::NewCell(slot) => {
+                self.slots[base + slot as usize] = Slot::Cell(Rc::new(RefCell::new(None)));
+            }
+            Op::LoadPath(var, path) => {
+                let path = &proto.paths[path as usize];
+                let start = self.stack.len() - path.indexes;
+                let indexes = &self.stack[start..];
+                let value = self.with_var(var, closure, base, |root| load_path(root, indexes, path))
+                    .ok_or_else(|| self.undefined(var, proto).with_span(path.root_span))??;
+                self.stack.truncate(start);
+                self.stack.push(value);
+            }
+            Op::StorePath(var, path) => self.store_through(var, path, None, closure, base)?,
+            Op::UpdatePath(var, path, op) => self.store_through(var, path, Some(op), closure, base)?,
+            Op::Field(name) => {
+                let object = self.pop();
+                let value = field_of(&object, &proto.names[name as usize])?.clone();
+                self.stack.push(value);
+            }
+            Op::Index => {
+                let index = self.pop();
+                let container = self.pop();
+                let value = index_of(&container, &index)?.into_owned();
+                self.stack.push(value);
+            }
+            Op::Binary(op) => {
+                let right = self.pop();
+                let left = self.stack.last_mut().expect("operand stack underflow");
+                *left = evaluate_binary_op(left, op, &right)?;
+            }
+            Op::Unary(op) => {
+                let operand = self.stack.last_mut().expect("operand stack underflow");
+                *operand = evaluate_unary_op(op, operand)?;
+            }
+            Op::LoopEnter => self.loops.push(self.stack.len()),
+            Op::LoopExit => {
+                self.loops.pop();
+            }
+            Op::Unwind => {
+                let height = *self.loops.last().expect("loop");
+                self.stack.truncate(height);
+            }
+            Op::IntoIter(slot) => {
+                let items = iteration_items(self.pop())?;
+                let slot = base + slot as usize;
+                self.slots[slot] = Slot::Value(Value::Array(items));
+                self.slots[slot + 1] = Slot::Value(Value::Int(0));
+            }
+            Op::IterNext(slot, exit) => {
+                let slot = base + slot as usize;
+                let Slot::Value(Value::Int(position)) = &mut self.slots[slot + 1] else {
+                    unreachable!("loop position");
+                };
+                let next = *position as usize;
+                *position += 1;
+                match &mut self.slots[slot] {
+                    // Each item is visited once, so it can be moved out
+                    Slot::Value(Value::Array(items)) if next < items.len() => {
+                        let item = mem::replace(&mut items[next], Value::None);
+                        self.stack.push(item);
+                    }
+                    _ => *ip = exit as usize,
+                }
+            }
+            Op::Match(pattern) => {
+                let value = self.pop();
+                let matched = self.match_and_bind(&proto.patterns[pattern as usize], &value, closure, base)?;
+                self.stack.push(Value::Bool(matched));
+            }
+            Op::Destructure(pattern) => {
+                let value = self.pop();
+                let info = &proto.patterns[pattern as usize];
+                if !self.match_and_bind(info, &value, closure, base)? {
+                    let message = match info.usage {
+                        PatternUse::Loop => format!("Loop item {} doesn't match the pattern", value_to_string(&value)),
+                        _ => format!("Value {} doesn't match the pattern in this let", value_to_string(&value)),
+                    };
+                    return Err(TogError::RuntimeError(message, None));
+                }
+            }
+            Op::MakeArray(count) => {
+                let items = self.pop_n(count);
+                self.stack.push(Value::Array(items));
+            }
+            Op::MakeTuple(count) => {
+                let items = self.pop_n(count);
+                self.stack.push(Value::Tuple(items));
+            }
+            Op::MakeMap(count) => {
+                let items = self.pop_n(count * 2);
+                let mut map = BTreeMap::new();
+                let mut items = items.into_iter();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    map.insert(MapKey::from_value(&key)?, value);
+                }
+                self.stack.push(Value::Map(map));
+            }
+            Op::MakeStruct(shape) => {
+                let shape = &proto.structs[shape as usize];
+                let values = self.pop_n(shape.fields.len() as u32);
+                let (field_defs, _) = self.definitions.struct_defs.get(&shape.name)
+                    .ok_or_else(|| TogError::RuntimeError(format!("Unknown struct: {}", shape.name), None))?;
+                let fields: HashMap<String, Value> = shape.fields.iter().cloned().zip(values).collect();
+                for (field, _) in field_defs {
+                    if !fields.contains_key(field) {
+                        return Err(TogError::RuntimeError(
+                            format!("Missing field '{}' in struct literal {}", field, shape.name),
+                            None
+                        ));
+                    }
+                }
+                self.stack.push(Value::Struct { name: shape.name.clone(), fields });
+            }
+            Op::ToString => {
+                let value = self.pop();
+                self.stack.push(Value::String(value_to_string(&value)));
+            }
+            Op::Format(spec) => {
+                let value = self.pop();
+                self.stack.push(Value::String(format_value(&value, &proto.formats[spec as usize])?));
+            }
+            Op::Concat(count) => {
+                let mut text = String::new();
+                for part in self.pop_n(count) {
+                    if let Value::String(part) = part {
+                        text.push_str(&part);
+                    }
+                }
+                self.stack.push(Value::String(text));
+            }
+            Op::Closure(index) => {
+                let function = Rc::clone(&proto.functions[index as usize]);
+                let upvalues = function.captures.iter()
+                    .map(|capture| match *capture {
+                        Capture::Local(slot) => {
+                            let slot = &mut self.slots[base + slot as usize];
+                            if !matches!(slot, Slot::Cell(_)) {
+                                *slot = Slot::Cell(Rc::new(RefCell::new(None)));
+                            }
+                            let Slot::Cell(cell) = slot else { unreachable!() };
+                            Rc::clone(cell)
+                        }
+                        Capture::Upvalue(index) => Rc::clone(&closure.upvalues[index as usize]),
+                    })
+                    .collect();
+                self.stack.push(Value::Closure(Rc::new(Closure { proto: function, upvalues })));
+            }
+            Op::Call(argc) => {
+                self.call_site = proto.spans[*ip - 1];
+                let callee = self.pop();
+                return self.call_value(callee, argc as usize);
+            }
+            Op::CallName { var, callee, argc } => {
+                self.call_site = proto.spans[*ip - 1];
+                let function = self.with_var(var, closure, base, |value| match value {
+                    Value::Closure(function) => Some(Rc::clone(function)),
+                    _ => None,
+                });
+                if let Some(Some(function)) = function {
+                    return self.call_closure(function, argc as usize);
+                }
+                return self.call_by_name(var, &proto.callees[callee as usize], argc as usize, closure, base);
+            }
+            Op::CallMethod { name, argc } => {
+                self.call_site = proto.spans[*ip - 1];
+                let object = self.pop();
+                return self.call_method_on(object, &proto.names[name as usize], argc as usize);
+            }
+            Op::CallMethodAt { name, argc, var, path } => {
+                self.call_site = proto.spans[*ip - 1];
+                let place = &proto.paths[path as usize];
+                let start = self.stack.len() - place.indexes;
+                let indexes = &self.stack[start..];
+                let object = self.with_var(var, closure, base, |root| load_path(root, indexes, place))
+                    .ok_or_else(|| self.undefined(var, proto).with_span(place.root_span))??;
+                let indexes = self.stack.split_off(start);
+                let depth = self.frames.len();
+                let flow = self.call_method_on(object, &proto.names[name as usize], argc as usize)?;
+                if self.frames.len() > depth {
+                    let callee = self.frames.last_mut().expect("frame");
+                    if callee.closure.proto.params.first().is_some_and(|p| p.by_ref) {
+                        callee.write_back = Some(WriteBack { var, path, indexes });
+                    }
+                }
+                return Ok(flow);
+            }
+            Op::CallStatic { type_name, method, argc } => {
+                self.call_site = proto.spans[*ip - 1];
+                let (type_name, method) = (&proto.names[type_name as usize], &proto.names[method as usize]);
+                let function = self.find_method(type_name, method)?
+                    .ok_or_else(|| TogError::RuntimeError(format!("Unknown method '{}' on {}", method, type_name), None))?;
+                return self.call_method(type_name, function, None, argc as usize);
+            }
+            Op::CallPath { type_name, name, argc } => {
+                self.call_site = proto.spans[*ip - 1];
+                let (type_name, name) = (&proto.names[type_name as usize], &proto.names[name as usize]);
+                return self.call_path(type_name, name, argc as usize);
+            }
+            Op::EnumPath { type_name, name, has_data } => {
+                self.call_site = proto.spans[*ip - 1];
+                let (type_name, name) = (&proto.names[type_name as usize], &proto.names[name as usize]);
+                return self.enum_path(type_name, name, has_data);
+            }
+            Op::Try => {
+                let value = self.pop();
+                let variant = match &value {
+                    Value::Enum { variant_name, .. } => variant_name.as_str(),
+                    _ => "",
+                };
+                match variant {
+                    "Ok" | "Some" => {
+                        let Value::Enum { data, .. } = value else { unreachable!() };
+                        self.stack.push(data.map_or(Value::None, |data| *data));
+                    }
+                    "Err" | "None" if self.call_stack.is_empty() => {
+                        return Err(TogError::RuntimeError(
+                            "The `?` operator can only be used inside a function".to_string(),
+                            None
+                        ));
+                    }
+                    "Err" | "None" => return Ok(Flow::Return(value)),
+                    _ => {
+                        return Err(TogError::RuntimeError(
+                            format!("The `?` operator needs a Result or Option, got {}", value_to_string(&value)),
+                            None
+                        ));
+                    }
+                }
+            }
+            Op::Return => return Ok(Flow::Return(self.pop())),
+            Op::Fail(message) => {
+                return Err(TogError::RuntimeError(proto.names[message as usize].clone(), None));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // --- Variables ---
+
+    /// Applies `f` to the variable's value, or returns None if it isn't defined.
+    fn with_var<R>(&self, var: Var, closure: &Closure, base: usize, f: impl FnOnce(&Value) -> R) -> Option<R> {
+        match var {
+            Var::Local(slot) => match &self.slots[base + slot as usize] {
+                Slot::Value(value) => Some(f(value)),
+                _ => None,
+            },
+            Var::Cell(slot) => match &self.slots[base + slot as usize] {
+                Slot::Cell(cell) => cell.borrow().as_ref().map(f),
+                _ => None,
+            },
+            Var::Upvalue(index) => closure.upvalues[index as usize].borrow().as_ref().map(f),
+            Var::Global(index) => self.globals[index as usize].as_ref().map(f),
+        }
+    }
+
+    fn define(&mut self, var: Var, value: Value, closure: &Closure, base: usize) {
+        match var {
+            Var::Local(slot) => self.slots[base + slot as usize] = Slot::Value(value),
+            Var::Cell(slot) => match &self.slots[base + slot as usize] {
+                Slot::Cell(cell) => *cell.borrow_mut() = Some(value),
+                _ => self.slots[base + slot as usize] = Slot::Cell(Rc::new(RefCell::new(Some(value)))),
+            },
+            Var::Upvalue(index) => *closure.upvalues[index as usize].borrow_mut() = Some(value),
+            Var::Global(index) => self.globals[index as usize] = Some(value),
+        }
+    }
+
+    /// Binds a pattern variable. Each binding of a captured variable gets its
+    /// own cell, so closures made in different loop iterations don't share it.
+    fn bind(&mut self, var: Var, value: Value, closure: &Closure, base: usize) {
+        match var {
+            Var::Cell(slot) => self.slots[base + slot as usize] = Slot::Cell(Rc::new(RefCell::new(Some(value)))),
+            var => self.define(var, value, closure, base),
+        }
+    }
+
+    fn store(&mut self, var: Var, value: Value, closure: &Closure, base: usize) -> Result<(), TogError> {
+        let target = match var {
+            Var::Local(slot) => match &mut self.slots[base + slot as usize] {
+                Slot::Value(target) => {
+                    *target = value;
+                    return Ok(());
+                }
+                _ => None,
+            },
+            Var::Cell(slot) => match &self.slots[base + slot as usize] {
+                Slot::Cell(cell) => Some(Rc::clone(cell)),
+                _ => None,
+            },
+            Var::Upvalue(index) => Some(Rc::clone(&closure.upvalues[index as usize])),
+            Var::Global(index) => match &mut self.globals[index as usize] {
+                Some(target) => {
+                    *target = value;
+                    return Ok(());
+                }
+                None => None,
+            },
+        };
+        match target {
+            Some(cell) if cell.borrow().is_some() => {
+                *cell.borrow_mut() = Some(value);
+                Ok(())
+            }
+            _ => Err(TogError::RuntimeError(
+                format!("Cannot assign to undefined variable: {}", self.var_name(var, &closure.proto)),
+                None
+            )),
+        }
+    }
+
+    /// StorePath and UpdatePath: assigns the value below the path's index
+    /// values through the path, popping both.
+    fn store_through(&mut self, var: Var, path: u32, op: Option<BinaryOp>, closure: &Closure, base: usize) -> Result<(), TogError> {
+        let proto = &*closure.proto;
+        let path = &proto.paths[path as usize];
+        let start = self.stack.len() - path.indexes;
+        let value = mem::replace(&mut self.stack[start - 1], Value::None);
+        let indexes = &self.stack[start..];
+        let stored = match var {
+            Var::Local(slot) => match &mut self.slots[base + slot as usize] {
+                Slot::Value(root) => Some(store_path(root, indexes, path, op, value)),
+                _ => None,
+            },
+            Var::Cell(slot) => match &self.slots[base + slot as usize] {
+                Slot::Cell(cell) => cell.borrow_mut().as_mut().map(|root| store_path(root, indexes, path, op, value)),
+                _ => None,
+            },
+            Var::Upvalue(index) => closure.upvalues[index as usize].borrow_mut().as_mut()
+                .map(|root| store_path(root, indexes, path, op, value)),
+            Var::Global(index) => self.globals[index as usize].as_mut()
+                .map(|root| store_path(root, indexes, path, op, value)),
+        };
+        stored.ok_or_else(|| self.undefined(var, proto).with_span(path.root_span))??;
+        self.stack.truncate(start - 1);
+        Ok(())
+    }
+
+    /// The `self` a returning frame leaves in its first slot.
+    fn take_receiver(&mut self, frame: &Frame) -> Option<Value> {
+        match mem::take(&mut self.slots[frame.base]) {
+            Slot::Value(value) => Some(value),
+            Slot::Cell(cell) => cell.borrow().clone(),
+            Slot::Empty => None,
+        }
+    }
+
+    /// Assigns a `&mut self` method's final `self` to the place it was called on.
+    fn write_back(&mut self, to: WriteBack, receiver: Value, closure: &Closure, base: usize) -> Result<(), TogError> {
+        if closure.proto.paths[to.path as usize].steps.is_empty() {
+            return self.store(to.var, receiver, closure, base);
+        }
+        self.stack.push(receiver);
+        self.stack.extend(to.indexes);
+        self.store_through(to.var, to.path, None, closure, base)
+    }
+
+    fn var_name<'a>(&'a self, var: Var, proto: &'a Proto) -> &'a str {
+        match var {
+            Var::Local(slot) | Var::Cell(slot) => &proto.slot_names[slot as usize],
+            Var::Upvalue(index) => &proto.upvalue_names[index as usize],
+            Var::Global(index) => &self.global_names[index as usize],
+        }
+    }
+
+    fn undefined(&self, var: Var, proto: &Proto) -> TogError {
+        let name = self.var_name(var, proto);
+        let error = TogError::RuntimeError(format!("Undefined variable: {}", name), None);
+        let globals = self.global_names.iter().zip(&self.globals)
+            .filter(|(_, value)| value.is_some())
+            .map(|(name, _)| name.as_str());
+        let visible = proto.slot_names.iter().chain(&proto.upvalue_names)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+            .chain(globals);
+        match did_you_mean(name, visible) {
+            Some(help) => error.with_help(help),
+            None => error,
+        }
+    }
+
+    fn match_and_bind(&mut self, info: &PatternInfo, value: &Value, closure: &Closure, base: usize) -> Result<bool, TogError> {
+        let mut bindings = Vec::new();
+        if !match_pattern(&info.pattern, value, &mut bindings)? {
+            return Ok(false);
+        }
+        for (name, bound) in bindings {
+            if let Some((_, var)) = info.bindings.iter().find(|(n, _)| *n == name) {
+                self.bind(*var, bound, closure, base);
+            }
+        }
+        Ok(true)
+    }
+
+    // --- Calls ---
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<Flow, TogError> {
+        match callee {
+            Value::Closure(function) => self.call_closure(function, argc),
+            _ => Err(TogError::TypeError("Can only call functions".to_string(), None)),
+        }
+    }
+
+    fn call_closure(&mut self, function: Rc<Closure>, argc: usize) -> Result<Flow, TogError> {
+        if argc != function.arity() {
+            return Err(TogError::RuntimeError(
+                format!("Function expects {} arguments, got {}", function.arity(), argc),
+                None
+            ));
+        }
+        let frame = StackFrame { function: function.name().to_string(), receiver: None, call_site: self.call_site };
+        self.enter(function, argc, None, Some(frame));
+        Ok(Flow::Call)
+    }
+
+    /// `name(args)` where the variable doesn't hold a function: `print`, a
+    /// builtin, or a call of whatever the variable holds.
+    fn call_by_name(&mut self, var: Var, callee: &(String, Span), argc: usize, closure: &Closure, base: usize) -> Result<Flow, TogError> {
+        let (name, span) = callee;
+        let args = self.pop_n(argc as u32);
+        if name == "print" {
+            for arg in &args {
+                print!("{}", value_to_string(arg));
+            }
+            println!();
+            self.stack.push(Value::None);
+            return Ok(Flow::Next);
+        }
+        match stdlib::call_builtin(self, name, &args) {
+            Ok(result) => {
+                self.stack.push(result);
+                Ok(Flow::Next)
+            }
+            Err(TogError::RuntimeError(ref msg, _)) if msg.contains("Unknown builtin") => {
+                let callee = self.with_var(var, closure, base, Value::clone)
+                    .ok_or_else(|| self.undefined(var, &closure.proto).with_span(*span))?;
+                self.stack.extend(args);
+                self.call_value(callee, argc)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `object.name(args)`: a method of the object's type, or a field
+    /// holding a function.
+    fn call_method_on(&mut self, object: Value, name: &str, argc: usize) -> Result<Flow, TogError> {
+        // Builtin types only have the methods of `impl Trait for int` etc.
+        let builtin_type = match &object {
+            Value::Int(_) => Some("int"),
+            Value::Float(_) => Some("float"),
+            Value::String(_) => Some("string"),
+            Value::Bool(_) => Some("bool"),
+            _ => None,
+        };
+        if let Some(type_name) = builtin_type {
+            if let Some(method) = self.find_method(type_name, name)? {
+                return self.call_method(type_name, method, Some(object), argc);
+            }
+        }
+        let receiver_type = match &object {
+            Value::Struct { name, .. } => Some(name.clone()),
+            Value::Enum { enum_name, .. } => Some(enum_name.clone()),
+            _ => None,
+        };
+        if let Some(type_name) = receiver_type {
+            if let Some(method) = self.find_method(&type_name, name)? {
+                return self.call_method(&type_name, method, Some(object), argc);
+            }
+            // A struct field holding a function is called like a method
+            let is_field = matches!(&object, Value::Struct { fields, .. } if fields.contains_key(name));
+            if !is_field {
+                return Err(TogError::RuntimeError(format!("Unknown method '{}' on {}", name, type_name), None));
+            }
+        }
+        let callee = field_of(&object, name)?.clone();
+        self.call_value(callee, argc)
+    }
+
+    /// Resolves `method_name` on `type_name`, with the tree-walker's
+    /// precedence: methods written inline in a struct body, then inherent
+    /// `impl Type` blocks, then `impl Trait for Type` blocks.
+    fn find_method(&self, type_name: &str, method_name: &str) -> Result<Option<Rc<Closure>>, TogError> {
+        let Some(methods) = self.methods.get(type_name) else {
+            return Ok(None);
+        };
+        if let Some(method) = methods.inline.get(method_name).or_else(|| methods.inherent.get(method_name)) {
+            return Ok(Some(Rc::clone(method)));
+        }
+        let candidates: Vec<(&String, &Rc<Closure>)> = methods.traits.iter()
+            .filter_map(|(trait_name, methods)| methods.get(method_name).map(|m| (trait_name, m)))
+            .collect();
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [(_, method)] => Ok(Some(Rc::clone(method))),
+            _ => {
+                let traits: Vec<&str> = candidates.iter().map(|(t, _)| t.as_str()).collect();
+                Err(TogError::RuntimeError(
+                    format!(
+                        "Ambiguous method '{}' on {}: provided by traits {}",
+                        method_name, type_name, traits.join(", ")
+                    ),
+                    None
+                ))
+            }
+        }
+    }
+
+    /// Calls a method with its last `argc` arguments on the stack. A leading
+    /// `self` parameter is bound to `receiver`, or to the first argument when
+    /// called through the type (`Type::method(obj)`).
+    fn call_method(&mut self, type_name: &str, method: Rc<Closure>, receiver: Option<Value>, argc: usize) -> Result<Flow, TogError> {
+        let params = &method.proto.params;
+        let takes_self = params.first().is_some_and(|p| p.name == "self");
+        let (receiver, given) = match (takes_self, receiver) {
+            (true, Some(receiver)) => (Some(receiver), argc),
+            (true, None) if params[0].by_ref => return Err(mut_self_without_receiver(type_name, method.name())),
+            (true, None) if argc == 0 => {
+                return Err(TogError::RuntimeError(
+                    format!("Method '{}' on {} needs a receiver", method.name(), type_name),
+                    None
+                ));
+            }
+            (true, None) => (None, argc - 1),
+            (false, _) => (None, argc),
+        };
+        let expected = params.len() - usize::from(takes_self);
+        if given != expected {
+            return Err(TogError::RuntimeError(
+                format!("Method '{}' on {} expects {} arguments, got {}", method.name(), type_name, expected, given),
+                None
+            ));
+        }
+        let frame = StackFrame {
+            function: method.name().to_string(),
+            receiver: Some(type_name.to_string()),
+            call_site: self.call_site,
+        };
+        self.enter(method, argc, receiver, Some(frame));
+        Ok(Flow::Call)
+    }
+
+    fn is_type_name(&self, name: &str) -> bool {
+        self.definitions.struct_defs.contains_key(name)
+            || self.definitions.enum_defs.contains_key(name)
+            || self.definitions.inherent_impls.contains_key(name)
+    }
+
+    fn is_enum_variant(&self, enum_name: &str, variant_name: &str) -> bool {
+        self.definitions.enum_defs.get(enum_name)
+            .is_some_and(|variants| variants.iter().any(|v| v.name == variant_name))
+    }
+
+    /// Calls an associated function of a builtin type, such as `Map::new()`,
+    /// unless the program declares a type of that name.
+    fn call_builtin_associated(&mut self, type_name: &str, name: &str, args: &[Value]) -> Result<Option<Value>, TogError> {
+        if self.is_type_name(type_name) {
+            return Ok(None);
+        }
+        match stdlib::call_builtin(self, &format!("{}::{}", type_name, name), args) {
+            Ok(result) => Ok(Some(result)),
+            Err(TogError::RuntimeError(ref msg, _)) if msg.contains("Unknown builtin") => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `Type::name(args)`: an associated function, or a variant holding
+    /// several values.
+    fn call_path(&mut self, type_name: &str, name: &str, argc: usize) -> Result<Flow, TogError> {
+        if !self.is_enum_variant(type_name, name) {
+            let args = self.pop_n(argc as u32);
+            if let Some(result) = self.call_builtin_associated(type_name, name, &args)? {
+                self.stack.push(result);
+                return Ok(Flow::Next);
+            }
+            let method = self.find_method(type_name, name)?
+                .ok_or_else(|| TogError::RuntimeError(format!("Unknown method '{}' on {}", name, type_name), None))?;
+            self.stack.extend(args);
+            return self.call_method(type_name, method, None, argc);
+        }
+        if argc == 0 {
+            return Err(TogError::RuntimeError(
+                format!("Enum variant {}::{} needs a value inside the parentheses", type_name, name),
+                None
+            ).with_help(format!("write `{}::{}` for a variant without data", type_name, name)));
+        }
+        // `Move(1, 2)` holds its values as a tuple
+        let values = self.pop_n(argc as u32);
+        self.stack.push(Value::Enum {
+            enum_name: type_name.to_string(),
+            variant_name: name.to_string(),
+            data: Some(Box::new(Value::Tuple(values))),
+        });
+        Ok(Flow::Next)
+    }
+
+    /// `Type::name` or `Type::name(data)`: an enum variant, or an associated
+    /// function taking `data`.
+    fn enum_path(&mut self, type_name: &str, name: &str, has_data: bool) -> Result<Flow, TogError> {
+        let argc = usize::from(has_data);
+        if !self.is_enum_variant(type_name, name) {
+            if let Some(method) = self.find_method(type_name, name)? {
+                return self.call_method(type_name, method, None, argc);
+            }
+            let args = self.pop_n(argc as u32);
+            if let Some(result) = self.call_builtin_associated(type_name, name, &args)? {
+                self.stack.push(result);
+                return Ok(Flow::Next);
+            }
+            if !self.definitions.enum_defs.contains_key(type_name) {
+                return Err(TogError::RuntimeError(format!("Unknown enum: {}", type_name), None));
+            }
+            return Err(TogError::RuntimeError(
+                format!("Enum '{}' has no variant or method '{}'", type_name, name),
+                None
+            ));
+        }
+        let data = if has_data { Some(Box::new(self.pop())) } else { None };
+        self.stack.push(Value::Enum {
+            enum_name: type_name.to_string(),
+            variant_name: name.to_string(),
+            data,
+        });
+        Ok(Flow::Next)
+    }
+}
+
+/// Reads through `path` from `root`, borrowing until a step has to make a
+/// new value (indexing a string).
+fn load_path(root: &Value, indexes: &[Value], path: &Path) -> Result<Value, TogError> {
+    let mut current = Cow::Borrowed(root);
+    let mut indexes = indexes.iter();
+    for (step, span) in &path.steps {
+        let next = match step {
+            Step::Field(field) => match current {
+                Cow::Borrowed(value) => field_of(value, field).map(Cow::Borrowed),
+                Cow::Owned(value) => field_of(&value, field).cloned().map(Cow::Owned),
+            },
+            Step::Index => {
+                let index = indexes.next().expect("path index");
+                match current {
+                    Cow::Borrowed(value) => index_of(value, index),
+                    Cow::Owned(value) => index_of(&value, index).map(|v| Cow::Owned(v.into_owned())),
+                }
+            }
+        };
+        current = next.map_err(|e| e.with_span(*span))?;
+    }
+    Ok(current.into_owned())
+}
+
+/// `evaluate_binary_op` on two ints, or `None` for the ops and overflows
+/// it reports an error for.
+fn int_binary(a: i64, op: BinaryOp, b: i64) -> Option<Value> {
+    Some(match op {
+        BinaryOp::Add => Value::Int(a.checked_add(b)?),
+        BinaryOp::Sub => Value::Int(a.checked_sub(b)?),
+        BinaryOp::Mul => Value::Int(a.checked_mul(b)?),
+        BinaryOp::Eq => Value::Bool(a == b),
+        BinaryOp::Ne => Value::Bool(a != b),
+        BinaryOp::Lt => Value::Bool(a < b),
+        BinaryOp::Le => Value::Bool(a <= b),
+        BinaryOp::Gt => Value::Bool(a > b),
+        BinaryOp::Ge => Value::Bool(a >= b),
+        _ => return None,
+    })
+}
+
+/// Assigns `value` at the end of `path`, changing `root` in place. With
+/// `op`, the value assigned is the one already there `op` `value`.
+fn store_path(root: &mut Value, indexes: &[Value], path: &Path, op: Option<BinaryOp>, value: Value) -> Result<(), TogError> {
+    let mut indexes = indexes.iter();
+    let (last, init) = path.steps.split_last().expect("path step");
+    let mut current = root;
+    for (step, span) in init {
+        current = match step {
+            Step::Field(field) => field_mut(current, field),
+            Step::Index => index_mut(current, indexes.next().expect("path index")),
+        }.map_err(|e| e.with_span(*span))?;
+    }
+    match &last.0 {
+        Step::Field(field) => {
+            let value = match op {
+                Some(op) => evaluate_binary_op(field_of(current, field)?, op, &value)?,
+                None => value,
+            };
+            set_field(current, field, value)
+        }
+        Step::Index => {
+            let index = indexes.next().expect("path index");
+            let value = match op {
+                Some(op) => evaluate_binary_op(&*index_of(current, index)?, op, &value)?,
+                None => value,
+            };
+            set_index(current, index.clone(), value)
+        }
+    }
+}
+
+impl FunctionCaller for Machine {
+    fn call_function(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, TogError> {
+        let Value::Closure(function) = func else {
+            return Err(TogError::TypeError("Can only call functions".to_string(), None));
+        };
+        let depth = self.frames.len();
+        let argc = args.len();
+        self.stack.extend(args);
+        match self.call_closure(Rc::clone(function), argc) {
+            Ok(_) => self.execute(depth),
+            Err(e) => {
+                self.stack.truncate(self.stack.len() - argc);
+                Err(e)
+            }
+        }
+    }
+
+    fn share_function(&self, func: &Value) -> Result<SharedFunction, TogError> {
+        // Functions are keyed by closure so recursion and functions reached
+        // twice are only shared once, while closures of the same code with
+        // different captures stay apart. The queue is first-in first-out, so
+        // a function's index is its position in `functions`.
+        let Value::Closure(root) = func else {
+            return Err(TogError::TypeError("Can only call functions".to_string(), None));
+        };
+        let mut indices: HashMap<*const Closure, usize> = HashMap::from([(Rc::as_ptr(root), 0)]);
+        let mut pending = VecDeque::from([Rc::clone(root)]);
+        let mut functions: Vec<SharedClosure> = Vec::new();
+
+        while let Some(function) = pending.pop_front() {
+            let proto = &function.proto;
+            let body = proto.body.as_deref().expect("functions keep their source");
+            let captures = captured_variables(&proto.params, body);
+            if let Some(var) = captures.assigned.iter().next() {
+                return Err(TogError::RuntimeError(
+                    format!("Function '{}' cannot run in parallel: it assigns to captured variable '{}'", proto.name, var),
+                    None
+                ));
+            }
+
+            let mut shared = SharedClosure {
+                name: proto.name.clone(),
+                params: proto.params.clone(),
+                body: body.clone(),
+                captures: Vec::new(),
+                captured_functions: Vec::new(),
+            };
+            for var in &captures.read {
+                // Builtins, type names and undefined names are resolved on the worker
+                let upvalue = proto.upvalue_names.iter().position(|name| name == var)
+                    .and_then(|index| function.upvalues[index].borrow().clone());
+                let global = || self.global_names.iter().position(|name| name == var)
+                    .and_then(|index| self.globals[index].clone());
+                let Some(captured) = upvalue.or_else(global) else { continue };
+                if let Value::Closure(captured) = captured {
+                    let next = indices.len();
+                    let index = *indices.entry(Rc::as_ptr(&captured)).or_insert_with(|| {
+                        pending.push_back(Rc::clone(&captured));
+                        next
+                    });
+                    shared.captured_functions.push((var.clone(), index));
+                } else {
+                    shared.captures.push((var.clone(), SharedValue::from_value(&captured)?));
+                }
+            }
+            functions.push(shared);
+        }
+
+        Ok(SharedFunction { definitions: self.definitions.clone(), functions })
+    }
+
+    fn stack_trace(&self) -> String {
+        format_trace(&self.call_stack, self.call_site).trim_end().to_string()
+    }
+}
@@ -0,0 +1,39 @@
+// Bytecode VM
+//
+// `tog run` compiles the program to bytecode and runs it on a stack machine
+// instead of walking the AST (`tog run --tree-walk` still uses interpreter.rs).
+// - compiler.rs turns the resolver's (depth, slot) pairs into variable
+//   accesses: a slot in the function's frame, a cell shared with closures, or
+//   a global. Nothing is looked up by name while the program runs.
+// - Reads and assignments through fields and indexes (`grid[i][j]`,
+//   `team.players[0].score = 3`) walk the variable in place rather than
+//   copying it.
+// - machine.rs runs the bytecode. Calls between TOG functions push a frame
+//   instead of recursing in Rust.
+// Both engines share `Value` and the value helpers in interpreter.rs, so
+// programs print the same output and fail with the same errors. Parallel
+// builtins still run their workers on the tree-walker, from the function's
+// source.
+
+mod bytecode;
+mod compiler;
+mod machine;
+
+use crate::ast::Program;
+use crate::error::TogError;
+use crate::{resolver, stdlib};
+
+pub use bytecode::Closure;
+
+/// Compiles and runs a program, loading the prelude first unless told not to.
+pub fn run(program: Program, load_prelude: bool) -> Result<(), TogError> {
+    let mut statements = Vec::new();
+    if load_prelude {
+        statements.extend(stdlib::prelude()?.statements);
+    }
+    statements.extend(program.statements);
+
+    let resolution = resolver::resolve(&statements)?;
+    let compiled = compiler::compile(&statements, &resolution)?;
+    machine::Machine::new(&compiled).run(compiled)
+}